@@ -27,3 +27,18 @@ pub extern "C" fn test_identity_struct(x: S) -> S {
 
 #[unsafe(no_mangle)]
 pub static HELLO: &str = "Hello!";
+
+#[repr(C)]
+pub struct VTable {
+    add: fn(i32, i32) -> i32,
+}
+
+#[unsafe(no_mangle)]
+pub static PLUGIN_ABI_VERSION: u32 = 1;
+
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[unsafe(no_mangle)]
+pub static PLUGIN_VTABLE: VTable = VTable { add };
@@ -0,0 +1,49 @@
+#![no_std]
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+unsafe extern "Rust" {
+    fn a() -> i32;
+}
+
+extern "C" fn slow() -> i32 {
+    0
+}
+
+extern "C" fn fast() -> i32 {
+    42
+}
+
+// The resolver reads relocated data and calls a function through the plt, so it only works
+// after the other relocations of this library are done.
+static IMPLS: [extern "C" fn() -> i32; 2] = [slow, fast];
+
+#[unsafe(no_mangle)]
+extern "C" fn select_value() -> extern "C" fn() -> i32 {
+    IMPLS[(unsafe { a() } == 1) as usize]
+}
+
+core::arch::global_asm!(
+    ".globl ifunc_value",
+    ".hidden ifunc_value",
+    ".type ifunc_value, %gnu_indirect_function",
+    ".set ifunc_value, select_value",
+);
+
+unsafe extern "C" {
+    fn ifunc_value() -> i32;
+}
+
+// Taking the address of the hidden ifunc makes the linker emit an IRELATIVE relocation.
+#[unsafe(no_mangle)]
+static VALUE_FN: unsafe extern "C" fn() -> i32 = ifunc_value;
+
+#[unsafe(no_mangle)]
+fn ifunc() -> i32 {
+    unsafe { VALUE_FN() }
+}
@@ -5,14 +5,18 @@ extern crate alloc;
 use alloc::string::ToString;
 use core::{
     arch::global_asm,
-    ffi::{CStr, c_int},
+    ffi::c_int,
     fmt,
     panic::PanicInfo,
     ptr::{addr_of_mut, null},
 };
 use elf_loader::{
     abi::{DT_NULL, DT_RELA, DT_RELACOUNT, PT_DYNAMIC},
-    arch::{Dyn, ElfPhdr, REL_RELATIVE},
+    arch::{Dyn, REL_RELATIVE},
+    bootstrap::{
+        AT_BASE, AT_NULL, AT_PHDR, AT_PHNUM, AuxEntry, InitialStack, find_interp, jump_to_entry,
+        prepare_stack,
+    },
     load,
 };
 use linked_list_allocator::LockedHeap;
@@ -37,14 +41,6 @@ fn exit(status: c_int) -> ! {
     unreachable!()
 }
 
-const AT_NULL: u64 = 0;
-const AT_PHDR: u64 = 3;
-const AT_PHENT: u64 = 4;
-const AT_PHNUM: u64 = 5;
-const AT_BASE: u64 = 7;
-const AT_ENTRY: u64 = 9;
-const AT_EXECFN: u64 = 31;
-
 #[global_allocator]
 static mut ALLOCATOR: LockedHeap = LockedHeap::empty();
 
@@ -79,26 +75,6 @@ _start:
 	hlt"
 );
 
-global_asm!(
-    "	
-	.text
-	.align	4
-	.globl	trampoline
-	.type	trampoline,@function
-trampoline:
-	xor rdx, rdx
-	mov	rsp, rsi
-	jmp	rdi
-	/* Should not reach. */
-	hlt"
-);
-
-#[repr(C)]
-struct Aux {
-    tag: u64,
-    val: u64,
-}
-
 // auxv <---sp + argc + 2 + env_count + 2
 // 0    <---sp + argc + 2 + env_count + 1
 // env  <---sp + argc + 2
@@ -136,7 +112,7 @@ unsafe extern "C" fn rust_main(sp: *mut usize, dynv: *mut Dyn) {
         env_count += 1;
         cur_env = unsafe { cur_env.add(1) };
     }
-    let auxv = unsafe { env.add(env_count + 1).cast::<Aux>() };
+    let auxv = unsafe { env.add(env_count + 1).cast::<AuxEntry>() };
 
     // 获得mini-loader的phdrs
     let mut cur_aux_ptr = auxv;
@@ -146,7 +122,7 @@ unsafe extern "C" fn rust_main(sp: *mut usize, dynv: *mut Dyn) {
             AT_NULL => break,
             AT_PHDR => ph = cur_aux.val as *const elf::segment::Elf64_Phdr,
             AT_PHNUM => phnum = cur_aux.val,
-            AT_BASE => base = cur_aux.val as usize,
+            AT_BASE => base = cur_aux.val,
             _ => {}
         }
         cur_aux_ptr = unsafe { cur_aux_ptr.add(1) };
@@ -154,7 +130,7 @@ unsafe extern "C" fn rust_main(sp: *mut usize, dynv: *mut Dyn) {
     }
     // 通常是0，需要自行计算
     if base == 0 {
-        let phdrs = unsafe { &*core::ptr::slice_from_raw_parts(ph, phnum as usize) };
+        let phdrs = unsafe { &*core::ptr::slice_from_raw_parts(ph, phnum) };
         let mut idx = 0;
         loop {
             let phdr = &phdrs[idx];
@@ -181,54 +157,14 @@ unsafe extern "C" fn rust_main(sp: *mut usize, dynv: *mut Dyn) {
         panic!("no input file");
     }
     // 加载输入的elf文件
-    let argv = unsafe { sp.add(1) };
-    let elf_name = unsafe { CStr::from_ptr(argv.add(1).read() as _) };
+    let mut stack = unsafe { InitialStack::from_sp(sp) };
+    let elf_name = stack.arg(1).unwrap();
     let elf = load!(elf_name.to_str().unwrap()).unwrap();
-    let mut interp_dylib = None;
     // 加载动态加载器ld.so，如果有的话
-    if let Some(interp_name) = elf.interp() {
-        interp_dylib = Some(load!(interp_name).unwrap());
-    }
-    let phdrs = elf.phdrs();
-    // 重新设置aux
-    let mut cur_aux_ptr = auxv as *mut Aux;
-    let mut cur_aux = unsafe { &mut *cur_aux_ptr };
-    loop {
-        match cur_aux.tag {
-            AT_NULL => break,
-            AT_PHDR => cur_aux.val = phdrs.as_ptr() as u64,
-            AT_PHNUM => cur_aux.val = phdrs.len() as u64,
-            AT_PHENT => cur_aux.val = size_of::<ElfPhdr>() as u64,
-            AT_ENTRY => cur_aux.val = elf.entry() as u64,
-            AT_EXECFN => cur_aux.val = unsafe { argv.add(1).read() } as u64,
-            AT_BASE => {
-                cur_aux.val = interp_dylib
-                    .as_ref()
-                    .map(|dylib| dylib.entry())
-                    .unwrap_or(elf.entry()) as u64
-            }
-            _ => {}
-        }
-        cur_aux_ptr = unsafe { cur_aux_ptr.add(1) };
-        cur_aux = unsafe { &mut *cur_aux_ptr };
-    }
-
-    unsafe extern "C" {
-        fn trampoline(entry: usize, sp: *const usize) -> !;
-    }
-
-    // 修改argv，将mini-loader去除，这里涉及到16字节对齐，因此只能拷贝
-    let size = unsafe { cur_aux_ptr.add(1) as usize - sp.add(1) as usize };
-    unsafe { core::ptr::copy(sp.add(1), sp, size / size_of::<usize>()) };
-    unsafe { sp.write(argc - 1) };
-
-    unsafe {
-        if let Some(interp_dylib) = interp_dylib {
-            trampoline(interp_dylib.entry(), sp);
-        } else {
-            trampoline(elf.entry(), sp);
-        }
-    }
+    let interp_dylib = find_interp(&elf).map(|interp_name| load!(interp_name).unwrap());
+    // 重新设置aux，并将mini-loader从argv中去除
+    let entry = prepare_stack(&mut stack, &elf, interp_dylib.as_ref(), 1);
+    unsafe { jump_to_entry(entry, stack.sp()) }
 }
 
 #[inline]
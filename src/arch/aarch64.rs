@@ -1,4 +1,8 @@
-use core::arch::global_asm;
+use super::ObjSlot;
+use core::{
+    arch::{asm, global_asm},
+    ops::Range,
+};
 use elf::abi::*;
 
 pub const EM_ARCH: u16 = EM_AARCH64;
@@ -12,7 +16,8 @@ pub const REL_JUMP_SLOT: u32 = R_AARCH64_JUMP_SLOT;
 pub const REL_DTPOFF: u32 = R_AARCH64_TLS_DTPREL;
 pub const REL_IRELATIVE: u32 = R_AARCH64_IRELATIVE;
 pub const REL_COPY: u32 = R_AARCH64_COPY;
-pub const REL_TPOFF:u32 = R_AARCH64_TLS_TPREL;
+pub const REL_TPOFF: u32 = R_AARCH64_TLS_TPREL;
+pub const REL_TLSDESC: u32 = R_AARCH64_TLSDESC;
 
 global_asm!(
     "
@@ -47,6 +52,165 @@ dl_runtime_resolve:
 "
 );
 
+// tls descriptor的解析函数: x0中是描述符的地址,返回值是变量相对于线程指针的偏移,除x0外不能破坏任何寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_tlsdesc_return
+	.type dl_tlsdesc_return, @function
+	.align 16
+dl_tlsdesc_return:
+// 静态tls中的变量,描述符的第二项就是偏移
+    ldr x0,[x0,8]
+    ret
+
+    .globl dl_tlsdesc_undefweak
+	.type dl_tlsdesc_undefweak, @function
+	.align 16
+dl_tlsdesc_undefweak:
+// 未定义的弱符号的地址为0,因此返回 addend - tp
+    str x1,[sp,-16]!
+    ldr x0,[x0,8]
+    mrs x1,tpidr_el0
+    sub x0,x0,x1
+    ldr x1,[sp],16
+    ret
+
+    .globl dl_tlsdesc_dynamic
+	.type dl_tlsdesc_dynamic, @function
+	.align 16
+dl_tlsdesc_dynamic:
+// 保存调用者保存的寄存器和浮点寄存器
+    sub sp,sp,672
+    stp x29,x30,[sp,0]
+    stp x1,x2,[sp,16]
+    stp x3,x4,[sp,32]
+    stp x5,x6,[sp,48]
+    stp x7,x8,[sp,64]
+    stp x9,x10,[sp,80]
+    stp x11,x12,[sp,96]
+    stp x13,x14,[sp,112]
+    stp x15,x16,[sp,128]
+    stp x17,x18,[sp,144]
+    stp q0,q1,[sp,160]
+    stp q2,q3,[sp,192]
+    stp q4,q5,[sp,224]
+    stp q6,q7,[sp,256]
+    stp q8,q9,[sp,288]
+    stp q10,q11,[sp,320]
+    stp q12,q13,[sp,352]
+    stp q14,q15,[sp,384]
+    stp q16,q17,[sp,416]
+    stp q18,q19,[sp,448]
+    stp q20,q21,[sp,480]
+    stp q22,q23,[sp,512]
+    stp q24,q25,[sp,544]
+    stp q26,q27,[sp,576]
+    stp q28,q29,[sp,608]
+    stp q30,q31,[sp,640]
+    mov x29,sp
+// 描述符的第二项是TlsDescDynamic
+    ldr x1,[x0,8]
+    ldr x2,[x1]
+    add x0,x1,8
+    blr x2
+    mrs x1,tpidr_el0
+    sub x0,x0,x1
+// 恢复寄存器
+    ldp q0,q1,[sp,160]
+    ldp q2,q3,[sp,192]
+    ldp q4,q5,[sp,224]
+    ldp q6,q7,[sp,256]
+    ldp q8,q9,[sp,288]
+    ldp q10,q11,[sp,320]
+    ldp q12,q13,[sp,352]
+    ldp q14,q15,[sp,384]
+    ldp q16,q17,[sp,416]
+    ldp q18,q19,[sp,448]
+    ldp q20,q21,[sp,480]
+    ldp q22,q23,[sp,512]
+    ldp q24,q25,[sp,544]
+    ldp q26,q27,[sp,576]
+    ldp q28,q29,[sp,608]
+    ldp q30,q31,[sp,640]
+    ldp x29,x30,[sp,0]
+    ldp x1,x2,[sp,16]
+    ldp x3,x4,[sp,32]
+    ldp x5,x6,[sp,48]
+    ldp x7,x8,[sp,64]
+    ldp x9,x10,[sp,80]
+    ldp x11,x12,[sp,96]
+    ldp x13,x14,[sp,112]
+    ldp x15,x16,[sp,128]
+    ldp x17,x18,[sp,144]
+    add sp,sp,672
+    ret
+"
+);
+
+// 切换到新的栈并跳转到程序入口,同时清空用于传递atexit函数的寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_trampoline
+	.type dl_trampoline, @function
+	.align 16
+dl_trampoline:
+    mov sp,x1
+    mov x16,x0
+    mov x0,xzr
+    br x16
+"
+);
+
+// 保存callee-saved寄存器后调用函数,dl_context_leave会恢复这些寄存器并从dl_context_enter返回
+global_asm!(
+    "
+    .text
+    .globl dl_context_enter
+	.type dl_context_enter, @function
+	.align 16
+dl_context_enter:
+    stp x19,x20,[x0,0]
+    stp x21,x22,[x0,16]
+    stp x23,x24,[x0,32]
+    stp x25,x26,[x0,48]
+    stp x27,x28,[x0,64]
+    stp x29,x30,[x0,80]
+    mov x9,sp
+    str x9,[x0,96]
+    stp d8,d9,[x0,104]
+    stp d10,d11,[x0,120]
+    stp d12,d13,[x0,136]
+    stp d14,d15,[x0,152]
+    stp x29,x30,[sp,-16]!
+    mov x29,sp
+    mov x0,x2
+    blr x1
+    ldp x29,x30,[sp],16
+    ret
+
+    .globl dl_context_leave
+	.type dl_context_leave, @function
+	.align 16
+dl_context_leave:
+    ldp x19,x20,[x0,0]
+    ldp x21,x22,[x0,16]
+    ldp x23,x24,[x0,32]
+    ldp x25,x26,[x0,48]
+    ldp x27,x28,[x0,64]
+    ldp x29,x30,[x0,80]
+    ldr x9,[x0,96]
+    mov sp,x9
+    ldp d8,d9,[x0,104]
+    ldp d10,d11,[x0,120]
+    ldp d12,d13,[x0,136]
+    ldp d14,d15,[x0,152]
+    mov w0,w1
+    ret
+"
+);
+
 pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
     unsafe extern "C" {
         fn dl_runtime_resolve();
@@ -57,3 +221,193 @@ pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
         got.add(2).write(dl_runtime_resolve as usize);
     }
 }
+
+/// Prepares the state `dl_tlsdesc_dynamic` needs before it is written into a tls descriptor.
+#[inline]
+pub(crate) fn prepare_tlsdesc_dynamic() {}
+
+/// Makes the instructions written to `range` visible to instruction fetches. The data cache lines
+/// are cleaned to the point of unification, and then the instruction cache lines are invalidated.
+pub fn flush_icache(range: Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+    let ctr: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack, preserves_flags)) };
+    // CTR_EL0.IDC为1时无需清理数据缓存
+    if ctr & (1 << 28) == 0 {
+        let line = 4 << ((ctr >> 16) & 0xf);
+        for addr in ((range.start & !(line - 1))..range.end).step_by(line) {
+            unsafe { asm!("dc cvau, {}", in(reg) addr, options(nostack, preserves_flags)) };
+        }
+    }
+    unsafe { asm!("dsb ish", options(nostack, preserves_flags)) };
+    // CTR_EL0.DIC为1时无需无效化指令缓存
+    if ctr & (1 << 29) == 0 {
+        let line = 4 << (ctr & 0xf);
+        for addr in ((range.start & !(line - 1))..range.end).step_by(line) {
+            unsafe { asm!("ic ivau, {}", in(reg) addr, options(nostack, preserves_flags)) };
+        }
+        unsafe { asm!("dsb ish", options(nostack, preserves_flags)) };
+    }
+    unsafe { asm!("isb", options(nostack, preserves_flags)) };
+}
+
+/// The size of a stub used by a branch to a function out of the range of `R_AARCH64_CALL26`.
+pub(crate) const OBJ_STUB_SIZE: usize = 16;
+
+// 可重定位目标文件中需要桩或者got项的重定位类型
+#[inline]
+pub(crate) fn obj_slot(r_type: u32) -> ObjSlot {
+    match r_type {
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => ObjSlot::Stub,
+        R_AARCH64_ADR_GOT_PAGE | R_AARCH64_LD64_GOT_LO12_NC => ObjSlot::Got,
+        _ => ObjSlot::None,
+    }
+}
+
+// 重定位写入的字节数,指令都是4字节
+#[inline]
+pub(crate) fn obj_width(r_type: u32) -> usize {
+    match r_type {
+        R_AARCH64_NONE => 0,
+        R_AARCH64_ABS64 | R_AARCH64_PREL64 => 8,
+        _ => 4,
+    }
+}
+
+// ldr x16, 8; br x16; .quad target
+pub(crate) unsafe fn obj_write_stub(stub: *mut u8, target: usize) {
+    unsafe {
+        stub.cast::<[u32; 2]>().write([0x58000050, 0xd61f0200]);
+        stub.add(8).cast::<usize>().write_unaligned(target);
+    }
+}
+
+#[inline]
+fn page(addr: usize) -> usize {
+    addr & !0xfff
+}
+
+// 检查有符号数val能否用bits位表示
+#[inline]
+fn fits(val: isize, bits: u32) -> bool {
+    let half = 1isize << (bits - 1);
+    (-half..half).contains(&val)
+}
+
+// 将imm的低bits位写入指令的第shift位开始的字段
+#[inline]
+unsafe fn patch(place: *mut u32, imm: usize, shift: u32, bits: u32) {
+    let mask = ((1u32 << bits) - 1) << shift;
+    unsafe {
+        let insn = place.read_unaligned();
+        place.write_unaligned(insn & !mask | ((imm as u32) << shift) & mask);
+    }
+}
+
+// adr和adrp的立即数被分为immlo(29-30位)和immhi(5-23位)
+#[inline]
+unsafe fn patch_adr(place: *mut u32, imm: usize) {
+    unsafe {
+        patch(place, imm, 29, 2);
+        patch(place, imm >> 2, 5, 19);
+    }
+}
+
+/// Applies a relocation of a relocatable object to `place`. `slot` is the stub or the got entry
+/// of the symbol if the relocation type needs one.
+pub(crate) unsafe fn obj_relocate(
+    r_type: u32,
+    place: usize,
+    sym: usize,
+    addend: isize,
+    slot: usize,
+) -> Result<(), &'static str> {
+    const OVERFLOW: &str = "the relocation value is out of range";
+    let val = sym.wrapping_add_signed(addend);
+    let insn = place as *mut u32;
+    let rel = |target: usize| target.wrapping_sub(place) as isize;
+    unsafe {
+        match r_type {
+            R_AARCH64_NONE => {}
+            R_AARCH64_ABS64 => (place as *mut u64).write_unaligned(val as u64),
+            R_AARCH64_ABS32 => {
+                if i32::try_from(val as isize).is_err() && u32::try_from(val).is_err() {
+                    return Err(OVERFLOW);
+                }
+                (place as *mut u32).write_unaligned(val as u32);
+            }
+            R_AARCH64_PREL64 => (place as *mut u64).write_unaligned(rel(val) as u64),
+            R_AARCH64_PREL32 => {
+                let val = i32::try_from(rel(val)).map_err(|_| OVERFLOW)?;
+                (place as *mut i32).write_unaligned(val);
+            }
+            R_AARCH64_CALL26 | R_AARCH64_JUMP26 => {
+                // 函数超出±128MB时通过桩跳转
+                let mut off = rel(val);
+                if !fits(off, 28) && slot != 0 {
+                    off = rel(slot.wrapping_add_signed(addend));
+                }
+                if !fits(off, 28) {
+                    return Err(OVERFLOW);
+                }
+                patch(insn, (off >> 2) as usize, 0, 26);
+            }
+            R_AARCH64_CONDBR19 => {
+                let off = rel(val);
+                if !fits(off, 21) {
+                    return Err(OVERFLOW);
+                }
+                patch(insn, (off >> 2) as usize, 5, 19);
+            }
+            R_AARCH64_TSTBR14 => {
+                let off = rel(val);
+                if !fits(off, 16) {
+                    return Err(OVERFLOW);
+                }
+                patch(insn, (off >> 2) as usize, 5, 14);
+            }
+            R_AARCH64_ADR_PREL_LO21 => {
+                let off = rel(val);
+                if !fits(off, 21) {
+                    return Err(OVERFLOW);
+                }
+                patch_adr(insn, off as usize);
+            }
+            R_AARCH64_ADR_PREL_PG_HI21 | R_AARCH64_ADR_PREL_PG_HI21_NC | R_AARCH64_ADR_GOT_PAGE => {
+                let target = if r_type == R_AARCH64_ADR_GOT_PAGE {
+                    slot.wrapping_add_signed(addend)
+                } else {
+                    val
+                };
+                let off = page(target).wrapping_sub(page(place)) as isize >> 12;
+                if r_type != R_AARCH64_ADR_PREL_PG_HI21_NC && !fits(off, 21) {
+                    return Err(OVERFLOW);
+                }
+                patch_adr(insn, off as usize);
+            }
+            R_AARCH64_ADD_ABS_LO12_NC | R_AARCH64_LDST8_ABS_LO12_NC => patch(insn, val, 10, 12),
+            R_AARCH64_LDST16_ABS_LO12_NC => patch(insn, (val & 0xfff) >> 1, 10, 12),
+            R_AARCH64_LDST32_ABS_LO12_NC => patch(insn, (val & 0xfff) >> 2, 10, 12),
+            R_AARCH64_LDST64_ABS_LO12_NC => patch(insn, (val & 0xfff) >> 3, 10, 12),
+            R_AARCH64_LDST128_ABS_LO12_NC => patch(insn, (val & 0xfff) >> 4, 10, 12),
+            R_AARCH64_LD64_GOT_LO12_NC => {
+                let got = slot.wrapping_add_signed(addend);
+                patch(insn, (got & 0xfff) >> 3, 10, 12);
+            }
+            R_AARCH64_MOVW_UABS_G0..=R_AARCH64_MOVW_UABS_G3 => {
+                let group = (r_type - R_AARCH64_MOVW_UABS_G0) / 2;
+                let shift = 16 * group;
+                // 不带NC的类型需要检查高位是否为0
+                let checked = (r_type - R_AARCH64_MOVW_UABS_G0) % 2 == 0;
+                if checked && shift < 48 && val >> (shift + 16) != 0 {
+                    return Err(OVERFLOW);
+                }
+                patch(insn, val >> shift, 5, 16);
+            }
+            _ => return Err("unsupported relocation type"),
+        }
+    }
+    Ok(())
+}
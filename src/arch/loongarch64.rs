@@ -1,4 +1,8 @@
-use core::arch::global_asm;
+use super::ObjSlot;
+use core::{
+    arch::{asm, global_asm},
+    ops::Range,
+};
 
 // https://loongson.github.io/LoongArch-Documentation/LoongArch-ELF-ABI-CN.html
 
@@ -23,6 +27,8 @@ pub const REL_DTPMOD: u32 = R_LARCH_TLS_DTPMOD64;
 pub const REL_DTPOFF: u32 = R_LARCH_TLS_DTPREL64;
 pub const REL_IRELATIVE: u32 = R_LARCH_IRELATIVE;
 pub const REL_TPOFF: u32 = R_LARCH_TLS_TPREL64;
+// tls descriptor is not supported yet
+pub const REL_TLSDESC: u32 = u32::MAX;
 
 pub const REL_GOT: u32 = u32::MAX;
 
@@ -36,6 +42,86 @@ dl_runtime_resolve:
 "
 );
 
+// 切换到新的栈并跳转到程序入口,同时清空用于传递atexit函数的寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_trampoline
+	.type dl_trampoline, @function
+	.align 16
+dl_trampoline:
+    move $sp,$a1
+    move $t0,$a0
+    move $a0,$zero
+    jr $t0
+"
+);
+
+// 保存callee-saved寄存器后调用函数,dl_context_leave会恢复这些寄存器并从dl_context_enter返回
+global_asm!(
+    "
+    .text
+    .globl dl_context_enter
+	.type dl_context_enter, @function
+	.align 16
+dl_context_enter:
+    st.d $ra,$a0,0
+    st.d $sp,$a0,8
+    st.d $fp,$a0,16
+    st.d $s0,$a0,24
+    st.d $s1,$a0,32
+    st.d $s2,$a0,40
+    st.d $s3,$a0,48
+    st.d $s4,$a0,56
+    st.d $s5,$a0,64
+    st.d $s6,$a0,72
+    st.d $s7,$a0,80
+    st.d $s8,$a0,88
+    fst.d $fs0,$a0,96
+    fst.d $fs1,$a0,104
+    fst.d $fs2,$a0,112
+    fst.d $fs3,$a0,120
+    fst.d $fs4,$a0,128
+    fst.d $fs5,$a0,136
+    fst.d $fs6,$a0,144
+    fst.d $fs7,$a0,152
+    addi.d $sp,$sp,-16
+    st.d $ra,$sp,8
+    move $a0,$a2
+    jirl $ra,$a1,0
+    ld.d $ra,$sp,8
+    addi.d $sp,$sp,16
+    jr $ra
+
+    .globl dl_context_leave
+	.type dl_context_leave, @function
+	.align 16
+dl_context_leave:
+    ld.d $ra,$a0,0
+    ld.d $sp,$a0,8
+    ld.d $fp,$a0,16
+    ld.d $s0,$a0,24
+    ld.d $s1,$a0,32
+    ld.d $s2,$a0,40
+    ld.d $s3,$a0,48
+    ld.d $s4,$a0,56
+    ld.d $s5,$a0,64
+    ld.d $s6,$a0,72
+    ld.d $s7,$a0,80
+    ld.d $s8,$a0,88
+    fld.d $fs0,$a0,96
+    fld.d $fs1,$a0,104
+    fld.d $fs2,$a0,112
+    fld.d $fs3,$a0,120
+    fld.d $fs4,$a0,128
+    fld.d $fs5,$a0,136
+    fld.d $fs6,$a0,144
+    fld.d $fs7,$a0,152
+    addi.w $a0,$a1,0
+    jr $ra
+"
+);
+
 #[inline]
 pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
     unsafe extern "C" {
@@ -48,3 +134,36 @@ pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
     }
     unimplemented!()
 }
+
+/// Makes the instructions written to `range` visible to instruction fetches.
+#[inline]
+pub fn flush_icache(range: Range<usize>) {
+    if !range.is_empty() {
+        unsafe { asm!("ibar 0", options(nostack, preserves_flags)) };
+    }
+}
+
+pub(crate) const OBJ_STUB_SIZE: usize = 16;
+
+// 暂不支持可重定位目标文件的重定位
+#[inline]
+pub(crate) fn obj_slot(_r_type: u32) -> ObjSlot {
+    ObjSlot::None
+}
+
+#[inline]
+pub(crate) fn obj_width(_r_type: u32) -> usize {
+    0
+}
+
+pub(crate) unsafe fn obj_write_stub(_stub: *mut u8, _target: usize) {}
+
+pub(crate) unsafe fn obj_relocate(
+    _r_type: u32,
+    _place: usize,
+    _sym: usize,
+    _addend: isize,
+    _slot: usize,
+) -> Result<(), &'static str> {
+    Err("relocatable objects are not supported on this architecture")
+}
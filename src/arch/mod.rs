@@ -1,9 +1,10 @@
 //! Contains content related to the CPU instruction set
+use crate::mmap::ProtFlags;
 use core::ops::Deref;
 
 use elf::abi::{
-    SHN_UNDEF, STB_GLOBAL, STB_GNU_UNIQUE, STB_LOCAL, STB_WEAK, STT_COMMON, STT_FUNC,
-    STT_GNU_IFUNC, STT_NOTYPE, STT_OBJECT, STT_TLS,
+    SHN_ABS, SHN_UNDEF, STB_GLOBAL, STB_GNU_UNIQUE, STB_LOCAL, STB_WEAK, STT_COMMON, STT_FUNC,
+    STT_GNU_IFUNC, STT_NOTYPE, STT_OBJECT, STT_TLS, STV_HIDDEN, STV_INTERNAL, STV_PROTECTED,
 };
 
 cfg_if::cfg_if! {
@@ -23,6 +24,27 @@ cfg_if::cfg_if! {
 }
 
 pub const REL_NONE: u32 = 0;
+
+/// The extra entry needed by a relocation of a relocatable object
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ObjSlot {
+    None,
+    /// A stub jumping to a function which is out of the range of the branch instruction.
+    Stub,
+    /// A got entry holding the address of the symbol.
+    Got,
+}
+#[cfg(target_endian = "little")]
+pub(crate) const E_DATA: u8 = elf::abi::ELFDATA2LSB;
+#[cfg(target_endian = "big")]
+pub(crate) const E_DATA: u8 = elf::abi::ELFDATA2MSB;
+// 与glibc的DEFAULT_STACK_PERMS相同,没有PT_GNU_STACK时只有x86_64默认使用可执行栈
+#[inline]
+pub(crate) fn exec_stack(stack_prot: Option<ProtFlags>) -> bool {
+    stack_prot.map_or(cfg!(target_arch = "x86_64"), |prot| {
+        prot.contains(ProtFlags::PROT_EXEC)
+    })
+}
 const OK_BINDS: usize = 1 << STB_GLOBAL | 1 << STB_WEAK | 1 << STB_GNU_UNIQUE;
 const OK_TYPES: usize = 1 << STT_NOTYPE
     | 1 << STT_OBJECT
@@ -43,6 +65,7 @@ cfg_if::cfg_if! {
         pub(crate) const REL_BIT: usize = 32;
         pub(crate) const PHDR_SIZE: usize = core::mem::size_of::<elf::segment::Elf64_Phdr>();
         pub(crate) const EHDR_SIZE: usize = core::mem::size_of::<elf::file::Elf64_Ehdr>();
+        pub(crate) const SHDR_SIZE: usize = core::mem::size_of::<elf::section::Elf64_Shdr>();
     }else{
         pub(crate) const E_CLASS: u8 = elf::abi::ELFCLASS32;
         pub(crate) type Phdr = elf::segment::Elf32_Phdr;
@@ -54,6 +77,7 @@ cfg_if::cfg_if! {
         pub(crate) const REL_BIT: usize = 8;
         pub(crate) const PHDR_SIZE: usize = core::mem::size_of::<elf::segment::Elf32_Phdr>();
         pub(crate) const EHDR_SIZE: usize = core::mem::size_of::<elf::file::Elf32_Ehdr>();
+        pub(crate) const SHDR_SIZE: usize = core::mem::size_of::<elf::section::Elf32_Shdr>();
     }
 }
 
@@ -127,11 +151,34 @@ impl ElfSymbol {
         self.sym.st_other
     }
 
+    /// STV_* define constants for the ELF Symbol's visibility (encoded in the st_other field).
+    #[inline]
+    pub fn st_visibility(&self) -> u8 {
+        self.sym.st_other & 0x3
+    }
+
     #[inline]
     pub fn is_undef(&self) -> bool {
         self.st_shndx() == SHN_UNDEF as usize
     }
 
+    /// Whether the value of the symbol is absolute and is not affected by the base address.
+    #[inline]
+    pub fn is_abs(&self) -> bool {
+        self.st_shndx() == SHN_ABS as usize
+    }
+
+    /// Whether the symbol can not be referenced by other elf objects(`STV_HIDDEN` or `STV_INTERNAL`).
+    #[inline]
+    pub fn is_hidden(&self) -> bool {
+        matches!(self.st_visibility(), STV_HIDDEN | STV_INTERNAL)
+    }
+
+    #[inline]
+    pub fn is_protected(&self) -> bool {
+        self.st_visibility() == STV_PROTECTED
+    }
+
     #[inline]
     pub fn is_ok_bind(&self) -> bool {
         (1 << self.st_bind()) & OK_BINDS != 0
@@ -151,6 +198,16 @@ impl ElfSymbol {
     pub fn is_weak(&self) -> bool {
         self.st_bind() == STB_WEAK
     }
+
+    #[inline]
+    pub fn is_global(&self) -> bool {
+        self.st_bind() == STB_GLOBAL
+    }
+
+    #[inline]
+    pub fn is_unique(&self) -> bool {
+        self.st_bind() == STB_GNU_UNIQUE
+    }
 }
 
 #[derive(Debug)]
@@ -1,4 +1,8 @@
-use core::arch::global_asm;
+use super::ObjSlot;
+use core::{
+    arch::{asm, global_asm},
+    ops::Range,
+};
 use elf::abi::*;
 
 pub const EM_ARCH: u16 = EM_RISCV;
@@ -7,7 +11,7 @@ TLS block.  */
 pub const TLS_DTV_OFFSET: usize = 0x800;
 
 pub const REL_RELATIVE: u32 = R_RISCV_RELATIVE;
-// RISCV does not have this
+// RISCV does not have this, u32::MAX is never treated as a valid type
 pub const REL_GOT: u32 = u32::MAX;
 pub const REL_DTPMOD: u32 = R_RISCV_TLS_DTPMOD64;
 pub const REL_SYMBOLIC: u32 = R_RISCV_64;
@@ -15,7 +19,9 @@ pub const REL_JUMP_SLOT: u32 = R_RISCV_JUMP_SLOT;
 pub const REL_DTPOFF: u32 = R_RISCV_TLS_DTPREL64;
 pub const REL_IRELATIVE: u32 = R_RISCV_IRELATIVE;
 pub const REL_COPY: u32 = R_RISCV_COPY;
-pub const REL_TPOFF:u32 = R_RISCV_TLS_TPREL64;
+pub const REL_TPOFF: u32 = R_RISCV_TLS_TPREL64;
+// tls descriptor is not supported yet
+pub const REL_TLSDESC: u32 = u32::MAX;
 
 global_asm!(
     "
@@ -58,6 +64,98 @@ dl_runtime_resolve:
 "
 );
 
+// 切换到新的栈并跳转到程序入口,同时清空用于传递atexit函数的寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_trampoline
+	.type dl_trampoline, @function
+	.align 16
+dl_trampoline:
+    mv sp,a1
+    mv t0,a0
+    li a0,0
+    jr t0
+"
+);
+
+// 保存callee-saved寄存器后调用函数,dl_context_leave会恢复这些寄存器并从dl_context_enter返回
+global_asm!(
+    "
+    .text
+    .globl dl_context_enter
+	.type dl_context_enter, @function
+	.align 16
+dl_context_enter:
+    sd ra,0(a0)
+    sd sp,8(a0)
+    sd s0,16(a0)
+    sd s1,24(a0)
+    sd s2,32(a0)
+    sd s3,40(a0)
+    sd s4,48(a0)
+    sd s5,56(a0)
+    sd s6,64(a0)
+    sd s7,72(a0)
+    sd s8,80(a0)
+    sd s9,88(a0)
+    sd s10,96(a0)
+    sd s11,104(a0)
+    fsd fs0,112(a0)
+    fsd fs1,120(a0)
+    fsd fs2,128(a0)
+    fsd fs3,136(a0)
+    fsd fs4,144(a0)
+    fsd fs5,152(a0)
+    fsd fs6,160(a0)
+    fsd fs7,168(a0)
+    fsd fs8,176(a0)
+    fsd fs9,184(a0)
+    fsd fs10,192(a0)
+    fsd fs11,200(a0)
+    addi sp,sp,-16
+    sd ra,8(sp)
+    mv a0,a2
+    jalr a1
+    ld ra,8(sp)
+    addi sp,sp,16
+    ret
+
+    .globl dl_context_leave
+	.type dl_context_leave, @function
+	.align 16
+dl_context_leave:
+    ld ra,0(a0)
+    ld sp,8(a0)
+    ld s0,16(a0)
+    ld s1,24(a0)
+    ld s2,32(a0)
+    ld s3,40(a0)
+    ld s4,48(a0)
+    ld s5,56(a0)
+    ld s6,64(a0)
+    ld s7,72(a0)
+    ld s8,80(a0)
+    ld s9,88(a0)
+    ld s10,96(a0)
+    ld s11,104(a0)
+    fld fs0,112(a0)
+    fld fs1,120(a0)
+    fld fs2,128(a0)
+    fld fs3,136(a0)
+    fld fs4,144(a0)
+    fld fs5,152(a0)
+    fld fs6,160(a0)
+    fld fs7,168(a0)
+    fld fs8,176(a0)
+    fld fs9,184(a0)
+    fld fs10,192(a0)
+    fld fs11,200(a0)
+    sext.w a0,a1
+    ret
+"
+);
+
 pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
     unsafe extern "C" {
         fn dl_runtime_resolve();
@@ -68,3 +166,38 @@ pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
         got.add(1).write(dylib);
     }
 }
+
+/// Makes the instructions written to `range` visible to instruction fetches of the current hart.
+/// Other harts which may run the code must execute `fence.i` as well.
+#[inline]
+pub fn flush_icache(range: Range<usize>) {
+    if !range.is_empty() {
+        // fence.i, 直接编码以免依赖汇编器对Zifencei扩展的支持
+        unsafe { asm!(".word 0x0000100f", options(nostack, preserves_flags)) };
+    }
+}
+
+pub(crate) const OBJ_STUB_SIZE: usize = 16;
+
+// 暂不支持可重定位目标文件的重定位
+#[inline]
+pub(crate) fn obj_slot(_r_type: u32) -> ObjSlot {
+    ObjSlot::None
+}
+
+#[inline]
+pub(crate) fn obj_width(_r_type: u32) -> usize {
+    0
+}
+
+pub(crate) unsafe fn obj_write_stub(_stub: *mut u8, _target: usize) {}
+
+pub(crate) unsafe fn obj_relocate(
+    _r_type: u32,
+    _place: usize,
+    _sym: usize,
+    _addend: isize,
+    _slot: usize,
+) -> Result<(), &'static str> {
+    Err("relocatable objects are not supported on this architecture")
+}
@@ -1,4 +1,12 @@
-use core::arch::global_asm;
+use super::ObjSlot;
+use core::{
+    arch::{
+        global_asm,
+        x86_64::{__cpuid, __cpuid_count},
+    },
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use elf::abi::*;
 
 pub const EM_ARCH: u16 = EM_X86_64;
@@ -12,8 +20,8 @@ pub const REL_JUMP_SLOT: u32 = R_X86_64_JUMP_SLOT;
 pub const REL_DTPOFF: u32 = R_X86_64_DTPOFF64;
 pub const REL_IRELATIVE: u32 = R_X86_64_IRELATIVE;
 pub const REL_COPY: u32 = R_X86_64_COPY;
-pub const REL_TPOFF:u32 = R_X86_64_TPOFF64;
-
+pub const REL_TPOFF: u32 = R_X86_64_TPOFF64;
+pub const REL_TLSDESC: u32 = R_X86_64_TLSDESC;
 
 global_asm!(
     "
@@ -49,6 +57,178 @@ dl_runtime_resolve:
 "
 );
 
+// tls descriptor的解析函数: rax中是描述符的地址,返回值是变量相对于线程指针的偏移,除rax外不能破坏任何寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_tlsdesc_return
+	.type dl_tlsdesc_return, @function
+	.align 16
+dl_tlsdesc_return:
+// 静态tls中的变量,描述符的第二项就是偏移
+    mov rax,[rax+8]
+    ret
+
+    .globl dl_tlsdesc_undefweak
+	.type dl_tlsdesc_undefweak, @function
+	.align 16
+dl_tlsdesc_undefweak:
+// 未定义的弱符号的地址为0,因此返回 addend - tp
+    mov rax,[rax+8]
+    sub rax,fs:[0]
+    ret
+
+    .globl dl_tlsdesc_dynamic
+	.type dl_tlsdesc_dynamic, @function
+	.align 16
+dl_tlsdesc_dynamic:
+    push rbp
+    mov rbp,rsp
+// 保存调用者保存的寄存器
+    push rbx
+    push rdi
+    push rsi
+    push rdx
+    push rcx
+    push r8
+    push r9
+    push r10
+    push r11
+    mov r11,rax
+// 保存浮点寄存器,支持xsave时还需保存AVX和AVX-512的状态,保存区的大小为0时使用fxsave
+    mov rbx,[rip+{xsave_size}]
+    test rbx,rbx
+    jz .Ltlsdesc_fxsave
+    sub rsp,rbx
+    and rsp,-64
+// xsave不会写入头部的其余字节,而xrstor要求它们为0
+    xor eax,eax
+    mov [rsp+512],rax
+    mov [rsp+520],rax
+    mov [rsp+528],rax
+    mov [rsp+536],rax
+    mov [rsp+544],rax
+    mov [rsp+552],rax
+    mov [rsp+560],rax
+    mov [rsp+568],rax
+    mov eax,{xsave_mask}
+    xor edx,edx
+    xsave [rsp]
+    jmp .Ltlsdesc_call
+.Ltlsdesc_fxsave:
+    sub rsp,512
+    and rsp,-16
+    fxsave64 [rsp]
+.Ltlsdesc_call:
+// 描述符的第二项是TlsDescDynamic
+    mov rax,[r11+8]
+    lea rdi,[rax+8]
+    call [rax]
+    sub rax,fs:[0]
+// 恢复寄存器
+    test rbx,rbx
+    jz .Ltlsdesc_fxrstor
+    mov r11,rax
+    mov eax,{xsave_mask}
+    xor edx,edx
+    xrstor [rsp]
+    mov rax,r11
+    jmp .Ltlsdesc_restored
+.Ltlsdesc_fxrstor:
+    fxrstor64 [rsp]
+.Ltlsdesc_restored:
+    lea rsp,[rbp-72]
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rcx
+    pop rdx
+    pop rsi
+    pop rdi
+    pop rbx
+    pop rbp
+    ret
+",
+    xsave_size = sym XSAVE_SIZE,
+    xsave_mask = const XSAVE_MASK,
+);
+
+// dl_tlsdesc_dynamic使用的xsave保存区的大小,为0时表示不支持xsave
+static XSAVE_SIZE: AtomicUsize = AtomicUsize::new(0);
+// 与glibc的STATE_SAVE_MASK相同: SSE, AVX, MPX和AVX-512的状态。x87的寄存器由调用者保存,控制字由被调用者保存
+const XSAVE_MASK: u32 = (1 << 1) | (1 << 2) | (1 << 3) | (1 << 5) | (1 << 6) | (1 << 7);
+
+/// Prepares the state `dl_tlsdesc_dynamic` needs before it is written into a tls descriptor.
+#[allow(unused_unsafe)]
+pub(crate) fn prepare_tlsdesc_dynamic() {
+    if XSAVE_SIZE.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    // CPUID.1:ECX.OSXSAVE表示支持xsave并且操作系统已经启用了它
+    if unsafe { __cpuid(1) }.ecx & (1 << 27) == 0 {
+        return;
+    }
+    // CPUID.(EAX=0DH,ECX=0):EBX是XCR0中启用的所有状态所需的保存区大小
+    let size = unsafe { __cpuid_count(0xd, 0) }.ebx as usize;
+    XSAVE_SIZE.store(size, Ordering::Relaxed);
+}
+
+// 切换到新的栈并跳转到程序入口,同时清空用于传递atexit函数的寄存器
+global_asm!(
+    "
+    .text
+    .globl dl_trampoline
+	.type dl_trampoline, @function
+	.align 16
+dl_trampoline:
+    xor rdx,rdx
+    mov rsp,rsi
+    jmp rdi
+"
+);
+
+// 保存callee-saved寄存器、MXCSR和x87控制字后调用函数,dl_context_leave会恢复它们并从dl_context_enter返回
+global_asm!(
+    "
+    .text
+    .globl dl_context_enter
+	.type dl_context_enter, @function
+	.align 16
+dl_context_enter:
+    mov [rdi],rbx
+    mov [rdi+8],rbp
+    mov [rdi+16],r12
+    mov [rdi+24],r13
+    mov [rdi+32],r14
+    mov [rdi+40],r15
+    mov [rdi+48],rsp
+    stmxcsr [rdi+56]
+    fnstcw [rdi+60]
+    sub rsp,8
+    mov rdi,rdx
+    call rsi
+    add rsp,8
+    ret
+
+    .globl dl_context_leave
+	.type dl_context_leave, @function
+	.align 16
+dl_context_leave:
+    mov rbx,[rdi]
+    mov rbp,[rdi+8]
+    mov r12,[rdi+16]
+    mov r13,[rdi+24]
+    mov r14,[rdi+32]
+    mov r15,[rdi+40]
+    mov rsp,[rdi+48]
+    ldmxcsr [rdi+56]
+    fldcw [rdi+60]
+    mov eax,esi
+    ret
+"
+);
+
 #[inline]
 pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
     unsafe extern "C" {
@@ -60,3 +240,94 @@ pub(crate) fn prepare_lazy_bind(got: *mut usize, dylib: usize) {
         got.add(2).write(dl_runtime_resolve as usize);
     }
 }
+
+/// Makes the instructions written to `range` visible to instruction fetches, which is a no-op
+/// because the instruction cache of x86_64 is coherent with the data cache.
+#[inline]
+pub fn flush_icache(_range: Range<usize>) {}
+
+/// The size of a stub used by a call to a function out of the range of `R_X86_64_PLT32`.
+pub(crate) const OBJ_STUB_SIZE: usize = 16;
+
+// 可重定位目标文件中需要桩或者got项的重定位类型
+#[inline]
+pub(crate) fn obj_slot(r_type: u32) -> ObjSlot {
+    match r_type {
+        R_X86_64_PLT32 => ObjSlot::Stub,
+        R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => ObjSlot::Got,
+        _ => ObjSlot::None,
+    }
+}
+
+// 重定位写入的字节数
+#[inline]
+pub(crate) fn obj_width(r_type: u32) -> usize {
+    match r_type {
+        R_X86_64_NONE => 0,
+        R_X86_64_64 | R_X86_64_PC64 => 8,
+        _ => 4,
+    }
+}
+
+// jmp [rip+0]; .quad target
+pub(crate) unsafe fn obj_write_stub(stub: *mut u8, target: usize) {
+    unsafe {
+        stub.cast::<[u8; 6]>()
+            .write([0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        stub.add(6).cast::<usize>().write_unaligned(target);
+    }
+}
+
+#[inline]
+fn pc32(place: usize, target: usize) -> Option<i32> {
+    i32::try_from(target.wrapping_sub(place) as isize).ok()
+}
+
+/// Applies a relocation of a relocatable object to `place`. `slot` is the stub or the got entry
+/// of the symbol if the relocation type needs one.
+pub(crate) unsafe fn obj_relocate(
+    r_type: u32,
+    place: usize,
+    sym: usize,
+    addend: isize,
+    slot: usize,
+) -> Result<(), &'static str> {
+    const OVERFLOW: &str = "the relocation value is out of range";
+    let val = sym.wrapping_add_signed(addend);
+    let place_ptr = place as *mut u8;
+    unsafe {
+        match r_type {
+            R_X86_64_NONE => {}
+            R_X86_64_64 => place_ptr.cast::<u64>().write_unaligned(val as u64),
+            R_X86_64_PC64 => place_ptr
+                .cast::<u64>()
+                .write_unaligned(val.wrapping_sub(place) as u64),
+            R_X86_64_32 => {
+                let val = u32::try_from(val).map_err(|_| OVERFLOW)?;
+                place_ptr.cast::<u32>().write_unaligned(val);
+            }
+            R_X86_64_32S => {
+                let val = i32::try_from(val as isize).map_err(|_| OVERFLOW)?;
+                place_ptr.cast::<i32>().write_unaligned(val);
+            }
+            R_X86_64_PC32 => {
+                let val = pc32(place, val).ok_or(OVERFLOW)?;
+                place_ptr.cast::<i32>().write_unaligned(val);
+            }
+            R_X86_64_PLT32 => {
+                // 函数超出±2GB时通过桩跳转
+                let stub = slot.wrapping_add_signed(addend);
+                let val = pc32(place, val)
+                    .or_else(|| pc32(place, stub).filter(|_| slot != 0))
+                    .ok_or(OVERFLOW)?;
+                place_ptr.cast::<i32>().write_unaligned(val);
+            }
+            R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                let val = pc32(place, slot.wrapping_add_signed(addend)).ok_or(OVERFLOW)?;
+                place_ptr.cast::<i32>().write_unaligned(val);
+            }
+            _ => return Err("unsupported relocation type"),
+        }
+    }
+    Ok(())
+}
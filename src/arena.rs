@@ -0,0 +1,247 @@
+//! Allocators for the transient data of the loader
+//!
+//! The buffer used to read program headers and the bitmaps tracking the progress of chunked and
+//! partial relocations only live while an elf object is loaded and relocated. In environments with
+//! a tiny heap, such as early boot, they can be allocated from an [`Arena`] given to
+//! `Loader::set_arena` instead of the global allocator. When the arena is exhausted, the global
+//! allocator is used as a fallback. The read buffer is freed at the end of each load and the
+//! bitmaps are freed by `finish()`, so nothing is left in the arena once the relocation finishes.
+//!
+//! The metadata kept by the loaded elf objects, such as the names of the needed libraries, the user
+//! data and the boxed closures, lives as long as the elf objects and is always allocated from the
+//! global allocator.
+//!
+//! # Examples
+//! ```
+//! use elf_loader::{Loader, arena::BumpArena, mmap::MmapImpl};
+//!
+//! let buf = Box::leak(vec![0u8; 16 * 1024].into_boxed_slice());
+//! let arena: &'static BumpArena = Box::leak(Box::new(BumpArena::new(buf)));
+//! let mut loader = Loader::<MmapImpl>::builder().arena(arena).build();
+//! // load and relocate elf objects
+//! assert_eq!(arena.used(), 0);
+//! ```
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    fmt::Debug,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// An allocator for the transient data of the loader
+///
+/// # Safety
+/// A block returned by `alloc` must be valid for `layout` until it is passed to `dealloc`.
+pub unsafe trait Arena: Send + Sync {
+    /// Allocates a block of memory, or returns `None` if the arena is exhausted.
+    fn alloc(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Frees a block of memory returned by `alloc` with the same layout.
+    ///
+    /// # Safety
+    /// `ptr` must be allocated by this arena with `layout` and must not be used anymore.
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// A bump allocator over a fixed buffer
+///
+/// Only the most recent block can be reused after it is freed, and the whole buffer is reused
+/// once all blocks are freed, which fits the data freed after each load.
+pub struct BumpArena {
+    start: usize,
+    end: usize,
+    lock: AtomicBool,
+    // (下一个可分配的地址, 未释放的块数)
+    state: UnsafeCell<(usize, usize)>,
+}
+
+unsafe impl Send for BumpArena {}
+unsafe impl Sync for BumpArena {}
+
+impl BumpArena {
+    /// Creates an arena allocating from `buf`.
+    pub fn new(buf: &'static mut [u8]) -> Self {
+        let start = buf.as_mut_ptr() as usize;
+        Self {
+            start,
+            end: start + buf.len(),
+            lock: AtomicBool::new(false),
+            state: UnsafeCell::new((start, 0)),
+        }
+    }
+
+    /// Gets the size of the buffer.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Gets the number of bytes between the start of the buffer and the end of the last block.
+    pub fn used(&self) -> usize {
+        self.with_state(|(next, _)| *next - self.start)
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut (usize, usize)) -> R) -> R {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let res = f(unsafe { &mut *self.state.get() });
+        self.lock.store(false, Ordering::Release);
+        res
+    }
+}
+
+impl Debug for BumpArena {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BumpArena")
+            .field("capacity", &self.capacity())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+unsafe impl Arena for BumpArena {
+    fn alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.with_state(|(next, live)| {
+            let addr = next.checked_next_multiple_of(layout.align())?;
+            let end = addr.checked_add(layout.size())?;
+            if end > self.end {
+                return None;
+            }
+            *next = end;
+            *live += 1;
+            NonNull::new(addr as *mut u8)
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.with_state(|(next, live)| {
+            *live -= 1;
+            let addr = ptr.as_ptr() as usize;
+            if *live == 0 {
+                *next = self.start;
+            } else if addr + layout.size() == *next {
+                // 最后分配的块可以直接回收
+                *next = addr;
+            }
+        });
+    }
+}
+
+/// A growable array of `Copy` items allocated from an arena, or from the global allocator if
+/// there is no arena or the arena is exhausted.
+pub(crate) struct ArenaVec<T: Copy> {
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    arena: Option<&'static dyn Arena>,
+    // 当前的内存是否来自arena
+    in_arena: bool,
+}
+
+unsafe impl<T: Copy + Send> Send for ArenaVec<T> {}
+unsafe impl<T: Copy + Sync> Sync for ArenaVec<T> {}
+
+impl<T: Copy> ArenaVec<T> {
+    pub(crate) const fn new(arena: Option<&'static dyn Arena>) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            cap: 0,
+            arena,
+            in_arena: false,
+        }
+    }
+
+    pub(crate) fn from_elem(elem: T, len: usize, arena: Option<&'static dyn Arena>) -> Self {
+        let mut vec = Self::new(arena);
+        vec.resize(len, elem);
+        vec
+    }
+
+    pub(crate) fn resize(&mut self, len: usize, elem: T) {
+        if len > self.cap {
+            self.grow(len);
+        }
+        for idx in self.len..len {
+            unsafe { self.ptr.add(idx).write(elem) };
+        }
+        self.len = len;
+    }
+
+    // 分配恰好能容纳cap个元素的内存,并复制原有的元素
+    fn grow(&mut self, cap: usize) {
+        let layout = Layout::array::<T>(cap).unwrap();
+        let (ptr, in_arena) = match self.arena.and_then(|arena| arena.alloc(layout)) {
+            Some(ptr) => (ptr.cast(), true),
+            None => {
+                let ptr = unsafe { alloc::alloc::alloc(layout) };
+                let ptr = NonNull::new(ptr.cast()).unwrap_or_else(|| {
+                    alloc::alloc::handle_alloc_error(layout);
+                });
+                (ptr, false)
+            }
+        };
+        unsafe { ptr.copy_from_nonoverlapping(self.ptr, self.len) };
+        self.free();
+        self.ptr = ptr;
+        self.cap = cap;
+        self.in_arena = in_arena;
+    }
+
+    fn free(&mut self) {
+        if self.cap == 0 {
+            return;
+        }
+        let layout = Layout::array::<T>(self.cap).unwrap();
+        let ptr = self.ptr.cast();
+        match self.arena {
+            Some(arena) if self.in_arena => unsafe { arena.dealloc(ptr, layout) },
+            _ => unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) },
+        }
+    }
+}
+
+impl<T: Copy> Drop for ArenaVec<T> {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
+
+impl<T: Copy> Deref for ArenaVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for ArenaVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> Clone for ArenaVec<T> {
+    fn clone(&self) -> Self {
+        let mut vec = Self::new(self.arena);
+        if self.len != 0 {
+            vec.grow(self.len);
+            unsafe { vec.ptr.copy_from_nonoverlapping(self.ptr, self.len) };
+            vec.len = self.len;
+        }
+        vec
+    }
+}
+
+impl<T: Copy + Debug> Debug for ArenaVec<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,224 @@
+//! Helpers for writing program loaders
+//!
+//! A program loader (such as `mini-loader` or an operating system kernel) receives the initial
+//! process stack, loads the program and its interpreter, patches the auxiliary vector so that it
+//! describes the loaded program, and finally jumps to the entry point.
+//!
+//! The initial process stack has the following layout:
+//! ```text
+//! auxv <---sp + argc + 2 + env_count + 2
+//! 0    <---sp + argc + 2 + env_count + 1
+//! env  <---sp + argc + 2
+//! 0    <---sp + argc + 1
+//! argv <---sp + 1
+//! argc <---sp
+//! ```
+use crate::{Elf, arch::ElfPhdr};
+use core::ffi::{CStr, c_char};
+
+/// End of vector
+pub const AT_NULL: usize = 0;
+/// Program headers for program
+pub const AT_PHDR: usize = 3;
+/// Size of program header entry
+pub const AT_PHENT: usize = 4;
+/// Number of program headers
+pub const AT_PHNUM: usize = 5;
+/// System page size
+pub const AT_PAGESZ: usize = 6;
+/// Base address of interpreter
+pub const AT_BASE: usize = 7;
+/// Entry point of program
+pub const AT_ENTRY: usize = 9;
+/// Address of random bytes
+pub const AT_RANDOM: usize = 25;
+/// Filename of program
+pub const AT_EXECFN: usize = 31;
+
+/// An entry of the auxiliary vector
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AuxEntry {
+    pub tag: usize,
+    pub val: usize,
+}
+
+/// The auxiliary vector on the initial process stack
+pub struct AuxVec<'stack> {
+    entries: &'stack mut [AuxEntry],
+}
+
+impl<'stack> AuxVec<'stack> {
+    /// Creates an auxiliary vector from a pointer to its first entry.
+    ///
+    /// # Safety
+    /// `ptr` must point to an auxiliary vector terminated by `AT_NULL`.
+    pub unsafe fn from_ptr(ptr: *mut AuxEntry) -> Self {
+        let mut len = 0;
+        while unsafe { (*ptr.add(len)).tag } != AT_NULL {
+            len += 1;
+        }
+        AuxVec {
+            entries: unsafe { core::slice::from_raw_parts_mut(ptr, len) },
+        }
+    }
+
+    /// Gets the value of the entry with the given tag.
+    #[inline]
+    pub fn get(&self, tag: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map(|entry| entry.val)
+    }
+
+    /// Sets the value of the entry with the given tag. Entries can not be added because the
+    /// auxiliary vector lives on the stack, so this function returns false if there is no such entry.
+    #[inline]
+    pub fn set(&mut self, tag: usize, val: usize) -> bool {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.tag == tag) {
+            entry.val = val;
+            return true;
+        }
+        false
+    }
+
+    /// Gets all entries except the terminating `AT_NULL`.
+    #[inline]
+    pub fn entries(&self) -> &[AuxEntry] {
+        self.entries
+    }
+
+    /// Makes the auxiliary vector describe the loaded program `elf`. If the program has an
+    /// interpreter, `interp` should be the loaded interpreter.
+    pub fn patch(&mut self, elf: &Elf, interp: Option<&Elf>) {
+        let phdrs = elf.phdrs();
+        self.set(AT_PHDR, phdrs.as_ptr() as usize);
+        self.set(AT_PHNUM, phdrs.len());
+        self.set(AT_PHENT, size_of::<ElfPhdr>());
+        self.set(AT_ENTRY, elf.entry());
+        self.set(AT_BASE, interp.map(|interp| interp.base()).unwrap_or(0));
+    }
+}
+
+/// The initial process stack
+pub struct InitialStack {
+    sp: *mut usize,
+}
+
+impl InitialStack {
+    /// Creates the initial process stack from the stack pointer passed to the program loader.
+    ///
+    /// # Safety
+    /// `sp` must point to a initial process stack.
+    pub unsafe fn from_sp(sp: *mut usize) -> Self {
+        InitialStack { sp }
+    }
+
+    /// Gets the stack pointer.
+    #[inline]
+    pub fn sp(&self) -> *mut usize {
+        self.sp
+    }
+
+    /// Gets the number of arguments.
+    #[inline]
+    pub fn argc(&self) -> usize {
+        unsafe { self.sp.read() }
+    }
+
+    /// Gets the arguments.
+    #[inline]
+    pub fn argv(&self) -> &[*const c_char] {
+        unsafe { core::slice::from_raw_parts(self.sp.add(1).cast(), self.argc()) }
+    }
+
+    /// Gets the argument at `idx`.
+    #[inline]
+    pub fn arg(&self, idx: usize) -> Option<&CStr> {
+        self.argv()
+            .get(idx)
+            .map(|arg| unsafe { CStr::from_ptr(*arg) })
+    }
+
+    /// Gets the environment variables.
+    #[inline]
+    pub fn envp(&self) -> &[*const c_char] {
+        let envp = unsafe { self.sp.add(self.argc() + 2) };
+        let mut len = 0;
+        while unsafe { envp.add(len).read() } != 0 {
+            len += 1;
+        }
+        unsafe { core::slice::from_raw_parts(envp.cast(), len) }
+    }
+
+    #[inline]
+    fn auxv_ptr(&self) -> *mut AuxEntry {
+        unsafe { self.sp.add(self.argc() + 2 + self.envp().len() + 1).cast() }
+    }
+
+    /// Gets the auxiliary vector.
+    #[inline]
+    pub fn auxv(&mut self) -> AuxVec<'_> {
+        unsafe { AuxVec::from_ptr(self.auxv_ptr()) }
+    }
+
+    /// Removes the first `n` arguments, which is usually the program loader itself.
+    /// The stack pointer does not change, so the stack is still 16 bytes aligned.
+    pub fn shift_args(&mut self, n: usize) {
+        let argc = self.argc();
+        assert!(n <= argc);
+        if n == 0 {
+            return;
+        }
+        let aux_len = self.auxv().entries().len() + 1;
+        let end = unsafe { self.auxv_ptr().add(aux_len) } as usize;
+        let start = unsafe { self.sp.add(1 + n) };
+        // 将argv之后的内容整体向低地址移动,sp保持不变
+        unsafe {
+            core::ptr::copy(
+                start,
+                self.sp.add(1),
+                (end - start as usize) / size_of::<usize>(),
+            );
+            self.sp.write(argc - n);
+        }
+    }
+}
+
+/// Finds the program interpreter (`PT_INTERP`) of the elf object.
+#[inline]
+pub fn find_interp(elf: &Elf) -> Option<&str> {
+    elf.interp()
+}
+
+/// Removes the first `skip_args` arguments from the initial process stack and makes the
+/// auxiliary vector describe the loaded program. Returns the entry point that should be
+/// jumped to, which is the entry of the interpreter if there is one.
+pub fn prepare_stack(
+    stack: &mut InitialStack,
+    elf: &Elf,
+    interp: Option<&Elf>,
+    skip_args: usize,
+) -> usize {
+    stack.shift_args(skip_args);
+    let execfn = stack.argv().first().map(|arg| *arg as usize);
+    let mut auxv = stack.auxv();
+    auxv.patch(elf, interp);
+    if let Some(execfn) = execfn {
+        auxv.set(AT_EXECFN, execfn);
+    }
+    interp.unwrap_or(elf).entry()
+}
+
+/// Switches to the stack `sp` and jumps to `entry`. The register that holds the `atexit`
+/// function of the dynamic linker is cleared as required by the ABI.
+///
+/// # Safety
+/// `sp` must point to a initial process stack which is prepared for the program at `entry`.
+pub unsafe fn jump_to_entry(entry: usize, sp: *const usize) -> ! {
+    unsafe extern "C" {
+        fn dl_trampoline(entry: usize, sp: *const usize) -> !;
+    }
+    unsafe { dl_trampoline(entry, sp) }
+}
@@ -0,0 +1,391 @@
+//! C-compatible `dlopen`/`dlsym`/`dlerror`/`dlclose`
+//!
+//! The functions of this module have the signatures of their POSIX counterparts, so they can be
+//! handed to C code loaded by this crate. They are not exported under these names, which would
+//! replace the functions of the system dynamic linker in the whole process. Instead [`symbol`]
+//! returns them by name and can be used as `pre_find`, so the elf objects loaded by the crate
+//! call them in place of the functions of libc.
+//!
+//! The libraries are loaded into a process wide [`Namespace`], and the handles are indices into a
+//! table of the opened libraries rather than pointers, so using a closed handle fails with an
+//! error instead of reading freed memory. Opening a library again returns the same handle, and the
+//! library is removed from the namespace when every `dlopen` has been matched by a `dlclose` and no
+//! other opened library depends on it. Error messages are kept per thread and returned by `dlerror`.
+//!
+//! Opening and closing libraries are serialized until the init or fini functions return, so other
+//! threads never see a library which is not initialized yet. The init and fini functions may call
+//! these functions themselves.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{cabi, load_dylib};
+//!
+//! // the plugin calls dlopen/dlsym to load other libraries at run time
+//! let plugin = load_dylib!("target/libplugin.so").unwrap();
+//! let plugin = plugin.easy_relocate([].iter(), &cabi::symbol).unwrap();
+//! // the same functions can be used from rust
+//! let handle = unsafe { cabi::dlopen(c"target/liba.so".as_ptr(), cabi::RTLD_NOW) };
+//! assert!(!handle.is_null());
+//! let a = unsafe { cabi::dlsym(handle, c"a".as_ptr()) };
+//! assert!(!a.is_null());
+//! assert_eq!(unsafe { cabi::dlclose(handle) }, 0);
+//! ```
+use crate::{
+    Loader, RelocatedDylib, Result, close_all, init_all, io_error,
+    mmap::MmapImpl,
+    namespace::{Namespace, check_noopen},
+    object::ElfFile,
+    scope::{Scope, Visibility},
+    search::{FsResolver, LibraryResolver, NeededBy, SearchConfig},
+};
+use alloc::{collections::BTreeMap, ffi::CString, format, string::ToString, vec, vec::Vec};
+use core::{
+    cell::RefCell,
+    ffi::{CStr, c_char, c_int, c_void},
+    fmt::Display,
+    ptr::null_mut,
+};
+use std::{
+    sync::{Condvar, Mutex},
+    thread::ThreadId,
+};
+
+/// Resolve the undefined symbols when they are first used. The libraries are relocated according
+/// to the laziness set on the loader of the namespace instead.
+pub const RTLD_LAZY: c_int = 0x1;
+/// Resolve all undefined symbols before `dlopen` returns.
+pub const RTLD_NOW: c_int = 0x2;
+/// Add the library and its dependencies to the global scope of the namespace.
+pub const RTLD_GLOBAL: c_int = 0x100;
+/// Only make the symbols of the library visible through its handle.
+pub const RTLD_LOCAL: c_int = 0;
+
+// dlopen(NULL)返回的句柄,用于在全局作用域中查找符号
+const GLOBAL_HANDLE: usize = 1;
+
+struct State {
+    namespace: Namespace<MmapImpl>,
+    resolver: FsResolver,
+    // 句柄 -> (库, dlopen的次数)
+    handles: BTreeMap<usize, (RelocatedDylib<'static>, usize)>,
+    next_handle: usize,
+}
+
+impl State {
+    // 从roots开始通过DT_NEEDED可以到达的库
+    fn reachable(&self, roots: impl Iterator<Item = usize>) -> Vec<bool> {
+        let libs = self.namespace.libs();
+        let mut reached = vec![false; libs.len()];
+        let mut stack: Vec<usize> = roots
+            .filter_map(|generation| libs.iter().position(|lib| lib.generation() == generation))
+            .collect();
+        while let Some(idx) = stack.pop() {
+            if core::mem::replace(&mut reached[idx], true) {
+                continue;
+            }
+            stack.extend(
+                libs[idx]
+                    .needed_libs()
+                    .iter()
+                    .filter_map(|needed| libs.iter().position(|dep| dep.provides(needed))),
+            );
+        }
+        reached
+    }
+
+    fn new(mut namespace: Namespace<MmapImpl>, config: SearchConfig) -> Self {
+        // 初始化函数可能会调用dlopen,因此在释放锁后再调用它们
+        namespace.loader().set_deferred_init(true);
+        Self {
+            namespace,
+            resolver: FsResolver::new(config),
+            handles: BTreeMap::new(),
+            next_handle: GLOBAL_HANDLE + 1,
+        }
+    }
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+// 打开和关闭库时持有的可重入锁,在初始化和fini函数返回之前不会释放.
+// STATE的锁在调用这些函数前已经释放,因此它们可以再次调用dlopen和dlclose
+struct InitLock {
+    // 持有锁的线程和重入的次数
+    owner: Mutex<(Option<ThreadId>, usize)>,
+    released: Condvar,
+}
+
+static INIT_LOCK: InitLock = InitLock {
+    owner: Mutex::new((None, 0)),
+    released: Condvar::new(),
+};
+
+struct InitGuard;
+
+fn lock_init() -> InitGuard {
+    let id = std::thread::current().id();
+    let mut owner = INIT_LOCK
+        .owner
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    loop {
+        match owner.0 {
+            None => *owner = (Some(id), 1),
+            Some(thread) if thread == id => owner.1 += 1,
+            Some(_) => {
+                owner = INIT_LOCK
+                    .released
+                    .wait(owner)
+                    .unwrap_or_else(|err| err.into_inner());
+                continue;
+            }
+        }
+        return InitGuard;
+    }
+}
+
+impl Drop for InitGuard {
+    fn drop(&mut self) {
+        let mut owner = INIT_LOCK
+            .owner
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        owner.1 -= 1;
+        if owner.1 == 0 {
+            owner.0 = None;
+            INIT_LOCK.released.notify_one();
+        }
+    }
+}
+
+std::thread_local! {
+    // 最近一次失败的错误信息,以及上一次dlerror返回的字符串
+    static ERROR: RefCell<(Option<CString>, Option<CString>)> = const { RefCell::new((None, None)) };
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> Result<R>) -> Result<R> {
+    let mut state = STATE.lock().unwrap_or_else(|err| err.into_inner());
+    let state = state.get_or_insert_with(|| {
+        let mut namespace = Namespace::new(Loader::new());
+        namespace.set_pre_find(symbol);
+        State::new(namespace, SearchConfig::new())
+    });
+    f(state)
+}
+
+#[cold]
+#[inline(never)]
+fn set_error(msg: impl Display) {
+    let msg = CString::new(msg.to_string()).unwrap_or_default();
+    ERROR.with(|error| error.borrow_mut().0 = Some(msg));
+}
+
+/// Sets the namespace the libraries are opened in and the config used to search them when
+/// `dlopen` is given a file name without a slash. The init functions of the libraries loaded by
+/// the namespace are deferred, so that they can call `dlopen` themselves.
+///
+/// By default the namespace uses a `Loader::new()` and resolves symbols with [`symbol`] before
+/// its scopes. The handles returned before are no longer valid.
+pub fn init(namespace: Namespace<MmapImpl>, config: SearchConfig) {
+    let state = State::new(namespace, config);
+    // 旧的库在锁外释放
+    let _old = STATE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .replace(state);
+}
+
+/// Gets the function of this module named `name`, which can be used as `pre_find`.
+pub fn symbol(name: &str) -> Option<*const ()> {
+    match name {
+        "dlopen" => Some(dlopen as *const ()),
+        "dlsym" => Some(dlsym as *const ()),
+        "dlerror" => Some(dlerror as *const ()),
+        "dlclose" => Some(dlclose as *const ()),
+        _ => None,
+    }
+}
+
+fn open(name: &str, visibility: Visibility) -> Result<(usize, Vec<RelocatedDylib<'static>>)> {
+    with_state(|state| {
+        let object = if name.contains('/') {
+            Some(ElfFile::from_path(name)?)
+        } else {
+            let needed_by = NeededBy {
+                name: "",
+                rpath: None,
+                runpath: None,
+            };
+            state.resolver.resolve(name, &needed_by)
+        };
+        let lib = match object {
+            Some(object) => state
+                .namespace
+                .open(object, visibility, &mut state.resolver)?,
+            None => {
+                let lib = state
+                    .namespace
+                    .get(name)
+                    .ok_or_else(|| io_error(format!("{name}: cannot open shared object file")))?;
+                check_noopen(lib)?;
+                lib.clone()
+            }
+        };
+        let opened = state
+            .handles
+            .iter_mut()
+            .find(|(_, (other, _))| other.generation() == lib.generation());
+        let handle = match opened {
+            Some((&handle, (_, count))) => {
+                *count += 1;
+                handle
+            }
+            None => {
+                let handle = state.next_handle;
+                state.next_handle += 1;
+                state.handles.insert(handle, (lib, 1));
+                handle
+            }
+        };
+        Ok((handle, state.namespace.libs().to_vec()))
+    })
+}
+
+/// Opens the library `filename`, which is searched with the config of the module unless it
+/// contains a slash. A null `filename` returns a handle to the global scope of the namespace.
+/// Returns null on failure, or if the library has `DF_1_NOOPEN`.
+///
+/// # Safety
+/// `filename` must be null or a valid C string. The init functions of the library are called.
+pub unsafe extern "C" fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void {
+    if filename.is_null() {
+        return GLOBAL_HANDLE as *mut c_void;
+    }
+    let Ok(name) = unsafe { CStr::from_ptr(filename) }.to_str() else {
+        set_error("the file name is not valid utf-8");
+        return null_mut();
+    };
+    let visibility = if flags & RTLD_GLOBAL != 0 {
+        Visibility::Global
+    } else {
+        Visibility::Local
+    };
+    let _guard = lock_init();
+    match open(name, visibility) {
+        Ok((handle, libs)) => {
+            init_all(None, &libs);
+            handle as *mut c_void
+        }
+        Err(err) => {
+            set_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Finds `symbol` in the library of `handle` and its dependencies, or in the global scope of the
+/// namespace if `handle` is null or was returned by `dlopen(NULL)`. Returns null on failure.
+///
+/// # Safety
+/// `symbol` must be a valid C string.
+pub unsafe extern "C" fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void {
+    let Ok(name) = unsafe { CStr::from_ptr(symbol) }.to_str() else {
+        set_error("the symbol name is not valid utf-8");
+        return null_mut();
+    };
+    let found = with_state(|state| {
+        let handle = handle as usize;
+        if handle == 0 || handle == GLOBAL_HANDLE {
+            return Ok(state.namespace.find(name));
+        }
+        let (lib, _) = state
+            .handles
+            .get(&handle)
+            .ok_or_else(|| invalid_handle(handle))?;
+        Ok(unsafe { lib.get::<()>(name) }
+            .map(|sym| sym.into_raw())
+            .or_else(|| Scope::local(lib, state.namespace.libs().iter()).find(name)))
+    });
+    match found {
+        Ok(Some(ptr)) => ptr as *mut c_void,
+        Ok(None) => {
+            set_error(format!("undefined symbol: {name}"));
+            null_mut()
+        }
+        Err(err) => {
+            set_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Gets the message of the last failure of the functions of this module in the current thread,
+/// or null if nothing failed since the last call. The message stays valid until the next call.
+pub extern "C" fn dlerror() -> *mut c_char {
+    ERROR.with(|error| {
+        let mut error = error.borrow_mut();
+        error.1 = error.0.take();
+        error
+            .1
+            .as_ref()
+            .map_or(null_mut(), |msg| msg.as_ptr().cast_mut())
+    })
+}
+
+/// Closes a handle returned by `dlopen`. The library is removed from the namespace when it has been
+/// closed as many times as it was opened and no other opened library depends on it, and unloaded
+/// once nothing else references it. Its dependencies which are no longer needed are removed as
+/// well. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+/// The symbols of the library must not be used after it is unloaded.
+pub unsafe extern "C" fn dlclose(handle: *mut c_void) -> c_int {
+    let _guard = lock_init();
+    let closed = with_state(|state| {
+        let handle = handle as usize;
+        if handle == GLOBAL_HANDLE {
+            return Ok(Vec::new());
+        }
+        let (_, count) = state
+            .handles
+            .get_mut(&handle)
+            .ok_or_else(|| invalid_handle(handle))?;
+        *count -= 1;
+        if *count != 0 {
+            return Ok(Vec::new());
+        }
+        let (lib, _) = state.handles.remove(&handle).unwrap();
+        // 只移除不再被其他句柄直接或间接依赖的库
+        let closing = state.reachable([lib.generation()].into_iter());
+        let used = state.reachable(state.handles.values().map(|(lib, _)| lib.generation()));
+        let libs: Vec<_> = state
+            .namespace
+            .libs()
+            .iter()
+            .zip(closing.into_iter().zip(used))
+            .filter(|(_, (closing, used))| *closing && !*used)
+            .map(|(lib, _)| lib.clone())
+            .collect();
+        libs.iter().for_each(|lib| {
+            state.namespace.close(lib);
+        });
+        Ok(libs)
+    });
+    match closed {
+        // 在锁外释放库,使其fini函数可以调用dlclose
+        Ok(libs) => {
+            close_all(libs);
+            0
+        }
+        Err(err) => {
+            set_error(err);
+            -1
+        }
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn invalid_handle(handle: usize) -> crate::Error {
+    io_error(format!("invalid handle {handle:#x}"))
+}
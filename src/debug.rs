@@ -0,0 +1,180 @@
+//! Debugger support through the `r_debug` protocol
+//!
+//! Debuggers such as gdb and lldb track shared objects by reading the `r_debug` structure whose
+//! address is stored in the `DT_DEBUG` entry of the executable, and by setting a breakpoint at
+//! `r_brk`, which is called whenever the list of objects changes. Elf objects loaded by this crate
+//! are added to the `r_debug` structure of this crate when they are relocated and removed when
+//! they are dropped.
+//!
+//! When the executable itself is loaded by `elf_loader` (as in `mini-loader`), its `DT_DEBUG` entry
+//! points to this structure, so the debugger finds it automatically. Otherwise the `DT_DEBUG`
+//! entry belongs to the system dynamic linker, and the structure has to be read from the symbol
+//! `elf_loader_r_debug` or through [`r_debug`]. Its `r_brk` is the function
+//! `elf_loader_debug_state`. Both symbols are named uniquely so that they do not clash with the
+//! ones of the system dynamic linker.
+use crate::{arch::Dyn, format::CoreComponent};
+use alloc::boxed::Box;
+use core::{
+    cell::UnsafeCell,
+    ffi::{c_char, c_int},
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use elf::abi::{DT_DEBUG, DT_NULL};
+
+/// An entry of the object list, the same as the public part of `struct link_map` in glibc
+#[repr(C)]
+pub struct LinkMap {
+    /// The difference between the address in the elf file and the address in memory.
+    pub l_addr: usize,
+    /// The absolute file name of the object.
+    pub l_name: *const c_char,
+    /// The dynamic section of the object.
+    pub l_ld: *mut Dyn,
+    /// The next object.
+    pub l_next: *mut LinkMap,
+    /// The previous object.
+    pub l_prev: *mut LinkMap,
+}
+
+/// The state of the object list
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RState {
+    /// The list is consistent.
+    Consistent = 0,
+    /// An object is being added.
+    Add = 1,
+    /// An object is being removed.
+    Delete = 2,
+}
+
+/// The rendezvous structure read by debuggers, the same as `struct r_debug` in glibc
+#[repr(C)]
+pub struct RDebug {
+    /// The version of the protocol.
+    pub r_version: c_int,
+    /// The head of the object list.
+    pub r_map: *mut LinkMap,
+    /// The address of the function called when the list changes.
+    pub r_brk: extern "C" fn(),
+    /// The state of the list.
+    pub r_state: RState,
+    /// The base address of the dynamic linker.
+    pub r_ldbase: usize,
+}
+
+struct DebugCell(UnsafeCell<RDebug>);
+
+unsafe impl Sync for DebugCell {}
+
+// 使用不会与ld.so冲突的名字导出,调试器可以通过符号找到它
+#[unsafe(export_name = "elf_loader_r_debug")]
+static R_DEBUG: DebugCell = DebugCell(UnsafeCell::new(RDebug {
+    r_version: 1,
+    r_map: null_mut(),
+    r_brk: debug_state,
+    r_state: RState::Consistent,
+    r_ldbase: 0,
+}));
+
+// 保护R_DEBUG中的链表
+static LOCK: AtomicBool = AtomicBool::new(false);
+
+// 调试器在这个函数上设置断点,以得知链表的变化
+#[unsafe(export_name = "elf_loader_debug_state")]
+#[inline(never)]
+extern "C" fn debug_state() {
+    // 防止函数被优化掉或与其他函数合并
+    unsafe { core::arch::asm!("", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Gets the address of the `r_debug` structure maintained by this crate.
+///
+/// It must only be modified by this crate, and read while no objects are being loaded or dropped.
+#[inline]
+pub fn r_debug() -> *mut RDebug {
+    R_DEBUG.0.get()
+}
+
+// 修改链表前后都需要通知调试器
+fn update(r_debug: &mut RDebug, state: RState, f: impl FnOnce(&mut RDebug)) {
+    r_debug.r_state = state;
+    debug_state();
+    f(r_debug);
+    r_debug.r_state = RState::Consistent;
+    debug_state();
+}
+
+fn lock<R>(f: impl FnOnce(&mut RDebug) -> R) -> R {
+    while LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    let res = f(unsafe { &mut *r_debug() });
+    LOCK.store(false, Ordering::Release);
+    res
+}
+
+pub(crate) fn add(core: &CoreComponent) {
+    let Some(dynamic) = core.dynamic() else {
+        return;
+    };
+    // 与ld.so相同,将r_debug的地址写入DT_DEBUG中
+    let mut cur = dynamic.as_ptr();
+    unsafe {
+        while (*cur).d_tag != DT_NULL {
+            if (*cur).d_tag == DT_DEBUG {
+                (*cur).d_un = r_debug() as _;
+            }
+            cur = cur.add(1);
+        }
+    }
+    let map = Box::into_raw(Box::new(LinkMap {
+        l_addr: core.base(),
+        l_name: core.cname().as_ptr(),
+        l_ld: dynamic.as_ptr(),
+        l_next: null_mut(),
+        l_prev: null_mut(),
+    }));
+    lock(|r_debug| {
+        update(r_debug, RState::Add, |r_debug| unsafe {
+            if r_debug.r_map.is_null() {
+                r_debug.r_map = map;
+                return;
+            }
+            let mut tail = r_debug.r_map;
+            while !(*tail).l_next.is_null() {
+                tail = (*tail).l_next;
+            }
+            (*tail).l_next = map;
+            (*map).l_prev = tail;
+        })
+    });
+}
+
+pub(crate) fn remove(dynamic: *mut Dyn) {
+    lock(|r_debug| {
+        let mut cur = r_debug.r_map;
+        while !cur.is_null() && unsafe { (*cur).l_ld } != dynamic {
+            cur = unsafe { (*cur).l_next };
+        }
+        // 没有被重定位的对象不在链表中
+        if cur.is_null() {
+            return;
+        }
+        update(r_debug, RState::Delete, |r_debug| unsafe {
+            let map = Box::from_raw(cur);
+            if map.l_prev.is_null() {
+                r_debug.r_map = map.l_next;
+            } else {
+                (*map.l_prev).l_next = map.l_next;
+            }
+            if !map.l_next.is_null() {
+                (*map.l_next).l_prev = map.l_prev;
+            }
+        });
+    });
+}
@@ -29,6 +29,8 @@ impl ElfDynamic {
         let mut fini_off = None;
         let mut init_array_off = None;
         let mut init_array_size = None;
+        let mut preinit_array_off = None;
+        let mut preinit_array_size = None;
         let mut fini_array_off = None;
         let mut fini_array_size = None;
         let mut version_ids_off = None;
@@ -39,6 +41,8 @@ impl ElfDynamic {
         let mut rpath_off = None;
         let mut runpath_off = None;
         let mut flags = 0;
+        let mut flags_1 = 0;
+        let mut textrel = false;
         let mut needed_libs = Vec::new();
 
         let mut cur_dyn_ptr = dynamic_ptr;
@@ -49,6 +53,8 @@ impl ElfDynamic {
             loop {
                 match dynamic.d_tag {
                     DT_FLAGS => flags = dynamic.d_un as usize,
+                    DT_FLAGS_1 => flags_1 = dynamic.d_un as usize,
+                    DT_TEXTREL => textrel = true,
                     DT_PLTGOT => got_off = Some(NonZeroUsize::new_unchecked(dynamic.d_un as usize)),
                     DT_NEEDED => {
                         needed_libs.push(NonZeroUsize::new_unchecked(dynamic.d_un as usize))
@@ -77,6 +83,13 @@ impl ElfDynamic {
                     DT_INIT_ARRAYSZ => {
                         init_array_size = Some(NonZeroUsize::new_unchecked(dynamic.d_un as usize))
                     }
+                    DT_PREINIT_ARRAY => {
+                        preinit_array_off = Some(NonZeroUsize::new_unchecked(dynamic.d_un as usize))
+                    }
+                    DT_PREINIT_ARRAYSZ => {
+                        preinit_array_size =
+                            Some(NonZeroUsize::new_unchecked(dynamic.d_un as usize))
+                    }
                     DT_FINI_ARRAY => {
                         fini_array_off = Some(NonZeroUsize::new_unchecked(dynamic.d_un as usize))
                     }
@@ -123,6 +136,9 @@ impl ElfDynamic {
         let init_array_fn = init_array_off.map(|init_array_off| {
             segments.get_slice(init_array_off.get(), init_array_size.unwrap().get())
         });
+        let preinit_array_fn = preinit_array_off.map(|preinit_array_off| {
+            segments.get_slice(preinit_array_off.get(), preinit_array_size.unwrap().get())
+        });
         let fini_fn = fini_off.map(|fini_off| unsafe {
             core::mem::transmute(segments.get_ptr::<fn()>(fini_off.get()))
         });
@@ -143,12 +159,15 @@ impl ElfDynamic {
         });
         let version_idx = version_ids_off
             .map(|off| unsafe { off.checked_add(segments.base()).unwrap_unchecked() });
+        let flags = DynamicFlags { flags, flags_1 };
         Ok(ElfDynamic {
             dyn_ptr: dynamic_ptr,
             hashtab: hash_off + base,
             symtab: symtab_off + base,
             strtab: strtab_off + base,
-            bind_now: flags & DF_BIND_NOW as usize != 0,
+            bind_now: flags.bind_now(),
+            textrel: textrel || flags.textrel(),
+            flags,
             got: NonNull::new(
                 got_off
                     .map(|off| (base + off.get()) as *mut usize)
@@ -159,6 +178,7 @@ impl ElfDynamic {
             dynrel,
             init_fn,
             init_array_fn,
+            preinit_array_fn,
             fini_fn,
             fini_array_fn,
             rela_count,
@@ -180,14 +200,20 @@ pub struct ElfDynamic {
     pub symtab: usize,
     /// DT_STRTAB
     pub strtab: usize,
-    /// DT_FLAGS
+    /// DF_BIND_NOW or DF_1_NOW
     pub bind_now: bool,
+    /// DT_TEXTREL or DF_TEXTREL
+    pub textrel: bool,
+    /// DT_FLAGS and DT_FLAGS_1
+    pub flags: DynamicFlags,
     /// DT_PLTGOT
     pub got: Option<NonNull<usize>>,
     /// DT_INIT
     pub init_fn: Option<extern "C" fn()>,
     /// DT_INIT_ARRAY
     pub init_array_fn: Option<&'static [extern "C" fn()]>,
+    /// DT_PREINIT_ARRAY
+    pub preinit_array_fn: Option<&'static [extern "C" fn()]>,
     /// DT_FINI
     pub fini_fn: Option<extern "C" fn()>,
     /// /// DT_FINI_ARRAY
@@ -211,3 +237,104 @@ pub struct ElfDynamic {
     /// DT_RUNPATH
     pub runpath_off: Option<NonZeroUsize>,
 }
+
+/// The flags in `DT_FLAGS` and `DT_FLAGS_1`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DynamicFlags {
+    /// DT_FLAGS
+    pub flags: usize,
+    /// DT_FLAGS_1
+    pub flags_1: usize,
+}
+
+impl DynamicFlags {
+    /// Whether all relocations must be processed before the elf object is used(`DF_BIND_NOW` or `DF_1_NOW`).
+    #[inline]
+    pub fn bind_now(&self) -> bool {
+        self.flags & DF_BIND_NOW as usize != 0 || self.flags_1 & DF_1_NOW as usize != 0
+    }
+
+    /// Whether the elf object has relocations against read-only segments(`DF_TEXTREL`).
+    #[inline]
+    pub fn textrel(&self) -> bool {
+        self.flags & DF_TEXTREL as usize != 0
+    }
+
+    /// Whether the elf object must not be unloaded(`DF_1_NODELETE`).
+    #[inline]
+    pub fn nodelete(&self) -> bool {
+        self.flags_1 & DF_1_NODELETE as usize != 0
+    }
+
+    /// Whether the elf object must not be opened by dlopen(`DF_1_NOOPEN`). `Namespace::open` only
+    /// loads such elf objects as dependencies.
+    #[inline]
+    pub fn noopen(&self) -> bool {
+        self.flags_1 & DF_1_NOOPEN as usize != 0
+    }
+
+    /// Whether the elf object uses `$ORIGIN`(`DF_ORIGIN` or `DF_1_ORIGIN`).
+    #[inline]
+    pub fn origin(&self) -> bool {
+        self.flags & DF_ORIGIN as usize != 0 || self.flags_1 & DF_1_ORIGIN as usize != 0
+    }
+}
+
+/// The raw entries of a dynamic section, including the tags not parsed by the loader
+///
+/// The values are the raw `d_val` or `d_ptr` fields. Addresses such as `DT_STRTAB` are relative
+/// to the base of the elf object, and string values such as `DT_SONAME` are offsets into the
+/// string table.
+#[derive(Clone, Copy)]
+pub struct DynamicTable<'a> {
+    entries: &'a [Dyn],
+}
+
+impl<'a> DynamicTable<'a> {
+    /// Creates a table from the entries before `DT_NULL`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a dynamic section terminated by `DT_NULL`, which lives for `'a`.
+    pub unsafe fn from_ptr(ptr: *const Dyn) -> Self {
+        let mut len = 0;
+        while unsafe { (*ptr.add(len)).d_tag } != DT_NULL {
+            len += 1;
+        }
+        Self {
+            entries: unsafe { core::slice::from_raw_parts(ptr, len) },
+        }
+    }
+
+    /// Gets the value of the first entry with the tag `tag`.
+    #[inline]
+    pub fn get(&self, tag: i64) -> Option<usize> {
+        self.get_all(tag).next()
+    }
+
+    /// Gets the values of all entries with the tag `tag`, such as the `DT_NEEDED` entries.
+    pub fn get_all(&self, tag: i64) -> impl Iterator<Item = usize> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.d_tag == tag)
+            .map(|entry| entry.d_un as usize)
+    }
+
+    /// Whether there is an entry with the tag `tag`, for flags such as `DT_TEXTREL`.
+    #[inline]
+    pub fn contains(&self, tag: i64) -> bool {
+        self.entries.iter().any(|entry| entry.d_tag == tag)
+    }
+
+    /// Iterates over the tags and values of all entries in order.
+    pub fn iter(&self) -> impl Iterator<Item = (i64, usize)> + 'a {
+        self.entries
+            .iter()
+            .map(|entry| (entry.d_tag, entry.d_un as usize))
+    }
+
+    /// Gets the entries, without the terminating `DT_NULL`.
+    #[inline]
+    pub fn entries(&self) -> &'a [Dyn] {
+        self.entries
+    }
+}
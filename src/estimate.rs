@@ -0,0 +1,250 @@
+//! Estimating the address space needed by elf objects
+//!
+//! Everything in this module only parses the elf header, the program headers and the dynamic
+//! section of the elf object, nothing is mapped into memory. This is useful when elf objects
+//! have to fit into a constrained virtual address window.
+use crate::{
+    ElfObject, Loader, Result,
+    arch::{Dyn, ElfPhdr},
+    loader::{ElfHeader, create_segments},
+    mmap::Mmap,
+    parse_dynamic_error,
+    search::{FnResolver, LibraryResolver, NeededBy},
+    tls::ThreadLocal,
+};
+use alloc::{
+    collections::{BTreeSet, VecDeque},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::ffi::CStr;
+use elf::abi::{
+    DT_NEEDED, DT_NULL, DT_RPATH, DT_RUNPATH, DT_STRSZ, DT_STRTAB, PT_DYNAMIC, PT_LOAD,
+};
+
+/// The dry-run result of mapping an elf object
+#[derive(Clone, Copy, Debug)]
+pub struct MappingPlan {
+    /// The page aligned minimum virtual address of the `PT_LOAD` segments.
+    pub min_vaddr: usize,
+    /// The length of the memory reserved for the elf object.
+    pub len: usize,
+    /// Whether the elf object must be mapped at `min_vaddr`(`ET_EXEC`).
+    pub fixed: bool,
+}
+
+/// The estimated address space consumption of an elf object and its dependencies
+#[derive(Debug, Default)]
+pub struct ClosureEstimate {
+    /// The name and mapping plan of each elf object in the closure, in breadth-first order.
+    pub objects: Vec<(String, MappingPlan)>,
+    /// The dependencies which can not be found by the resolver.
+    pub missing: Vec<String>,
+}
+
+impl ClosureEstimate {
+    /// Gets the sum of the reserved lengths of all elf objects in the closure.
+    #[inline]
+    pub fn total_len(&self) -> usize {
+        self.objects.iter().map(|(_, plan)| plan.len).sum()
+    }
+}
+
+impl MappingPlan {
+    fn new(ehdr: &ElfHeader, phdrs: &[ElfPhdr]) -> Self {
+        let (param, min_vaddr) = create_segments(phdrs, ehdr.is_dylib());
+        MappingPlan {
+            min_vaddr,
+            len: param.len,
+            fixed: !ehdr.is_dylib(),
+        }
+    }
+}
+
+struct Scanned {
+    plan: MappingPlan,
+    needed: Vec<String>,
+    rpath: Option<String>,
+    runpath: Option<String>,
+}
+
+/// The strings referenced by the dynamic section
+#[derive(Default)]
+struct DynamicStrs {
+    needed: Vec<String>,
+    rpath: Option<String>,
+    runpath: Option<String>,
+}
+
+/// Converts a virtual address to the offset in the elf object
+fn vaddr_to_offset(phdrs: &[ElfPhdr], vaddr: usize) -> Option<usize> {
+    phdrs
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .find(|phdr| {
+            let start = phdr.p_vaddr as usize;
+            start <= vaddr && vaddr < start + phdr.p_filesz as usize
+        })
+        .map(|phdr| vaddr - phdr.p_vaddr as usize + phdr.p_offset as usize)
+}
+
+fn read_dynamic_strs(object: &mut impl ElfObject, phdrs: &[ElfPhdr]) -> Result<DynamicStrs> {
+    let Some(dynamic) = phdrs.iter().find(|phdr| phdr.p_type == PT_DYNAMIC) else {
+        return Ok(DynamicStrs::default());
+    };
+    let count = dynamic.p_filesz as usize / size_of::<Dyn>();
+    // 直接读入Dyn数组,保证其对齐
+    let mut dyns: Vec<Dyn> = (0..count).map(|_| Dyn { d_tag: 0, d_un: 0 }).collect();
+    let dynamic_buf = unsafe {
+        core::slice::from_raw_parts_mut(dyns.as_mut_ptr().cast::<u8>(), count * size_of::<Dyn>())
+    };
+    object.read(dynamic_buf, dynamic.p_offset as usize)?;
+    let mut strtab = None;
+    let mut strsz = 0;
+    let mut needed = Vec::new();
+    let mut rpath = None;
+    let mut runpath = None;
+    for dynamic in &dyns {
+        match dynamic.d_tag as _ {
+            DT_NULL => break,
+            DT_NEEDED => needed.push(dynamic.d_un as usize),
+            DT_RPATH => rpath = Some(dynamic.d_un as usize),
+            DT_RUNPATH => runpath = Some(dynamic.d_un as usize),
+            DT_STRTAB => strtab = Some(dynamic.d_un as usize),
+            DT_STRSZ => strsz = dynamic.d_un as usize,
+            _ => {}
+        }
+    }
+    if needed.is_empty() && rpath.is_none() && runpath.is_none() {
+        return Ok(DynamicStrs::default());
+    }
+    // 动态段中记录的是字符串表的虚拟地址，需要转换为文件中的偏移
+    let strtab = strtab
+        .and_then(|vaddr| vaddr_to_offset(phdrs, vaddr))
+        .ok_or(parse_dynamic_error(
+            "dynamic section does not have DT_STRTAB",
+        ))?;
+    let mut strtab_buf = vec![0u8; strsz];
+    object.read(&mut strtab_buf, strtab)?;
+    let get_str = |off: usize, msg| {
+        strtab_buf
+            .get(off..)
+            .and_then(|bytes| CStr::from_bytes_until_nul(bytes).ok())
+            .and_then(|name| name.to_str().ok())
+            .map(|name| name.to_string())
+            .ok_or(parse_dynamic_error(msg))
+    };
+    Ok(DynamicStrs {
+        needed: needed
+            .into_iter()
+            .map(|off| get_str(off, "invalid DT_NEEDED entry"))
+            .collect::<Result<_>>()?,
+        rpath: rpath
+            .map(|off| get_str(off, "invalid DT_RPATH entry"))
+            .transpose()?,
+        runpath: runpath
+            .map(|off| get_str(off, "invalid DT_RUNPATH entry"))
+            .transpose()?,
+    })
+}
+
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
+    fn scan(&mut self, object: &mut impl ElfObject) -> Result<Scanned> {
+        let ehdr = self.buf.prepare_ehdr(object)?;
+        let phdrs = self.buf.prepare_phdr(&ehdr, object)?;
+        let plan = MappingPlan::new(&ehdr, phdrs);
+        let strs = read_dynamic_strs(object, phdrs)?;
+        Ok(Scanned {
+            plan,
+            needed: strs.needed,
+            rpath: strs.rpath,
+            runpath: strs.runpath,
+        })
+    }
+
+    fn check_scanned(&self, lib_name: &str, scanned: &Scanned) -> Result<()> {
+        if let Some(policy) = &self.soname_policy {
+            for needed in &scanned.needed {
+                policy.check(lib_name, needed)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes how the elf object would be mapped without mapping it.
+    pub fn plan_mapping(&mut self, object: &mut impl ElfObject) -> Result<MappingPlan> {
+        let ehdr = self.buf.prepare_ehdr(object)?;
+        let phdrs = self.buf.prepare_phdr(&ehdr, object)?;
+        Ok(MappingPlan::new(&ehdr, phdrs))
+    }
+
+    /// Reads the names of the dependencies(`DT_NEEDED`) of the elf object without mapping it.
+    pub fn scan_needed(&mut self, object: &mut impl ElfObject) -> Result<Vec<String>> {
+        self.scan(object).map(|scanned| scanned.needed)
+    }
+
+    /// Estimates the address space consumed by the elf object and all of its dependencies.
+    ///
+    /// `resolver` is used to find the dependency with the given name. Each dependency is only
+    /// counted once, and dependencies that can not be found are recorded in `missing`.
+    /// Dependencies are checked against the soname policy of the loader before they are resolved.
+    pub fn estimate_closure<O, F>(
+        &mut self,
+        object: impl ElfObject,
+        resolver: F,
+    ) -> Result<ClosureEstimate>
+    where
+        O: ElfObject,
+        F: FnMut(&str) -> Option<O>,
+    {
+        self.estimate_closure_with(object, &mut FnResolver(resolver))
+    }
+
+    /// Like `estimate_closure`, but the dependencies are found by a `LibraryResolver`, which
+    /// also gets the `DT_RPATH` and `DT_RUNPATH` of the elf object needing the dependency.
+    pub fn estimate_closure_with(
+        &mut self,
+        mut object: impl ElfObject,
+        resolver: &mut impl LibraryResolver,
+    ) -> Result<ClosureEstimate> {
+        let mut estimate = ClosureEstimate::default();
+        let scanned = self.scan(&mut object)?;
+        self.check_scanned(object.file_name().to_str().unwrap(), &scanned)?;
+        let name = object.file_name().to_string_lossy().into_owned();
+        let mut visited: BTreeSet<String> = BTreeSet::new();
+        // 记录每个依赖是被哪个elf对象需要的,以便使用它的DT_RPATH和DT_RUNPATH
+        let mut needed_by: Vec<(String, Option<String>, Option<String>)> = Vec::new();
+        let mut queue: VecDeque<(String, usize)> = VecDeque::new();
+        visited.insert(name.clone());
+        queue.extend(scanned.needed.into_iter().map(|needed| (needed, 0)));
+        needed_by.push((name.clone(), scanned.rpath, scanned.runpath));
+        estimate.objects.push((name, scanned.plan));
+        while let Some((name, parent)) = queue.pop_front() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let (parent_name, rpath, runpath) = &needed_by[parent];
+            let parent = NeededBy {
+                name: parent_name,
+                rpath: rpath.as_deref(),
+                runpath: runpath.as_deref(),
+            };
+            let Some(mut dep) = resolver.resolve(&name, &parent) else {
+                estimate.missing.push(name);
+                continue;
+            };
+            let scanned = self.scan(&mut dep)?;
+            self.check_scanned(&name, &scanned)?;
+            let idx = needed_by.len();
+            queue.extend(scanned.needed.into_iter().map(|needed| (needed, idx)));
+            needed_by.push((
+                dep.file_name().to_string_lossy().into_owned(),
+                scanned.rpath,
+                scanned.runpath,
+            ));
+            estimate.objects.push((name, scanned.plan));
+        }
+        Ok(estimate)
+    }
+}
@@ -0,0 +1,62 @@
+//! Load and unload events
+//!
+//! Callbacks set by `Loader::on_load` and `Loader::on_unload` are told when an elf object loaded by
+//! the loader becomes usable and when it is about to be unmapped, so that profilers, debuggers and
+//! crash reporters can track the address ranges of the elf objects. They are plain function
+//! pointers and can be used without `std`.
+use crate::{arch::ElfPhdr, segment::SegmentInfo};
+use alloc::borrow::Cow;
+use core::ffi::CStr;
+use elf::abi::PT_LOAD;
+
+/// The function called with a load or unload event.
+pub type EventCallback = fn(&LoadEvent);
+
+/// An elf object which is loaded or unloaded
+pub struct LoadEvent<'a> {
+    pub(crate) name: &'a CStr,
+    pub(crate) base: usize,
+    pub(crate) generation: usize,
+    pub(crate) phdrs: &'a [ElfPhdr],
+}
+
+impl LoadEvent<'_> {
+    /// Gets the name of the elf object. Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    #[inline]
+    pub fn name(&self) -> Cow<'_, str> {
+        self.name.to_string_lossy()
+    }
+
+    /// Gets the C style name of the elf object.
+    #[inline]
+    pub fn cname(&self) -> &CStr {
+        self.name
+    }
+
+    /// Gets the base address of the elf object.
+    #[inline]
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Gets the generation id of the elf object, see `CoreComponent::generation`.
+    #[inline]
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Gets the program headers of the elf object.
+    #[inline]
+    pub fn phdrs(&self) -> &[ElfPhdr] {
+        self.phdrs
+    }
+
+    /// Gets the final layout of the `PT_LOAD` segments of the elf object.
+    pub fn segments(&self) -> impl Iterator<Item = SegmentInfo> + '_ {
+        let base = self.base;
+        self.phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(move |phdr| SegmentInfo::new(phdr, base))
+    }
+}
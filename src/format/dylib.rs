@@ -1,18 +1,23 @@
-use super::{CoreComponentRef, ElfCommonPart, Relocated, create_lazy_scope};
+use super::{CoreComponentRef, ElfCommonPart, Relocated, create_lazy_scope, exec::RelocatedExec};
 use crate::{
     CoreComponent, Loader, Result, UserData,
-    arch::{ElfPhdr, ElfRela},
+    arch::{Dyn, EHDR_SIZE, ElfPhdr, ElfRela},
     dynamic::ElfDynamic,
-    loader::Builder,
-    mmap::Mmap,
+    loader::{Builder, ElfHeader, validate_phdrs},
+    mmap::{Mmap, MmapImpl},
     object::{ElfObject, ElfObjectAsync},
-    parse_ehdr_error,
-    relocation::{LazyScope, RelocateHelper, SymDef, relocate_impl},
-    segment::ElfSegments,
-    symbol::{SymbolInfo, SymbolTable},
+    parse_dynamic_error, parse_ehdr_error,
+    relocation::{
+        ChunkedRelocation, LazyScope, PartialRelocation, Record, RelocateAction, RelocateContext,
+        RelocateHelper, RelocateHook, SymDef, relocate_impl,
+    },
+    segment::{ElfSegments, MASK, PAGE_SIZE},
+    symbol::{SymbolBinding, SymbolInfo, SymbolTable},
+    tls::ThreadLocal,
 };
 use alloc::{boxed::Box, ffi::CString, sync::Arc, vec::Vec};
-use core::{any::Any, fmt::Debug, marker::PhantomData, ops::Deref};
+use core::{any::Any, fmt::Debug, marker::PhantomData, ops::Deref, ptr::NonNull};
+use elf::abi::{PT_DYNAMIC, PT_LOAD};
 
 /// An unrelocated dynamic library
 pub struct ElfDylib {
@@ -45,7 +50,8 @@ impl ElfDylib {
 
     /// Relocate the dynamic library with the given dynamic libraries and function closure.
     /// # Note
-    /// During relocation, the symbol is first searched in the function closure `pre_find`.
+    /// During relocation, the symbol is first searched in the function closure `pre_find`. With
+    /// lazy binding, this also holds for the symbols bound later, even if `scope` is empty.
     pub fn easy_relocate<'iter, 'scope, 'find, 'lib, S, F>(
         self,
         scope: S,
@@ -86,6 +92,83 @@ impl ElfDylib {
         deal_unknown: D,
         local_lazy_scope: Option<LazyScope<'lib>>,
     ) -> Result<RelocatedDylib<'lib>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone,
+        F: Fn(&str) -> Option<*const ()>,
+        D: Fn(&ElfRela, &CoreComponent, S) -> core::result::Result<(), Box<dyn Any>>,
+        'scope: 'iter,
+        'iter: 'lib,
+        'find: 'lib,
+    {
+        self.relocate_inner(scope, pre_find, deal_unknown, None, local_lazy_scope, None)
+    }
+
+    /// Relocate the dynamic library like `relocate`, and `hook` is called for each relocation which
+    /// refers to a symbol by address(such as `R_X86_64_JUMP_SLOT` and `R_X86_64_GLOB_DAT`) after the
+    /// symbol is resolved. The hook can record the relocation or replace the resolved address, which
+    /// is also used when the symbol can not be resolved.
+    ///
+    /// # Note
+    /// PLT relocations bound lazily are not passed to the hook, load the dynamic library with
+    /// `lazy_bind` set to `Some(false)` to intercept them.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use elf_loader::{load_dylib, RelocateAction};
+    /// extern "C" fn my_open() {}
+    /// let lib = load_dylib!("target/liba.so", lazy: false).unwrap();
+    /// let lib = lib
+    ///     .relocate_with_hook(
+    ///         [].iter(),
+    ///         &|_| None,
+    ///         |_, _, _| Err(Box::new(())),
+    ///         |ctx| {
+    ///             if ctx.name == "open" {
+    ///                 RelocateAction::Replace(my_open as *const ())
+    ///             } else {
+    ///                 RelocateAction::Default
+    ///             }
+    ///         },
+    ///         None,
+    ///     )
+    ///     .unwrap();
+    /// ```
+    pub fn relocate_with_hook<'iter, 'scope, 'find, 'lib, S, F, D, H>(
+        self,
+        scope: S,
+        pre_find: &'find F,
+        deal_unknown: D,
+        hook: H,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+    ) -> Result<RelocatedDylib<'lib>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone,
+        F: Fn(&str) -> Option<*const ()>,
+        D: Fn(&ElfRela, &CoreComponent, S) -> core::result::Result<(), Box<dyn Any>>,
+        H: Fn(&RelocateContext) -> RelocateAction,
+        'scope: 'iter,
+        'iter: 'lib,
+        'find: 'lib,
+    {
+        self.relocate_inner(
+            scope,
+            pre_find,
+            deal_unknown,
+            Some(&hook),
+            local_lazy_scope,
+            None,
+        )
+    }
+
+    pub(crate) fn relocate_inner<'iter, 'scope, 'find, 'lib, S, F, D>(
+        self,
+        scope: S,
+        pre_find: &'find F,
+        deal_unknown: D,
+        hook: Option<RelocateHook>,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+        record: Option<Record>,
+    ) -> Result<RelocatedDylib<'lib>>
     where
         S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone,
         F: Fn(&str) -> Option<*const ()>,
@@ -99,6 +182,7 @@ impl ElfDylib {
             .map(|lib| RelocateHelper {
                 base: lib.base(),
                 symtab: lib.symtab(),
+                tls: lib.tls(),
                 #[cfg(feature = "log")]
                 lib_name: lib.name(),
             })
@@ -107,9 +191,118 @@ impl ElfDylib {
         let wrapper =
             |rela: &ElfRela, core: &CoreComponent| deal_unknown(rela, core, scope_clone.clone());
         Ok(RelocatedDylib {
-            core: relocate_impl(self.common, helper, pre_find, &wrapper, local_lazy_scope)?,
+            core: relocate_impl(
+                self.common,
+                helper,
+                pre_find,
+                &wrapper,
+                hook,
+                local_lazy_scope,
+                record,
+            )?,
         })
     }
+
+    /// Prepares a relocation of the dynamic library which is performed in chunks by `ChunkedRelocation::step`.
+    /// The arguments are the same as the ones of `relocate`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use elf_loader::{load_dylib, RelocateStatus};
+    /// let lib = load_dylib!("target/liba.so").unwrap();
+    /// let mut relocation = lib
+    ///     .relocate_chunked([].iter(), &|_| None, |_, _, _| Err(Box::new(())), None)
+    ///     .unwrap();
+    /// // relocate at most 1024 entries at a time
+    /// while relocation.step(1024).unwrap() == RelocateStatus::Pending {
+    ///     // do other work
+    /// }
+    /// let lib = relocation.finish().unwrap();
+    /// ```
+    pub fn relocate_chunked<'iter, 'scope, 'find, 'lib, S, F, D>(
+        self,
+        scope: S,
+        pre_find: &'find F,
+        deal_unknown: D,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+    ) -> Result<ChunkedRelocation<'iter, 'find, 'lib, F>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone + 'lib,
+        F: Fn(&str) -> Option<*const ()>,
+        D: Fn(&ElfRela, &CoreComponent, S) -> core::result::Result<(), Box<dyn Any>> + 'lib,
+        'scope: 'iter,
+        'iter: 'lib,
+        'find: 'lib,
+    {
+        let helper = scope
+            .clone()
+            .map(|lib| RelocateHelper {
+                base: lib.base(),
+                symtab: lib.symtab(),
+                tls: lib.tls(),
+                #[cfg(feature = "log")]
+                lib_name: lib.name(),
+            })
+            .collect();
+        let wrapper =
+            move |rela: &ElfRela, core: &CoreComponent| deal_unknown(rela, core, scope.clone());
+        ChunkedRelocation::new(
+            self.common,
+            helper,
+            pre_find,
+            Box::new(wrapper),
+            local_lazy_scope,
+        )
+    }
+
+    /// Relocates the dynamic library as far as possible with the given dynamic libraries and
+    /// function closure. Instead of failing, the relocation entries whose symbols can not be
+    /// resolved are kept pending, and `PartialRelocation::unresolved` reports which symbols are
+    /// missing. They can then be provided in stages by `PartialRelocation::resume_with`, which only
+    /// revisits the pending entries.
+    ///
+    /// # Note
+    /// PLT relocations bound lazily are resolved through the lazy scope when they are called, so
+    /// they are never pending. Load the dynamic library with `lazy_bind` set to `Some(false)` to
+    /// resolve them in stages as well.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use elf_loader::load_dylib;
+    /// # fn print(s: &str) { println!("{}", s); }
+    /// let lib = load_dylib!("target/libb.so", lazy: false).unwrap();
+    /// let mut relocation = lib.relocate_partial([].iter(), &|_| None, None).unwrap();
+    /// for symbol in relocation.unresolved() {
+    ///     println!("{} is referenced {} times", symbol.name, symbol.count);
+    /// }
+    /// let provider = |name: &str| (name == "print").then_some(print as *const ());
+    /// if relocation.resume_with(&provider).unwrap() == 0 {
+    ///     let lib = relocation.finish().unwrap();
+    /// }
+    /// ```
+    pub fn relocate_partial<'iter, 'scope, 'find, 'lib, S, F>(
+        self,
+        scope: S,
+        pre_find: &'find F,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+    ) -> Result<PartialRelocation<'iter, 'lib>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>>,
+        F: Fn(&str) -> Option<*const ()>,
+        'scope: 'iter,
+        'iter: 'lib,
+    {
+        let helper = scope
+            .map(|lib| RelocateHelper {
+                base: lib.base(),
+                symtab: lib.symtab(),
+                tls: lib.tls(),
+                #[cfg(feature = "log")]
+                lib_name: lib.name(),
+            })
+            .collect();
+        PartialRelocation::new(self.common, helper, pre_find, local_lazy_scope)
+    }
 }
 
 impl Builder {
@@ -119,7 +312,7 @@ impl Builder {
     }
 }
 
-impl<M: Mmap> Loader<M> {
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
     /// Load a dynamic library into memory
     pub fn easy_load_dylib(&mut self, object: impl ElfObject) -> Result<ElfDylib> {
         self.load_dylib(object, None)
@@ -128,40 +321,51 @@ impl<M: Mmap> Loader<M> {
     /// Load a dynamic library into memory
     /// # Note
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
+    /// * Dynamic libraries with `DF_BIND_NOW` or `DF_1_NOW` are never bound lazily.
     pub fn load_dylib(
         &mut self,
-        mut object: impl ElfObject,
+        object: impl ElfObject,
         lazy_bind: Option<bool>,
     ) -> Result<ElfDylib> {
+        let mut object = self.track(object);
         let ehdr = self.buf.prepare_ehdr(&mut object)?;
         if !ehdr.is_dylib() {
             return Err(parse_ehdr_error("file type mismatch"));
         }
-        let (builder, phdrs) = self.load_impl(ehdr, object, lazy_bind)?;
-        builder.create_dylib(phdrs)
+        let res = self
+            .load_impl(ehdr, object, lazy_bind)
+            .and_then(|(builder, phdrs)| builder.create_dylib(phdrs));
+        self.release_buf();
+        res
     }
 
     /// Load a dynamic library into memory
     /// # Note
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
+    /// * Dynamic libraries with `DF_BIND_NOW` or `DF_1_NOW` are never bound lazily.
     pub async fn load_dylib_async(
         &mut self,
-        mut object: impl ElfObjectAsync,
+        object: impl ElfObjectAsync,
         lazy_bind: Option<bool>,
     ) -> Result<ElfDylib> {
-        let ehdr = self.buf.prepare_ehdr(&mut object)?;
+        let mut object = self.track(object);
+        let ehdr = self.buf.prepare_ehdr_async(&mut object).await?;
         if !ehdr.is_dylib() {
             return Err(parse_ehdr_error("file type mismatch"));
         }
-        let (builder, phdrs) = self.load_async_impl(ehdr, object, lazy_bind).await?;
-        builder.create_dylib(phdrs)
+        let res = self
+            .load_async_impl(ehdr, object, lazy_bind)
+            .await
+            .and_then(|(builder, phdrs)| builder.create_dylib(phdrs));
+        self.release_buf();
+        res
     }
 }
 
 /// A dynamic library that has been relocated
 #[derive(Clone)]
 pub struct RelocatedDylib<'scope> {
-    core: Relocated<'scope>,
+    pub(crate) core: Relocated<'scope>,
 }
 
 impl Debug for RelocatedDylib<'_> {
@@ -178,6 +382,172 @@ impl Deref for RelocatedDylib<'_> {
     }
 }
 
+impl<'scope> RelocatedDylib<'scope> {
+    /// Unloads the dynamic library if this is the last strong reference to it. The fini functions are
+    /// called and the memory is unmapped through the `Mmap` implementation used to load it.
+    /// Otherwise the library is returned unchanged. Libraries with `DF_1_NODELETE` are never unloaded.
+    pub fn try_unload(self) -> core::result::Result<(), Self> {
+        if self.strong_count() == 1 {
+            drop(self);
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Releases this reference to the dynamic library and returns whether the library was unloaded.
+    /// The library stays loaded while other references to it exist.
+    pub fn unload(self) -> bool {
+        self.try_unload().is_ok()
+    }
+
+    /// Gets an [`OwnedSymbol`] by symbol name, which keeps the library loaded and therefore can be
+    /// stored without borrowing the library. The symbol may call into the libraries the library
+    /// was relocated against, so it can't outlive them either.
+    ///
+    /// # Safety
+    /// Users of this API must specify the correct type of the function or variable loaded.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use elf_loader::{object::ElfFile, OwnedSymbol, mmap::MmapImpl, Loader};
+    /// # let mut loader = Loader::<MmapImpl>::new();
+    /// let f: OwnedSymbol<fn() -> i32> = {
+    ///     let lib = loader
+    ///         .easy_load_dylib(ElfFile::from_path("target/liba.so").unwrap())
+    ///         .unwrap()
+    ///         .easy_relocate([].iter(), &|_| None)
+    ///         .unwrap();
+    ///     unsafe { lib.get_owned::<fn() -> i32>("a").unwrap() }
+    /// };
+    /// assert_eq!(f(), 1);
+    /// ```
+    /// The symbol of a library can't outlive the libraries it depends on:
+    /// ```compile_fail
+    /// # use elf_loader::{object::ElfFile, OwnedSymbol, mmap::MmapImpl, Loader};
+    /// # let mut loader = Loader::<MmapImpl>::new();
+    /// let b: OwnedSymbol<fn() -> i32> = {
+    ///     let liba = loader
+    ///         .easy_load_dylib(ElfFile::from_path("target/liba.so").unwrap())
+    ///         .unwrap()
+    ///         .easy_relocate([].iter(), &|_| None)
+    ///         .unwrap();
+    ///     let libb = loader
+    ///         .easy_load_dylib(ElfFile::from_path("target/libb.so").unwrap())
+    ///         .unwrap()
+    ///         .easy_relocate([&liba].into_iter(), &|_| None)
+    ///         .unwrap();
+    ///     unsafe { libb.get_owned::<fn() -> i32>("b").unwrap() }
+    /// };
+    /// ```
+    #[inline]
+    pub unsafe fn get_owned<T>(&self, name: &str) -> Option<OwnedSymbol<'scope, T>> {
+        let sym = unsafe { self.get::<T>(name) }?;
+        Some(OwnedSymbol {
+            lib: self.clone(),
+            ptr: sym.ptr,
+            binding: sym.binding,
+            pd: PhantomData,
+        })
+    }
+}
+
+/// Unloads a set of dynamic libraries which may depend on each other.
+///
+/// A library is unloaded only after all libraries in `libs` that depend on it(`DT_NEEDED`), so the fini
+/// functions are called in reverse topological order. Libraries that are still referenced elsewhere can
+/// not be unloaded, and neither can their dependencies. They are returned in the order of `libs`.
+pub fn close_all(libs: Vec<RelocatedDylib<'_>>) -> Vec<RelocatedDylib<'_>> {
+    let len = libs.len();
+    let (deps, order) = dependency_order(&libs);
+    // 卸载时使用依赖在前的拓扑序的逆序
+    let mut slots: Vec<Option<RelocatedDylib>> = libs.into_iter().map(Some).collect();
+    let mut kept = alloc::vec![false; len];
+    for &idx in order.iter().rev() {
+        let needed_by_kept = (0..len).any(|other| kept[other] && deps[other].contains(&idx));
+        let lib = slots[idx].take().unwrap();
+        if needed_by_kept {
+            kept[idx] = true;
+            slots[idx] = Some(lib);
+        } else if let Err(lib) = lib.try_unload() {
+            kept[idx] = true;
+            slots[idx] = Some(lib);
+        }
+    }
+    slots.into_iter().flatten().collect()
+}
+
+/// Calls the init functions of an executable and a set of dynamic libraries which may depend on each
+/// other, when they are loaded with `Loader::set_deferred_init`.
+///
+/// The order follows the ELF specification: the `DT_PREINIT_ARRAY` of the executable first, then the
+/// dynamic libraries after all libraries in `libs` they depend on(`DT_NEEDED`), and the executable
+/// last. The init functions of each elf object are called only once, so objects that have already
+/// been initialized are skipped.
+///
+/// # Examples
+/// ```no_run
+/// use elf_loader::{Loader, init_all, mmap::MmapImpl, object::ElfFile};
+///
+/// let mut loader = Loader::<MmapImpl>::builder().deferred_init(true).build();
+/// let liba = loader
+///     .easy_load_dylib(ElfFile::from_path("target/liba.so").unwrap())
+///     .unwrap()
+///     .easy_relocate([].iter(), &|_| None)
+///     .unwrap();
+/// let libb = loader
+///     .easy_load_dylib(ElfFile::from_path("target/libb.so").unwrap())
+///     .unwrap()
+///     .easy_relocate([&liba].into_iter(), &|_| None)
+///     .unwrap();
+/// // liba is initialized before libb
+/// init_all(None, &[libb.clone(), liba.clone()]);
+/// ```
+pub fn init_all(exec: Option<&RelocatedExec<'_>>, libs: &[RelocatedDylib<'_>]) {
+    if let Some(exec) = exec {
+        exec.call_preinit();
+    }
+    let (_, order) = dependency_order(libs);
+    for idx in order {
+        libs[idx].call_init();
+    }
+    if let Some(exec) = exec {
+        exec.call_init();
+    }
+}
+
+// 返回每个库依赖的库在libs中的下标,以及依赖在前的拓扑序
+fn dependency_order(libs: &[RelocatedDylib<'_>]) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let len = libs.len();
+    // deps[i]记录了libs[i]依赖的库在libs中的下标
+    let deps: Vec<Vec<usize>> = libs
+        .iter()
+        .map(|lib| {
+            lib.needed_libs()
+                .iter()
+                .filter_map(|needed| libs.iter().position(|dep| dep.provides(needed)))
+                .collect()
+        })
+        .collect();
+    // 后序遍历得到依赖在前的拓扑序
+    fn visit(idx: usize, deps: &[Vec<usize>], visited: &mut [bool], order: &mut Vec<usize>) {
+        if visited[idx] {
+            return;
+        }
+        visited[idx] = true;
+        for &dep in &deps[idx] {
+            visit(dep, deps, visited, order);
+        }
+        order.push(idx);
+    }
+    let mut visited = alloc::vec![false; len];
+    let mut order = Vec::with_capacity(len);
+    for idx in 0..len {
+        visit(idx, &deps, &mut visited, &mut order);
+    }
+    (deps, order)
+}
+
 impl RelocatedDylib<'_> {
     /// # Safety
     /// The current elf object has not yet been relocated, so it is dangerous to use this
@@ -218,6 +588,89 @@ impl RelocatedDylib<'_> {
         }
     }
 
+    /// Wraps a dynamic library which is already mapped and relocated, such as the vDSO or an image
+    /// placed in memory by a hypervisor, without performing any new mappings. `base` is the address
+    /// of its elf header. The library is never unmapped and its fini functions are not called.
+    ///
+    /// # Safety
+    /// `base` must point to a mapped elf image whose dynamic section is unrelocated, and the image
+    /// must stay mapped while the returned library is used. Libraries loaded by glibc's dynamic linker
+    /// can't be wrapped, because it rewrites the dynamic section. Use `host::HostDylib` for them.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use elf_loader::RelocatedDylib;
+    ///
+    /// // the address of the vDSO, which is getauxval(AT_SYSINFO_EHDR)
+    /// let vdso_base = 0x7fff_f7fc_1000;
+    /// let vdso = unsafe { RelocatedDylib::from_mapped("linux-vdso.so.1", vdso_base) }.unwrap();
+    /// let clock_gettime = unsafe {
+    ///     vdso.get::<extern "C" fn(i32, *mut [i64; 2]) -> i32>("__vdso_clock_gettime")
+    /// };
+    /// ```
+    pub unsafe fn from_mapped(name: &str, base: usize) -> Result<Self> {
+        let ehdr =
+            ElfHeader::new(unsafe { core::slice::from_raw_parts(base as *const u8, EHDR_SIZE) })?;
+        // 程序头位于已经映射的第一个PT_LOAD中
+        let phdrs: &'static [ElfPhdr] = unsafe {
+            core::slice::from_raw_parts((base + ehdr.e_phoff()) as *const ElfPhdr, ehdr.e_phnum())
+        };
+        validate_phdrs(phdrs)?;
+        // elf头在文件中的偏移是0
+        let first = phdrs.iter().find(|phdr| phdr.p_type == PT_LOAD).unwrap();
+        let bias = base.wrapping_sub((first.p_vaddr - first.p_offset) as usize);
+        let dynamic = phdrs
+            .iter()
+            .find(|phdr| phdr.p_type == PT_DYNAMIC)
+            .ok_or_else(|| parse_dynamic_error("dylib does not have dynamic"))?;
+        unsafe {
+            Self::from_phdrs(
+                name,
+                bias,
+                phdrs,
+                bias.wrapping_add(dynamic.p_vaddr as usize) as *const Dyn,
+            )
+        }
+    }
+
+    /// Wraps a mapped dynamic library whose load bias is `bias`, using the dynamic section at
+    /// `dynamic` instead of the one in its image.
+    pub(crate) unsafe fn from_phdrs(
+        name: &str,
+        bias: usize,
+        phdrs: &'static [ElfPhdr],
+        dynamic: *const Dyn,
+    ) -> Result<Self> {
+        let loads = || phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD);
+        let first = loads().next().unwrap();
+        let min_vaddr = first.p_vaddr as usize & MASK;
+        let max_vaddr = loads()
+            .map(|phdr| (phdr.p_vaddr + phdr.p_memsz) as usize)
+            .max()
+            .unwrap();
+        let len = ((max_vaddr + PAGE_SIZE - 1) & MASK) - min_vaddr;
+        // 内存不属于ElfSegments,因此不会被释放
+        let segments = ElfSegments {
+            memory: unsafe { NonNull::new_unchecked(bias.wrapping_add(min_vaddr) as _) },
+            offset: min_vaddr,
+            len,
+            borrowed: len,
+            guard: 0,
+            munmap: MmapImpl::munmap,
+        };
+        let dynamic = ElfDynamic::new(dynamic, &segments)?;
+        Ok(unsafe {
+            Self::new_uncheck(
+                CString::new(name).unwrap(),
+                bias,
+                dynamic,
+                phdrs,
+                segments,
+                UserData::empty(),
+            )
+        })
+    }
+
     /// Gets the symbol table.
     #[inline]
     pub fn symtab(&self) -> &SymbolTable {
@@ -261,13 +714,17 @@ impl RelocatedDylib<'_> {
     pub unsafe fn get<'lib, T>(&'lib self, name: &str) -> Option<Symbol<'lib, T>> {
         self.symtab()
             .lookup_filter(&SymbolInfo::from_str(name))
-            .map(|sym| Symbol {
-                ptr: SymDef {
-                    sym: Some(sym),
-                    base: self.base(),
-                }
-                .convert() as _,
-                pd: PhantomData,
+            .map(|sym| {
+                Symbol::new(
+                    self,
+                    SymDef {
+                        sym: Some(sym),
+                        base: self.base(),
+                        tls: None,
+                    }
+                    .convert() as _,
+                    SymbolBinding::of(sym),
+                )
             })
     }
 
@@ -291,13 +748,17 @@ impl RelocatedDylib<'_> {
     ) -> Option<Symbol<'lib, T>> {
         self.symtab()
             .lookup_filter(&SymbolInfo::new_with_version(name, version))
-            .map(|sym| Symbol {
-                ptr: SymDef {
-                    sym: Some(sym),
-                    base: self.base(),
-                }
-                .convert() as _,
-                pd: PhantomData,
+            .map(|sym| {
+                Symbol::new(
+                    self,
+                    SymDef {
+                        sym: Some(sym),
+                        base: self.base(),
+                        tls: None,
+                    }
+                    .convert() as _,
+                    SymbolBinding::of(sym),
+                )
             })
     }
 }
@@ -305,7 +766,10 @@ impl RelocatedDylib<'_> {
 /// A symbol from elf object
 #[derive(Debug, Clone)]
 pub struct Symbol<'lib, T: 'lib> {
+    #[cfg(feature = "debug-handle")]
+    lib: &'lib CoreComponent,
     ptr: *mut (),
+    binding: SymbolBinding,
     pd: PhantomData<&'lib T>,
 }
 
@@ -317,7 +781,220 @@ impl<'lib, T> Deref for Symbol<'lib, T> {
 }
 
 impl<'lib, T> Symbol<'lib, T> {
+    #[inline]
+    fn new(_lib: &'lib CoreComponent, ptr: *mut (), binding: SymbolBinding) -> Self {
+        Symbol {
+            #[cfg(feature = "debug-handle")]
+            lib: _lib,
+            ptr,
+            binding,
+            pd: PhantomData,
+        }
+    }
+
+    /// Gets the binding of the symbol.
+    #[inline]
+    pub fn binding(&self) -> SymbolBinding {
+        self.binding
+    }
+
+    /// Gets the address of the symbol. The address is only valid while the library is loaded.
     pub fn into_raw(self) -> *const () {
         self.ptr
     }
+
+    /// Gets the generation id of the library that the symbol comes from.
+    #[cfg(feature = "debug-handle")]
+    #[inline]
+    pub fn generation(&self) -> usize {
+        self.lib.generation()
+    }
+
+    /// Converts the symbol into a [`WeakSymbol`], which does not keep the library loaded but
+    /// panics when it is used after the library has been unloaded.
+    #[cfg(feature = "debug-handle")]
+    pub fn downgrade(self) -> WeakSymbol<T> {
+        WeakSymbol {
+            lib: Arc::downgrade(&self.lib.inner),
+            generation: self.lib.generation(),
+            lib_name: CString::from(self.lib.cname()),
+            ptr: self.ptr,
+            binding: self.binding,
+            pd: PhantomData,
+        }
+    }
+}
+
+/// A symbol that does not keep the library it comes from loaded. Using it after the library has
+/// been unloaded panics with the name and the generation id of the library, instead of jumping
+/// into unmapped memory.
+#[cfg(feature = "debug-handle")]
+pub struct WeakSymbol<T> {
+    lib: alloc::sync::Weak<super::CoreComponentInner>,
+    generation: usize,
+    lib_name: CString,
+    ptr: *mut (),
+    binding: SymbolBinding,
+    pd: PhantomData<T>,
+}
+
+#[cfg(feature = "debug-handle")]
+impl<T> Clone for WeakSymbol<T> {
+    fn clone(&self) -> Self {
+        WeakSymbol {
+            lib: self.lib.clone(),
+            generation: self.generation,
+            lib_name: self.lib_name.clone(),
+            ptr: self.ptr,
+            binding: self.binding,
+            pd: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "debug-handle")]
+impl<T> Debug for WeakSymbol<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WeakSymbol")
+            .field("lib_name", &self.lib_name)
+            .field("generation", &self.generation)
+            .field("ptr", &self.ptr)
+            .finish()
+    }
+}
+
+#[cfg(feature = "debug-handle")]
+impl<T> Deref for WeakSymbol<T> {
+    type Target = T;
+    #[track_caller]
+    fn deref(&self) -> &T {
+        self.check();
+        unsafe { &*(&self.ptr as *const *mut _ as *const T) }
+    }
+}
+
+#[cfg(feature = "debug-handle")]
+impl<T> WeakSymbol<T> {
+    /// Gets the binding of the symbol.
+    #[inline]
+    pub fn binding(&self) -> SymbolBinding {
+        self.binding
+    }
+
+    /// Gets the generation id of the library that the symbol comes from.
+    #[inline]
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Whether the library that the symbol comes from is still loaded.
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        self.lib.strong_count() != 0
+    }
+
+    /// Panics if the library that the symbol comes from has been unloaded.
+    #[track_caller]
+    pub fn check(&self) {
+        if !self.is_alive() {
+            panic!(
+                "use of symbol at {:p} after its library [{}] (generation {}) was unloaded",
+                self.ptr,
+                self.lib_name.to_string_lossy(),
+                self.generation
+            );
+        }
+    }
+
+    /// Gets the address of the symbol, checking that the library is still loaded.
+    #[track_caller]
+    pub fn as_raw(&self) -> *const () {
+        self.check();
+        self.ptr
+    }
+
+    /// Gets an [`OwnedSymbol`] keeping the library loaded, or `None` if it has been unloaded.
+    ///
+    /// # Safety
+    /// The symbol does not know the libraries its library was relocated against, so they must stay
+    /// loaded for `'scope`.
+    pub unsafe fn upgrade<'scope>(&self) -> Option<OwnedSymbol<'scope, T>> {
+        let core = CoreComponent {
+            inner: self.lib.upgrade()?,
+        };
+        Some(OwnedSymbol {
+            lib: unsafe { RelocatedDylib::from_core_component(core) },
+            ptr: self.ptr,
+            binding: self.binding,
+            pd: PhantomData,
+        })
+    }
+}
+
+/// A symbol that holds a strong reference to the library it comes from, so the library stays
+/// mapped as long as the symbol exists. Like the library, it can't outlive the libraries the
+/// library was relocated against.
+pub struct OwnedSymbol<'scope, T> {
+    lib: RelocatedDylib<'scope>,
+    ptr: *mut (),
+    binding: SymbolBinding,
+    pd: PhantomData<T>,
+}
+
+// 持有库的强引用,因此符号在任意线程中都是有效的
+unsafe impl<T: Send> Send for OwnedSymbol<'_, T> {}
+unsafe impl<T: Sync> Sync for OwnedSymbol<'_, T> {}
+
+impl<T> Clone for OwnedSymbol<'_, T> {
+    fn clone(&self) -> Self {
+        OwnedSymbol {
+            lib: self.lib.clone(),
+            ptr: self.ptr,
+            binding: self.binding,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for OwnedSymbol<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OwnedSymbol")
+            .field("lib", &self.lib.name())
+            .field("ptr", &self.ptr)
+            .field("binding", &self.binding)
+            .finish()
+    }
+}
+
+impl<T> Deref for OwnedSymbol<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*(&self.ptr as *const *mut _ as *const T) }
+    }
+}
+
+impl<'scope, T> OwnedSymbol<'scope, T> {
+    /// Gets the binding of the symbol.
+    #[inline]
+    pub fn binding(&self) -> SymbolBinding {
+        self.binding
+    }
+
+    /// Gets the library that the symbol comes from.
+    #[inline]
+    pub fn lib(&self) -> &RelocatedDylib<'scope> {
+        &self.lib
+    }
+
+    /// Gets the address of the symbol without giving up the reference to the library.
+    #[inline]
+    pub fn as_raw(&self) -> *const () {
+        self.ptr
+    }
+
+    /// Gets the address of the symbol together with the library that keeps it valid.
+    #[inline]
+    pub fn into_raw(self) -> (*const (), RelocatedDylib<'scope>) {
+        (self.ptr, self.lib)
+    }
 }
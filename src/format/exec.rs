@@ -7,6 +7,7 @@ use crate::{
     object::{ElfObject, ElfObjectAsync},
     parse_ehdr_error,
     relocation::{LazyScope, RelocateHelper, relocate_impl},
+    tls::ThreadLocal,
 };
 use alloc::{boxed::Box, vec::Vec};
 use core::{any::Any, fmt::Debug, marker::PhantomData, ops::Deref};
@@ -36,7 +37,8 @@ impl Debug for ElfExec {
 impl ElfExec {
     /// Relocate the executable file with the given dynamic libraries and function closure.
     /// # Note
-    /// During relocation, the symbol is first searched in the function closure `pre_find`.
+    /// During relocation, the symbol is first searched in the function closure `pre_find`. With
+    /// lazy binding, this also holds for the symbols bound later, even if `scope` is empty.
     pub fn easy_relocate<'iter, 'scope, 'find, 'lib, S, F>(
         self,
         scope: S,
@@ -99,6 +101,7 @@ impl ElfExec {
                 core::mem::transmute(RelocateHelper {
                     base: self.base(),
                     symtab,
+                    tls: self.tls(),
                     #[cfg(feature = "log")]
                     lib_name: self.name(),
                 })
@@ -108,6 +111,7 @@ impl ElfExec {
             helper.push(RelocateHelper {
                 base: lib.base(),
                 symtab: lib.symtab(),
+                tls: lib.tls(),
                 #[cfg(feature = "log")]
                 lib_name: lib.name(),
             })
@@ -117,19 +121,27 @@ impl ElfExec {
             |rela: &ElfRela, core: &CoreComponent| deal_unknown(rela, core, scope_clone.clone());
         Ok(RelocatedExec {
             entry: self.entry,
-            core: relocate_impl(self.common, helper, pre_find, &wrapper, local_lazy_scope)?,
+            core: relocate_impl(
+                self.common,
+                helper,
+                pre_find,
+                &wrapper,
+                None,
+                local_lazy_scope,
+                None,
+            )?,
         })
     }
 }
 
 impl Builder {
-    pub(crate) fn create_exec(self, _phdrs: &[ElfPhdr]) -> Result<ElfExec> {
-        let common = self.create_common(&[], false)?;
+    pub(crate) fn create_exec(self, phdrs: &[ElfPhdr]) -> Result<ElfExec> {
+        let common = self.create_common(phdrs, false)?;
         Ok(ElfExec { common })
     }
 }
 
-impl<M: Mmap> Loader<M> {
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
     /// Load a executable file into memory
     pub fn easy_load_exec(&mut self, object: impl ElfObject) -> Result<ElfExec> {
         self.load_exec(object, None)
@@ -138,17 +150,23 @@ impl<M: Mmap> Loader<M> {
     /// Load a executable file into memory
     /// # Note
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
+    /// * The segments are mapped at the fixed addresses in the program headers(`MAP_FIXED_NOREPLACE`), so the base is always 0.
+    ///   Loading fails if the memory is already in use.
     pub fn load_exec(
         &mut self,
-        mut object: impl ElfObject,
+        object: impl ElfObject,
         lazy_bind: Option<bool>,
     ) -> Result<ElfExec> {
+        let mut object = self.track(object);
         let ehdr = self.buf.prepare_ehdr(&mut object)?;
         if ehdr.is_dylib() {
             return Err(parse_ehdr_error("file type mismatch"));
         }
-        let (builder, phdrs) = self.load_impl(ehdr, object, lazy_bind)?;
-        builder.create_exec(phdrs)
+        let res = self
+            .load_impl(ehdr, object, lazy_bind)
+            .and_then(|(builder, phdrs)| builder.create_exec(phdrs));
+        self.release_buf();
+        res
     }
 
     /// Load a executable file into memory
@@ -156,15 +174,20 @@ impl<M: Mmap> Loader<M> {
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
     pub async fn load_exec_async(
         &mut self,
-        mut object: impl ElfObjectAsync,
+        object: impl ElfObjectAsync,
         lazy_bind: Option<bool>,
     ) -> Result<ElfExec> {
-        let ehdr = self.buf.prepare_ehdr(&mut object)?;
+        let mut object = self.track(object);
+        let ehdr = self.buf.prepare_ehdr_async(&mut object).await?;
         if ehdr.is_dylib() {
             return Err(parse_ehdr_error("file type mismatch"));
         }
-        let (builder, phdrs) = self.load_async_impl(ehdr, object, lazy_bind).await?;
-        builder.create_exec(phdrs)
+        let res = self
+            .load_async_impl(ehdr, object, lazy_bind)
+            .await
+            .and_then(|(builder, phdrs)| builder.create_exec(phdrs));
+        self.release_buf();
+        res
     }
 }
 
@@ -1,17 +1,25 @@
 pub(crate) mod dylib;
 pub(crate) mod exec;
+pub(crate) mod relocatable;
 
+#[cfg(feature = "stats")]
+use crate::stats::StatsCollector;
 use crate::{
     ELFRelro, ElfRelocation, Loader, Result,
-    arch::{Dyn, ElfPhdr, ElfRela},
-    dynamic::ElfDynamic,
+    arch::{Dyn, ElfPhdr, ElfRela, exec_stack},
+    arena::Arena,
+    dynamic::{DynamicFlags, DynamicTable, ElfDynamic},
+    event::{EventCallback, LoadEvent},
     loader::Builder,
-    mmap::Mmap,
+    mmap::{Mmap, ProtFlags},
     object::{ElfObject, ElfObjectAsync},
     parse_dynamic_error,
-    relocation::LazyScope,
-    segment::ElfSegments,
+    property::GnuProperty,
+    relocation::{BindingMismatch, BindingReport, LazyScope, WriteMode},
+    search::NeededBy,
+    segment::{ElfSegments, SegmentInfo, TextRel},
     symbol::SymbolTable,
+    tls::{ElfTls, ThreadLocal, TlsDescs},
 };
 use alloc::{
     boxed::Box,
@@ -24,12 +32,12 @@ use core::{
     ffi::{CStr, c_int},
     fmt::Debug,
     marker::PhantomData,
-    ops::Deref,
+    ops::{Deref, Range},
     ptr::{NonNull, null},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use dylib::{ElfDylib, RelocatedDylib};
-use elf::abi::PT_LOAD;
+use elf::abi::{DT_JMPREL, DT_PLTRELSZ, DT_RELA, DT_RELASZ, DT_SONAME, PT_GNU_RELRO, PT_LOAD};
 use exec::{ElfExec, RelocatedExec};
 
 struct DataItem {
@@ -84,32 +92,46 @@ pub(crate) struct InitParams {
 
 pub(crate) struct ElfInit {
     init_param: Option<InitParams>,
+    /// .preinit_array, only used by executables
+    preinit_array_fn: Option<&'static [extern "C" fn()]>,
     /// .init
     init_fn: Option<extern "C" fn()>,
     /// .init_array
     init_array_fn: Option<&'static [extern "C" fn()]>,
+    /// whether .preinit_array has been called
+    preinit_called: AtomicBool,
 }
 
 impl ElfInit {
-    #[inline]
-    pub(crate) fn call_init(self) {
+    fn call<'a>(&self, fns: impl Iterator<Item = &'a extern "C" fn()>) {
         if let Some(init_params) = self.init_param {
-            self.init_fn
-                .iter()
-                .chain(self.init_array_fn.unwrap_or(&[]).iter())
-                .for_each(|init| unsafe {
-                    core::mem::transmute::<_, extern "C" fn(c_int, usize, usize)>(*init)(
-                        init_params.argc as _,
-                        init_params.argv,
-                        init_params.envp,
-                    );
-                });
+            fns.for_each(|init| unsafe {
+                core::mem::transmute::<_, extern "C" fn(c_int, usize, usize)>(*init)(
+                    init_params.argc as _,
+                    init_params.argv,
+                    init_params.envp,
+                );
+            });
         } else {
+            fns.for_each(|init| init());
+        }
+    }
+
+    #[inline]
+    fn call_preinit(&self) {
+        if !self.preinit_called.swap(true, Ordering::Relaxed) {
+            self.call(self.preinit_array_fn.unwrap_or(&[]).iter());
+        }
+    }
+
+    #[inline]
+    fn call_init(&self) {
+        self.call_preinit();
+        self.call(
             self.init_fn
                 .iter()
-                .chain(self.init_array_fn.unwrap_or(&[]).iter())
-                .for_each(|init| init());
-        }
+                .chain(self.init_array_fn.unwrap_or(&[]).iter()),
+        );
     }
 }
 
@@ -173,13 +195,14 @@ impl Deref for Elf {
 }
 
 // 使用CoreComponentRef是防止出现循环引用
+// 与重定位时相同,先在pre_find中查找,所以没有依赖库时pre_find也会被使用
 pub(crate) fn create_lazy_scope<F>(libs: Vec<CoreComponentRef>, pre_find: &F) -> LazyScope
 where
     F: Fn(&str) -> Option<*const ()>,
 {
     Box::new(move |name| {
-        libs.iter().find_map(|lib| {
-            pre_find(name).or_else(|| unsafe {
+        pre_find(name).or_else(|| {
+            libs.iter().find_map(|lib| unsafe {
                 RelocatedDylib::from_core_component(lib.upgrade().unwrap())
                     .get::<()>(name)
                     .map(|sym| sym.into_raw())
@@ -191,7 +214,8 @@ where
 impl Elf {
     /// Relocate the elf file with the given dynamic libraries and function closure.
     /// # Note
-    /// During relocation, the symbol is first searched in the function closure `pre_find`.
+    /// During relocation, the symbol is first searched in the function closure `pre_find`. With
+    /// lazy binding, this also holds for the symbols bound later, even if `scope` is empty.
     pub fn easy_relocate<'iter, 'scope, 'find, 'lib, S, F>(
         self,
         scope: S,
@@ -257,9 +281,21 @@ pub(crate) struct Relocated<'scope> {
     pub(crate) _marker: PhantomData<&'scope ()>,
 }
 
+// 每个被加载的elf对象都会获得一个唯一的代数id
+static GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+#[inline]
+fn next_generation() -> usize {
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
 pub(crate) struct CoreComponentInner {
     /// is initialized
     is_init: AtomicBool,
+    /// the load event has been sent
+    is_loaded: AtomicBool,
+    /// generation id
+    generation: usize,
     /// file name
     name: CString,
     /// elf symbols
@@ -280,8 +316,29 @@ pub(crate) struct CoreComponentInner {
     user_data: UserData,
     /// lazy binding scope
     pub(crate) lazy_scope: Option<LazyScope<'static>>,
+    /// how relocation results are written
+    pub(crate) write_mode: WriteMode,
+    /// eager and lazy resolutions of plt symbols
+    pub(crate) binding_report: Option<BindingReport>,
+    /// statistics of loading and relocation
+    #[cfg(feature = "stats")]
+    pub(crate) stats: Option<Arc<StatsCollector>>,
+    /// tls module
+    tls: Option<ElfTls>,
+    /// dynamic tls descriptors
+    tls_desc: TlsDescs,
+    /// GNU_RELRO segment which has been protected
+    pub(crate) relro: Option<ELFRelro>,
+    /// init functions
+    init: ElfInit,
+    /// load event callback
+    on_load: Option<EventCallback>,
+    /// unload event callback
+    on_unload: Option<EventCallback>,
     /// semgents
     pub(crate) segments: ElfSegments,
+    /// dependencies kept loaded by the elf object, which are released after its fini functions
+    deps: Box<[CoreComponent]>,
 }
 
 impl Drop for CoreComponentInner {
@@ -292,6 +349,31 @@ impl Drop for CoreComponentInner {
                 .chain(self.fini_array_fn.unwrap_or(&[]).iter())
                 .for_each(|fini| fini());
         }
+        // 延迟初始化时库可能从未初始化,但只要发送过加载事件就要发送卸载事件,此时内存仍然映射着
+        if let Some(on_unload) = self
+            .on_unload
+            .filter(|_| self.is_loaded.load(Ordering::Relaxed))
+        {
+            on_unload(&self.event());
+        }
+        #[cfg(feature = "dl-iterate-phdr")]
+        crate::registry::unregister(self.generation);
+        #[cfg(feature = "debug")]
+        if let Some(dynamic) = self.dynamic {
+            crate::debug::remove(dynamic.as_ptr());
+        }
+    }
+}
+
+impl CoreComponentInner {
+    #[inline]
+    fn event(&self) -> LoadEvent<'_> {
+        LoadEvent {
+            name: &self.name,
+            base: self.segments.base(),
+            generation: self.generation,
+            phdrs: self.phdrs,
+        }
     }
 }
 
@@ -315,6 +397,8 @@ pub struct CoreComponent {
 
 unsafe impl Sync for CoreComponent {}
 unsafe impl Send for CoreComponent {}
+// 裸指针都指向由core持有的内存,因此可以把未重定位的elf对象交给其他线程
+unsafe impl Send for ElfCommonPart {}
 
 impl CoreComponent {
     #[inline]
@@ -328,8 +412,71 @@ impl CoreComponent {
     }
 
     #[inline]
-    pub(crate) fn set_init(&self) {
-        self.inner.is_init.store(true, Ordering::Relaxed);
+    pub(crate) fn set_tls_desc(&self, tls_desc: TlsDescs) {
+        // 与set_lazy_scope相同,此时只有当前线程可以访问CoreComponent
+        unsafe {
+            let ptr = &mut *(Arc::as_ptr(&self.inner) as *mut CoreComponentInner);
+            ptr.tls_desc = tls_desc;
+        };
+    }
+
+    #[inline]
+    pub(crate) fn set_relro(&self, relro: ELFRelro) {
+        // 与set_lazy_scope相同,此时只有当前线程可以访问CoreComponent
+        unsafe {
+            let ptr = &mut *(Arc::as_ptr(&self.inner) as *mut CoreComponentInner);
+            ptr.relro = Some(relro);
+        };
+    }
+
+    #[inline]
+    pub(crate) fn set_deps(&self, deps: Box<[CoreComponent]>) {
+        // 只在CoreComponent被克隆之前调用,此时没有其他引用
+        unsafe {
+            let ptr = &mut *(Arc::as_ptr(&self.inner) as *mut CoreComponentInner);
+            ptr.deps = deps;
+        };
+    }
+
+    #[inline]
+    pub(crate) fn tls(&self) -> Option<&ElfTls> {
+        self.inner.tls.as_ref()
+    }
+
+    /// Gets the tls module id of the elf object.
+    #[inline]
+    pub fn tls_modid(&self) -> Option<usize> {
+        self.tls().map(|tls| tls.modid)
+    }
+
+    /// Whether the init functions of the elf object have been called, see `Loader::set_deferred_init`.
+    #[inline]
+    pub fn is_init(&self) -> bool {
+        self.inner.is_init.load(Ordering::Relaxed)
+    }
+
+    // 在依赖的初始化函数之前调用可执行文件的.preinit_array
+    #[inline]
+    pub(crate) fn call_preinit(&self) {
+        if !self.is_init() {
+            self.inner.init.call_preinit();
+        }
+    }
+
+    // 初始化函数只会被调用一次
+    #[inline]
+    pub(crate) fn call_init(&self) {
+        if !self.inner.is_init.swap(true, Ordering::Relaxed) {
+            self.inner.init.call_init();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn notify_load(&self) {
+        self.inner.is_loaded.store(true, Ordering::Relaxed);
+        if let Some(on_load) = self.inner.on_load {
+            on_load(&self.inner.event());
+        }
     }
 
     #[inline]
@@ -346,6 +493,12 @@ impl CoreComponent {
         &self.inner.user_data
     }
 
+    /// Gets the generation id of the elf object. Each loaded elf object has a different generation id.
+    #[inline]
+    pub fn generation(&self) -> usize {
+        self.inner.generation
+    }
+
     /// Gets the number of strong references to the elf object.
     #[inline]
     pub fn strong_count(&self) -> usize {
@@ -388,6 +541,49 @@ impl CoreComponent {
         self.inner.segments.len()
     }
 
+    /// Gets the address range of the whole memory mapping of the elf object.
+    #[inline]
+    pub fn map_range(&self) -> Range<usize> {
+        let start = self.inner.segments.memory.as_ptr() as usize;
+        start..start + self.inner.segments.len()
+    }
+
+    /// Gets the final layout of the `PT_LOAD` segments of the elf object.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use elf_loader::{load_dylib, mmap::ProtFlags};
+    ///
+    /// let liba = load_dylib!("target/liba.so").unwrap();
+    /// for segment in liba.segments() {
+    ///     if segment.prot.contains(ProtFlags::PROT_EXEC) {
+    ///         // flush the instruction cache of `segment.page_range()`
+    ///     }
+    /// }
+    /// ```
+    pub fn segments(&self) -> impl Iterator<Item = SegmentInfo> + '_ {
+        let base = self.base();
+        self.inner
+            .phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(move |phdr| SegmentInfo::new(phdr, base))
+    }
+
+    /// Gets the address range of `PT_GNU_RELRO`, which is made read-only after relocation
+    /// unless lazy binding is used.
+    pub fn relro_range(&self) -> Option<Range<usize>> {
+        let base = self.base();
+        self.inner
+            .phdrs
+            .iter()
+            .find(|phdr| phdr.p_type == PT_GNU_RELRO)
+            .map(|phdr| {
+                let start = base + phdr.p_vaddr as usize;
+                start..start + phdr.p_memsz as usize
+            })
+    }
+
     /// Gets the program headers of the elf object.
     #[inline]
     pub fn phdrs(&self) -> &[ElfPhdr] {
@@ -400,6 +596,54 @@ impl CoreComponent {
         self.inner.dynamic
     }
 
+    /// Gets the raw entries of the dynamic section.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use elf_loader::{abi::DT_SONAME, load_dylib};
+    ///
+    /// let liba = load_dylib!("target/liba.so").unwrap();
+    /// let dynamic = liba.dynamic_table().unwrap();
+    /// assert_eq!(dynamic.get(DT_SONAME).is_some(), liba.soname().is_some());
+    /// for (tag, val) in dynamic.iter() {
+    ///     println!("{tag:#x}: {val:#x}");
+    /// }
+    /// ```
+    #[inline]
+    pub fn dynamic_table(&self) -> Option<DynamicTable<'_>> {
+        self.inner
+            .dynamic
+            .map(|dynamic| unsafe { DynamicTable::from_ptr(dynamic.as_ptr()) })
+    }
+
+    /// 读取.rela.dyn和.rela.plt中的所有重定位项
+    pub(crate) fn relas(&self) -> Vec<&ElfRela> {
+        let Some(dynamic) = self.dynamic_table() else {
+            return Vec::new();
+        };
+        let base = self.base();
+        [(DT_RELA, DT_RELASZ), (DT_JMPREL, DT_PLTRELSZ)]
+            .into_iter()
+            .filter_map(|(tag, size_tag)| Some((dynamic.get(tag)?, dynamic.get(size_tag)?)))
+            .flat_map(|(addr, size)| unsafe {
+                let start = (base + addr) as *const ElfRela;
+                core::slice::from_raw_parts(start, size / size_of::<ElfRela>())
+            })
+            .collect()
+    }
+
+    /// Gets the DT_SONAME value.
+    pub fn soname(&self) -> Option<&str> {
+        let off = self.dynamic_table()?.get(DT_SONAME)?;
+        Some(self.symtab()?.strtab().get_str(off))
+    }
+
+    /// Whether the elf object satisfies the dependency `needed`, which is its file name or soname.
+    #[inline]
+    pub(crate) fn provides(&self, needed: &str) -> bool {
+        self.shortname() == needed || self.soname() == Some(needed)
+    }
+
     /// Gets the needed libs' name of the elf object.
     #[inline]
     pub fn needed_libs(&self) -> &[&str] {
@@ -412,8 +656,28 @@ impl CoreComponent {
         self.inner.symbols.as_ref()
     }
 
+    /// Gets the PLT symbols whose lazy binding resolved to a different address than the eager
+    /// resolution performed when the elf object was relocated. Only symbols that have already been
+    /// bound lazily are compared. Mismatches usually mean that the result depends on the order of
+    /// resolution, such as a library providing the symbol being loaded after the relocation.
+    ///
+    /// Returns `None` unless the elf object was loaded by a loader with `Loader::set_binding_report`
+    /// enabled and relocated with lazy binding.
+    pub fn binding_report(&self) -> Option<Vec<BindingMismatch>> {
+        let report = self.inner.binding_report.as_ref()?;
+        let pltrel = self.inner.pltrel.map_or(&[][..], |pltrel| unsafe {
+            core::slice::from_raw_parts(pltrel.as_ptr(), report.len())
+        });
+        Some(report.mismatches(self.symtab().unwrap(), pltrel))
+    }
+
     #[inline]
-    pub(crate) fn segments(&self) -> &ElfSegments {
+    pub(crate) fn write_mode(&self) -> WriteMode {
+        self.inner.write_mode
+    }
+
+    #[inline]
+    pub(crate) fn elf_segments(&self) -> &ElfSegments {
         &self.inner.segments
     }
 
@@ -426,20 +690,45 @@ impl CoreComponent {
         user_data: UserData,
     ) -> Self {
         segments.offset = (segments.memory.as_ptr() as usize).wrapping_sub(base);
+        let symbols = SymbolTable::new(&dynamic);
+        let needed_libs: Vec<&'static str> = dynamic
+            .needed_libs
+            .iter()
+            .map(|needed_lib| symbols.strtab().get_str(needed_lib.get()))
+            .collect();
         Self {
             inner: Arc::new(CoreComponentInner {
                 name,
                 is_init: AtomicBool::new(true),
-                symbols: Some(SymbolTable::new(&dynamic)),
+                is_loaded: AtomicBool::new(false),
+                generation: next_generation(),
+                symbols: Some(symbols),
                 pltrel: None,
                 dynamic: NonNull::new(dynamic.dyn_ptr as _),
                 phdrs,
                 segments,
                 fini_fn: None,
                 fini_array_fn: None,
-                needed_libs: Box::new([]),
+                needed_libs: needed_libs.into_boxed_slice(),
                 user_data,
                 lazy_scope: None,
+                write_mode: WriteMode::Plain,
+                binding_report: None,
+                #[cfg(feature = "stats")]
+                stats: None,
+                tls: None,
+                tls_desc: Vec::new(),
+                relro: None,
+                init: ElfInit {
+                    init_param: None,
+                    preinit_array_fn: None,
+                    init_fn: None,
+                    init_array_fn: None,
+                    preinit_called: AtomicBool::new(true),
+                },
+                on_load: None,
+                on_unload: None,
+                deps: Box::new([]),
             }),
         }
     }
@@ -470,16 +759,30 @@ pub struct ElfCommonPart {
     pub(crate) relocation: ElfRelocation,
     /// GNU_RELRO segment
     pub(crate) relro: Option<ELFRelro>,
-    /// init
-    pub(crate) init: ElfInit,
+    /// DT_TEXTREL
+    pub(crate) textrel: Option<TextRel>,
+    /// protect GNU_RELRO even with lazy binding
+    pub(crate) enforce_relro: bool,
+    /// init functions are called by `init_all`
+    pub(crate) defer_init: bool,
+    /// the arena of the transient data of the relocation
+    pub(crate) arena: Option<&'static dyn Arena>,
     /// lazy binding
     lazy: bool,
+    /// DT_FLAGS and DT_FLAGS_1
+    flags: DynamicFlags,
     /// DT_RPATH
     rpath: Option<&'static str>,
     /// DT_RUNPATH
     runpath: Option<&'static str>,
     /// PT_INTERP
     interp: Option<&'static str>,
+    /// PT_GNU_STACK
+    stack_prot: Option<ProtFlags>,
+    /// NT_GNU_BUILD_ID in PT_NOTE
+    build_id: Option<&'static [u8]>,
+    /// PT_GNU_PROPERTY
+    gnu_property: Option<GnuProperty>,
     /// core component
     pub(crate) core: CoreComponent,
 }
@@ -509,6 +812,18 @@ impl ElfCommonPart {
         self.lazy
     }
 
+    /// Gets the DT_FLAGS and DT_FLAGS_1 values.
+    #[inline]
+    pub fn flags(&self) -> DynamicFlags {
+        self.flags
+    }
+
+    /// Whether the elf object has text relocations(`DT_TEXTREL` or `DF_TEXTREL`).
+    #[inline]
+    pub fn textrel(&self) -> bool {
+        self.textrel.is_some()
+    }
+
     /// Gets the DT_RPATH value.
     #[inline]
     pub fn rpath(&self) -> Option<&str> {
@@ -526,6 +841,41 @@ impl ElfCommonPart {
     pub fn interp(&self) -> Option<&str> {
         self.interp
     }
+
+    /// Gets the stack permissions requested by PT_GNU_STACK.
+    #[inline]
+    pub fn stack_prot(&self) -> Option<ProtFlags> {
+        self.stack_prot
+    }
+
+    /// Whether the elf object requests an executable stack. Without PT_GNU_STACK, the default
+    /// of the architecture is used as glibc does, which is only executable on x86_64.
+    #[inline]
+    pub fn exec_stack(&self) -> bool {
+        exec_stack(self.stack_prot)
+    }
+
+    /// Gets the GNU build-id(`NT_GNU_BUILD_ID`) of the elf object.
+    #[inline]
+    pub fn build_id(&self) -> Option<&[u8]> {
+        self.build_id
+    }
+
+    /// Gets the GNU properties(`PT_GNU_PROPERTY`) of the elf object.
+    #[inline]
+    pub fn gnu_property(&self) -> Option<GnuProperty> {
+        self.gnu_property
+    }
+
+    /// Gets the information used to search the dependencies of the elf object.
+    #[inline]
+    pub fn needed_by(&self) -> NeededBy<'_> {
+        NeededBy {
+            name: self.name(),
+            rpath: self.rpath,
+            runpath: self.runpath,
+        }
+    }
 }
 
 impl Builder {
@@ -560,17 +910,34 @@ impl Builder {
                 .iter()
                 .map(|needed_lib| symbols.strtab().get_str(needed_lib.get()))
                 .collect();
+            // 带有DF_BIND_NOW的elf对象总是立即绑定
+            let lazy = !dynamic.bind_now && self.lazy_bind.unwrap_or(true);
+            let init = ElfInit {
+                init_param: self.init_params,
+                // 只有可执行文件的DT_PREINIT_ARRAY会被处理
+                preinit_array_fn: if is_dylib {
+                    None
+                } else {
+                    dynamic.preinit_array_fn
+                },
+                init_fn: dynamic.init_fn,
+                init_array_fn: dynamic.init_array_fn,
+                preinit_called: AtomicBool::new(false),
+            };
             ElfCommonPart {
                 entry: self.ehdr.e_entry as usize,
                 relro: self.relro,
+                textrel: self.textrel,
+                enforce_relro: self.enforce_relro,
                 relocation,
-                init: ElfInit {
-                    init_param: self.init_params,
-                    init_fn: dynamic.init_fn,
-                    init_array_fn: dynamic.init_array_fn,
-                },
+                defer_init: self.defer_init,
+                arena: self.arena,
                 interp: self.interp,
-                lazy: self.lazy_bind.unwrap_or(!dynamic.bind_now),
+                stack_prot: self.stack_prot,
+                build_id: self.build_id,
+                gnu_property: self.gnu_property,
+                lazy,
+                flags: dynamic.flags,
                 got: dynamic.got,
                 rpath: dynamic
                     .rpath_off
@@ -581,6 +948,8 @@ impl Builder {
                 core: CoreComponent {
                     inner: Arc::new(CoreComponentInner {
                         is_init: AtomicBool::new(false),
+                        is_loaded: AtomicBool::new(false),
+                        generation: next_generation(),
                         name: self.name,
                         symbols: Some(symbols),
                         dynamic: NonNull::new(dynamic.dyn_ptr as _),
@@ -592,6 +961,18 @@ impl Builder {
                         needed_libs: needed_libs.into_boxed_slice(),
                         user_data: self.user_data,
                         lazy_scope: None,
+                        write_mode: self.write_mode,
+                        binding_report: (self.binding_report && lazy)
+                            .then(|| BindingReport::new(dynamic.pltrel.map_or(0, |plt| plt.len()))),
+                        #[cfg(feature = "stats")]
+                        stats: self.stats,
+                        tls: self.tls,
+                        tls_desc: Vec::new(),
+                        relro: None,
+                        init,
+                        on_load: self.on_load,
+                        on_unload: self.on_unload,
+                        deps: Box::new([]),
                     }),
                 },
             }
@@ -600,23 +981,35 @@ impl Builder {
                 return Err(parse_dynamic_error("dylib does not have dynamic"));
             }
             let relocation = ElfRelocation::new(None, None, None);
+            let init = ElfInit {
+                init_param: self.init_params,
+                preinit_array_fn: None,
+                init_fn: None,
+                init_array_fn: None,
+                preinit_called: AtomicBool::new(false),
+            };
             ElfCommonPart {
                 entry: self.ehdr.e_entry as usize,
                 relro: self.relro,
+                textrel: self.textrel,
+                enforce_relro: self.enforce_relro,
                 relocation,
-                init: ElfInit {
-                    init_param: self.init_params,
-                    init_fn: None,
-                    init_array_fn: None,
-                },
+                defer_init: self.defer_init,
+                arena: self.arena,
                 interp: self.interp,
+                stack_prot: self.stack_prot,
+                build_id: self.build_id,
+                gnu_property: self.gnu_property,
                 lazy: self.lazy_bind.unwrap_or(false),
+                flags: DynamicFlags::default(),
                 got: None,
                 rpath: None,
                 runpath: None,
                 core: CoreComponent {
                     inner: Arc::new(CoreComponentInner {
                         is_init: AtomicBool::new(false),
+                        is_loaded: AtomicBool::new(false),
+                        generation: next_generation(),
                         name: self.name,
                         symbols: None,
                         dynamic: None,
@@ -628,6 +1021,17 @@ impl Builder {
                         needed_libs: Box::new([]),
                         user_data: self.user_data,
                         lazy_scope: None,
+                        write_mode: self.write_mode,
+                        binding_report: None,
+                        #[cfg(feature = "stats")]
+                        stats: self.stats,
+                        tls: self.tls,
+                        tls_desc: Vec::new(),
+                        relro: None,
+                        init,
+                        on_load: self.on_load,
+                        on_unload: self.on_unload,
+                        deps: Box::new([]),
                     }),
                 },
             }
@@ -645,7 +1049,7 @@ impl Builder {
     }
 }
 
-impl<M: Mmap> Loader<M> {
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
     /// Load a elf file into memory
     pub fn easy_load(&mut self, object: impl ElfObject) -> Result<Elf> {
         self.load(object, None)
@@ -654,24 +1058,35 @@ impl<M: Mmap> Loader<M> {
     /// Load a elf file into memory
     /// # Note
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
-    pub fn load(&mut self, mut object: impl ElfObject, lazy_bind: Option<bool>) -> Result<Elf> {
+    /// * Elf objects with `DF_BIND_NOW` or `DF_1_NOW` are never bound lazily.
+    pub fn load(&mut self, object: impl ElfObject, lazy_bind: Option<bool>) -> Result<Elf> {
+        let mut object = self.track(object);
         let ehdr = self.buf.prepare_ehdr(&mut object)?;
         let is_dylib = ehdr.is_dylib();
-        let (builder, phdrs) = self.load_impl(ehdr, object, lazy_bind)?;
-        builder.create_elf(phdrs, is_dylib)
+        let res = self
+            .load_impl(ehdr, object, lazy_bind)
+            .and_then(|(builder, phdrs)| builder.create_elf(phdrs, is_dylib));
+        self.release_buf();
+        res
     }
 
     /// Load a elf file into memory
     /// # Note
     /// * When `lazy_bind` is not set, lazy binding is enabled using the dynamic library's DT_FLAGS flag.
+    /// * Elf objects with `DF_BIND_NOW` or `DF_1_NOW` are never bound lazily.
     pub async fn load_async(
         &mut self,
-        mut object: impl ElfObjectAsync,
+        object: impl ElfObjectAsync,
         lazy_bind: Option<bool>,
     ) -> Result<Elf> {
-        let ehdr = self.buf.prepare_ehdr(&mut object)?;
+        let mut object = self.track(object);
+        let ehdr = self.buf.prepare_ehdr_async(&mut object).await?;
         let is_dylib = ehdr.is_dylib();
-        let (builder, phdrs) = self.load_async_impl(ehdr, object, lazy_bind).await?;
-        builder.create_elf(phdrs, is_dylib)
+        let res = self
+            .load_async_impl(ehdr, object, lazy_bind)
+            .await
+            .and_then(|(builder, phdrs)| builder.create_elf(phdrs, is_dylib));
+        self.release_buf();
+        res
     }
 }
@@ -0,0 +1,521 @@
+//! Relocatable object files
+//!
+//! A relocatable object(`.o`, `ET_REL`) has no program headers and is not linked yet, so the
+//! loader lays out its sections by itself like the module loader of a kernel. The allocated
+//! sections are grouped into executable, read-only and writable pages, `COMMON` symbols are
+//! allocated after the writable sections, and the `SHT_RELA` sections are applied to the sections
+//! they refer to. Undefined symbols are looked up in `pre_find` first and then in the scope.
+//! Calls to functions out of the range of the branch instructions go through stubs, and the
+//! relocations referencing the got use entries allocated by the loader. Only x86_64 and aarch64
+//! are supported.
+use crate::{
+    Loader, RelocFailure, RelocateErrorKind, RelocatedDylib, Result,
+    arch::{
+        E_CLASS, EHDR_SIZE, OBJ_STUB_SIZE, ObjSlot, SHDR_SIZE, flush_icache, obj_relocate,
+        obj_slot, obj_width, obj_write_stub,
+    },
+    io_error,
+    loader::ElfHeader,
+    mmap::{MapFlags, Mmap, ProtFlags},
+    object::ElfObject,
+    parse_ehdr_error, relocate_error,
+    relocation::SymDef,
+    segment::{ElfSegments, MASK, PAGE_SIZE},
+    symbol::SymbolInfo,
+    tls::ThreadLocal,
+};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    ffi::c_void,
+    fmt::Debug,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::{Deref, Range},
+    ptr::NonNull,
+};
+use elf::{
+    ElfBytes,
+    abi::{
+        ELFCLASS64, ET_REL, SHF_ALLOC, SHF_EXECINSTR, SHF_TLS, SHF_WRITE, SHN_ABS, SHN_COMMON,
+        SHN_UNDEF, SHT_NOBITS, SHT_REL, SHT_RELA, STB_LOCAL, STB_WEAK, STV_HIDDEN, STV_INTERNAL,
+    },
+    endian::NativeEndian,
+    file::Class,
+    section::{SectionHeader, SectionHeaderTable},
+};
+
+// 节被分为三组,每组占用单独的页
+const TEXT: usize = 0;
+const RODATA: usize = 1;
+const DATA: usize = 2;
+
+/// A relocatable object which has been loaded and relocated. It holds the dynamic libraries in
+/// the scope which its undefined symbols are bound to, so it can not outlive their scope.
+pub struct ElfRelocatableObject<'scope> {
+    name: String,
+    segments: ElfSegments,
+    sections: Vec<(String, Range<usize>)>,
+    symbols: BTreeMap<String, usize>,
+    deps: Vec<RelocatedDylib<'scope>>,
+}
+
+impl Debug for ElfRelocatableObject<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ElfRelocatableObject")
+            .field("name", &self.name)
+            .field("segments", &self.segments)
+            .finish()
+    }
+}
+
+impl<'scope> ElfRelocatableObject<'scope> {
+    /// Gets the name of the relocatable object. Invalid UTF-8 sequences are replaced with
+    /// `U+FFFD`.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the dynamic libraries which the undefined symbols are bound to.
+    #[inline]
+    pub fn deps(&self) -> &[RelocatedDylib<'scope>] {
+        &self.deps
+    }
+
+    /// Gets the address where the sections are mapped.
+    #[inline]
+    pub fn base(&self) -> usize {
+        self.segments.base()
+    }
+
+    /// Gets the memory range of the relocatable object.
+    #[inline]
+    pub fn map_range(&self) -> Range<usize> {
+        self.base()..self.base() + self.segments.len()
+    }
+
+    /// Gets the address of the allocated section `name`, such as `.text`.
+    pub fn section(&self, name: &str) -> Option<Range<usize>> {
+        self.sections
+            .iter()
+            .find(|(section, _)| section == name)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Gets the address of the global symbol `name` defined in the relocatable object.
+    #[inline]
+    pub fn symbol(&self, name: &str) -> Option<*const ()> {
+        self.symbols.get(name).map(|&addr| addr as *const ())
+    }
+
+    /// Iterates over the global symbols defined in the relocatable object and their addresses.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, *const ())> {
+        self.symbols
+            .iter()
+            .map(|(name, &addr)| (name.as_str(), addr as *const ()))
+    }
+
+    /// Gets a pointer to the global symbol `name` as `T`, like `RelocatedDylib::get`.
+    ///
+    /// # Safety
+    /// Users of this API must specify the correct type of the function or variable loaded.
+    #[inline]
+    pub unsafe fn get<'obj, T>(&'obj self, name: &str) -> Option<ObjectSymbol<'obj, T>> {
+        self.symbols.get(name).map(|&addr| ObjectSymbol {
+            ptr: addr as *mut (),
+            pd: PhantomData,
+        })
+    }
+}
+
+/// A symbol of a relocatable object, which can not outlive the object
+#[derive(Debug, Clone)]
+pub struct ObjectSymbol<'obj, T: 'obj> {
+    ptr: *mut (),
+    pd: PhantomData<&'obj T>,
+}
+
+impl<T> Deref for ObjectSymbol<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*(&self.ptr as *const *mut _ as *const T) }
+    }
+}
+
+impl<T> ObjectSymbol<'_, T> {
+    /// Gets the address of the symbol.
+    #[inline]
+    pub fn into_raw(self) -> *const () {
+        self.ptr
+    }
+}
+
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
+    /// Loads a relocatable object(`ET_REL`) into memory and relocates it.
+    /// # Note
+    /// During relocation, the undefined symbols are first searched in the function closure
+    /// `pre_find` and then in `scope`. Undefined weak symbols which are not found are null.
+    /// # Examples
+    /// ```no_run
+    /// use elf_loader::{Loader, mmap::MmapImpl, object::ElfFile};
+    ///
+    /// extern "C" fn host_add(a: i32, b: i32) -> i32 {
+    ///     a + b
+    /// }
+    ///
+    /// let pre_find = |name: &str| (name == "host_add").then_some(host_add as *const ());
+    /// let mut loader = Loader::<MmapImpl>::new();
+    /// let module = ElfFile::from_path("target/module.o").unwrap();
+    /// let module = loader
+    ///     .load_relocatable(module, [].iter(), &pre_find)
+    ///     .unwrap();
+    /// let init = unsafe { module.get::<extern "C" fn() -> i32>("module_init").unwrap() };
+    /// init();
+    /// ```
+    pub fn load_relocatable<'iter, 'scope, S, F>(
+        &mut self,
+        mut object: impl ElfObject,
+        scope: S,
+        pre_find: &F,
+    ) -> Result<ElfRelocatableObject<'scope>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>>,
+        F: Fn(&str) -> Option<*const ()>,
+        'scope: 'iter,
+    {
+        let bytes = read_object(&mut object)?;
+        let name = object.file_name().to_string_lossy().into_owned();
+        let shortname = name.split('/').next_back().unwrap();
+        let file = ElfBytes::<NativeEndian>::minimal_parse(&bytes).map_err(parse_ehdr_error)?;
+        let (Some(shdrs), Some(shstrtab)) = file
+            .section_headers_with_strtab()
+            .map_err(parse_ehdr_error)?
+        else {
+            return Err(parse_ehdr_error("missing section headers"));
+        };
+        let shdrs: Vec<SectionHeader> = shdrs.iter().collect();
+        let Some((symtab, strtab)) = file.symbol_table().map_err(parse_ehdr_error)? else {
+            return Err(parse_ehdr_error("missing symbol table"));
+        };
+        let syms: Vec<_> = symtab.iter().collect();
+        let section_name = |shdr: &SectionHeader| shstrtab.get(shdr.sh_name as usize).unwrap_or("");
+        let symbol_name = |idx: usize| strtab.get(syms[idx].st_name as usize).unwrap_or("");
+        let reloc_error = |shdr: &SectionHeader, idx, r_type: u32, msg| {
+            let kind = RelocateErrorKind::Section {
+                section: section_name(shdr).to_string(),
+                idx,
+                r_type: r_type as usize,
+                msg,
+            };
+            relocate_error(shortname, kind)
+        };
+
+        // 只处理作用于已分配节的重定位节
+        let mut relas = Vec::new();
+        for shdr in &shdrs {
+            let target = shdr.sh_info as usize;
+            let allocated = shdrs
+                .get(target)
+                .is_some_and(|target| target.sh_flags & SHF_ALLOC as u64 != 0);
+            match shdr.sh_type {
+                SHT_RELA if allocated => relas.push((shdr, target)),
+                SHT_REL if allocated => {
+                    return Err(reloc_error(shdr, 0, 0, "SHT_REL is not supported"));
+                }
+                _ => {}
+            }
+        }
+
+        // 计算每个节在其所在组中的偏移
+        let mut sizes = [0; 3];
+        let mut offsets = vec![None; shdrs.len()];
+        for (idx, shdr) in shdrs.iter().enumerate() {
+            let Some(group) = section_group(shdr) else {
+                continue;
+            };
+            if shdr.sh_flags & SHF_TLS as u64 != 0 && shdr.sh_size != 0 {
+                return Err(io_error(
+                    "thread local variables are not supported in relocatable objects",
+                ));
+            }
+            let offset = place(&mut sizes[group], shdr.sh_size, shdr.sh_addralign);
+            offsets[idx] = Some((group, offset));
+        }
+        // 桩放在代码之后,got放在只读数据之后,COMMON符号放在可写数据之后
+        let mut stubs = vec![None; syms.len()];
+        let mut gots = vec![None; syms.len()];
+        for &(shdr, _) in &relas {
+            for rela in file.section_data_as_relas(shdr).map_err(parse_ehdr_error)? {
+                let r_sym = rela.r_sym as usize;
+                if r_sym == 0 || r_sym >= syms.len() {
+                    continue;
+                }
+                match obj_slot(rela.r_type) {
+                    // 只有外部的函数才可能超出跳转范围
+                    ObjSlot::Stub if syms[r_sym].is_undefined() && stubs[r_sym].is_none() => {
+                        let size = OBJ_STUB_SIZE as u64;
+                        stubs[r_sym] = Some(place(&mut sizes[TEXT], size, size));
+                    }
+                    ObjSlot::Got if gots[r_sym].is_none() => {
+                        let size = size_of::<usize>() as u64;
+                        gots[r_sym] = Some(place(&mut sizes[RODATA], size, size));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut commons = vec![None; syms.len()];
+        for (idx, sym) in syms.iter().enumerate() {
+            if sym.st_shndx == SHN_COMMON {
+                // COMMON符号的值是其对齐
+                commons[idx] = Some(place(&mut sizes[DATA], sym.st_size, sym.st_value));
+            }
+        }
+
+        let mut starts = [0; 3];
+        let mut total = 0;
+        for group in [TEXT, RODATA, DATA] {
+            starts[group] = total;
+            total += (sizes[group] + PAGE_SIZE - 1) & MASK;
+        }
+        let memory = unsafe {
+            M::mmap_anonymous(
+                0,
+                total.max(PAGE_SIZE),
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE,
+            )
+        }?;
+        let segments = ElfSegments::new(memory, total.max(PAGE_SIZE), M::munmap);
+        let base = memory.as_ptr() as usize;
+        let group_base = |group: usize| base + starts[group];
+
+        let mut sections = Vec::new();
+        let mut section_addrs = vec![None; shdrs.len()];
+        for (idx, shdr) in shdrs.iter().enumerate() {
+            let Some((group, offset)) = offsets[idx] else {
+                continue;
+            };
+            let addr = group_base(group) + offset;
+            if shdr.sh_type != SHT_NOBITS {
+                let (data, _) = file.section_data(shdr).map_err(parse_ehdr_error)?;
+                let dest = addr as *mut u8;
+                unsafe { dest.copy_from_nonoverlapping(data.as_ptr(), data.len()) };
+            }
+            section_addrs[idx] = Some(addr);
+            let name = section_name(shdr).to_string();
+            sections.push((name, addr..addr + shdr.sh_size as usize));
+        }
+
+        let scope: Vec<&RelocatedDylib> = scope.collect();
+        // 记录符号绑定到的动态库,保证它们与目标文件一样长
+        let mut used = vec![false; scope.len()];
+        let mut addrs = Vec::with_capacity(syms.len());
+        for (idx, sym) in syms.iter().enumerate() {
+            let addr = match sym.st_shndx {
+                _ if idx == 0 => Some(0),
+                SHN_UNDEF => find_symbol(symbol_name(idx), pre_find, &scope, &mut used)
+                    // 未找到的弱符号为null
+                    .or((sym.st_bind() == STB_WEAK).then_some(0)),
+                SHN_ABS => Some(sym.st_value as usize),
+                SHN_COMMON => commons[idx].map(|offset| group_base(DATA) + offset),
+                shndx => section_addrs
+                    .get(shndx as usize)
+                    .copied()
+                    .flatten()
+                    .map(|addr: usize| addr + sym.st_value as usize),
+            };
+            addrs.push(addr);
+        }
+        for (idx, stub) in stubs.iter().enumerate() {
+            if let (Some(offset), Some(addr)) = (stub, addrs[idx]) {
+                let stub = (group_base(TEXT) + offset) as *mut u8;
+                unsafe { obj_write_stub(stub, addr) };
+            }
+        }
+        for (idx, got) in gots.iter().enumerate() {
+            if let (Some(offset), Some(addr)) = (got, addrs[idx]) {
+                let got = (group_base(RODATA) + offset) as *mut usize;
+                unsafe { got.write(addr) };
+            }
+        }
+
+        let mut failures = Vec::new();
+        for (shdr, target) in relas {
+            let section_addr = section_addrs[target].unwrap();
+            let section_size = shdrs[target].sh_size;
+            let relas = file.section_data_as_relas(shdr).map_err(parse_ehdr_error)?;
+            for (idx, rela) in relas.enumerate() {
+                let r_sym = rela.r_sym as usize;
+                let width = obj_width(rela.r_type).max(1) as u64;
+                if rela
+                    .r_offset
+                    .checked_add(width)
+                    .is_none_or(|end| end > section_size)
+                {
+                    let msg = "the target is out of the section";
+                    return Err(reloc_error(shdr, idx, rela.r_type, msg));
+                }
+                if r_sym >= syms.len() {
+                    let msg = "the symbol index is out of range";
+                    return Err(reloc_error(shdr, idx, rela.r_type, msg));
+                }
+                let place = section_addr + rela.r_offset as usize;
+                let Some(addr) = addrs[r_sym] else {
+                    failures.push(RelocFailure {
+                        r_type: rela.r_type as usize,
+                        r_offset: place - base,
+                        symbol: Some(symbol_name(r_sym).to_string()),
+                        custom_err: Box::new(()),
+                    });
+                    continue;
+                };
+                let slot = match obj_slot(rela.r_type) {
+                    ObjSlot::Stub => stubs[r_sym].map(|offset| group_base(TEXT) + offset),
+                    ObjSlot::Got => gots[r_sym].map(|offset| group_base(RODATA) + offset),
+                    ObjSlot::None => None,
+                };
+                let addend = rela.r_addend as isize;
+                unsafe { obj_relocate(rela.r_type, place, addr, addend, slot.unwrap_or(0)) }
+                    .map_err(|msg| reloc_error(shdr, idx, rela.r_type, msg))?;
+            }
+        }
+        if !failures.is_empty() {
+            return Err(relocate_error(
+                shortname,
+                RelocateErrorKind::Unresolved(failures),
+            ));
+        }
+
+        for (group, prot) in [
+            (TEXT, ProtFlags::PROT_READ | ProtFlags::PROT_EXEC),
+            (RODATA, ProtFlags::PROT_READ),
+        ] {
+            let len = (sizes[group] + PAGE_SIZE - 1) & MASK;
+            if len != 0 {
+                let addr = unsafe { NonNull::new_unchecked(group_base(group) as *mut c_void) };
+                unsafe { M::mprotect(addr, len, prot) }?;
+            }
+        }
+        flush_icache(group_base(TEXT)..group_base(TEXT) + sizes[TEXT]);
+
+        let mut symbols = BTreeMap::new();
+        for (idx, sym) in syms.iter().enumerate() {
+            if sym.is_undefined()
+                || sym.st_bind() == STB_LOCAL
+                || matches!(sym.st_vis(), STV_HIDDEN | STV_INTERNAL)
+            {
+                continue;
+            }
+            if let Some(addr) = addrs[idx] {
+                symbols.insert(symbol_name(idx).to_string(), addr);
+            }
+        }
+        let deps = scope
+            .into_iter()
+            .zip(used)
+            .filter(|(_, used)| *used)
+            .map(|(lib, _)| lib.clone())
+            .collect();
+        Ok(ElfRelocatableObject {
+            name,
+            segments,
+            sections,
+            symbols,
+            deps,
+        })
+    }
+}
+
+// 可重定位目标文件没有程序头,需要读取节头表以及所有的节
+fn read_object(object: &mut impl ElfObject) -> Result<Vec<u8>> {
+    let mut ehdr = MaybeUninit::<ElfHeader>::zeroed();
+    let buf = unsafe { core::slice::from_raw_parts_mut(ehdr.as_mut_ptr().cast(), EHDR_SIZE) };
+    object.read(buf, 0)?;
+    let ehdr = unsafe { ehdr.assume_init() };
+    ehdr.validate_ident()?;
+    if ehdr.e_type != ET_REL {
+        return Err(parse_ehdr_error("file type mismatch"));
+    }
+    // e_shnum为0时真正的数量保存在第一个section header中,这里不支持
+    if ehdr.e_shentsize as usize != SHDR_SIZE || ehdr.e_shnum == 0 {
+        return Err(parse_ehdr_error("unsupported section header table"));
+    }
+    let shoff = ehdr.e_shoff as usize;
+    let shdrs_size = ehdr.e_shnum as usize * SHDR_SIZE;
+    let out_of_bounds = || parse_ehdr_error("sections are out of bounds");
+    let mut end = shoff.checked_add(shdrs_size).ok_or_else(out_of_bounds)?;
+    let mut shdrs = vec![0; shdrs_size];
+    object.read(&mut shdrs, shoff)?;
+    let class = if E_CLASS == ELFCLASS64 {
+        Class::ELF64
+    } else {
+        Class::ELF32
+    };
+    for shdr in SectionHeaderTable::new(NativeEndian, class, &shdrs).iter() {
+        if shdr.sh_type != SHT_NOBITS {
+            let section_end = shdr.sh_offset.checked_add(shdr.sh_size);
+            end = end.max(section_end.ok_or_else(out_of_bounds)? as usize);
+        }
+    }
+    if object.size().is_some_and(|size| end > size) {
+        return Err(out_of_bounds());
+    }
+    let mut bytes = vec![0; end];
+    object.read(&mut bytes, 0)?;
+    Ok(bytes)
+}
+
+#[inline]
+fn section_group(shdr: &SectionHeader) -> Option<usize> {
+    let flags = shdr.sh_flags as u32;
+    if flags & SHF_ALLOC == 0 {
+        None
+    } else if flags & SHF_EXECINSTR != 0 {
+        Some(TEXT)
+    } else if flags & SHF_WRITE != 0 {
+        Some(DATA)
+    } else {
+        Some(RODATA)
+    }
+}
+
+// 在组的末尾分配一块对齐的内存,返回其在组中的偏移
+#[inline]
+fn place(len: &mut usize, size: u64, align: u64) -> usize {
+    let start = len.next_multiple_of(align.max(1) as usize);
+    *len = start + size as usize;
+    start
+}
+
+// used中标记提供了符号定义的动态库
+fn find_symbol<F>(
+    name: &str,
+    pre_find: &F,
+    scope: &[&RelocatedDylib],
+    used: &mut [bool],
+) -> Option<usize>
+where
+    F: Fn(&str) -> Option<*const ()>,
+{
+    pre_find(name)
+        .or_else(|| {
+            scope.iter().enumerate().find_map(|(idx, lib)| {
+                let sym = lib.symtab().lookup_filter(&SymbolInfo::from_str(name))?;
+                used[idx] = true;
+                let def = SymDef {
+                    sym: Some(sym),
+                    base: lib.base(),
+                    tls: None,
+                };
+                Some(def.convert())
+            })
+        })
+        .map(|addr| addr as usize)
+}
@@ -0,0 +1,217 @@
+//! Symbols of the host process
+//!
+//! An elf object loaded by this crate can be relocated against the libraries that the system
+//! dynamic linker has already loaded into the process, such as the libc of the host, instead of
+//! loading another copy of them. [`host_symbol`] looks up the global scope of the process and can
+//! be used as `pre_find`, while [`HostDylib`] wraps one library opened by `dlopen` so that it can be
+//! put into the scope of the relocation like a `RelocatedDylib`.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{Loader, host::{HostDylib, host_symbol}, mmap::MmapImpl, object::ElfFile};
+//!
+//! let mut loader = Loader::<MmapImpl>::new();
+//! // resolve the undefined symbols in the libraries loaded by the host
+//! let liba = loader
+//!     .easy_load_dylib(ElfFile::from_path("target/liba.so").unwrap())
+//!     .unwrap()
+//!     .easy_relocate([].iter(), &host_symbol)
+//!     .unwrap();
+//! // or only in the libc of the host
+//! let libc = HostDylib::open("libc.so.6").unwrap();
+//! let libb = loader
+//!     .easy_load_dylib(ElfFile::from_path("target/libb.so").unwrap())
+//!     .unwrap()
+//!     .easy_relocate([libc.dylib()].into_iter(), &|_| None)
+//!     .unwrap();
+//! ```
+use crate::{
+    RelocatedDylib, Result,
+    arch::{Dyn, ElfPhdr},
+    io_error,
+};
+use alloc::{ffi::CString, format, vec::Vec};
+use core::{
+    ffi::{CStr, c_int, c_void},
+    mem::ManuallyDrop,
+    ptr::NonNull,
+};
+use elf::abi::*;
+
+/// Looks up `name` in the global scope of the host process by `dlsym(RTLD_DEFAULT, name)`.
+///
+/// It has the signature of `pre_find`, so it can be passed to the relocation functions directly.
+pub fn host_symbol(name: &str) -> Option<*const ()> {
+    let name = CString::new(name).ok()?;
+    let ptr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    (!ptr.is_null()).then_some(ptr as *const ())
+}
+
+/// A dynamic library opened by the system dynamic linker
+///
+/// The library stays open until this value is dropped. Its symbol table is read in place, and
+/// relocating other elf objects against it does not map or relocate the library again.
+pub struct HostDylib {
+    dylib: ManuallyDrop<RelocatedDylib<'static>>,
+    // 动态链接器改写过的dynamic段在还原后的副本
+    _dynamic: Vec<Dyn>,
+    handle: NonNull<c_void>,
+}
+
+struct PhdrInfo {
+    name: CString,
+    bias: usize,
+    phdrs: &'static [ElfPhdr],
+}
+
+impl HostDylib {
+    /// Opens the library `name` with `dlopen(name, RTLD_NOW)`, which loads it if it has not been
+    /// loaded into the process yet.
+    pub fn open(name: &str) -> Result<Self> {
+        let cname = CString::new(name).map_err(|_| io_error("invalid library name"))?;
+        let handle = unsafe { libc::dlopen(cname.as_ptr(), libc::RTLD_NOW) };
+        let Some(handle) = NonNull::new(handle) else {
+            return Err(dlopen_error(name));
+        };
+        // 对同一个库再次dlopen会得到相同的句柄,以此找到它的程序头
+        let info = loaded_objects().into_iter().find(|info| {
+            let other =
+                unsafe { libc::dlopen(info.name.as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD) };
+            if other.is_null() {
+                return false;
+            }
+            unsafe { libc::dlclose(other) };
+            other == handle.as_ptr()
+        });
+        let Some(info) = info else {
+            unsafe { libc::dlclose(handle.as_ptr()) };
+            return Err(io_error(format!("{name} is not found by dl_iterate_phdr")));
+        };
+        let Some(dynamic) = info.phdrs.iter().find(|phdr| phdr.p_type == PT_DYNAMIC) else {
+            unsafe { libc::dlclose(handle.as_ptr()) };
+            return Err(io_error(format!("{name} does not have dynamic")));
+        };
+        let dynamic = unsafe { restore_dynamic(info.bias, dynamic) };
+        let dylib =
+            unsafe { RelocatedDylib::from_phdrs(name, info.bias, info.phdrs, dynamic.as_ptr()) };
+        match dylib {
+            Ok(dylib) => Ok(Self {
+                dylib: ManuallyDrop::new(dylib),
+                _dynamic: dynamic,
+                handle,
+            }),
+            Err(err) => {
+                unsafe { libc::dlclose(handle.as_ptr()) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Gets the library as a dependency which can be used in the scope of the relocation.
+    #[inline]
+    pub fn dylib(&self) -> &RelocatedDylib<'_> {
+        &self.dylib
+    }
+
+    /// Gets the handle returned by `dlopen`.
+    #[inline]
+    pub fn handle(&self) -> NonNull<c_void> {
+        self.handle
+    }
+}
+
+impl Drop for HostDylib {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.dylib);
+            libc::dlclose(self.handle.as_ptr());
+        }
+    }
+}
+
+// 持有的句柄和库都可以在线程间共享
+unsafe impl Send for HostDylib {}
+unsafe impl Sync for HostDylib {}
+
+#[cold]
+#[inline(never)]
+fn dlopen_error(name: &str) -> crate::Error {
+    let msg = unsafe { libc::dlerror() };
+    if msg.is_null() {
+        io_error(format!("dlopen {name} failed"))
+    } else {
+        let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+        io_error(format!("dlopen {name} failed: {msg}"))
+    }
+}
+
+// 在dl_iterate_phdr的回调中调用dlopen可能会死锁,因此先收集所有对象
+fn loaded_objects() -> Vec<PhdrInfo> {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: usize,
+        data: *mut c_void,
+    ) -> c_int {
+        let infos = unsafe { &mut *data.cast::<Vec<PhdrInfo>>() };
+        let info = unsafe { &*info };
+        if info.dlpi_name.is_null() || info.dlpi_phdr.is_null() {
+            return 0;
+        }
+        let name = unsafe { CStr::from_ptr(info.dlpi_name) };
+        // 可执行文件的名字是空字符串
+        if !name.is_empty() {
+            infos.push(PhdrInfo {
+                name: name.into(),
+                bias: info.dlpi_addr as usize,
+                phdrs: unsafe {
+                    core::slice::from_raw_parts(info.dlpi_phdr.cast(), info.dlpi_phnum as usize)
+                },
+            });
+        }
+        0
+    }
+    let mut infos: Vec<PhdrInfo> = Vec::new();
+    unsafe { libc::dl_iterate_phdr(Some(callback), (&raw mut infos).cast()) };
+    infos
+}
+
+// elf库中没有定义DT_RELR
+const DT_RELR: i64 = 36;
+
+/// Copies the dynamic section and turns the addresses written by the dynamic linker back into
+/// offsets from the load bias.
+unsafe fn restore_dynamic(bias: usize, dynamic: &ElfPhdr) -> Vec<Dyn> {
+    // 与glibc的dl_relocate_ld相同: 只有dynamic段可写且架构没有定义DL_RO_DYN_SECTION时才会改写,musl从不改写
+    let relocated = cfg!(all(target_env = "gnu", not(target_arch = "riscv64")))
+        && dynamic.p_flags & PF_W != 0
+        && bias != 0;
+    let mut ptr = bias.wrapping_add(dynamic.p_vaddr as usize) as *const Dyn;
+    let mut dynamic = Vec::new();
+    loop {
+        let mut entry = unsafe { ptr.read() };
+        // glibc的ADJUST_DYN_INFO改写的表项,支持的架构都只使用RELA,因此DT_REL不会被改写
+        if relocated
+            && matches!(
+                entry.d_tag,
+                DT_HASH
+                    | DT_GNU_HASH
+                    | DT_SYMTAB
+                    | DT_STRTAB
+                    | DT_RELA
+                    | DT_RELR
+                    | DT_JMPREL
+                    | DT_PLTGOT
+                    | DT_VERSYM
+            )
+        {
+            entry.d_un = (entry.d_un as usize).wrapping_sub(bias) as _;
+        }
+        let is_null = entry.d_tag == DT_NULL;
+        dynamic.push(entry);
+        if is_null {
+            break;
+        }
+        ptr = unsafe { ptr.add(1) };
+    }
+    dynamic
+}
@@ -0,0 +1,296 @@
+//! Inspection of elf files of any architecture and byte order
+//!
+//! The loader only accepts elf objects built for the host, and reads their headers as native
+//! structures. [`ElfInfo`] instead parses the elf header, the program headers, the dynamic section
+//! and the dynamic symbols with the word size and byte order recorded in the file, so a little
+//! endian host can inspect big endian images and the other way around. Nothing is mapped into
+//! memory, and the addresses are the virtual addresses in the file.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{abi::ELFDATA2MSB, inspect::ElfInfo};
+//!
+//! let bytes = std::fs::read("firmware/libfoo.so").unwrap();
+//! let info = ElfInfo::parse(&bytes).unwrap();
+//! if info.data == ELFDATA2MSB {
+//!     println!("big endian, e_machine {}", info.machine);
+//! }
+//! for symbol in info.symbols.iter().filter(|symbol| symbol.defined) {
+//!     println!("{}: {:#x}", symbol.name, symbol.value);
+//! }
+//! ```
+use crate::{
+    ElfObject, Result,
+    arch::{E_CLASS, E_DATA, EM_ARCH},
+    io_error, parse_ehdr_error,
+};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::ffi::CStr;
+use elf::{
+    ElfBytes,
+    abi::{
+        DT_GNU_HASH, DT_HASH, DT_NEEDED, DT_NULL, DT_SONAME, DT_STRSZ, DT_STRTAB, DT_SYMTAB,
+        ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB, PT_LOAD, SHN_UNDEF,
+    },
+    endian::{AnyEndian, EndianParse},
+    file::Class,
+    string_table::StringTable,
+    symbol::SymbolTable,
+};
+
+pub use elf::segment::ProgramHeader;
+
+/// A dynamic symbol of an inspected elf file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InspectedSymbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    /// The type of the symbol(`STT_*`).
+    pub symtype: u8,
+    /// The binding of the symbol(`STB_*`).
+    pub bind: u8,
+    /// Whether the symbol is defined by the elf file rather than imported.
+    pub defined: bool,
+}
+
+/// The headers and the dynamic section of an elf file of any architecture and byte order
+#[derive(Clone, Debug)]
+pub struct ElfInfo {
+    /// `ELFCLASS32` or `ELFCLASS64`
+    pub class: u8,
+    /// `ELFDATA2LSB` or `ELFDATA2MSB`
+    pub data: u8,
+    pub machine: u16,
+    /// The type of the elf file(`ET_*`).
+    pub elf_type: u16,
+    pub entry: u64,
+    pub phdrs: Vec<ProgramHeader>,
+    /// The entries of the dynamic section without the terminating `DT_NULL`.
+    pub dynamic: Vec<(i64, u64)>,
+    pub soname: Option<String>,
+    pub needed: Vec<String>,
+    /// The symbols in `.dynsym`. Without section headers, they are found by `DT_SYMTAB` and counted
+    /// by `DT_HASH` or `DT_GNU_HASH`.
+    pub symbols: Vec<InspectedSymbol>,
+}
+
+impl ElfInfo {
+    /// Parses the elf file `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let file = ElfBytes::<AnyEndian>::minimal_parse(bytes).map_err(parse_ehdr_error)?;
+        let ehdr = &file.ehdr;
+        let phdrs: Vec<ProgramHeader> = file
+            .segments()
+            .map(|phdrs| phdrs.iter().collect())
+            .unwrap_or_default();
+        let dynamic: Vec<(i64, u64)> = file
+            .dynamic()
+            .map_err(parse_ehdr_error)?
+            .map(|dynamic| {
+                dynamic
+                    .iter()
+                    .take_while(|entry| entry.d_tag != DT_NULL)
+                    .map(|entry| (entry.d_tag, entry.d_val()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut info = ElfInfo {
+            class: match ehdr.class {
+                Class::ELF32 => ELFCLASS32,
+                Class::ELF64 => ELFCLASS64,
+            },
+            data: match ehdr.endianness {
+                AnyEndian::Little => ELFDATA2LSB,
+                AnyEndian::Big => ELFDATA2MSB,
+            },
+            machine: ehdr.e_machine,
+            elf_type: ehdr.e_type,
+            entry: ehdr.e_entry,
+            phdrs,
+            dynamic,
+            soname: None,
+            needed: Vec::new(),
+            symbols: Vec::new(),
+        };
+        info.read_dynamic_strings(bytes)?;
+        info.read_symbols(&file, bytes)?;
+        Ok(info)
+    }
+
+    /// Reads the whole elf object and parses it. The size of the object must be known.
+    pub fn from_object(object: &mut impl ElfObject) -> Result<Self> {
+        if let Some(bytes) = object.as_static_bytes() {
+            return Self::parse(bytes);
+        }
+        let size = object
+            .size()
+            .ok_or_else(|| io_error("the size of the elf object is unknown"))?;
+        let mut bytes = vec![0u8; size];
+        object.read(&mut bytes, 0)?;
+        Self::parse(&bytes)
+    }
+
+    /// Whether the elf file is built for the host, so that it can be loaded by a `Loader`.
+    #[inline]
+    pub fn is_native(&self) -> bool {
+        self.class == E_CLASS && self.data == E_DATA && self.machine == EM_ARCH
+    }
+
+    /// Converts a virtual address to the offset in the elf file by the `PT_LOAD` segments.
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .find(|phdr| vaddr >= phdr.p_vaddr && vaddr - phdr.p_vaddr < phdr.p_filesz)
+            .map(|phdr| vaddr - phdr.p_vaddr + phdr.p_offset)
+    }
+
+    /// Gets the value of the first dynamic entry with `tag`.
+    pub fn dynamic_value(&self, tag: i64) -> Option<u64> {
+        self.dynamic
+            .iter()
+            .find(|(d_tag, _)| *d_tag == tag)
+            .map(|(_, val)| *val)
+    }
+
+    // 虚拟地址通过PT_LOAD转换为文件中的偏移
+    fn file_offset(&self, vaddr: u64, msg: &'static str) -> Result<usize> {
+        self.vaddr_to_offset(vaddr)
+            .and_then(|offset| usize::try_from(offset).ok())
+            .ok_or_else(|| parse_ehdr_error(msg))
+    }
+
+    fn read_symbols(&mut self, file: &ElfBytes<AnyEndian>, bytes: &[u8]) -> Result<()> {
+        let table = match file.dynamic_symbol_table().map_err(parse_ehdr_error)? {
+            Some(table) => Some(table),
+            None => self.dynamic_symbol_table(file.ehdr.endianness, file.ehdr.class, bytes)?,
+        };
+        let Some((symtab, strtab)) = table else {
+            return Ok(());
+        };
+        // 跳过第一个空符号
+        for sym in symtab.iter().skip(1) {
+            self.symbols.push(InspectedSymbol {
+                name: strtab
+                    .get(sym.st_name as usize)
+                    .map_err(parse_ehdr_error)?
+                    .to_string(),
+                value: sym.st_value,
+                size: sym.st_size,
+                symtype: sym.st_symtype(),
+                bind: sym.st_bind(),
+                defined: sym.st_shndx != SHN_UNDEF,
+            });
+        }
+        Ok(())
+    }
+
+    // 没有节头时通过动态段找到符号表,符号的数量来自哈希表
+    fn dynamic_symbol_table<'data>(
+        &self,
+        endian: AnyEndian,
+        class: Class,
+        bytes: &'data [u8],
+    ) -> Result<Option<(SymbolTable<'data, AnyEndian>, StringTable<'data>)>> {
+        let (Some(symtab), Some(strtab)) =
+            (self.dynamic_value(DT_SYMTAB), self.dynamic_value(DT_STRTAB))
+        else {
+            return Ok(None);
+        };
+        let read_u32 = |offset: usize| {
+            endian
+                .parse_u32_at(&mut { offset }, bytes)
+                .map(|val| val as usize)
+                .map_err(parse_ehdr_error)
+        };
+        let count = if let Some(hash) = self.dynamic_value(DT_HASH) {
+            // nchain等于符号的数量
+            read_u32(self.file_offset(hash, "DT_HASH is not in any PT_LOAD segment")? + 4)?
+        } else if let Some(gnu_hash) = self.dynamic_value(DT_GNU_HASH) {
+            let gnu_hash =
+                self.file_offset(gnu_hash, "DT_GNU_HASH is not in any PT_LOAD segment")?;
+            let nbuckets = read_u32(gnu_hash)?;
+            let symoffset = read_u32(gnu_hash + 4)?;
+            let bloom_size = read_u32(gnu_hash + 8)?;
+            let word_size = match class {
+                Class::ELF32 => 4,
+                Class::ELF64 => 8,
+            };
+            let buckets = gnu_hash + 16 + bloom_size * word_size;
+            let chains = buckets + nbuckets * 4;
+            let mut last = 0;
+            for idx in 0..nbuckets {
+                last = last.max(read_u32(buckets + idx * 4)?);
+            }
+            if last < symoffset {
+                symoffset
+            } else {
+                // 沿着最后一个哈希链找到最后一个符号
+                while read_u32(chains + (last - symoffset) * 4)? & 1 == 0 {
+                    last += 1;
+                }
+                last + 1
+            }
+        } else {
+            return Ok(None);
+        };
+        let syment = match class {
+            Class::ELF32 => 16,
+            Class::ELF64 => 24,
+        };
+        let symtab = self.file_offset(symtab, "DT_SYMTAB is not in any PT_LOAD segment")?;
+        let symtab = count
+            .checked_mul(syment)
+            .and_then(|size| bytes.get(symtab..symtab.checked_add(size)?))
+            .ok_or_else(|| parse_ehdr_error("the dynamic symbol table is out of bounds"))?;
+        let strtab = self.file_offset(strtab, "DT_STRTAB is not in any PT_LOAD segment")?;
+        let strsz = self
+            .dynamic_value(DT_STRSZ)
+            .map_or(bytes.len().saturating_sub(strtab), |size| size as usize);
+        let strtab = strtab
+            .checked_add(strsz)
+            .and_then(|end| bytes.get(strtab..end))
+            .ok_or_else(|| parse_ehdr_error("the dynamic string table is out of bounds"))?;
+        Ok(Some((
+            SymbolTable::new(endian, class, symtab),
+            StringTable::new(strtab),
+        )))
+    }
+
+    // DT_STRTAB是虚拟地址,需要通过PT_LOAD转换为文件中的偏移
+    fn read_dynamic_strings(&mut self, bytes: &[u8]) -> Result<()> {
+        let Some(strtab) = self.dynamic_value(DT_STRTAB) else {
+            return Ok(());
+        };
+        let strtab = self
+            .vaddr_to_offset(strtab)
+            .ok_or_else(|| parse_ehdr_error("DT_STRTAB is not in any PT_LOAD segment"))?;
+        let get_str = |off: u64| -> Result<String> {
+            let start = strtab
+                .checked_add(off)
+                .and_then(|start| usize::try_from(start).ok())
+                .filter(|&start| start < bytes.len())
+                .ok_or_else(|| parse_ehdr_error("dynamic string is out of bounds"))?;
+            let s = CStr::from_bytes_until_nul(&bytes[start..])
+                .map_err(|_| parse_ehdr_error("dynamic string is not terminated"))?;
+            Ok(s.to_string_lossy().into_owned())
+        };
+        let mut needed = Vec::new();
+        let mut soname = None;
+        for &(tag, val) in &self.dynamic {
+            match tag {
+                DT_NEEDED => needed.push(get_str(val)?),
+                DT_SONAME => soname = Some(get_str(val)?),
+                _ => {}
+            }
+        }
+        self.needed = needed;
+        self.soname = soname;
+        Ok(())
+    }
+}
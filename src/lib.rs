@@ -31,6 +31,8 @@
 //! ```
 #![no_std]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 #[cfg(not(any(
     target_arch = "x86_64",
@@ -50,21 +52,56 @@ compile_error!("use at least one of libc and syscall");
 compile_error!("only one of use-libc and use-syscall can be used");
 
 pub mod arch;
+pub mod arena;
+pub mod bootstrap;
+#[cfg(feature = "cabi")]
+pub mod cabi;
+#[cfg(feature = "debug")]
+pub mod debug;
 pub mod dynamic;
+pub mod estimate;
+pub mod event;
 mod format;
+#[cfg(all(feature = "std", feature = "use-libc", target_os = "linux"))]
+pub mod host;
+pub mod inspect;
 mod loader;
 mod macros;
 pub mod mmap;
+pub mod namespace;
+mod note;
 pub mod object;
+pub mod plugin;
+pub mod policy;
+pub mod prelink;
+pub mod progress;
+pub mod property;
+pub mod rebind;
+#[cfg(feature = "dl-iterate-phdr")]
+pub mod registry;
 mod relocation;
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub mod run;
+pub mod scope;
+pub mod search;
 pub mod segment;
+pub mod snapshot;
+#[cfg(feature = "stats")]
+pub mod stats;
 mod symbol;
+#[cfg(feature = "std")]
+pub mod symtab;
+pub mod tls;
+pub mod typed;
+mod unique;
+pub mod verify;
 #[cfg(feature = "version")]
 mod version;
 
 use alloc::{
     boxed::Box,
     string::{String, ToString},
+    vec::Vec,
 };
 use core::{
     any::Any,
@@ -75,23 +112,33 @@ use relocation::{ElfRelocation, GLOBAL_SCOPE};
 use segment::ELFRelro;
 
 pub use elf::abi;
-pub use format::dylib::{ElfDylib, RelocatedDylib, Symbol};
+#[cfg(feature = "debug-handle")]
+pub use format::dylib::WeakSymbol;
+pub use format::dylib::{ElfDylib, OwnedSymbol, RelocatedDylib, Symbol, close_all, init_all};
 pub use format::exec::{ElfExec, RelocatedExec};
+pub use format::relocatable::{ElfRelocatableObject, ObjectSymbol};
 pub use format::{CoreComponent, CoreComponentRef, Elf, UserData};
-pub use loader::Loader;
+pub use loader::{GuardPages, Loader, LoaderBuilder, SequentialBase};
+pub use relocation::{
+    BindingMismatch, ChunkedRelocation, PartialRelocation, RelocateAction, RelocateContext,
+    RelocateStatus, UnresolvedSymbol, WriteMode,
+};
+pub use symbol::SymbolBinding;
+#[cfg(feature = "version")]
+pub use version::{VersionDefinition, VersionRequirement};
 
 /// elf_loader error types
 #[derive(Debug)]
 pub enum Error {
-    /// An error occurred while opening or reading or writing elf files.
-    #[cfg(feature = "fs")]
+    /// An error occurred while opening or reading or writing elf files, or reading a block device.
     IOError { msg: String },
     /// An error occurred while memory mapping.
     MmapError { msg: String },
     /// An error occurred during dynamic library relocation.
     RelocateError {
-        msg: String,
-        custom_err: Box<dyn Any>,
+        /// The name of the elf object.
+        lib_name: String,
+        kind: RelocateErrorKind,
     },
     /// An error occurred while parsing dynamic section.
     ParseDynamicError { msg: &'static str },
@@ -102,25 +149,249 @@ pub enum Error {
         msg: String,
         custom_err: Box<dyn Any>,
     },
+    /// A dependency of the elf object is rejected by the soname policy or can not be found.
+    DependencyError {
+        /// The name of the elf object.
+        lib_name: String,
+        /// The rejected `DT_NEEDED` entry.
+        needed: String,
+        msg: String,
+    },
+    /// The elf object requests an executable stack, which is rejected by the exec stack policy.
+    ExecStackDenied {
+        /// The name of the elf object.
+        lib_name: String,
+    },
+    /// The elf object has text relocations, which are rejected by the text relocation policy.
+    TextRelDenied {
+        /// The name of the elf object.
+        lib_name: String,
+    },
+    /// The elf object is rejected by the verifier.
+    VerifyError {
+        /// The name of the elf object.
+        lib_name: String,
+        custom_err: Box<dyn Any>,
+    },
+    /// A relocation cache is malformed or can not be recorded or replayed.
+    CacheError { msg: String },
+    /// A plugin does not export the expected interface.
+    PluginError {
+        /// The name of the plugin.
+        lib_name: String,
+        msg: String,
+    },
+    /// A symbol is missing or does not have the expected type.
+    SymbolError {
+        /// The name of the elf object.
+        lib_name: String,
+        /// The name of the symbol.
+        symbol: String,
+        msg: &'static str,
+    },
+    /// The elf object is built for another architecture(`e_machine`).
+    ArchMismatch { expected: u16, found: u16 },
+    /// The elf object is built for another word size(`EI_CLASS`).
+    ClassMismatch { expected: u8, found: u8 },
+    /// The elf object is built for another byte order(`EI_DATA`).
+    EndianMismatch { expected: u8, found: u8 },
+    /// The program header table described by the elf header is malformed.
+    InvalidPhdrTable {
+        e_phoff: usize,
+        e_phentsize: usize,
+        e_phnum: usize,
+        msg: &'static str,
+    },
+    /// A program header is malformed.
+    InvalidPhdr {
+        /// The index of the program header.
+        index: usize,
+        p_type: u32,
+        msg: &'static str,
+    },
+    /// Two `PT_LOAD` segments overlap, the fields are the indices of their program headers.
+    SegmentOverlap { first: usize, second: usize },
+    /// The dynamic section is not inside any `PT_LOAD` segment.
+    DynamicOutsideLoad { vaddr: usize, size: usize },
+    /// A version required by the elf object(`DT_VERNEED`) is not defined by its dependency.
+    VersionError {
+        /// The name of the elf object.
+        lib_name: String,
+        /// The dependency which should define the version.
+        needed: String,
+        version: String,
+    },
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            #[cfg(feature = "fs")]
             Error::IOError { msg } => write!(f, "{msg}"),
             Error::MmapError { msg } => write!(f, "{msg}"),
-            Error::RelocateError { msg, .. } => write!(f, "{msg}"),
+            Error::RelocateError { lib_name, kind } => match kind {
+                RelocateErrorKind::Unresolved(failures) => {
+                    for (idx, failure) in failures.iter().enumerate() {
+                        if idx != 0 {
+                            writeln!(f)?;
+                        }
+                        write!(f, "file: {lib_name}, relocation type: {}, ", failure.r_type)?;
+                        match &failure.symbol {
+                            Some(name) => write!(f, "symbol name: {name}")?,
+                            None => write!(f, "no symbol")?,
+                        }
+                    }
+                    Ok(())
+                }
+                RelocateErrorKind::InvalidTarget {
+                    table,
+                    idx,
+                    r_type,
+                    r_offset,
+                } => write!(
+                    f,
+                    "file: {lib_name}, {table} relocation [{idx}], relocation type: {r_type}, offset: {r_offset:#x}, the target is not in a writable segment"
+                ),
+                RelocateErrorKind::StaticTls => write!(
+                    f,
+                    "file: {lib_name}, cannot allocate memory in static tls block"
+                ),
+                RelocateErrorKind::Section {
+                    section,
+                    idx,
+                    r_type,
+                    msg,
+                } => write!(
+                    f,
+                    "file: {lib_name}, {section} relocation [{idx}], relocation type: {r_type}, {msg}"
+                ),
+            },
             Error::ParseDynamicError { msg } => write!(f, "{msg}"),
             Error::ParseEhdrError { msg } => write!(f, "{msg}"),
             Error::ParsePhdrError { msg, .. } => write!(f, "{msg}"),
+            Error::DependencyError { msg, .. } => write!(f, "{msg}"),
+            Error::ExecStackDenied { lib_name } => write!(
+                f,
+                "{lib_name} requests an executable stack, which is denied by the policy"
+            ),
+            Error::TextRelDenied { lib_name } => write!(
+                f,
+                "{lib_name} has text relocations, which are denied by the policy"
+            ),
+            Error::VerifyError { lib_name, .. } => {
+                write!(f, "{lib_name} is rejected by the integrity verification")
+            }
+            Error::CacheError { msg } => write!(f, "{msg}"),
+            Error::PluginError { msg, .. } => write!(f, "{msg}"),
+            Error::SymbolError {
+                lib_name,
+                symbol,
+                msg,
+            } => write!(f, "file: {lib_name}, symbol: {symbol}, {msg}"),
+            Error::ArchMismatch { expected, found } => write!(
+                f,
+                "file arch mismatch: expected e_machine {expected}, found {found}"
+            ),
+            Error::ClassMismatch { expected, found } => write!(
+                f,
+                "file class mismatch: expected EI_CLASS {expected}, found {found}"
+            ),
+            Error::EndianMismatch { expected, found } => write!(
+                f,
+                "file endianness mismatch: expected EI_DATA {expected}, found {found}"
+            ),
+            Error::InvalidPhdrTable {
+                e_phoff,
+                e_phentsize,
+                e_phnum,
+                msg,
+            } => write!(
+                f,
+                "invalid program header table (e_phoff {e_phoff:#x}, e_phentsize {e_phentsize}, e_phnum {e_phnum}): {msg}"
+            ),
+            Error::InvalidPhdr { index, p_type, msg } => write!(
+                f,
+                "invalid program header {index} (p_type {p_type:#x}): {msg}"
+            ),
+            Error::SegmentOverlap { first, second } => write!(
+                f,
+                "PT_LOAD segments overlap: program headers {first} and {second}"
+            ),
+            Error::DynamicOutsideLoad { vaddr, size } => write!(
+                f,
+                "dynamic section [{vaddr:#x}, {:#x}) is not inside any PT_LOAD segment",
+                vaddr.wrapping_add(*size)
+            ),
+            Error::VersionError {
+                lib_name,
+                needed,
+                version,
+            } => write!(
+                f,
+                "version `{version}` not found in {needed} (required by {lib_name})"
+            ),
         }
     }
 }
 
 impl core::error::Error for Error {}
 
-#[cfg(feature = "fs")]
+impl Error {
+    /// Gets the names of the symbols that could not be resolved during relocation.
+    ///
+    /// The list can be used to find another library providing the symbols before retrying.
+    pub fn unresolved_symbols(&self) -> Vec<&str> {
+        match self {
+            Error::RelocateError {
+                kind: RelocateErrorKind::Unresolved(failures),
+                ..
+            } => failures
+                .iter()
+                .filter_map(|failure| failure.symbol.as_deref())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The reason of a relocation error
+#[derive(Debug)]
+pub enum RelocateErrorKind {
+    /// Relocation entries that could not be processed, usually because their symbols are not found.
+    /// All such entries of the elf object are collected before the error is returned.
+    Unresolved(Vec<RelocFailure>),
+    /// The target of a relocation entry is not in a writable segment.
+    InvalidTarget {
+        /// The relocation table containing the entry.
+        table: &'static str,
+        /// The index of the entry in the table.
+        idx: usize,
+        r_type: usize,
+        r_offset: usize,
+    },
+    /// There is not enough space in the static tls block.
+    StaticTls,
+    /// A relocation entry of a relocatable object can not be applied.
+    Section {
+        /// The relocation section containing the entry.
+        section: String,
+        /// The index of the entry in the section.
+        idx: usize,
+        r_type: usize,
+        msg: &'static str,
+    },
+}
+
+/// A relocation entry that could not be processed
+#[derive(Debug)]
+pub struct RelocFailure {
+    pub r_type: usize,
+    pub r_offset: usize,
+    /// The name of the symbol referenced by the entry, `None` if the entry has no symbol.
+    pub symbol: Option<String>,
+    /// The error returned by `deal_unknown`.
+    pub custom_err: Box<dyn Any>,
+}
+
 #[cold]
 #[inline(never)]
 fn io_error(msg: impl ToString) -> Error {
@@ -131,10 +402,18 @@ fn io_error(msg: impl ToString) -> Error {
 
 #[cold]
 #[inline(never)]
-fn relocate_error(msg: impl ToString, custom_err: Box<dyn Any>) -> Error {
-    Error::RelocateError {
+fn mmap_error(msg: impl ToString) -> Error {
+    Error::MmapError {
         msg: msg.to_string(),
-        custom_err,
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn relocate_error(lib_name: impl ToString, kind: RelocateErrorKind) -> Error {
+    Error::RelocateError {
+        lib_name: lib_name.to_string(),
+        kind,
     }
 }
 
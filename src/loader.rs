@@ -1,25 +1,37 @@
+#[cfg(feature = "stats")]
+use crate::stats::{Clock, StatsCollector, StatsObject, default_clock};
 use crate::{
-    ElfObject, Result, UserData,
-    arch::{E_CLASS, EHDR_SIZE, EM_ARCH, Ehdr, ElfPhdr, PHDR_SIZE, Phdr},
+    ElfObject, Error, Result, UserData,
+    arch::{E_CLASS, E_DATA, EHDR_SIZE, EM_ARCH, Ehdr, ElfPhdr, PHDR_SIZE, Phdr, exec_stack},
+    arena::{Arena, ArenaVec},
     dynamic::ElfDynamic,
+    event::EventCallback,
     format::InitParams,
     mmap::{self, MapFlags, Mmap, ProtFlags},
-    object::ElfObjectAsync,
+    mmap_error,
+    object::{ElfObjectAsync, OffsetType},
     parse_ehdr_error, parse_phdr_error,
-    segment::{ELFRelro, ElfSegments, MASK, PAGE_SIZE},
+    policy::{ExecStackPolicy, SonamePolicy, TextRelPolicy},
+    property::GnuProperty,
+    relocation::WriteMode,
+    segment::{ELFRelro, ElfSegments, MASK, PAGE_SIZE, TextRel},
+    tls::{ElfTls, ThreadLocal},
+    verify::{Image, Verifier, find_build_id, verify_error},
 };
-use alloc::{borrow::ToOwned, boxed::Box, ffi::CString, format, vec::Vec};
+#[cfg(feature = "stats")]
+use alloc::sync::Arc;
+use alloc::{borrow::ToOwned, boxed::Box, ffi::CString, format, vec, vec::Vec};
 use core::{
     any::Any,
     ffi::{CStr, c_void},
     marker::PhantomData,
     mem::MaybeUninit,
-    ops::Deref,
+    ops::{Deref, Range},
     ptr::NonNull,
 };
 use elf::abi::{
-    EI_CLASS, EI_VERSION, ELFMAGIC, ET_DYN, EV_CURRENT, PT_DYNAMIC, PT_GNU_RELRO, PT_INTERP,
-    PT_LOAD, PT_PHDR,
+    EI_CLASS, EI_DATA, EI_VERSION, ELFMAGIC, ET_DYN, EV_CURRENT, PF_W, PF_X, PN_XNUM, PT_DYNAMIC,
+    PT_GNU_PROPERTY, PT_GNU_RELRO, PT_GNU_STACK, PT_INTERP, PT_LOAD, PT_NOTE, PT_PHDR, PT_TLS,
 };
 
 #[repr(transparent)]
@@ -72,17 +84,66 @@ impl ElfHeader {
     }
 
     pub(crate) fn vaildate(&self) -> Result<()> {
+        self.validate_ident()?;
+        self.validate_phdr_table()
+    }
+
+    // 检查与程序头无关的部分,可重定位目标文件没有程序头
+    pub(crate) fn validate_ident(&self) -> Result<()> {
         if self.e_ident[0..4] != ELFMAGIC {
             return Err(parse_ehdr_error("invalid ELF magic"));
         }
         if self.e_ident[EI_CLASS] != E_CLASS {
-            return Err(parse_ehdr_error("file class mismatch"));
+            return Err(Error::ClassMismatch {
+                expected: E_CLASS,
+                found: self.e_ident[EI_CLASS],
+            });
+        }
+        if self.e_ident[EI_DATA] != E_DATA {
+            return Err(Error::EndianMismatch {
+                expected: E_DATA,
+                found: self.e_ident[EI_DATA],
+            });
         }
         if self.e_ident[EI_VERSION] != EV_CURRENT {
             return Err(parse_ehdr_error("invalid ELF version"));
         }
         if self.e_machine != EM_ARCH {
-            return Err(parse_ehdr_error("file arch mismatch"));
+            return Err(Error::ArchMismatch {
+                expected: EM_ARCH,
+                found: self.e_machine,
+            });
+        }
+        Ok(())
+    }
+
+    fn validate_phdr_table(&self) -> Result<()> {
+        let err = |msg| Error::InvalidPhdrTable {
+            e_phoff: self.e_phoff(),
+            e_phentsize: self.e_phentsize(),
+            e_phnum: self.e_phnum(),
+            msg,
+        };
+        if self.e_phentsize() != PHDR_SIZE {
+            return Err(err("e_phentsize is not the size of a program header"));
+        }
+        // PN_XNUM表示真正的数量保存在第一个section header中,这里不支持
+        if self.e_phnum() == 0 || self.e_phnum() >= PN_XNUM as usize {
+            return Err(err("unsupported number of program headers"));
+        }
+        if self.e_phoff() < EHDR_SIZE {
+            return Err(err("program headers overlap the elf header"));
+        }
+        // 程序头会被直接当作ElfPhdr读取,必须对齐
+        if self.e_phoff() % align_of::<Phdr>() != 0 {
+            return Err(err("program headers are misaligned"));
+        }
+        if self
+            .e_phoff()
+            .checked_add(PHDR_SIZE * self.e_phnum())
+            .is_none()
+        {
+            return Err(err("program headers are out of bounds"));
         }
         Ok(())
     }
@@ -111,6 +172,402 @@ impl ElfHeader {
     }
 }
 
+// 在映射之前检查程序头,避免损坏的文件导致越界访问
+pub(crate) fn validate_phdrs(phdrs: &[ElfPhdr]) -> Result<()> {
+    let mut has_load = false;
+    for (index, phdr) in phdrs.iter().enumerate() {
+        let err = |msg| Error::InvalidPhdr {
+            index,
+            p_type: phdr.p_type,
+            msg,
+        };
+        if phdr.p_vaddr.checked_add(phdr.p_memsz).is_none() {
+            return Err(err("segment wraps around the address space"));
+        }
+        if phdr.p_type != PT_LOAD {
+            continue;
+        }
+        has_load = true;
+        if phdr.p_offset.checked_add(phdr.p_filesz).is_none() {
+            return Err(err("segment is out of the bounds of the file"));
+        }
+        if phdr.p_filesz > phdr.p_memsz {
+            return Err(err("p_filesz is larger than p_memsz"));
+        }
+        let align = phdr.p_align;
+        if align > 1 {
+            if !align.is_power_of_two() {
+                return Err(err("p_align is not a power of two"));
+            }
+            if phdr.p_vaddr % align != phdr.p_offset % align {
+                return Err(err("p_vaddr and p_offset are not congruent modulo p_align"));
+            }
+        }
+        let (start, end) = (phdr.p_vaddr, phdr.p_vaddr + phdr.p_memsz);
+        for (first, other) in phdrs[..index].iter().enumerate() {
+            if other.p_type == PT_LOAD
+                && start < other.p_vaddr + other.p_memsz
+                && other.p_vaddr < end
+            {
+                return Err(Error::SegmentOverlap {
+                    first,
+                    second: index,
+                });
+            }
+        }
+    }
+    if !has_load {
+        return Err(Error::InvalidPhdrTable {
+            e_phoff: 0,
+            e_phentsize: PHDR_SIZE,
+            e_phnum: phdrs.len(),
+            msg: "no PT_LOAD segment",
+        });
+    }
+    for phdr in phdrs.iter().filter(|phdr| phdr.p_type == PT_DYNAMIC) {
+        let (start, end) = (phdr.p_vaddr, phdr.p_vaddr + phdr.p_memsz);
+        if !phdrs.iter().any(|load| {
+            load.p_type == PT_LOAD && load.p_vaddr <= start && end <= load.p_vaddr + load.p_memsz
+        }) {
+            return Err(Error::DynamicOutsideLoad {
+                vaddr: start as usize,
+                size: phdr.p_memsz as usize,
+            });
+        }
+    }
+    Ok(())
+}
+
+// 已知elf对象的大小时检查segment是否越界
+fn check_file_size(phdrs: &[ElfPhdr], size: Option<usize>) -> Result<()> {
+    let Some(size) = size else {
+        return Ok(());
+    };
+    for (index, phdr) in phdrs.iter().enumerate() {
+        if phdr.p_type == PT_LOAD && (phdr.p_offset + phdr.p_filesz) as usize > size {
+            return Err(Error::InvalidPhdr {
+                index,
+                p_type: phdr.p_type,
+                msg: "segment is out of the bounds of the file",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Assigns sequential base addresses to the dynamic libraries loaded by a loader, so that the
+/// memory layout is the same in every run. It is intended for reproducible tests.
+///
+/// The libraries are mapped with `MAP_FIXED_NOREPLACE`, so loading fails instead of silently
+/// using another address if the memory is already in use.
+#[derive(Clone, Debug)]
+pub struct SequentialBase {
+    next: usize,
+    gap: usize,
+}
+
+impl SequentialBase {
+    /// Creates a policy that places the first library at `seed`, which is rounded up to the page size.
+    pub const fn new(seed: usize) -> Self {
+        Self {
+            next: (seed + PAGE_SIZE - 1) & MASK,
+            gap: 0,
+        }
+    }
+
+    /// Leaves `gap` bytes, rounded up to the page size, between two adjacent libraries.
+    pub const fn with_gap(mut self, gap: usize) -> Self {
+        self.gap = (gap + PAGE_SIZE - 1) & MASK;
+        self
+    }
+
+    /// Gets the base address that the next library will be mapped at.
+    #[inline]
+    pub fn peek(&self) -> usize {
+        self.next
+    }
+
+    fn next(&mut self, len: usize) -> usize {
+        let addr = self.next;
+        self.next += ((len + PAGE_SIZE - 1) & MASK) + self.gap;
+        addr
+    }
+}
+
+/// Surrounds each dynamic library loaded by a loader with inaccessible guard pages, so that
+/// overflows out of its memory fault instead of reaching the neighbouring mappings.
+///
+/// The memory of the library and its guard pages is reserved as a whole through
+/// `Mmap::mmap_anonymous`, and the library is then mapped over it with `MAP_FIXED`. The base can
+/// also be randomized within a range of addresses chosen by the caller.
+///
+/// # Examples
+/// ```
+/// use elf_loader::{GuardPages, Loader, mmap::MmapImpl};
+///
+/// fn random() -> usize {
+///     // a random number from the platform
+///     # 42
+/// }
+///
+/// let loader = Loader::<MmapImpl>::builder()
+///     .guard_pages(
+///         GuardPages::new(0x10000)
+///             .with_align(0x200000)
+///             .with_random_base(0x7000_0000_0000..0x7100_0000_0000, random),
+///     )
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct GuardPages {
+    size: usize,
+    align: usize,
+    random_base: Option<RandomBase>,
+}
+
+#[derive(Clone, Debug)]
+struct RandomBase {
+    range: Range<usize>,
+    random: fn() -> usize,
+}
+
+impl GuardPages {
+    /// The number of addresses tried before the random base is given up.
+    const ATTEMPTS: usize = 16;
+
+    /// Puts `size` bytes of guard pages, rounded up to the page size, before and after each library.
+    pub const fn new(size: usize) -> Self {
+        Self {
+            size: (size + PAGE_SIZE - 1) & MASK,
+            align: PAGE_SIZE,
+            random_base: None,
+        }
+    }
+
+    /// Aligns the base of each library to `align`, which must be a power of two.
+    pub const fn with_align(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two());
+        if align > PAGE_SIZE {
+            self.align = align;
+        }
+        self
+    }
+
+    /// Places each library, including its guard pages, at a random address in `range`. `random`
+    /// returns a random number each time it is called.
+    ///
+    /// Loading fails if no free address is found after a few attempts.
+    pub const fn with_random_base(mut self, range: Range<usize>, random: fn() -> usize) -> Self {
+        self.random_base = Some(RandomBase { range, random });
+        self
+    }
+
+    /// Gets the size of the guard pages on each side of a library.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reserves the memory of a library of `len` bytes and its guard pages, and returns the base
+    /// of the library.
+    fn reserve<M: Mmap>(&self, len: usize, hint: Option<usize>) -> Result<usize> {
+        // 多预留的部分用于对齐,之后释放
+        let total = self.size * 2 + len;
+        let reserve_len = total + self.align - PAGE_SIZE;
+        let ptr = match &self.random_base {
+            Some(random) => self.reserve_random::<M>(random, reserve_len)?,
+            None => unsafe {
+                M::mmap_anonymous(
+                    hint.unwrap_or(0),
+                    reserve_len,
+                    ProtFlags::PROT_NONE,
+                    MapFlags::MAP_PRIVATE,
+                )
+            }?,
+        };
+        let start = ptr.as_ptr() as usize;
+        let base = (start + self.size + self.align - 1) & !(self.align - 1);
+        let head = base - self.size - start;
+        let tail = reserve_len - head - total;
+        unsafe {
+            if head != 0 {
+                M::munmap(ptr, head)?;
+            }
+            if tail != 0 {
+                M::munmap(ptr.byte_add(head + total), tail)?;
+            }
+        }
+        Ok(base)
+    }
+
+    fn reserve_random<M: Mmap>(&self, random: &RandomBase, len: usize) -> Result<NonNull<c_void>> {
+        let RandomBase { range, random } = random;
+        let start = range.start.saturating_add(PAGE_SIZE - 1) & MASK;
+        // 范围内至少要放得下一次映射
+        if let Some(slots) = range
+            .end
+            .checked_sub(start)
+            .and_then(|size| size.checked_sub(len))
+            .map(|free| free / PAGE_SIZE)
+        {
+            let flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED_NOREPLACE;
+            for _ in 0..Self::ATTEMPTS {
+                let addr = start + random() % (slots + 1) * PAGE_SIZE;
+                match unsafe { M::mmap_anonymous(addr, len, ProtFlags::PROT_NONE, flags) } {
+                    Ok(ptr) if ptr.as_ptr() as usize == addr => return Ok(ptr),
+                    // 旧的内核会忽略MAP_FIXED_NOREPLACE
+                    Ok(ptr) => unsafe { M::munmap(ptr, len) }?,
+                    Err(_) => {}
+                }
+            }
+        }
+        Err(mmap_error(format!(
+            "no free memory is found in [{:#x}, {:#x}) for the random base",
+            range.start, range.end
+        )))
+    }
+}
+
+/// A builder used to configure a `Loader`, created by `Loader::builder`
+pub struct LoaderBuilder<M, T = ()>
+where
+    M: Mmap,
+    T: ThreadLocal,
+{
+    loader: Loader<M, T>,
+}
+
+impl<M: Mmap, T: ThreadLocal> LoaderBuilder<M, T> {
+    /// Sets whether lazy binding is used when the `lazy_bind` argument of a load function is `None`.
+    /// Elf objects with `DF_BIND_NOW` or `DF_1_NOW` are always bound eagerly.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.loader.lazy_bind = Some(lazy);
+        self
+    }
+
+    /// Makes `PT_GNU_RELRO` read-only even when lazy binding is used. By default it is only
+    /// protected for eagerly bound elf objects. The GOT used by lazy binding must not be in it.
+    pub fn enforce_relro(mut self, enforce: bool) -> Self {
+        self.loader.enforce_relro = enforce;
+        self
+    }
+
+    /// Sets the address which dynamic libraries are preferably mapped at. The `Mmap`
+    /// implementation may map them elsewhere, and it is ignored if `sequential_base` is set.
+    pub fn map_hint(mut self, addr: usize) -> Self {
+        self.loader.map_hint = Some(addr);
+        self
+    }
+
+    /// Rejects elf objects with more than `max` program headers with `Error::InvalidPhdrTable`.
+    pub fn max_phdrs(mut self, max: usize) -> Self {
+        self.loader.max_phdrs = Some(max);
+        self
+    }
+
+    /// See `Loader::set_init_params`.
+    pub fn init_params(mut self, argc: usize, argv: usize, envp: usize) -> Self {
+        self.loader.set_init_params(argc, argv, envp);
+        self
+    }
+
+    /// See `Loader::set_write_mode`.
+    pub fn write_mode(mut self, mode: WriteMode) -> Self {
+        self.loader.set_write_mode(mode);
+        self
+    }
+
+    /// See `Loader::set_binding_report`.
+    pub fn binding_report(mut self, enable: bool) -> Self {
+        self.loader.set_binding_report(enable);
+        self
+    }
+
+    /// See `Loader::set_soname_policy`.
+    pub fn soname_policy(mut self, policy: SonamePolicy) -> Self {
+        self.loader.set_soname_policy(policy);
+        self
+    }
+
+    /// See `Loader::set_exec_stack_policy`.
+    pub fn exec_stack_policy(mut self, policy: ExecStackPolicy) -> Self {
+        self.loader.set_exec_stack_policy(policy);
+        self
+    }
+
+    /// See `Loader::set_textrel_policy`.
+    pub fn textrel_policy(mut self, policy: TextRelPolicy) -> Self {
+        self.loader.set_textrel_policy(policy);
+        self
+    }
+
+    /// See `Loader::set_verifier`.
+    pub fn verifier(mut self, verifier: Verifier) -> Self {
+        self.loader.set_verifier(verifier);
+        self
+    }
+
+    /// See `Loader::set_deferred_init`.
+    pub fn deferred_init(mut self, defer: bool) -> Self {
+        self.loader.set_deferred_init(defer);
+        self
+    }
+
+    /// See `Loader::set_huge_bss_threshold`.
+    pub fn huge_bss_threshold(mut self, len: usize) -> Self {
+        self.loader.set_huge_bss_threshold(len);
+        self
+    }
+
+    /// See `Loader::set_arena`.
+    pub fn arena(mut self, arena: &'static dyn Arena) -> Self {
+        self.loader.set_arena(arena);
+        self
+    }
+
+    /// See `Loader::set_clock`.
+    #[cfg(feature = "stats")]
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.loader.set_clock(clock);
+        self
+    }
+
+    /// See `Loader::set_guard_pages`.
+    pub fn guard_pages(mut self, guard: GuardPages) -> Self {
+        self.loader.set_guard_pages(guard);
+        self
+    }
+
+    /// See `Loader::set_sequential_base`.
+    pub fn sequential_base(mut self, bases: SequentialBase) -> Self {
+        self.loader.set_sequential_base(bases);
+        self
+    }
+
+    /// See `Loader::on_load`.
+    pub fn on_load(mut self, callback: EventCallback) -> Self {
+        self.loader.on_load(callback);
+        self
+    }
+
+    /// See `Loader::on_unload`.
+    pub fn on_unload(mut self, callback: EventCallback) -> Self {
+        self.loader.on_unload(callback);
+        self
+    }
+
+    /// See `Loader::set_hook`.
+    pub fn hook(mut self, hook: Hook<'static>) -> Self {
+        self.loader.set_hook(hook);
+        self
+    }
+
+    /// Creates the loader.
+    pub fn build(self) -> Loader<M, T> {
+        self.loader
+    }
+}
+
 /// This struct is used to specify the offset and length for memory-mapped regions.
 struct MmapRange {
     /// The length of the memory region to be mapped.
@@ -119,18 +576,58 @@ struct MmapRange {
     offset: usize,
 }
 
-struct MmapParam {
+pub(crate) struct MmapParam {
     addr: Option<usize>,
-    len: usize,
+    pub(crate) len: usize,
     prot: ProtFlags,
     flags: MapFlags,
     range: MmapRange,
 }
 
+#[inline]
+fn check_fixed<M: Mmap>(param: &MmapParam, ptr: NonNull<c_void>) -> Result<()> {
+    // 不带MAP_FIXED的地址只是一个提示
+    let fixed = param
+        .flags
+        .intersects(MapFlags::MAP_FIXED | MapFlags::MAP_FIXED_NOREPLACE);
+    match param.addr {
+        Some(addr) if fixed && addr != ptr.as_ptr() as usize => {
+            // 旧的内核会忽略MAP_FIXED_NOREPLACE,此时需要释放映射到其他地址的内存
+            unsafe { M::munmap(ptr, param.len) }?;
+            Err(mmap_error(format!(
+                "the memory is mapped at {:#x} instead of {:#x}",
+                ptr.as_ptr() as usize,
+                addr
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+fn object_fd(offset_type: OffsetType) -> Option<i32> {
+    match offset_type {
+        OffsetType::Fd(fd) => Some(fd),
+        OffsetType::Copy => None,
+    }
+}
+
+/// The contents of a segment which are read into anonymous memory by `copy_segments`
+struct SegmentCopy {
+    offset: usize,
+    dest: NonNull<u8>,
+    len: usize,
+    /// The pages holding the contents, which are protected after the read.
+    pages: NonNull<c_void>,
+    pages_len: usize,
+    prot: ProtFlags,
+}
+
 #[inline(always)]
 fn mmap_segment<M: Mmap>(
     param: &MmapParam,
     object: &mut impl ElfObject,
+    copies: &mut Vec<SegmentCopy>,
 ) -> Result<NonNull<c_void>> {
     let mut need_copy = false;
     let ptr = unsafe {
@@ -140,20 +637,79 @@ fn mmap_segment<M: Mmap>(
             param.prot,
             param.flags,
             param.range.offset,
-            object.as_fd(),
+            object_fd(object.offset_type()),
             &mut need_copy,
         )
     }?;
+    check_fixed::<M>(param, ptr)?;
     if need_copy {
-        unsafe {
-            let dest = core::slice::from_raw_parts_mut(ptr.as_ptr().cast::<u8>(), param.range.len);
-            object.read(dest, param.range.offset)?;
-            M::mprotect(ptr, param.len, param.prot)?;
+        // 内容在copy_segments中读取,其余的页现在就可以设置保护
+        let pages_len = ((param.range.len + PAGE_SIZE - 1) & MASK).min(param.len);
+        if pages_len < param.len {
+            let rest = unsafe { NonNull::new_unchecked(ptr.as_ptr().byte_add(pages_len)) };
+            unsafe { M::mprotect(rest, param.len - pages_len, param.prot) }?;
         }
+        copies.push(SegmentCopy {
+            offset: param.range.offset,
+            dest: ptr.cast(),
+            len: param.range.len,
+            pages: ptr,
+            pages_len,
+            prot: param.prot,
+        });
     }
     Ok(ptr)
 }
 
+/// Reads the contents of the segments mapped by `mmap_segment`. Segments which are consecutive in
+/// the elf object are read by one `read_vectored`, the gaps shorter than a page between them are
+/// read into a scratch buffer.
+fn copy_segments<M: Mmap>(
+    object: &mut impl ElfObject,
+    copies: &mut Vec<SegmentCopy>,
+) -> Result<()> {
+    let mut start = 0;
+    while start < copies.len() {
+        let mut end = start + 1;
+        let mut gaps = 0;
+        while let Some(next) = copies.get(end) {
+            let prev = &copies[end - 1];
+            let prev_end = prev.offset + prev.len;
+            // 缓冲区在文件和内存中都不能重叠
+            if next.offset < prev_end
+                || next.offset - prev_end >= PAGE_SIZE
+                || (next.dest.as_ptr() as usize) < prev.dest.as_ptr() as usize + prev.len
+            {
+                break;
+            }
+            gaps += next.offset - prev_end;
+            end += 1;
+        }
+        let mut scratch = vec![0u8; gaps];
+        let mut rest = scratch.as_mut_slice();
+        let mut bufs: Vec<&mut [u8]> = Vec::with_capacity((end - start) * 2);
+        for idx in start..end {
+            let copy = &copies[idx];
+            if idx != start {
+                let prev = &copies[idx - 1];
+                let (gap, tail) =
+                    core::mem::take(&mut rest).split_at_mut(copy.offset - prev.offset - prev.len);
+                rest = tail;
+                if !gap.is_empty() {
+                    bufs.push(gap);
+                }
+            }
+            bufs.push(unsafe { core::slice::from_raw_parts_mut(copy.dest.as_ptr(), copy.len) });
+        }
+        object.read_vectored(&mut bufs, copies[start].offset)?;
+        start = end;
+    }
+    for copy in copies.drain(..) {
+        unsafe { M::mprotect(copy.pages, copy.pages_len, copy.prot) }?;
+    }
+    Ok(())
+}
+
 #[inline(always)]
 async fn mmap_segment_async<M: Mmap>(
     param: &MmapParam,
@@ -167,10 +723,11 @@ async fn mmap_segment_async<M: Mmap>(
             param.prot,
             param.flags,
             param.range.offset,
-            object.as_fd(),
+            object_fd(object.offset_type()),
             &mut need_copy,
         )
     }?;
+    check_fixed::<M>(param, ptr)?;
     if need_copy {
         let dest =
             unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr().cast::<u8>(), param.range.len) };
@@ -180,8 +737,17 @@ async fn mmap_segment_async<M: Mmap>(
     Ok(ptr)
 }
 
+/// 映射失败时释放预留的内存
+#[cold]
+fn release_guard<M: Mmap>(param: &MmapParam, guard: usize) {
+    if let (Some(addr), true) = (param.addr, guard != 0) {
+        let start = unsafe { NonNull::new_unchecked((addr - guard) as *mut c_void) };
+        let _ = unsafe { M::munmap(start, param.len + guard * 2) };
+    }
+}
+
 #[inline]
-fn create_segments(phdrs: &[ElfPhdr], is_dylib: bool) -> (MmapParam, usize) {
+pub(crate) fn create_segments(phdrs: &[ElfPhdr], is_dylib: bool) -> (MmapParam, usize) {
     let mut min_vaddr = usize::MAX;
     let mut max_vaddr = 0;
     // 最小偏移地址对应内容在文件中的偏移
@@ -216,7 +782,12 @@ fn create_segments(phdrs: &[ElfPhdr], is_dylib: bool) -> (MmapParam, usize) {
             addr: if is_dylib { None } else { Some(min_vaddr) },
             len: total_size,
             prot,
-            flags: mmap::MapFlags::MAP_PRIVATE,
+            // 可执行文件只能被映射到固定的地址上,地址已被占用时加载失败而不是覆盖原有的映射
+            flags: if is_dylib {
+                mmap::MapFlags::MAP_PRIVATE
+            } else {
+                mmap::MapFlags::MAP_PRIVATE | mmap::MapFlags::MAP_FIXED_NOREPLACE
+            },
             range: MmapRange {
                 len: min_filesz,
                 offset: min_off,
@@ -226,14 +797,110 @@ fn create_segments(phdrs: &[ElfPhdr], is_dylib: bool) -> (MmapParam, usize) {
     )
 }
 
+/// Uses the leading read-only segments of a `'static` elf image in place and reserves the memory
+/// of the other segments right after them. It returns the start of the memory and the length used
+/// in place, or `None` if the layout of the image does not allow this.
+fn mmap_in_place<M: Mmap>(
+    bytes: &'static [u8],
+    phdrs: &[ElfPhdr],
+    param: &MmapParam,
+    min_vaddr: usize,
+) -> Result<Option<(NonNull<c_void>, usize)>> {
+    let start = bytes.as_ptr() as usize;
+    let loads = phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD);
+    let Some(first) = loads.clone().next() else {
+        return Ok(None);
+    };
+    // 原地使用的segment在内存中的地址与在文件中的偏移之差必须相同
+    let delta = (first.p_vaddr as usize).wrapping_sub(first.p_offset as usize);
+    if start & !MASK != 0 || delta & !MASK != 0 {
+        return Ok(None);
+    }
+    let base = start.wrapping_sub(delta);
+    let mut end = min_vaddr;
+    let mut count = 0;
+    for phdr in loads.clone() {
+        let vaddr = phdr.p_vaddr as usize;
+        if vaddr & MASK < end {
+            // 与原地使用的segment共用一页
+            return Ok(None);
+        }
+        let page_end = (vaddr + phdr.p_memsz as usize + PAGE_SIZE - 1) & MASK;
+        // 修改权限的页不能超出镜像,否则会改变镜像之后的内存的权限
+        if phdr.p_flags & PF_W != 0
+            || phdr.p_filesz != phdr.p_memsz
+            || vaddr.wrapping_sub(phdr.p_offset as usize) != delta
+            || page_end - delta > bytes.len()
+        {
+            break;
+        }
+        end = page_end;
+        count += 1;
+    }
+    // 其余segment使用的新内存不能与原始数据重叠
+    if count == 0 || base + end < (start + bytes.len() + PAGE_SIZE - 1) & MASK {
+        return Ok(None);
+    }
+    // 先预留其余segment的内存,预留失败时镜像的权限没有被修改
+    let borrowed = end - min_vaddr;
+    let mut reserved = None;
+    if param.len > borrowed {
+        let addr = base + end;
+        let len = param.len - borrowed;
+        let flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED_NOREPLACE;
+        match unsafe { M::mmap_anonymous(addr, len, ProtFlags::PROT_NONE, flags) } {
+            Ok(ptr) if ptr.as_ptr() as usize == addr => reserved = Some((ptr, len)),
+            // 旧的内核会忽略MAP_FIXED_NOREPLACE
+            Ok(ptr) => {
+                unsafe { M::munmap(ptr, len) }?;
+                return Ok(None);
+            }
+            Err(_) => return Ok(None),
+        }
+    }
+    let page_range = |phdr: &ElfPhdr| {
+        let min_vaddr = phdr.p_vaddr as usize & MASK;
+        let max_vaddr = (phdr.p_vaddr as usize + phdr.p_memsz as usize + PAGE_SIZE - 1) & MASK;
+        let addr = unsafe { NonNull::new_unchecked((base + min_vaddr) as *mut c_void) };
+        (addr, max_vaddr - min_vaddr)
+    };
+    // 镜像是只读的'static数据,失败时将已经修改的页恢复为只读并释放预留的内存
+    let rollback = |done: usize| -> Result<()> {
+        for phdr in loads.clone().take(done) {
+            let (addr, len) = page_range(phdr);
+            unsafe { M::mprotect(addr, len, ProtFlags::PROT_READ) }?;
+        }
+        if let Some((ptr, len)) = reserved {
+            unsafe { M::munmap(ptr, len) }?;
+        }
+        Ok(())
+    };
+    for (idx, phdr) in loads.clone().take(count).enumerate() {
+        let (addr, len) = page_range(phdr);
+        match unsafe { M::mmap_in_place(addr, len, ElfSegments::map_prot(phdr.p_flags)) } {
+            Ok(true) => {}
+            Ok(false) => {
+                rollback(idx)?;
+                return Ok(None);
+            }
+            Err(err) => {
+                let _ = rollback(idx);
+                return Err(err);
+            }
+        }
+    }
+    let memory = unsafe { NonNull::new_unchecked((base + min_vaddr) as *mut c_void) };
+    Ok(Some((memory, borrowed)))
+}
+
 #[inline]
 fn load_segment(segments: &ElfSegments, phdr: &Phdr) -> Option<MmapParam> {
     let addr_min = segments.offset();
     let base = segments.base();
     // 映射的起始地址与结束地址都是页对齐的
     let min_vaddr = phdr.p_vaddr as usize & MASK;
-    let max_vaddr = (phdr.p_vaddr as usize + phdr.p_memsz as usize + PAGE_SIZE - 1) & MASK;
-    let memsz = max_vaddr - min_vaddr;
+    // 只映射文件中有内容的页,其余的bss由fill_bss映射为匿名页
+    let file_end = (phdr.p_vaddr as usize + phdr.p_filesz as usize + PAGE_SIZE - 1) & MASK;
     let prot = ElfSegments::map_prot(phdr.p_flags);
     let real_addr = min_vaddr + base;
     let offset = phdr.p_offset as usize & MASK;
@@ -242,10 +909,11 @@ fn load_segment(segments: &ElfSegments, phdr: &Phdr) -> Option<MmapParam> {
     let filesz = phdr.p_filesz as usize + align_len;
     // 这是一个优化，可以减少一次mmap调用。
     // 映射create_segments产生的参数时会将处于最低地址处的segment也映射进去，所以这里不需要在映射它
-    if addr_min != min_vaddr {
+    // 原地使用的segment也不需要再映射
+    if addr_min != min_vaddr && min_vaddr - addr_min >= segments.borrowed {
         Some(MmapParam {
             addr: Some(real_addr),
-            len: memsz,
+            len: file_end - min_vaddr,
             prot,
             flags: mmap::MapFlags::MAP_PRIVATE | mmap::MapFlags::MAP_FIXED,
             range: MmapRange {
@@ -259,9 +927,14 @@ fn load_segment(segments: &ElfSegments, phdr: &Phdr) -> Option<MmapParam> {
 }
 
 #[inline]
-fn fill_bss<M: Mmap>(segments: &mut ElfSegments, phdr: &Phdr) -> Result<()> {
+fn fill_bss<M: Mmap>(
+    segments: &mut ElfSegments,
+    phdr: &Phdr,
+    prot_mask: ProtFlags,
+    huge_threshold: Option<usize>,
+) -> Result<()> {
     if phdr.p_filesz != phdr.p_memsz {
-        let prot = ElfSegments::map_prot(phdr.p_flags);
+        let prot = ElfSegments::map_prot(phdr.p_flags) & prot_mask;
         let max_vaddr = (phdr.p_vaddr as usize + phdr.p_memsz as usize + PAGE_SIZE - 1) & MASK;
         // 用0填充这一页
         let zero_start = (phdr.p_vaddr + phdr.p_filesz) as usize;
@@ -277,30 +950,66 @@ fn fill_bss<M: Mmap>(segments: &mut ElfSegments, phdr: &Phdr) -> Result<()> {
             //如果有剩余的页的话，将其映射为匿名页
             let zero_mmap_addr = segments.base() + zero_end;
             let zero_mmap_len = max_vaddr - zero_end;
-            unsafe {
+            let ptr = unsafe {
                 M::mmap_anonymous(
                     zero_mmap_addr,
                     zero_mmap_len,
                     prot,
                     mmap::MapFlags::MAP_PRIVATE | mmap::MapFlags::MAP_FIXED,
-                )?;
+                )?
+            };
+            if huge_threshold.is_some_and(|threshold| zero_mmap_len >= threshold) {
+                unsafe { M::advise_huge(ptr, zero_mmap_len) }?;
             }
         }
     }
     Ok(())
 }
 
+// 校验通过后恢复可执行segment的权限,并加上gnu property要求的保护
+fn restore_exec<M: Mmap>(
+    segments: &ElfSegments,
+    phdrs: &[ElfPhdr],
+    extra: ProtFlags,
+) -> Result<()> {
+    for phdr in phdrs
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_X != 0)
+    {
+        let min_vaddr = phdr.p_vaddr as usize & MASK;
+        let max_vaddr = (phdr.p_vaddr as usize + phdr.p_memsz as usize + PAGE_SIZE - 1) & MASK;
+        let addr = unsafe { NonNull::new_unchecked((segments.base() + min_vaddr) as *mut c_void) };
+        let prot = ElfSegments::map_prot(phdr.p_flags) | extra;
+        unsafe { M::mprotect(addr, max_vaddr - min_vaddr, prot) }?;
+    }
+    Ok(())
+}
+
 pub(crate) struct Builder {
     pub(crate) phdr_mmap: Option<&'static [ElfPhdr]>,
     pub(crate) name: CString,
     pub(crate) lazy_bind: Option<bool>,
     pub(crate) ehdr: ElfHeader,
     pub(crate) relro: Option<ELFRelro>,
+    pub(crate) textrel: Option<TextRel>,
     pub(crate) dynamic: Option<ElfDynamic>,
     pub(crate) user_data: UserData,
     pub(crate) segments: ElfSegments,
     pub(crate) init_params: Option<InitParams>,
     pub(crate) interp: Option<&'static str>,
+    pub(crate) stack_prot: Option<ProtFlags>,
+    pub(crate) build_id: Option<&'static [u8]>,
+    pub(crate) gnu_property: Option<GnuProperty>,
+    pub(crate) tls: Option<ElfTls>,
+    pub(crate) write_mode: WriteMode,
+    pub(crate) binding_report: bool,
+    pub(crate) enforce_relro: bool,
+    pub(crate) on_load: Option<EventCallback>,
+    pub(crate) on_unload: Option<EventCallback>,
+    pub(crate) defer_init: bool,
+    pub(crate) arena: Option<&'static dyn Arena>,
+    #[cfg(feature = "stats")]
+    pub(crate) stats: Option<Arc<StatsCollector>>,
 }
 
 impl Builder {
@@ -310,6 +1019,8 @@ impl Builder {
         lazy_bind: Option<bool>,
         ehdr: ElfHeader,
         init_params: Option<InitParams>,
+        write_mode: WriteMode,
+        binding_report: bool,
     ) -> Self {
         Self {
             phdr_mmap: None,
@@ -317,11 +1028,25 @@ impl Builder {
             lazy_bind,
             ehdr,
             relro: None,
+            textrel: None,
             dynamic: None,
             segments,
             user_data: UserData::empty(),
             init_params,
             interp: None,
+            stack_prot: None,
+            build_id: None,
+            gnu_property: None,
+            tls: None,
+            write_mode,
+            binding_report,
+            enforce_relro: false,
+            on_load: None,
+            on_unload: None,
+            defer_init: false,
+            arena: None,
+            #[cfg(feature = "stats")]
+            stats: None,
         }
     }
 
@@ -354,6 +1079,19 @@ impl Builder {
                         .get_slice::<ElfPhdr>(phdr.p_vaddr as usize, phdr.p_memsz as usize),
                 );
             }
+            PT_GNU_STACK => self.stack_prot = Some(ElfSegments::map_prot(phdr.p_flags)),
+            PT_NOTE if self.build_id.is_none() => {
+                let notes = self
+                    .segments
+                    .get_slice::<u8>(phdr.p_vaddr as usize, phdr.p_filesz as usize);
+                self.build_id = find_build_id(notes, phdr.p_align as usize);
+            }
+            PT_GNU_PROPERTY => {
+                let notes = self
+                    .segments
+                    .get_slice::<u8>(phdr.p_vaddr as usize, phdr.p_filesz as usize);
+                self.gnu_property = Some(GnuProperty::parse(notes, phdr.p_align as usize));
+            }
             PT_INTERP => {
                 self.interp = Some(unsafe {
                     CStr::from_ptr(self.segments.get_ptr(phdr.p_vaddr as usize))
@@ -369,7 +1107,7 @@ impl Builder {
 
 pub(crate) struct ElfBuf {
     stack_buf: MaybeUninit<[u8; EHDR_SIZE + 12 * PHDR_SIZE]>,
-    heap_buf: Vec<u8>,
+    heap_buf: ArenaVec<u8>,
 }
 
 impl ElfBuf {
@@ -378,7 +1116,7 @@ impl ElfBuf {
     const fn new() -> Self {
         ElfBuf {
             stack_buf: MaybeUninit::uninit(),
-            heap_buf: Vec::new(),
+            heap_buf: ArenaVec::new(None),
         }
     }
 
@@ -406,7 +1144,7 @@ impl ElfBuf {
     }
 
     #[inline]
-    fn heap_buf(&mut self) -> &mut Vec<u8> {
+    fn heap_buf(&mut self) -> &mut ArenaVec<u8> {
         &mut self.heap_buf
     }
 
@@ -438,40 +1176,121 @@ impl ElfBuf {
             object.read(self.heap_buf(), phdr_start)?;
             self.get_phdrs_from_heap()
         };
+        validate_phdrs(phdrs)?;
         Ok(unsafe { core::mem::transmute(phdrs) })
     }
+
+    pub(crate) async fn prepare_ehdr_async(
+        &mut self,
+        object: &mut impl ElfObjectAsync,
+    ) -> Result<ElfHeader> {
+        object.read_async(self.stack_buf(), 0).await?;
+        ElfHeader::new(self.stack_buf()).cloned()
+    }
+
+    pub(crate) async fn prepare_phdr_async<'buf>(
+        &mut self,
+        ehdr: &ElfHeader,
+        object: &mut impl ElfObjectAsync,
+    ) -> Result<&'buf [ElfPhdr]> {
+        let (phdr_start, phdr_end) = ehdr.phdr_range();
+        let phdrs = if let Some(phdrs) = self.get_phdrs_from_stack(phdr_start, phdr_end) {
+            phdrs
+        } else {
+            self.heap_buf().resize(phdr_end - phdr_start, 0);
+            object.read_async(self.heap_buf(), phdr_start).await?;
+            self.get_phdrs_from_heap()
+        };
+        validate_phdrs(phdrs)?;
+        Ok(unsafe { core::mem::transmute::<&[ElfPhdr], &'buf [ElfPhdr]>(phdrs) })
+    }
 }
 
 pub(crate) type Hook<'hook> = Box<
-    dyn Fn(&CStr, &ElfPhdr, &ElfSegments, &mut UserData) -> core::result::Result<(), Box<dyn Any>>,
+    dyn Fn(&CStr, &ElfPhdr, &ElfSegments, &mut UserData) -> core::result::Result<(), Box<dyn Any>>
+        + Send
+        + 'hook,
 >;
 
 /// The elf object loader
-pub struct Loader<M>
+pub struct Loader<M, T = ()>
 where
     M: Mmap,
+    T: ThreadLocal,
 {
     pub(crate) init_params: Option<InitParams>,
     pub(crate) buf: ElfBuf,
-    hook: Option<
-        Box<
-            dyn Fn(
-                &CStr,
-                &ElfPhdr,
-                &ElfSegments,
-                &mut UserData,
-            ) -> core::result::Result<(), Box<dyn Any>>,
-        >,
-    >,
-    _marker: PhantomData<M>,
-}
-
-impl<M: Mmap> Loader<M> {
+    write_mode: WriteMode,
+    binding_report: bool,
+    lazy_bind: Option<bool>,
+    enforce_relro: bool,
+    map_hint: Option<usize>,
+    max_phdrs: Option<usize>,
+    pub(crate) soname_policy: Option<SonamePolicy>,
+    exec_stack_policy: ExecStackPolicy,
+    textrel_policy: TextRelPolicy,
+    sequential_base: Option<SequentialBase>,
+    guard_pages: Option<GuardPages>,
+    hook: Option<Hook<'static>>,
+    verifier: Option<Verifier>,
+    on_load: Option<EventCallback>,
+    on_unload: Option<EventCallback>,
+    defer_init: bool,
+    arena: Option<&'static dyn Arena>,
+    huge_bss: Option<usize>,
+    #[cfg(feature = "stats")]
+    clock: Clock,
+    // 当前正在加载的elf对象的统计数据
+    #[cfg(feature = "stats")]
+    stats: Option<Arc<StatsCollector>>,
+    _marker: PhantomData<(M, T)>,
+}
+
+impl<M: Mmap, T: ThreadLocal> Loader<M, T> {
+    /// Creates a builder used to configure a new loader.
+    ///
+    /// # Examples
+    /// ```
+    /// use elf_loader::{Loader, mmap::MmapImpl};
+    ///
+    /// let loader = Loader::<MmapImpl>::builder()
+    ///     .lazy(false)
+    ///     .enforce_relro(true)
+    ///     .max_phdrs(32)
+    ///     .build();
+    /// ```
+    pub const fn builder() -> LoaderBuilder<M, T> {
+        LoaderBuilder {
+            loader: Self::new(),
+        }
+    }
+
     /// Create a new loader
     pub const fn new() -> Self {
         Self {
             init_params: None,
+            write_mode: WriteMode::Plain,
+            binding_report: false,
+            lazy_bind: None,
+            enforce_relro: false,
+            map_hint: None,
+            max_phdrs: None,
+            soname_policy: None,
+            exec_stack_policy: ExecStackPolicy::Allow,
+            textrel_policy: TextRelPolicy::Allow,
+            sequential_base: None,
+            guard_pages: None,
             hook: None,
+            verifier: None,
+            on_load: None,
+            on_unload: None,
+            defer_init: false,
+            arena: None,
+            huge_bss: None,
+            #[cfg(feature = "stats")]
+            clock: default_clock,
+            #[cfg(feature = "stats")]
+            stats: None,
             buf: ElfBuf::new(),
             _marker: PhantomData,
         }
@@ -482,11 +1301,254 @@ impl<M: Mmap> Loader<M> {
         self.init_params = Some(InitParams { argc, argv, envp });
     }
 
+    /// Sets how relocation results are written to the memory of the elf objects loaded by this loader.
+    /// The default is `WriteMode::Plain`, device-backed or shared memory may need other modes.
+    pub fn set_write_mode(&mut self, mode: WriteMode) {
+        self.write_mode = mode;
+    }
+
+    /// Enables the diagnostic mode comparing lazy and eager binding. When an elf object loaded by
+    /// this loader is relocated with lazy binding, its PLT symbols are also resolved eagerly into a
+    /// scratch table without writing the GOT, and `CoreComponent::binding_report` compares the
+    /// lazily bound addresses against it.
+    pub fn set_binding_report(&mut self, enable: bool) {
+        self.binding_report = enable;
+    }
+
+    /// Sets the policy that the dependencies(`DT_NEEDED`) of the elf objects loaded by this loader must follow.
+    /// Loading an elf object fails with `Error::DependencyError` if one of its dependencies is rejected.
+    pub fn set_soname_policy(&mut self, policy: SonamePolicy) {
+        self.soname_policy = Some(policy);
+    }
+
+    /// Sets the policy for the elf objects which request an executable stack(`PT_GNU_STACK`).
+    /// Loading such an elf object fails with `Error::ExecStackDenied` if it is rejected.
+    pub fn set_exec_stack_policy(&mut self, policy: ExecStackPolicy) {
+        self.exec_stack_policy = policy;
+    }
+
+    /// Sets the policy for the elf objects with text relocations(`DT_TEXTREL`).
+    /// Loading such an elf object fails with `Error::TextRelDenied` if it is rejected.
+    pub fn set_textrel_policy(&mut self, policy: TextRelPolicy) {
+        self.textrel_policy = policy;
+    }
+
+    /// Defers the init functions of the elf objects loaded by this loader. Relocating such an elf
+    /// object no longer calls its init functions, which are called by `init_all` in the order of
+    /// the dependencies instead.
+    pub fn set_deferred_init(&mut self, defer: bool) {
+        self.defer_init = defer;
+    }
+
+    /// Allocates the buffer used to read program headers and the progress of chunked and partial
+    /// relocations from `arena`. The buffer is then freed after each load instead of being reused.
+    ///
+    /// # Note
+    /// Only this transient data is allocated from the arena. The metadata kept by the elf objects,
+    /// such as the names of the needed libraries, the user data and the boxed closures, still uses
+    /// the global allocator.
+    pub fn set_arena(&mut self, arena: &'static dyn Arena) {
+        self.arena = Some(arena);
+        self.buf.heap_buf = ArenaVec::new(self.arena);
+    }
+
+    /// Frees the buffers reused between loads. They are allocated again by the next load.
+    pub fn shrink(&mut self) {
+        self.buf.heap_buf = ArenaVec::new(self.arena);
+    }
+
+    // 使用arena时读取程序头的缓冲区不在两次加载之间保留
+    #[inline]
+    pub(crate) fn release_buf(&mut self) {
+        if self.arena.is_some() {
+            self.shrink();
+        }
+    }
+
+    /// Sets the clock used to measure the durations in the statistics of the elf objects loaded
+    /// by this loader, see `CoreComponent::stats`.
+    #[cfg(feature = "stats")]
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// 开始记录一次加载的统计数据,之后对elf对象的读取都会被记录
+    #[cfg(feature = "stats")]
+    pub(crate) fn track<O>(&mut self, object: O) -> StatsObject<O> {
+        let stats = Arc::new(StatsCollector::new(self.clock));
+        self.stats = Some(stats.clone());
+        StatsObject::new(object, stats)
+    }
+
+    #[cfg(not(feature = "stats"))]
+    #[inline(always)]
+    pub(crate) fn track<O>(&mut self, object: O) -> O {
+        object
+    }
+
+    /// Asks for huge pages through `Mmap::advise_huge` when the anonymous pages mapped for the
+    /// `.bss` of a segment are at least `len` bytes.
+    pub fn set_huge_bss_threshold(&mut self, len: usize) {
+        self.huge_bss = Some(len);
+    }
+
+    /// Makes the loader map dynamic libraries at deterministic addresses.
+    pub fn set_sequential_base(&mut self, bases: SequentialBase) {
+        self.sequential_base = Some(bases);
+    }
+
+    /// Surrounds the dynamic libraries loaded by this loader with guard pages. It is not used for
+    /// the libraries placed by `set_sequential_base`.
+    pub fn set_guard_pages(&mut self, guard: GuardPages) {
+        self.guard_pages = Some(guard);
+    }
+
+    #[inline]
+    fn assign_base(&mut self, param: &mut MmapParam, is_dylib: bool) {
+        if let (true, Some(bases)) = (is_dylib, &mut self.sequential_base) {
+            param.addr = Some(bases.next(param.len));
+            param.flags |= MapFlags::MAP_FIXED_NOREPLACE;
+        } else if let (true, Some(hint)) = (is_dylib, self.map_hint) {
+            param.addr = Some(hint & MASK);
+        }
+    }
+
+    /// 为动态库及其保护页预留内存,返回保护页的大小
+    fn reserve_guard(&self, param: &mut MmapParam, is_dylib: bool) -> Result<usize> {
+        match &self.guard_pages {
+            Some(guard) if is_dylib && self.sequential_base.is_none() => {
+                param.addr = Some(guard.reserve::<M>(param.len, param.addr)?);
+                param.flags |= MapFlags::MAP_FIXED;
+                Ok(guard.size)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    #[inline]
+    fn check_phnum(&self, ehdr: &ElfHeader) -> Result<()> {
+        match self.max_phdrs {
+            Some(max) if ehdr.e_phnum() > max => Err(Error::InvalidPhdrTable {
+                e_phoff: ehdr.e_phoff(),
+                e_phentsize: ehdr.e_phentsize(),
+                e_phnum: ehdr.e_phnum(),
+                msg: "too many program headers",
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_needed(&self, builder: &Builder) -> Result<()> {
+        let (Some(policy), Some(dynamic)) = (&self.soname_policy, &builder.dynamic) else {
+            return Ok(());
+        };
+        let lib_name = builder.name.to_string_lossy();
+        for needed in dynamic.needed_libs.iter() {
+            let needed = unsafe { CStr::from_ptr((dynamic.strtab + needed.get()) as _) };
+            policy.check(&lib_name, &needed.to_string_lossy())?;
+        }
+        Ok(())
+    }
+
+    fn check_exec_stack(&self, builder: &Builder) -> Result<()> {
+        self.exec_stack_policy.check(
+            &builder.name.to_string_lossy(),
+            exec_stack(builder.stack_prot),
+        )
+    }
+
+    // 有文本重定位的elf对象在重定位期间需要使只读段可写
+    fn check_textrel(&self, builder: &mut Builder, phdrs: &[ElfPhdr]) -> Result<()> {
+        let textrel = matches!(&builder.dynamic, Some(dynamic) if dynamic.textrel);
+        self.textrel_policy
+            .check(&builder.name.to_string_lossy(), textrel)?;
+        if textrel {
+            let exec_prot = Self::exec_prot(builder);
+            builder.textrel = Some(TextRel::new::<M>(phdrs, builder.segments.base(), exec_prot));
+        }
+        Ok(())
+    }
+
     /// `hook` functions are called first when a program header is processed
-    pub fn set_hook(&mut self, hook: Hook) {
+    pub fn set_hook(&mut self, hook: Hook<'static>) {
         self.hook = Some(hook)
     }
 
+    /// Sets the function verifying the integrity of the elf objects, see the `verify` module.
+    /// Loading an elf object fails with `Error::VerifyError` if it is rejected.
+    pub fn set_verifier(&mut self, verifier: Verifier) {
+        self.verifier = Some(verifier)
+    }
+
+    /// Sets the function called when an elf object loaded by this loader is relocated, right before
+    /// its initialization functions are called.
+    ///
+    /// # Examples
+    /// ```
+    /// use elf_loader::{Loader, event::LoadEvent, mmap::MmapImpl};
+    ///
+    /// fn on_load(event: &LoadEvent) {
+    ///     for segment in event.segments() {
+    ///         let _ = (event.name(), segment.page_range());
+    ///     }
+    /// }
+    ///
+    /// let mut loader = Loader::<MmapImpl>::new();
+    /// loader.on_load(on_load);
+    /// ```
+    pub fn on_load(&mut self, callback: EventCallback) {
+        self.on_load = Some(callback)
+    }
+
+    /// Sets the function called when an elf object relocated after being loaded by this loader is
+    /// dropped, after its finalization functions are called and before it is unmapped. It is also
+    /// called for elf objects whose deferred initialization never ran.
+    pub fn on_unload(&mut self, callback: EventCallback) {
+        self.on_unload = Some(callback)
+    }
+
+    // 在校验通过之前segment都不可执行
+    #[inline]
+    fn prot_mask(&self) -> ProtFlags {
+        if self.verifier.is_some() {
+            ProtFlags::all().difference(ProtFlags::PROT_EXEC)
+        } else {
+            ProtFlags::all()
+        }
+    }
+
+    fn verify(&self, builder: &Builder, phdrs: &[ElfPhdr]) -> Result<()> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(());
+        };
+        let image = Image {
+            name: &builder.name,
+            phdrs,
+            segments: &builder.segments,
+            build_id: builder.build_id,
+        };
+        verifier(&image).map_err(|err| verify_error(&builder.name, err))
+    }
+
+    // 可执行段额外需要的权限
+    fn exec_prot(builder: &Builder) -> ProtFlags {
+        builder
+            .gnu_property
+            .map_or(ProtFlags::PROT_NONE, |property| {
+                M::exec_prot(&property, property.prot_hint())
+            })
+    }
+
+    fn protect_exec(&self, builder: &Builder, phdrs: &[ElfPhdr]) -> Result<()> {
+        // 段的内容可能是通过读取写入内存的
+        builder.segments.flush_icache(phdrs, false);
+        let extra = Self::exec_prot(builder);
+        if self.verifier.is_some() || !extra.is_empty() {
+            restore_exec::<M>(&builder.segments, phdrs, extra)?;
+        }
+        Ok(())
+    }
+
     pub fn read_ehdr(&mut self, object: &mut impl ElfObject) -> Result<ElfHeader> {
         let buf = &mut self.buf.stack_buf()[0..EHDR_SIZE];
         object.read(buf, 0)?;
@@ -501,7 +1563,9 @@ impl<M: Mmap> Loader<M> {
         let (phdr_start, phdr_end) = ehdr.phdr_range();
         self.buf.heap_buf().resize(phdr_end - phdr_start, 0);
         object.read(self.buf.heap_buf(), phdr_start)?;
-        Ok(self.buf.get_phdrs_from_heap())
+        let phdrs = self.buf.get_phdrs_from_heap();
+        validate_phdrs(phdrs)?;
+        Ok(phdrs)
     }
 
     pub(crate) fn load_impl(
@@ -511,14 +1575,48 @@ impl<M: Mmap> Loader<M> {
         lazy_bind: Option<bool>,
     ) -> Result<(Builder, &[ElfPhdr])> {
         let init_params = self.init_params;
+        let lazy_bind = lazy_bind.or(self.lazy_bind);
+        #[cfg(feature = "stats")]
+        let stats = self
+            .stats
+            .take()
+            .unwrap_or_else(|| Arc::new(StatsCollector::new(self.clock)));
+        self.check_phnum(&ehdr)?;
         let phdrs = self.buf.prepare_phdr(&ehdr, &mut object)?;
+        check_file_size(phdrs, object.size())?;
         // 创建加载动态库所需的空间，并同时映射min_vaddr对应的segment
-        let (param, min_vaddr) = create_segments(&phdrs, ehdr.is_dylib());
-        let memory = mmap_segment::<M>(&param, &mut object)?;
+        let (mut param, min_vaddr) = create_segments(&phdrs, ehdr.is_dylib());
+        self.assign_base(&mut param, ehdr.is_dylib());
+        let guard = self.reserve_guard(&mut param, ehdr.is_dylib())?;
+        let prot_mask = self.prot_mask();
+        param.prot &= prot_mask;
+        let in_place = match object.as_static_bytes() {
+            // 只有地址由mmap决定的动态库才能原地使用,需要校验时不使用原地映射
+            Some(bytes) if param.addr.is_none() && self.verifier.is_none() => {
+                mmap_in_place::<M>(bytes, phdrs, &param, min_vaddr)?
+            }
+            _ => None,
+        };
+        // 需要读入内存的segment在被使用之前一起读取
+        let mut copies = Vec::new();
+        let (memory, borrowed) = match in_place {
+            Some(in_place) => in_place,
+            None => {
+                #[cfg(feature = "stats")]
+                let start = stats.now();
+                let memory = mmap_segment::<M>(&param, &mut object, &mut copies);
+                #[cfg(feature = "stats")]
+                stats.record_mmap(start);
+                let memory = memory.inspect_err(|_| release_guard::<M>(&param, guard))?;
+                (memory, 0)
+            }
+        };
         let segments = ElfSegments {
             memory,
             offset: min_vaddr,
             len: param.len,
+            borrowed,
+            guard,
             munmap: M::munmap,
         };
         let mut builder = Builder::new(
@@ -527,23 +1625,55 @@ impl<M: Mmap> Loader<M> {
             lazy_bind,
             ehdr,
             init_params,
+            self.write_mode,
+            self.binding_report,
         );
+        builder.enforce_relro = self.enforce_relro;
+        builder.on_load = self.on_load;
+        builder.on_unload = self.on_unload;
+        builder.defer_init = self.defer_init;
+        builder.arena = self.arena;
         // 根据Phdr的类型进行不同操作
         for phdr in phdrs.iter() {
+            // 其他phdr的处理可能会读取segment的内容
+            let reads_memory =
+                !matches!(phdr.p_type, PT_LOAD | PT_PHDR | PT_GNU_RELRO | PT_GNU_STACK);
+            if !copies.is_empty() && (self.hook.is_some() || reads_memory) {
+                copy_segments::<M>(&mut object, &mut copies)?;
+            }
             if let Some(hook) = &self.hook {
                 builder.exec_hook(hook, phdr)?;
             }
             match phdr.p_type {
                 // 将segment加载到内存中
                 PT_LOAD => {
-                    if let Some(param) = load_segment(&builder.segments, phdr) {
-                        mmap_segment::<M>(&param, &mut object)?;
-                        fill_bss::<M>(&mut builder.segments, phdr)?;
+                    if let Some(mut param) = load_segment(&builder.segments, phdr) {
+                        param.prot &= prot_mask;
+                        // 只有bss的段没有需要从文件映射的页
+                        if param.len != 0 {
+                            #[cfg(feature = "stats")]
+                            let start = stats.now();
+                            mmap_segment::<M>(&param, &mut object, &mut copies)?;
+                            #[cfg(feature = "stats")]
+                            stats.record_mmap(start);
+                        }
+                        fill_bss::<M>(&mut builder.segments, phdr, prot_mask, self.huge_bss)?;
                     }
                 }
+                PT_TLS => builder.tls = ElfTls::new::<T>(phdr, builder.segments.base()),
                 _ => builder.parse_other_phdr::<M>(phdr)?,
             }
         }
+        copy_segments::<M>(&mut object, &mut copies)?;
+        #[cfg(feature = "stats")]
+        {
+            builder.stats = Some(stats);
+        }
+        self.check_needed(&builder)?;
+        self.check_exec_stack(&builder)?;
+        self.check_textrel(&mut builder, phdrs)?;
+        self.verify(&builder, phdrs)?;
+        self.protect_exec(&builder, phdrs)?;
         Ok((builder, phdrs))
     }
 
@@ -554,14 +1684,46 @@ impl<M: Mmap> Loader<M> {
         lazy_bind: Option<bool>,
     ) -> Result<(Builder, &[ElfPhdr])> {
         let init_params = self.init_params;
-        let phdrs = self.buf.prepare_phdr(&ehdr, &mut object)?;
+        let lazy_bind = lazy_bind.or(self.lazy_bind);
+        #[cfg(feature = "stats")]
+        let stats = self
+            .stats
+            .take()
+            .unwrap_or_else(|| Arc::new(StatsCollector::new(self.clock)));
+        self.check_phnum(&ehdr)?;
+        let phdrs = self.buf.prepare_phdr_async(&ehdr, &mut object).await?;
+        check_file_size(phdrs, object.size())?;
         // 创建加载动态库所需的空间，并同时映射min_vaddr对应的segment
-        let (param, min_vaddr) = create_segments(&phdrs, ehdr.is_dylib());
-        let memory = mmap_segment_async::<M>(&param, &mut object).await?;
+        let (mut param, min_vaddr) = create_segments(&phdrs, ehdr.is_dylib());
+        self.assign_base(&mut param, ehdr.is_dylib());
+        let guard = self.reserve_guard(&mut param, ehdr.is_dylib())?;
+        let prot_mask = self.prot_mask();
+        param.prot &= prot_mask;
+        let in_place = match object.as_static_bytes() {
+            // 只有地址由mmap决定的动态库才能原地使用,需要校验时不使用原地映射
+            Some(bytes) if param.addr.is_none() && self.verifier.is_none() => {
+                mmap_in_place::<M>(bytes, phdrs, &param, min_vaddr)?
+            }
+            _ => None,
+        };
+        let (memory, borrowed) = match in_place {
+            Some(in_place) => in_place,
+            None => {
+                #[cfg(feature = "stats")]
+                let start = stats.now();
+                let memory = mmap_segment_async::<M>(&param, &mut object).await;
+                #[cfg(feature = "stats")]
+                stats.record_mmap(start);
+                let memory = memory.inspect_err(|_| release_guard::<M>(&param, guard))?;
+                (memory, 0)
+            }
+        };
         let segments = ElfSegments {
             memory,
             offset: min_vaddr,
             len: param.len,
+            borrowed,
+            guard,
             munmap: M::munmap,
         };
         let mut builder = Builder::new(
@@ -570,7 +1732,14 @@ impl<M: Mmap> Loader<M> {
             lazy_bind,
             ehdr,
             init_params,
+            self.write_mode,
+            self.binding_report,
         );
+        builder.enforce_relro = self.enforce_relro;
+        builder.on_load = self.on_load;
+        builder.on_unload = self.on_unload;
+        builder.defer_init = self.defer_init;
+        builder.arena = self.arena;
         // 根据Phdr的类型进行不同操作
         for phdr in phdrs.iter() {
             if let Some(hook) = self.hook.as_ref() {
@@ -579,14 +1748,31 @@ impl<M: Mmap> Loader<M> {
             match phdr.p_type {
                 // 将segment加载到内存中
                 PT_LOAD => {
-                    if let Some(param) = load_segment(&builder.segments, phdr) {
-                        mmap_segment_async::<M>(&param, &mut object).await?;
-                        fill_bss::<M>(&mut builder.segments, phdr)?;
+                    if let Some(mut param) = load_segment(&builder.segments, phdr) {
+                        param.prot &= prot_mask;
+                        if param.len != 0 {
+                            #[cfg(feature = "stats")]
+                            let start = stats.now();
+                            mmap_segment_async::<M>(&param, &mut object).await?;
+                            #[cfg(feature = "stats")]
+                            stats.record_mmap(start);
+                        }
+                        fill_bss::<M>(&mut builder.segments, phdr, prot_mask, self.huge_bss)?;
                     }
                 }
+                PT_TLS => builder.tls = ElfTls::new::<T>(phdr, builder.segments.base()),
                 _ => builder.parse_other_phdr::<M>(phdr)?,
             }
         }
+        #[cfg(feature = "stats")]
+        {
+            builder.stats = Some(stats);
+        }
+        self.check_needed(&builder)?;
+        self.check_exec_stack(&builder)?;
+        self.check_textrel(&mut builder, phdrs)?;
+        self.verify(&builder, phdrs)?;
+        self.protect_exec(&builder, phdrs)?;
         Ok((builder, phdrs))
     }
 }
@@ -133,3 +133,102 @@ mod imp {
         };
     }
 }
+
+/// Defines a struct of typed symbols, which are looked up and checked all at once.
+///
+/// Each field is a symbol named after the field, and its type must implement
+/// `typed::SymbolType`, such as function pointers, raw pointers to variables or `Option` of them
+/// for optional symbols. The generated `load` function fails with `Error::SymbolError` if a
+/// symbol is missing or is not a function or a large enough variable as the type requires. The
+/// struct keeps the library loaded and, like `OwnedSymbol`, can't outlive the libraries it was
+/// relocated against, so the symbols can be used without further `unsafe`.
+///
+/// # Examples
+/// ```no_run
+/// use elf_loader::{define_symbols, load_dylib};
+///
+/// define_symbols! {
+///     /// The interface of liba.so
+///     pub struct LibA {
+///         pub a: extern "C" fn() -> i32,
+///         pub HELLO: *const u8,
+///         pub optional: Option<extern "C" fn(i32)>,
+///     }
+/// }
+///
+/// let liba = load_dylib!("target/liba.so")
+///     .unwrap()
+///     .easy_relocate([].iter(), &|_| None)
+///     .unwrap();
+/// let liba = unsafe { LibA::load(&liba) }.unwrap();
+/// assert_eq!((liba.a)(), 1);
+/// ```
+/// The symbols of a library can't outlive the libraries it depends on:
+/// ```compile_fail
+/// use elf_loader::{define_symbols, load_dylib};
+///
+/// define_symbols! {
+///     struct LibB {
+///         b: extern "C" fn() -> i32,
+///     }
+/// }
+///
+/// let libb = {
+///     let liba = load_dylib!("target/liba.so")
+///         .unwrap()
+///         .easy_relocate([].iter(), &|_| None)
+///         .unwrap();
+///     let libb = load_dylib!("target/libb.so")
+///         .unwrap()
+///         .easy_relocate([&liba].into_iter(), &|_| None)
+///         .unwrap();
+///     unsafe { LibB::load(&libb) }.unwrap()
+/// };
+/// assert_eq!((libb.b)(), 2);
+/// ```
+#[macro_export]
+macro_rules! define_symbols {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        $vis struct $name<'scope> {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $ty,
+            )*
+            __lib: $crate::RelocatedDylib<'scope>,
+        }
+
+        impl<'scope> $name<'scope> {
+            /// Looks up all the symbols in `lib` and checks their types.
+            ///
+            /// # Safety
+            /// The types of the fields must match the symbols, for example the signatures of the
+            /// functions.
+            $vis unsafe fn load(lib: &$crate::RelocatedDylib<'scope>) -> $crate::Result<Self> {
+                Ok(Self {
+                    $(
+                        $field: unsafe {
+                            $crate::typed::lookup::<$ty>(lib, stringify!($field))
+                        }?,
+                    )*
+                    __lib: lib.clone(),
+                })
+            }
+
+            /// Gets the library that the symbols come from.
+            #[inline]
+            $vis fn lib(&self) -> &$crate::RelocatedDylib<'scope> {
+                &self.__lib
+            }
+        }
+    };
+}
@@ -0,0 +1,155 @@
+use super::{MapFlags, Mmap, ProtFlags};
+use crate::{Error, Result, property::GnuProperty};
+use alloc::format;
+use core::{ffi::c_void, marker::PhantomData, ptr::NonNull};
+
+/// The kind of a memory mapping operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapKind {
+    /// Mapping a segment of the elf object, see `Mmap::mmap`.
+    Segment,
+    /// Mapping zeroed memory, see `Mmap::mmap_anonymous`.
+    Anonymous,
+    /// Changing the protection of mapped memory, see `Mmap::mprotect`.
+    Protect,
+    /// Using memory of an elf image in place, see `Mmap::mmap_in_place`.
+    InPlace,
+}
+
+/// A memory mapping operation requested by the loader
+pub struct MapRequest {
+    /// The kind of the operation.
+    pub kind: MapKind,
+    /// The address of the memory region. It is `None` when the loader lets the `Mmap` implementation choose it.
+    pub addr: Option<usize>,
+    /// The length of the memory region.
+    pub len: usize,
+    /// The requested protection.
+    pub prot: ProtFlags,
+    /// The requested flags. The policy can change them, for example to add `MAP_FIXED_NOREPLACE`.
+    /// They are ignored for `MapKind::Protect` and `MapKind::InPlace`.
+    pub flags: MapFlags,
+    /// The offset of the segment in the elf object. It is `None` unless the kind is `MapKind::Segment`.
+    pub offset: Option<usize>,
+}
+
+/// A trait representing the policy used by `AuditedMmap` to check memory mapping operations.
+pub trait MmapPolicy {
+    /// This function is called before every memory mapping operation. It can log or adjust the
+    /// request, or reject it by returning an error, which usually should be `Error::MmapError`.
+    fn check(request: &mut MapRequest) -> Result<()>;
+}
+
+/// A policy which rejects memory that is both writable and executable.
+pub struct WxorX;
+
+impl MmapPolicy for WxorX {
+    fn check(request: &mut MapRequest) -> Result<()> {
+        if request
+            .prot
+            .contains(ProtFlags::PROT_WRITE | ProtFlags::PROT_EXEC)
+        {
+            return Err(Error::MmapError {
+                msg: format!(
+                    "W^X violation: {:?} mapping at {:#x?} with len {:#x} is writable and executable",
+                    request.kind, request.addr, request.len
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// An implementation of Mmap trait which checks every operation with the policy `P` before
+/// passing it to the underlying implementation `M`.
+///
+/// # Examples
+/// ```
+/// use elf_loader::{Loader, mmap::{AuditedMmap, MmapImpl, WxorX}};
+///
+/// let loader = Loader::<AuditedMmap<MmapImpl, WxorX>>::new();
+/// ```
+pub struct AuditedMmap<M: Mmap, P: MmapPolicy> {
+    _marker: PhantomData<(M, P)>,
+}
+
+impl<M: Mmap, P: MmapPolicy> Mmap for AuditedMmap<M, P> {
+    unsafe fn mmap(
+        addr: Option<usize>,
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+        offset: usize,
+        fd: Option<i32>,
+        need_copy: &mut bool,
+    ) -> Result<NonNull<c_void>> {
+        let mut request = MapRequest {
+            kind: MapKind::Segment,
+            addr,
+            len,
+            prot,
+            flags,
+            offset: Some(offset),
+        };
+        P::check(&mut request)?;
+        unsafe { M::mmap(addr, len, prot, request.flags, offset, fd, need_copy) }
+    }
+
+    unsafe fn mmap_anonymous(
+        addr: usize,
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+    ) -> Result<NonNull<c_void>> {
+        let mut request = MapRequest {
+            kind: MapKind::Anonymous,
+            addr: Some(addr),
+            len,
+            prot,
+            flags,
+            offset: None,
+        };
+        P::check(&mut request)?;
+        unsafe { M::mmap_anonymous(addr, len, prot, request.flags) }
+    }
+
+    unsafe fn munmap(addr: NonNull<c_void>, len: usize) -> Result<()> {
+        unsafe { M::munmap(addr, len) }
+    }
+
+    unsafe fn mprotect(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> Result<()> {
+        let mut request = MapRequest {
+            kind: MapKind::Protect,
+            addr: Some(addr.as_ptr() as usize),
+            len,
+            prot,
+            flags: MapFlags::empty(),
+            offset: None,
+        };
+        P::check(&mut request)?;
+        unsafe { M::mprotect(addr, len, prot) }
+    }
+
+    unsafe fn mmap_in_place(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> Result<bool> {
+        let mut request = MapRequest {
+            kind: MapKind::InPlace,
+            addr: Some(addr.as_ptr() as usize),
+            len,
+            prot,
+            flags: MapFlags::empty(),
+            offset: None,
+        };
+        P::check(&mut request)?;
+        unsafe { M::mmap_in_place(addr, len, prot) }
+    }
+
+    #[inline]
+    unsafe fn advise_huge(addr: NonNull<c_void>, len: usize) -> Result<()> {
+        unsafe { M::advise_huge(addr, len) }
+    }
+
+    #[inline]
+    fn exec_prot(property: &GnuProperty, hint: ProtFlags) -> ProtFlags {
+        M::exec_prot(property, hint)
+    }
+}
@@ -0,0 +1,51 @@
+use super::{MapFlags, Mmap, ProtFlags, no_mmap::MmapFromAlloc};
+use crate::arch::flush_icache;
+use core::{ffi::c_void, ptr::NonNull};
+
+/// An implementation of Mmap trait for bare-metal aarch64.
+///
+/// Like `MmapFromAlloc`, the segments are copied into memory obtained from the global allocator
+/// and page protection is not supported. The instructions are written through the data cache,
+/// which is not coherent with the instruction cache on aarch64, so the cache lines of a region
+/// are cleaned and invalidated whenever it becomes executable.
+pub struct MmapBareMetal;
+
+impl Mmap for MmapBareMetal {
+    unsafe fn mmap(
+        addr: Option<usize>,
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+        offset: usize,
+        fd: Option<i32>,
+        need_copy: &mut bool,
+    ) -> crate::Result<NonNull<c_void>> {
+        unsafe { MmapFromAlloc::mmap(addr, len, prot, flags, offset, fd, need_copy) }
+    }
+
+    unsafe fn mmap_anonymous(
+        addr: usize,
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+    ) -> crate::Result<NonNull<c_void>> {
+        let ptr = unsafe { MmapFromAlloc::mmap_anonymous(addr, len, prot, flags) }?;
+        // 清零的内存可能是可执行的
+        if prot.contains(ProtFlags::PROT_EXEC) {
+            flush_icache(addr..addr + len);
+        }
+        Ok(ptr)
+    }
+
+    unsafe fn munmap(addr: NonNull<c_void>, len: usize) -> crate::Result<()> {
+        unsafe { MmapFromAlloc::munmap(addr, len) }
+    }
+
+    unsafe fn mprotect(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> crate::Result<()> {
+        if prot.contains(ProtFlags::PROT_EXEC) {
+            let start = addr.as_ptr() as usize;
+            flush_icache(start..start + len);
+        }
+        Ok(())
+    }
+}
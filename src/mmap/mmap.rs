@@ -87,6 +87,25 @@ mod imp {
             }
             Ok(())
         }
+
+        unsafe fn mmap_in_place(
+            addr: core::ptr::NonNull<core::ffi::c_void>,
+            len: usize,
+            prot: ProtFlags,
+        ) -> crate::Result<bool> {
+            unsafe { Self::mprotect(addr, len, prot) }?;
+            Ok(true)
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        unsafe fn advise_huge(
+            addr: core::ptr::NonNull<core::ffi::c_void>,
+            len: usize,
+        ) -> crate::Result<()> {
+            // 内核不支持透明大页时会失败,此时忽略即可
+            unsafe { libc::madvise(addr.as_ptr(), len, libc::MADV_HUGEPAGE) };
+            Ok(())
+        }
     }
 }
 
@@ -102,6 +121,9 @@ mod imp {
         ptr::NonNull,
     };
     use syscalls::Sysno;
+
+    const MADV_HUGEPAGE: usize = 14;
+
     /// An implementation of Mmap trait
     pub struct MmapImpl;
 
@@ -213,6 +235,25 @@ mod imp {
             mprotect(addr.as_ptr(), len, prot)?;
             Ok(())
         }
+
+        unsafe fn mmap_in_place(
+            addr: core::ptr::NonNull<core::ffi::c_void>,
+            len: usize,
+            prot: ProtFlags,
+        ) -> crate::Result<bool> {
+            mprotect(addr.as_ptr(), len, prot)?;
+            Ok(true)
+        }
+
+        unsafe fn advise_huge(
+            addr: core::ptr::NonNull<core::ffi::c_void>,
+            len: usize,
+        ) -> crate::Result<()> {
+            // 内核不支持透明大页时会失败,此时忽略即可
+            let _ =
+                unsafe { syscalls::syscall!(Sysno::madvise, addr.as_ptr(), len, MADV_HUGEPAGE) };
+            Ok(())
+        }
     }
 }
 
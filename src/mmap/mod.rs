@@ -1,15 +1,26 @@
 //! Map memory to address space
+mod audit;
+pub(crate) mod no_mmap;
+pub use audit::{AuditedMmap, MapKind, MapRequest, MmapPolicy, WxorX};
+pub use no_mmap::MmapFromAlloc;
+
 cfg_if::cfg_if! {
-    if #[cfg(feature = "mmap")]{
+    if #[cfg(all(windows, feature = "std"))]{
+        pub(crate) mod windows;
+        pub use windows::MmapImpl;
+    }else if #[cfg(feature = "mmap")]{
         pub(crate) mod mmap;
         pub use mmap::MmapImpl;
+    }else if #[cfg(all(target_arch = "aarch64", target_os = "none"))]{
+        pub(crate) mod bare_metal;
+        pub use bare_metal::MmapBareMetal;
+        pub use bare_metal::MmapBareMetal as MmapImpl;
     }else {
-        pub(crate) mod no_mmap;
-        pub use no_mmap::MmapImpl;
+        pub use no_mmap::MmapFromAlloc as MmapImpl;
     }
 }
 
-use crate::Result;
+use crate::{Result, property::GnuProperty};
 use bitflags::bitflags;
 use core::{
     ffi::{c_int, c_void},
@@ -17,7 +28,7 @@ use core::{
 };
 
 bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     /// Desired memory protection of a memory mapping.
     pub struct ProtFlags: c_int {
         /// Pages cannot be accessed.
@@ -28,11 +39,13 @@ bitflags! {
         const PROT_WRITE = 2;
         /// Pages can be executed
         const PROT_EXEC = 4;
+        /// Pages are guarded by branch target identification, only supported on aarch64.
+        const PROT_BTI = 0x10;
     }
 }
 
 bitflags! {
-	#[derive(Clone, Copy)]
+    #[derive(Clone, Copy)]
      /// Additional parameters for [`mmap`].
      pub struct MapFlags: c_int {
         /// Create a private copy-on-write mapping. Mutually exclusive with `MAP_SHARED`.
@@ -41,6 +54,8 @@ bitflags! {
         const MAP_FIXED = 16;
         /// The mapping is not backed by any file.
         const MAP_ANONYMOUS = 32;
+        /// Like `MAP_FIXED`, but fails instead of replacing an existing mapping.
+        const MAP_FIXED_NOREPLACE = 0x100000;
     }
 }
 
@@ -103,4 +118,49 @@ pub trait Mmap {
     /// * `len` - The length of the memory region to protect.
     /// * `prot` - The new protection options for the mapping.
     unsafe fn mprotect(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> Result<()>;
+
+    /// This function uses memory of a `'static` elf image as a segment without copying it.
+    ///
+    /// It returns `false` if the implementation can't do this, and then the segment is copied into
+    /// memory mapped by `mmap`. The default implementation always returns `false`.
+    ///
+    /// # Safety
+    /// The memory must belong to an elf image that lives forever and is not written by others.
+    ///
+    /// # Arguments
+    /// * `addr` - A `NonNull` pointer to the start of the memory region. It is always aligned by page size.
+    /// * `len` - The length of the memory region. It is always aligned by page size.
+    /// * `prot` - The protection of the segment.
+    unsafe fn mmap_in_place(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> Result<bool> {
+        let _ = (addr, len, prot);
+        Ok(false)
+    }
+
+    /// Hints that the anonymous memory region is large enough to be backed by huge pages.
+    ///
+    /// It is called for the `.bss` of the segments when the loader is configured by
+    /// `Loader::set_huge_bss_threshold`. The hint is only an optimization, so the default
+    /// implementation does nothing.
+    ///
+    /// # Safety
+    /// The memory region must have been mapped by `mmap_anonymous`.
+    ///
+    /// # Arguments
+    /// * `addr` - A `NonNull` pointer to the start of the memory region. It is always aligned by page size.
+    /// * `len` - The length of the memory region. It is always aligned by page size.
+    unsafe fn advise_huge(addr: NonNull<c_void>, len: usize) -> Result<()> {
+        let _ = (addr, len);
+        Ok(())
+    }
+
+    /// Gets the protection added to the executable segments of an elf object with the GNU
+    /// properties `property`.
+    ///
+    /// `hint` is the protection suggested by the loader for the current architecture, such as
+    /// `PROT_BTI` for elf objects supporting BTI on aarch64. Since it is only accepted by kernels
+    /// and cpus supporting the feature, the default implementation ignores it.
+    fn exec_prot(property: &GnuProperty, hint: ProtFlags) -> ProtFlags {
+        let _ = (property, hint);
+        ProtFlags::PROT_NONE
+    }
 }
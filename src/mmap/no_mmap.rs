@@ -3,10 +3,15 @@ use crate::segment::PAGE_SIZE;
 use alloc::alloc::{dealloc, handle_alloc_error};
 use core::{alloc::Layout, ptr::NonNull, slice::from_raw_parts_mut};
 
-/// An implementation of Mmap trait
-pub struct MmapImpl;
+/// An implementation of Mmap trait which copies segments into memory obtained from the global allocator.
+///
+/// It works on hosts without any memory mapping support. Page protection is not supported, so the
+/// memory keeps the protection the allocator gives it, which is usually readable and writable but
+/// not executable. Code in the loaded objects can only run if the host does not enforce
+/// non-executable memory, e.g. bare metal without an MMU.
+pub struct MmapFromAlloc;
 
-impl Mmap for MmapImpl {
+impl Mmap for MmapFromAlloc {
     unsafe fn mmap(
         addr: Option<usize>,
         len: usize,
@@ -17,12 +22,11 @@ impl Mmap for MmapImpl {
         need_copy: &mut bool,
     ) -> crate::Result<core::ptr::NonNull<core::ffi::c_void>> {
         *need_copy = true;
-        if let Some(addr) = addr {
+        if let (Some(addr), true) = (addr, flags.contains(super::MapFlags::MAP_FIXED)) {
             let ptr = addr as *mut u8;
             Ok(unsafe { NonNull::new_unchecked(ptr as _) })
         } else {
-            // 只有创建整个空间时会走这条路径
-            assert!((super::MapFlags::MAP_FIXED & flags).bits() == 0);
+            // 只有创建整个空间时会走这条路径,不带MAP_FIXED的地址只是一个提示
             let layout = unsafe { Layout::from_size_align_unchecked(len, PAGE_SIZE) };
             let memory = unsafe { alloc::alloc::alloc(layout) };
             if memory.is_null() {
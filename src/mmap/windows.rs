@@ -0,0 +1,116 @@
+use super::{MapFlags, Mmap, ProtFlags};
+use crate::{Error, Result};
+use alloc::string::ToString;
+use core::{ffi::c_void, ptr::NonNull};
+
+const MEM_COMMIT: u32 = 0x1000;
+const MEM_RESERVE: u32 = 0x2000;
+const MEM_RELEASE: u32 = 0x8000;
+const PAGE_NOACCESS: u32 = 0x01;
+const PAGE_READONLY: u32 = 0x02;
+const PAGE_READWRITE: u32 = 0x04;
+const PAGE_EXECUTE: u32 = 0x10;
+const PAGE_EXECUTE_READ: u32 = 0x20;
+const PAGE_EXECUTE_READWRITE: u32 = 0x40;
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn VirtualAlloc(
+        lpaddress: *const c_void,
+        dwsize: usize,
+        flallocationtype: u32,
+        flprotect: u32,
+    ) -> *mut c_void;
+    fn VirtualFree(lpaddress: *mut c_void, dwsize: usize, dwfreetype: u32) -> i32;
+    fn VirtualProtect(
+        lpaddress: *const c_void,
+        dwsize: usize,
+        flnewprotect: u32,
+        lpfloldprotect: *mut u32,
+    ) -> i32;
+}
+
+#[inline]
+fn page_protect(prot: ProtFlags) -> u32 {
+    let read = prot.contains(ProtFlags::PROT_READ);
+    let write = prot.contains(ProtFlags::PROT_WRITE);
+    let exec = prot.contains(ProtFlags::PROT_EXEC);
+    // windows中可写的页一定可读
+    match (exec, write, read) {
+        (false, false, false) => PAGE_NOACCESS,
+        (false, false, true) => PAGE_READONLY,
+        (false, true, _) => PAGE_READWRITE,
+        (true, false, false) => PAGE_EXECUTE,
+        (true, false, true) => PAGE_EXECUTE_READ,
+        (true, true, _) => PAGE_EXECUTE_READWRITE,
+    }
+}
+
+/// An implementation of Mmap trait based on `VirtualAlloc`.
+///
+/// Windows can only map files at offsets aligned to the allocation granularity (64KiB), so the
+/// contents of segments are always copied into the allocated memory.
+pub struct MmapImpl;
+
+impl Mmap for MmapImpl {
+    unsafe fn mmap(
+        addr: Option<usize>,
+        len: usize,
+        _prot: ProtFlags,
+        flags: MapFlags,
+        _offset: usize,
+        _fd: Option<i32>,
+        need_copy: &mut bool,
+    ) -> Result<NonNull<c_void>> {
+        *need_copy = true;
+        // 创建整个空间时需要先保留地址空间,之后的segment都在这段空间中提交
+        // 可执行文件的整个空间以MAP_FIXED_NOREPLACE映射,地址已被占用时保留会失败
+        let alloc_type = if flags.contains(MapFlags::MAP_FIXED) {
+            MEM_COMMIT
+        } else {
+            MEM_RESERVE | MEM_COMMIT
+        };
+        let ptr = unsafe { VirtualAlloc(addr.unwrap_or(0) as _, len, alloc_type, PAGE_READWRITE) };
+        NonNull::new(ptr).ok_or_else(|| map_error("VirtualAlloc failed"))
+    }
+
+    unsafe fn mmap_anonymous(
+        addr: usize,
+        len: usize,
+        prot: ProtFlags,
+        _flags: MapFlags,
+    ) -> Result<NonNull<c_void>> {
+        let ptr = unsafe { VirtualAlloc(addr as _, len, MEM_COMMIT, PAGE_READWRITE) };
+        let ptr = NonNull::new(ptr).ok_or_else(|| map_error("VirtualAlloc failed"))?;
+        // 这段内存可能已经被提交过,因此需要手动清零
+        unsafe {
+            ptr.as_ptr().cast::<u8>().write_bytes(0, len);
+            Self::mprotect(ptr, len, prot)?;
+        }
+        Ok(ptr)
+    }
+
+    unsafe fn munmap(addr: NonNull<c_void>, _len: usize) -> Result<()> {
+        // MEM_RELEASE只能释放整个保留的空间,并且要求长度为0
+        if unsafe { VirtualFree(addr.as_ptr(), 0, MEM_RELEASE) } == 0 {
+            return Err(map_error("VirtualFree failed"));
+        }
+        Ok(())
+    }
+
+    unsafe fn mprotect(addr: NonNull<c_void>, len: usize, prot: ProtFlags) -> Result<()> {
+        let mut old = 0;
+        if unsafe { VirtualProtect(addr.as_ptr(), len, page_protect(prot), &mut old) } == 0 {
+            return Err(map_error("VirtualProtect failed"));
+        }
+        Ok(())
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn map_error(msg: &str) -> Error {
+    Error::MmapError {
+        msg: msg.to_string(),
+    }
+}
@@ -0,0 +1,339 @@
+//! Isolated namespaces of dynamic libraries
+//!
+//! A [`Namespace`] is similar to a link map created by `dlmopen(LM_ID_NEWLM, ...)` in glibc. It has
+//! its own loader, its own global scope and its own registry of loaded libraries, so the same
+//! library tree can be loaded into several namespaces without their symbols clashing. The
+//! dependencies of a library are looked up in the registry of the namespace first, and only loaded
+//! through the `LibraryResolver` if they are not there.
+//!
+//! Each library keeps the libraries of its local scope loaded, so closing a library or dropping the
+//! namespace never unloads the dependencies of a library which is still used. Libraries depending on
+//! each other are relocated against each other, but only the library relocated last keeps the
+//! others loaded, so a cycle of dependencies is unloaded once all of its libraries are closed.
+//!
+//! The tls module ids are allocated by the `ThreadLocal` implementation of the namespace.
+//! `tls::ThreadLocalImpl` never reuses an id, so a library loaded into several namespaces has a
+//! separate module id and separate tls variables in each of them.
+use crate::{
+    CoreComponent, CoreComponentRef, ElfDylib, Loader, RelocatedDylib, Result, init_all, io_error,
+    mmap::Mmap,
+    object::ElfObject,
+    policy::dependency_error,
+    scope::{Scope, Visibility},
+    search::LibraryResolver,
+    tls::ThreadLocal,
+};
+use alloc::{boxed::Box, collections::VecDeque, string::ToString, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use elf::abi::{DF_1_NOOPEN, DT_FLAGS_1};
+
+// 每个命名空间都有唯一的id
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+type PreFind = Arc<dyn Fn(&str) -> Option<*const ()> + Send + Sync>;
+
+/// A set of dynamic libraries isolated from the libraries of other namespaces
+///
+/// # Examples
+/// ```no_run
+/// use elf_loader::{
+///     Loader,
+///     mmap::MmapImpl,
+///     namespace::Namespace,
+///     object::ElfFile,
+///     scope::Visibility,
+///     search::{FsResolver, SearchConfig},
+/// };
+///
+/// let mut resolver = FsResolver::new(SearchConfig::new().library_path("target"));
+/// let mut first = Namespace::new(Loader::<MmapImpl>::new());
+/// let mut second = Namespace::new(Loader::<MmapImpl>::new());
+/// // the same library tree is loaded twice
+/// let a = first
+///     .open(ElfFile::from_path("target/libc.so").unwrap(), Visibility::Global, &mut resolver)
+///     .unwrap();
+/// let b = second
+///     .open(ElfFile::from_path("target/libc.so").unwrap(), Visibility::Global, &mut resolver)
+///     .unwrap();
+/// assert_ne!(a.base(), b.base());
+/// ```
+pub struct Namespace<M, T = ()>
+where
+    M: Mmap,
+    T: ThreadLocal,
+{
+    id: usize,
+    loader: Loader<M, T>,
+    global: Scope<'static>,
+    libs: Vec<RelocatedDylib<'static>>,
+    pre_find: Option<PreFind>,
+}
+
+impl<M: Mmap, T: ThreadLocal> Namespace<M, T> {
+    /// Creates an empty namespace which loads libraries with `loader`.
+    pub fn new(loader: Loader<M, T>) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            loader,
+            global: Scope::new(),
+            libs: Vec::new(),
+            pre_find: None,
+        }
+    }
+
+    /// Sets the function used to find symbols before the scopes of the namespace, which is usually
+    /// used to provide the symbols of the host.
+    pub fn set_pre_find(
+        &mut self,
+        pre_find: impl Fn(&str) -> Option<*const ()> + Send + Sync + 'static,
+    ) {
+        self.pre_find = Some(Arc::new(pre_find));
+    }
+
+    /// Gets the id of the namespace, which is unique in the process.
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Gets the loader of the namespace.
+    #[inline]
+    pub fn loader(&mut self) -> &mut Loader<M, T> {
+        &mut self.loader
+    }
+
+    /// Gets the global scope of the namespace.
+    #[inline]
+    pub fn global_scope(&self) -> &Scope<'static> {
+        &self.global
+    }
+
+    /// Gets the libraries loaded in the namespace, dependencies come before the libraries needing them.
+    #[inline]
+    pub fn libs(&self) -> &[RelocatedDylib<'static>] {
+        &self.libs
+    }
+
+    /// Gets the loaded library whose file name or soname is `name`.
+    pub fn get(&self, name: &str) -> Option<&RelocatedDylib<'static>> {
+        self.libs.iter().find(|lib| lib.provides(name))
+    }
+
+    /// Finds the symbol `name` in the global scope of the namespace.
+    pub fn find(&self, name: &str) -> Option<*const ()> {
+        self.global.find(name)
+    }
+
+    /// Loads and relocates a dynamic library and the dependencies which are not loaded in the
+    /// namespace yet. If a library with the same file name or soname is already loaded, it is
+    /// returned instead.
+    ///
+    /// The symbols are looked up in the global scope of the namespace and then in the local scope of
+    /// each library. With `Visibility::Global`, the library and its dependencies are added to the
+    /// global scope afterwards.
+    ///
+    /// Libraries with `DF_1_NOOPEN` are rejected, even if they are already loaded as dependencies.
+    pub fn open<R: LibraryResolver>(
+        &mut self,
+        object: impl ElfObject,
+        visibility: Visibility,
+        resolver: &mut R,
+    ) -> Result<RelocatedDylib<'static>> {
+        let name = object.file_name().to_string_lossy();
+        let name = name.rsplit('/').next().unwrap().to_string();
+        let lib = match self.get(&name) {
+            Some(lib) => {
+                check_noopen(lib)?;
+                lib.clone()
+            }
+            None => {
+                let lib = self.loader.easy_load_dylib(object)?;
+                check_noopen(&lib)?;
+                self.load_tree(lib, resolver)?
+            }
+        };
+        let local = Scope::local(&lib, self.libs.iter());
+        self.global.register(&lib, &local, visibility);
+        Ok(lib)
+    }
+
+    /// Removes `lib` from the namespace. The library is unloaded once all other references to it
+    /// are dropped, including the ones held by the libraries depending on it. In a cycle of
+    /// dependencies, only the libraries relocated after it hold it. Returns `false` if the library
+    /// is not loaded in the namespace.
+    pub fn close(&mut self, lib: &CoreComponent) -> bool {
+        self.global.remove(lib);
+        let len = self.libs.len();
+        self.libs
+            .retain(|other| other.generation() != lib.generation());
+        self.libs.len() != len
+    }
+
+    // 先加载整个依赖树,再按依赖在前的顺序重定位,最后调用初始化函数
+    fn load_tree<R: LibraryResolver>(
+        &mut self,
+        lib: ElfDylib,
+        resolver: &mut R,
+    ) -> Result<RelocatedDylib<'static>> {
+        let mut pending = alloc::vec![lib];
+        let mut idx = 0;
+        while idx < pending.len() {
+            let lib = &pending[idx];
+            let mut deps: Vec<ElfDylib> = Vec::new();
+            for needed in lib.needed_libs() {
+                if self.get(needed).is_some()
+                    || pending.iter().chain(&deps).any(|lib| lib.provides(needed))
+                {
+                    continue;
+                }
+                let dep = resolver.resolve(needed, &lib.needed_by()).ok_or_else(|| {
+                    dependency_error(
+                        lib.name(),
+                        needed,
+                        alloc::format!("cannot find {needed} needed by {}", lib.name()),
+                    )
+                })?;
+                deps.push(self.loader.easy_load_dylib(dep)?);
+            }
+            pending.extend(deps);
+            idx += 1;
+        }
+        // 整个树重定位完成后才调用初始化函数,使循环依赖中的库在初始化时都已经重定位
+        let defer_init = pending[0].common.defer_init;
+        pending
+            .iter_mut()
+            .for_each(|lib| lib.common.defer_init = true);
+        let order = relocation_order(&pending);
+        // 库持有局部scope中先于它重定位的库,使它们在库被卸载之前不会被卸载。循环依赖中后重定位的库
+        // 不被持有,否则引用计数形成环,这些库永远不会被卸载。依赖在库被克隆之前设置
+        for (pos, &idx) in order.iter().enumerate() {
+            let deps = self.strong_deps(&pending, idx, &order[..pos]);
+            pending[idx].set_deps(deps);
+        }
+        // 尚未重定位的库只用于查找符号,因此循环依赖中的库可以互相绑定
+        let unrelocated: Vec<RelocatedDylib<'static>> = pending
+            .iter()
+            .map(|lib| unsafe { RelocatedDylib::from_core_component((**lib).clone()) })
+            .collect();
+        let mut slots: Vec<Option<ElfDylib>> = pending.into_iter().map(Some).collect();
+        let mut relocated: Vec<RelocatedDylib<'static>> = Vec::with_capacity(slots.len());
+        for idx in order {
+            let lib = slots[idx].take().unwrap();
+            let local = Scope::local(&lib, self.libs.iter().chain(unrelocated.iter()));
+            relocated.push(self.relocate(lib, &local)?);
+        }
+        if !defer_init {
+            init_all(None, &relocated);
+        }
+        self.libs.extend(relocated.iter().cloned());
+        // 第一个库是被打开的库
+        let opened = unrelocated[0].generation();
+        Ok(relocated
+            .into_iter()
+            .find(|lib| lib.generation() == opened)
+            .unwrap())
+    }
+
+    // 与Scope::local相同的顺序查找pending[idx]的局部scope,只保留命名空间中已有的库和relocated中的库
+    fn strong_deps(
+        &self,
+        pending: &[ElfDylib],
+        idx: usize,
+        relocated: &[usize],
+    ) -> Box<[CoreComponent]> {
+        let mut visited = alloc::vec![idx];
+        let mut deps: Vec<CoreComponent> = Vec::new();
+        let mut queue: VecDeque<&str> = pending[idx].needed_libs().iter().copied().collect();
+        while let Some(needed) = queue.pop_front() {
+            if let Some(dep) = self.libs.iter().find(|dep| dep.provides(needed)) {
+                if deps
+                    .iter()
+                    .all(|other| other.generation() != dep.generation())
+                {
+                    deps.push((**dep).clone());
+                    queue.extend(dep.needed_libs().iter().copied());
+                }
+                continue;
+            }
+            let Some(dep) = pending.iter().position(|lib| lib.provides(needed)) else {
+                continue;
+            };
+            if visited.contains(&dep) {
+                continue;
+            }
+            visited.push(dep);
+            if relocated.contains(&dep) {
+                deps.push((*pending[dep]).clone());
+            }
+            queue.extend(pending[dep].needed_libs().iter().copied());
+        }
+        deps.into_boxed_slice()
+    }
+
+    fn relocate(&self, lib: ElfDylib, local: &Scope<'static>) -> Result<RelocatedDylib<'static>> {
+        let scope = self.global.chain(local);
+        let pre_find = self.pre_find.clone();
+        // 延迟绑定使用的scope不能借用命名空间,因此持有pre_find和弱引用
+        let weak: Vec<CoreComponentRef> = scope.iter().map(|lib| lib.downgrade()).collect();
+        let find = pre_find.clone();
+        let lazy_scope = Box::new(move |name: &str| {
+            find.as_ref().and_then(|find| find(name)).or_else(|| {
+                weak.iter().find_map(|lib| unsafe {
+                    RelocatedDylib::from_core_component(lib.upgrade()?)
+                        .get::<()>(name)
+                        .map(|sym| sym.into_raw())
+                })
+            })
+        });
+        let pre_find = |name: &str| pre_find.as_ref().and_then(|find| find(name));
+        let lib = lib.relocate(
+            scope.iter(),
+            &pre_find,
+            |_, _, _| Err(Box::new(())),
+            Some(lazy_scope),
+        )?;
+        // 库持有依赖的强引用,因此可以去掉生命周期
+        Ok(unsafe { RelocatedDylib::from_core_component((*lib).clone()) })
+    }
+}
+
+// 依赖在前的重定位顺序,循环依赖中的库按照被需要的顺序排列
+fn relocation_order(libs: &[ElfDylib]) -> Vec<usize> {
+    fn visit(idx: usize, libs: &[ElfDylib], visited: &mut [bool], order: &mut Vec<usize>) {
+        if visited[idx] {
+            return;
+        }
+        visited[idx] = true;
+        for needed in libs[idx].needed_libs() {
+            if let Some(dep) = libs.iter().position(|lib| lib.provides(needed)) {
+                visit(dep, libs, visited, order);
+            }
+        }
+        order.push(idx);
+    }
+    let mut visited = alloc::vec![false; libs.len()];
+    let mut order = Vec::with_capacity(libs.len());
+    visit(0, libs, &mut visited, &mut order);
+    order
+}
+
+/// Fails if `lib` has `DF_1_NOOPEN`, such libraries can only be loaded as dependencies.
+pub(crate) fn check_noopen(lib: &CoreComponent) -> Result<()> {
+    let flags_1 = lib
+        .dynamic_table()
+        .and_then(|table| table.get(DT_FLAGS_1))
+        .unwrap_or(0);
+    if flags_1 & DF_1_NOOPEN as usize != 0 {
+        return Err(noopen_error(lib));
+    }
+    Ok(())
+}
+
+#[cold]
+#[inline(never)]
+fn noopen_error(lib: &CoreComponent) -> crate::Error {
+    io_error(alloc::format!(
+        "{}: cannot dlopen() this object",
+        lib.name()
+    ))
+}
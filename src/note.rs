@@ -0,0 +1,60 @@
+//! Notes in the `PT_NOTE` and `PT_GNU_PROPERTY` segments
+
+/// A note of a note segment
+pub(crate) struct Note<'a> {
+    pub(crate) n_type: u32,
+    /// The name of the owner including the trailing nul, such as `GNU\0`.
+    pub(crate) name: &'a [u8],
+    pub(crate) desc: &'a [u8],
+}
+
+/// An iterator over the notes in the contents of a note segment. It stops at the first malformed
+/// note.
+pub(crate) struct Notes<'a> {
+    rest: &'a [u8],
+    align: usize,
+}
+
+impl<'a> Notes<'a> {
+    /// The descriptors and the notes are aligned to `align`, the alignment of the segment.
+    pub(crate) fn new(notes: &'a [u8], align: usize) -> Self {
+        Self {
+            rest: notes,
+            align: align.max(4),
+        }
+    }
+
+    fn parse(&mut self) -> Option<Note<'a>> {
+        let rest = self.rest;
+        if rest.len() < 12 {
+            return None;
+        }
+        let aligned = |len: usize| {
+            len.checked_add(self.align - 1)
+                .map(|len| len & !(self.align - 1))
+        };
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(rest[offset..offset + 4].try_into().unwrap());
+        let namesz = read_u32(0) as usize;
+        let descsz = read_u32(4) as usize;
+        let n_type = read_u32(8);
+        let desc_start = aligned(namesz.checked_add(12)?)?;
+        let desc_end = desc_start.checked_add(descsz)?;
+        let name = rest.get(12..12 + namesz)?;
+        let desc = rest.get(desc_start..desc_end)?;
+        self.rest = &rest[aligned(desc_end)?.min(rest.len())..];
+        Some(Note { n_type, name, desc })
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let note = self.parse();
+        if note.is_none() {
+            self.rest = &[];
+        }
+        note
+    }
+}
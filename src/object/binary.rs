@@ -1,4 +1,4 @@
-use crate::ElfObject;
+use crate::{ElfObject, ElfObjectAsync};
 use alloc::ffi::CString;
 use core::ffi::CStr;
 
@@ -6,6 +6,7 @@ use core::ffi::CStr;
 pub struct ElfBinary<'bytes> {
     name: CString,
     bytes: &'bytes [u8],
+    in_place: Option<&'static [u8]>,
 }
 
 impl<'bytes> ElfBinary<'bytes> {
@@ -13,6 +14,27 @@ impl<'bytes> ElfBinary<'bytes> {
         Self {
             name: CString::new(name).unwrap(),
             bytes,
+            in_place: None,
+        }
+    }
+}
+
+impl ElfBinary<'static> {
+    /// Creates an elf object from a `'static` image, such as one embedded with `include_bytes!`.
+    ///
+    /// When the image is page-aligned, the leading read-only segments of a dynamic library are used
+    /// in place, and only the other segments and the bss are mapped into fresh memory placed right
+    /// after them. The segments are copied as usual if the layout of the image does not allow this
+    /// or the `Mmap` implementation does not support `Mmap::mmap_in_place`.
+    ///
+    /// # Safety
+    /// The protection of the pages used in place is changed to that of their segments and is never
+    /// restored, so they must not be shared with memory that is written or executed by others.
+    pub unsafe fn from_static(name: &str, bytes: &'static [u8]) -> Self {
+        Self {
+            name: CString::new(name).unwrap(),
+            bytes,
+            in_place: Some(bytes),
         }
     }
 }
@@ -23,6 +45,10 @@ impl<'bytes> ElfObject for ElfBinary<'bytes> {
         Ok(())
     }
 
+    fn size(&self) -> Option<usize> {
+        Some(self.bytes.len())
+    }
+
     fn file_name(&self) -> &CStr {
         &self.name
     }
@@ -30,4 +56,14 @@ impl<'bytes> ElfObject for ElfBinary<'bytes> {
     fn as_fd(&self) -> Option<i32> {
         None
     }
+
+    fn as_static_bytes(&self) -> Option<&'static [u8]> {
+        self.in_place
+    }
+}
+
+impl<'bytes> ElfObjectAsync for ElfBinary<'bytes> {
+    async fn read_async(&mut self, buf: &mut [u8], offset: usize) -> crate::Result<()> {
+        self.read(buf, offset)
+    }
 }
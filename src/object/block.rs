@@ -0,0 +1,182 @@
+use crate::{ElfObject, ElfObjectAsync, Result, io_error};
+use alloc::{ffi::CString, vec, vec::Vec};
+use core::ffi::CStr;
+
+/// A storage read in fixed-size blocks, such as a block device or a file opened by a FAT driver
+pub trait BlockDevice {
+    /// Gets the size of a block in bytes, which must be a power of two.
+    fn block_size(&self) -> usize;
+
+    /// Gets the alignment required for the buffers passed to `read_blocks`, for example by DMA.
+    /// The default is 1.
+    fn buf_align(&self) -> usize {
+        1
+    }
+
+    /// Reads the blocks starting at the block `lba` into `buf`, whose length is a multiple of the
+    /// block size.
+    ///
+    /// Returns the number of bytes read, which can be less than the length of `buf`. A read shorter
+    /// than one block is only allowed at the end of the storage, and returning `0` means the end of
+    /// the storage is reached.
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A `BlockDevice` backed by a `read_at(lba, buf)` function
+pub struct ReadAt<F> {
+    block_size: usize,
+    read: F,
+}
+
+impl<F: FnMut(u64, &mut [u8]) -> Result<usize>> ReadAt<F> {
+    /// Creates a block device with blocks of `block_size` bytes, `read` has the same contract as
+    /// `BlockDevice::read_blocks`.
+    pub fn new(block_size: usize, read: F) -> Self {
+        assert!(block_size.is_power_of_two());
+        Self { block_size, read }
+    }
+}
+
+impl<F: FnMut(u64, &mut [u8]) -> Result<usize>> BlockDevice for ReadAt<F> {
+    #[inline]
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    #[inline]
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<usize> {
+        (self.read)(lba, buf)
+    }
+}
+
+/// An elf object stored on a block device, which can be used without a file system of `std`.
+///
+/// The elf object starts at a byte offset of the device that does not need to be block-aligned.
+/// Reads of whole aligned blocks go directly into the buffers of the loader, and the rest goes
+/// through a buffer of one block, which also serves the following reads of the same block.
+///
+/// # Examples
+/// ```no_run
+/// use elf_loader::{Loader, mmap::MmapImpl, object::{ElfBlockReader, ReadAt}};
+///
+/// # fn disk_read(lba: u64, buf: &mut [u8]) -> elf_loader::Result<usize> { Ok(buf.len()) }
+/// // the elf file occupies 20000 bytes starting at the block 64
+/// let device = ReadAt::new(512, |lba, buf: &mut [u8]| disk_read(lba, buf));
+/// let object = ElfBlockReader::new("liba.so", device, Some(20000)).with_start(64 * 512);
+/// let mut loader = Loader::<MmapImpl>::new();
+/// let liba = loader.easy_load_dylib(object).unwrap();
+/// ```
+pub struct ElfBlockReader<D: BlockDevice> {
+    name: CString,
+    device: D,
+    start: u64,
+    size: Option<usize>,
+    // 缓存一个块,其中有效的字节数为cached_len
+    cache: Vec<u8>,
+    cache_offset: usize,
+    cached_lba: Option<u64>,
+    cached_len: usize,
+}
+
+impl<D: BlockDevice> ElfBlockReader<D> {
+    /// Creates an elf object of `size` bytes at the beginning of `device`.
+    pub fn new(name: &str, device: D, size: Option<usize>) -> Self {
+        let block_size = device.block_size();
+        let align = device.buf_align();
+        assert!(block_size.is_power_of_two() && align.is_power_of_two());
+        // 多分配align字节以便取出对齐的缓冲区
+        let cache = vec![0; block_size + align - 1];
+        let cache_offset = cache.as_ptr().align_offset(align);
+        Self {
+            name: CString::new(name).unwrap(),
+            device,
+            start: 0,
+            size,
+            cache,
+            cache_offset,
+            cached_lba: None,
+            cached_len: 0,
+        }
+    }
+
+    /// Sets the byte offset of the elf object on the device.
+    pub fn with_start(mut self, start: u64) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Gets the underlying device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn fill_cache(&mut self, lba: u64) -> Result<()> {
+        if self.cached_lba == Some(lba) {
+            return Ok(());
+        }
+        self.cached_lba = None;
+        let block_size = self.device.block_size();
+        let cache = &mut self.cache[self.cache_offset..self.cache_offset + block_size];
+        // 只有最后一个块可能读不满
+        let len = self.device.read_blocks(lba, cache)?;
+        self.cached_lba = Some(lba);
+        self.cached_len = len.min(block_size);
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> ElfObject for ElfBlockReader<D> {
+    fn read(&mut self, mut buf: &mut [u8], offset: usize) -> Result<()> {
+        if let Some(size) = self.size {
+            if offset.checked_add(buf.len()).is_none_or(|end| end > size) {
+                return Err(io_error("read beyond the end of the elf object"));
+            }
+        }
+        let block_size = self.device.block_size() as u64;
+        let align = self.device.buf_align();
+        let mut pos = self.start + offset as u64;
+        while !buf.is_empty() {
+            let lba = pos / block_size;
+            let skip = (pos % block_size) as usize;
+            let whole = buf.len() / block_size as usize * block_size as usize;
+            let len = if skip == 0 && whole != 0 && buf.as_ptr().align_offset(align) == 0 {
+                // 完整且对齐的块直接读入目标缓冲区
+                let n = self.device.read_blocks(lba, &mut buf[..whole])?;
+                if n == 0 {
+                    return Err(io_error("unexpected end of the block device"));
+                }
+                n.min(whole)
+            } else {
+                self.fill_cache(lba)?;
+                if self.cached_len <= skip {
+                    return Err(io_error("unexpected end of the block device"));
+                }
+                let len = (self.cached_len - skip).min(buf.len());
+                let start = self.cache_offset + skip;
+                buf[..len].copy_from_slice(&self.cache[start..start + len]);
+                len
+            };
+            buf = &mut buf[len..];
+            pos += len as u64;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    fn file_name(&self) -> &CStr {
+        &self.name
+    }
+
+    fn as_fd(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl<D: BlockDevice + Send> ElfObjectAsync for ElfBlockReader<D> {
+    async fn read_async(&mut self, buf: &mut [u8], offset: usize) -> Result<()> {
+        self.read(buf, offset)
+    }
+}
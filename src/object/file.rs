@@ -26,7 +26,7 @@ mod imp {
     use crate::{Result, io_error, object::ElfObject};
     use alloc::ffi::CString;
     use core::{ffi::CStr, str::FromStr};
-    use libc::{O_RDONLY, SEEK_SET};
+    use libc::{O_RDONLY, SEEK_END, SEEK_SET};
 
     impl Drop for ElfFile {
         fn drop(&mut self) {
@@ -83,6 +83,19 @@ mod imp {
             Ok(())
         }
 
+        fn read_vectored(&mut self, bufs: &mut [&mut [u8]], offset: usize) -> Result<()> {
+            lseek(self.fd, offset)?;
+            for buf in bufs.iter_mut() {
+                read_exact(self.fd, buf)?;
+            }
+            Ok(())
+        }
+
+        fn size(&self) -> Option<usize> {
+            let size = unsafe { libc::lseek(self.fd, 0, SEEK_END) };
+            (size != -1).then_some(size as usize)
+        }
+
         fn file_name(&self) -> &CStr {
             &self.name
         }
@@ -139,6 +152,11 @@ mod imp {
             Ok(())
         }
 
+        fn size(&self) -> Option<usize> {
+            const SEEK_END: u32 = 2;
+            unsafe { syscalls::syscall!(Sysno::lseek, self.fd, 0, SEEK_END) }.ok()
+        }
+
         fn file_name(&self) -> &CStr {
             &self.name
         }
@@ -2,12 +2,28 @@
 use crate::Result;
 use core::ffi::CStr;
 mod binary;
+mod block;
 #[cfg(feature = "fs")]
 mod file;
+#[cfg(feature = "std")]
+mod reader;
 
 pub use binary::ElfBinary;
+pub use block::{BlockDevice, ElfBlockReader, ReadAt};
 #[cfg(feature = "fs")]
 pub use file::ElfFile;
+#[cfg(feature = "std")]
+pub use reader::ElfReader;
+
+/// How the loader puts the segments of an elf object into memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OffsetType {
+    /// Map the segments from the file descriptor through `Mmap::mmap`.
+    Fd(i32),
+    /// Map anonymous memory and read the segments into it, for the elf objects which can not be
+    /// mapped. The contents of consecutive `PT_LOAD` segments are read by one `read_vectored`.
+    Copy,
+}
 
 /// The original elf object
 pub trait ElfObject {
@@ -15,8 +31,32 @@ pub trait ElfObject {
     fn file_name(&self) -> &CStr;
     /// Read data from the elf object
     fn read(&mut self, buf: &mut [u8], offset: usize) -> Result<()>;
+    /// Reads data from the elf object into several buffers in order, starting at `offset`.
+    fn read_vectored(&mut self, bufs: &mut [&mut [u8]], mut offset: usize) -> Result<()> {
+        for buf in bufs.iter_mut() {
+            self.read(buf, offset)?;
+            offset += buf.len();
+        }
+        Ok(())
+    }
+    /// Gets the size of the elf object if it is known. The loader uses it to reject segments out of bounds.
+    fn size(&self) -> Option<usize> {
+        None
+    }
     /// Extracts the raw file descriptor.
-    fn as_fd(&self) -> Option<i32>;
+    fn as_fd(&self) -> Option<i32> {
+        None
+    }
+    /// Gets how the loader puts the segments into memory. It is `OffsetType::Fd` if the elf object
+    /// has a file descriptor, and `OffsetType::Copy` otherwise.
+    fn offset_type(&self) -> OffsetType {
+        self.as_fd().map_or(OffsetType::Copy, OffsetType::Fd)
+    }
+    /// Gets the bytes of the elf object if it is a `'static` image in memory whose read-only
+    /// segments can be used in place instead of being copied.
+    fn as_static_bytes(&self) -> Option<&'static [u8]> {
+        None
+    }
 }
 
 /// The original elf object
@@ -0,0 +1,70 @@
+use crate::{ElfObject, ElfObjectAsync, Result, io_error};
+use alloc::ffi::CString;
+use core::ffi::CStr;
+use std::io::{Read, Seek, SeekFrom};
+
+/// An elf object read from a reader, such as a file in a custom file system or an archive.
+/// Its segments are read into anonymous memory.
+///
+/// # Examples
+/// ```no_run
+/// use elf_loader::{Loader, mmap::MmapImpl, object::ElfReader};
+/// use std::fs::File;
+///
+/// let file = File::open("target/liba.so").unwrap();
+/// let mut loader = Loader::<MmapImpl>::new();
+/// let liba = loader
+///     .easy_load_dylib(ElfReader::new("liba.so", file))
+///     .unwrap();
+/// ```
+pub struct ElfReader<R: Read + Seek> {
+    name: CString,
+    reader: R,
+    size: Option<usize>,
+}
+
+impl<R: Read + Seek> ElfReader<R> {
+    pub fn new(name: &str, mut reader: R) -> Self {
+        let size = reader.seek(SeekFrom::End(0)).ok().map(|size| size as usize);
+        Self {
+            name: CString::new(name).unwrap(),
+            reader,
+            size,
+        }
+    }
+
+    /// Gets the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> ElfObject for ElfReader<R> {
+    fn read(&mut self, buf: &mut [u8], offset: usize) -> Result<()> {
+        self.read_vectored(&mut [buf], offset)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [&mut [u8]], offset: usize) -> Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(offset as u64))
+            .map_err(io_error)?;
+        for buf in bufs.iter_mut() {
+            self.reader.read_exact(buf).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    fn file_name(&self) -> &CStr {
+        &self.name
+    }
+}
+
+impl<R: Read + Seek + Send> ElfObjectAsync for ElfReader<R> {
+    async fn read_async(&mut self, buf: &mut [u8], offset: usize) -> Result<()> {
+        self.read(buf, offset)
+    }
+}
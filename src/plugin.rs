@@ -0,0 +1,143 @@
+//! A minimal plugin host
+//!
+//! A plugin is a dynamic library which exports an ABI version and a `repr(C)` interface struct,
+//! usually a table of function pointers. [`Plugin`] loads and relocates the library, checks the
+//! ABI version and gives access to the interface in place, so the host only deals with a typed
+//! struct. The interface is only borrowed from the plugin, so it can not outlive the library.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{Loader, mmap::MmapImpl, object::ElfFile, plugin::{Plugin, PluginInterface}};
+//!
+//! // the plugin defines `PLUGIN_ABI_VERSION: u32 = 1` and `PLUGIN_VTABLE: VTable`
+//! #[repr(C)]
+//! struct VTable {
+//!     name: extern "C" fn() -> *const u8,
+//!     run: extern "C" fn(i32) -> i32,
+//! }
+//!
+//! unsafe impl PluginInterface for VTable {
+//!     const SYMBOL: &'static str = "PLUGIN_VTABLE";
+//!     const VERSION_SYMBOL: &'static str = "PLUGIN_ABI_VERSION";
+//!     const VERSION: u32 = 1;
+//! }
+//!
+//! let mut loader = Loader::<MmapImpl>::new();
+//! let object = ElfFile::from_path("target/plugin.so").unwrap();
+//! let plugin = Plugin::<VTable>::load(&mut loader, object, &|_| None).unwrap();
+//! let ret = (plugin.interface().run)(42);
+//! ```
+use crate::{
+    Error, Loader, RelocatedDylib, Result, mmap::Mmap, object::ElfObject, tls::ThreadLocal,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::ptr::NonNull;
+
+/// The interface exported by a plugin.
+///
+/// # Safety
+/// The type must be `repr(C)` and have the same layout as the object exported by the plugin as
+/// `SYMBOL`, and all of its fields must be valid for any value the plugin may export.
+pub unsafe trait PluginInterface {
+    /// The name of the symbol of the interface struct.
+    const SYMBOL: &'static str;
+    /// The name of the symbol of the ABI version, which is a `u32`.
+    const VERSION_SYMBOL: &'static str;
+    /// The ABI version the host expects.
+    const VERSION: u32;
+}
+
+/// A loaded plugin with the interface `I`
+pub struct Plugin<'lib, I: PluginInterface> {
+    lib: RelocatedDylib<'lib>,
+    // 指向插件中的接口,插件被卸载前一直有效
+    interface: NonNull<I>,
+}
+
+unsafe impl<I: PluginInterface + Sync> Send for Plugin<'_, I> {}
+unsafe impl<I: PluginInterface + Sync> Sync for Plugin<'_, I> {}
+
+impl<'lib, I: PluginInterface> Plugin<'lib, I> {
+    /// Loads and relocates the plugin. The symbols it imports are looked up in `pre_find`.
+    pub fn load<M, T, F>(
+        loader: &mut Loader<M, T>,
+        object: impl ElfObject,
+        pre_find: &'lib F,
+    ) -> Result<Self>
+    where
+        M: Mmap,
+        T: ThreadLocal,
+        F: Fn(&str) -> Option<*const ()>,
+    {
+        let lib = loader
+            .easy_load_dylib(object)?
+            .easy_relocate([].into_iter(), pre_find)?;
+        Self::from_dylib(lib)
+    }
+
+    /// Checks the ABI version of a relocated library and reads its interface.
+    pub fn from_dylib(lib: RelocatedDylib<'lib>) -> Result<Self> {
+        let version = unsafe { lib.get::<()>(I::VERSION_SYMBOL) }
+            .map(|sym| unsafe { sym.into_raw().cast::<u32>().read() })
+            .ok_or_else(|| plugin_error(&lib, format!("missing symbol {}", I::VERSION_SYMBOL)))?;
+        if version != I::VERSION {
+            return Err(plugin_error(
+                &lib,
+                format!(
+                    "ABI version mismatch: expected {}, found {}",
+                    I::VERSION,
+                    version
+                ),
+            ));
+        }
+        let interface = unsafe { lib.get::<()>(I::SYMBOL) }
+            .and_then(|sym| NonNull::new(sym.into_raw().cast::<I>().cast_mut()))
+            .ok_or_else(|| plugin_error(&lib, format!("missing symbol {}", I::SYMBOL)))?;
+        Ok(Self { lib, interface })
+    }
+
+    /// Gets the interface of the plugin, which stays in the memory of the plugin.
+    ///
+    /// The interface is borrowed from the plugin, so it can not be used after the plugin is
+    /// dropped:
+    /// ```compile_fail
+    /// # use elf_loader::{Loader, mmap::MmapImpl, object::ElfFile, plugin::{Plugin, PluginInterface}};
+    /// # #[repr(C)]
+    /// # struct VTable {
+    /// #     run: extern "C" fn(i32) -> i32,
+    /// # }
+    /// # unsafe impl PluginInterface for VTable {
+    /// #     const SYMBOL: &'static str = "PLUGIN_VTABLE";
+    /// #     const VERSION_SYMBOL: &'static str = "PLUGIN_ABI_VERSION";
+    /// #     const VERSION: u32 = 1;
+    /// # }
+    /// let mut loader = Loader::<MmapImpl>::new();
+    /// let object = ElfFile::from_path("target/plugin.so").unwrap();
+    /// let plugin = Plugin::<VTable>::load(&mut loader, object, &|_| None).unwrap();
+    /// let interface = plugin.interface();
+    /// drop(plugin);
+    /// (interface.run)(42);
+    /// ```
+    #[inline]
+    pub fn interface(&self) -> &I {
+        unsafe { self.interface.as_ref() }
+    }
+
+    /// Gets the library of the plugin.
+    #[inline]
+    pub fn library(&self) -> &RelocatedDylib<'lib> {
+        &self.lib
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn plugin_error(lib: &RelocatedDylib, msg: String) -> Error {
+    Error::PluginError {
+        lib_name: lib.name().to_string(),
+        msg: format!("plugin {}: {}", lib.shortname(), msg),
+    }
+}
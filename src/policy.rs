@@ -0,0 +1,158 @@
+//! Policies for the elf objects being loaded
+use crate::{Error, Result};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+};
+
+/// A policy deciding which sonames the dependencies(`DT_NEEDED`) of an elf object may have.
+///
+/// # Examples
+/// ```
+/// use elf_loader::policy::SonamePolicy;
+///
+/// let policy = SonamePolicy::new().pin("libssl.so", "libssl.so.3");
+/// assert!(policy.check("libplugin.so", "libssl.so.3").is_ok());
+/// assert!(policy.check("libplugin.so", "libssl.so.1.1").is_err());
+/// // libraries that are not pinned are allowed unless the policy is strict
+/// assert!(policy.check("libplugin.so", "libc.so.6").is_ok());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SonamePolicy {
+    /// stem -> the only allowed soname
+    pins: BTreeMap<String, String>,
+    allowed: BTreeSet<String>,
+    strict: bool,
+}
+
+/// Gets the soname without the version, such as `libssl.so` for `libssl.so.3`.
+#[inline]
+fn soname_stem(soname: &str) -> &str {
+    soname.find(".so").map_or(soname, |idx| &soname[..idx + 3])
+}
+
+impl SonamePolicy {
+    /// Creates a policy that allows every dependency.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins all versions of a library to `soname`. `stem` is the soname without the version,
+    /// such as `libssl.so`. Dependencies with the same stem but a different soname are rejected.
+    pub fn pin(mut self, stem: &str, soname: &str) -> Self {
+        self.pins.insert(stem.to_string(), soname.to_string());
+        self
+    }
+
+    /// Allows the dependency with exactly this soname.
+    pub fn allow(mut self, soname: &str) -> Self {
+        self.allowed.insert(soname.to_string());
+        self
+    }
+
+    /// When the policy is strict, dependencies which are neither allowed nor pinned are rejected.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Checks the dependency `needed` of the elf object `lib_name`.
+    pub fn check(&self, lib_name: &str, needed: &str) -> Result<()> {
+        if self.allowed.contains(needed) {
+            return Ok(());
+        }
+        if let Some(pinned) = self.pins.get(soname_stem(needed)) {
+            if pinned == needed {
+                return Ok(());
+            }
+            return Err(dependency_error(
+                lib_name,
+                needed,
+                format!("{needed} needed by {lib_name} is pinned to {pinned}"),
+            ));
+        }
+        if self.strict {
+            return Err(dependency_error(
+                lib_name,
+                needed,
+                format!("{needed} needed by {lib_name} is not allowed"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cold]
+#[inline(never)]
+pub(crate) fn dependency_error(lib_name: &str, needed: &str, msg: String) -> Error {
+    Error::DependencyError {
+        lib_name: lib_name.to_string(),
+        needed: needed.to_string(),
+        msg,
+    }
+}
+
+/// A policy deciding whether elf objects requesting an executable stack may be loaded, like the
+/// `glibc.rtld.execstack` tunable of glibc.
+///
+/// An elf object requests an executable stack when its `PT_GNU_STACK` has `PF_X`, or when it has no
+/// `PT_GNU_STACK` on architectures where the stack is executable by default. The loader never changes
+/// the protection of the stack itself.
+#[derive(Default)]
+pub enum ExecStackPolicy {
+    /// Loads such elf objects.
+    #[default]
+    Allow,
+    /// Rejects such elf objects with `Error::ExecStackDenied`.
+    Deny,
+    /// Calls the function with the name of such an elf object, which can make the stack executable
+    /// and returns whether the elf object may be loaded.
+    Callback(Box<dyn Fn(&str) -> bool + Send>),
+}
+
+impl ExecStackPolicy {
+    /// Checks the elf object `lib_name`, which requests an executable stack if `exec_stack` is true.
+    pub fn check(&self, lib_name: &str, exec_stack: bool) -> Result<()> {
+        let allowed = match self {
+            _ if !exec_stack => true,
+            ExecStackPolicy::Allow => true,
+            ExecStackPolicy::Deny => false,
+            ExecStackPolicy::Callback(callback) => callback(lib_name),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::ExecStackDenied {
+                lib_name: lib_name.to_string(),
+            })
+        }
+    }
+}
+
+/// A policy deciding whether elf objects with text relocations(`DT_TEXTREL`) may be loaded.
+///
+/// Relocating such an elf object makes its read-only segments writable until the relocation is
+/// finished, which may be forbidden by embedders enforcing W^X.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextRelPolicy {
+    /// Loads such elf objects.
+    #[default]
+    Allow,
+    /// Rejects such elf objects with `Error::TextRelDenied`.
+    Deny,
+}
+
+impl TextRelPolicy {
+    /// Checks the elf object `lib_name`, which has text relocations if `textrel` is true.
+    pub fn check(&self, lib_name: &str, textrel: bool) -> Result<()> {
+        if textrel && *self == TextRelPolicy::Deny {
+            Err(Error::TextRelDenied {
+                lib_name: lib_name.to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
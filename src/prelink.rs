@@ -0,0 +1,570 @@
+//! Caching the relocation results of dynamic libraries
+//!
+//! Relocating a dynamic library mostly consists of looking up symbols. When the same library is
+//! loaded again, e.g. on every boot of a virtual machine, the lookups can be skipped by replaying
+//! the results of a previous load. [`ElfDylib::relocate_recorded`] relocates a library and returns a
+//! [`RelocationCache`], which records every written word either relative to the base of the library
+//! or as an offset into a library of the scope. [`ElfDylib::relocate_cached`] writes the recorded
+//! words at the new base without looking up any symbol in the symbol tables.
+//!
+//! The cache is identified by the GNU build-id of the library, or by a hash of its read-only
+//! segments if it has no build-id. The libraries of the scope the symbols were bound to are
+//! identified in the same way, and the cache is rejected if one of them has changed. IFUNC
+//! resolvers are called again when the cache is replayed.
+//!
+//! # Note
+//! Only libraries with lazy binding disabled can be cached, and relocations of tls variables and
+//! copy relocations are not supported.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{load_dylib, prelink::RelocationCache};
+//!
+//! let liba = load_dylib!("target/liba.so", lazy: false).unwrap();
+//! let (liba, cache) = liba.relocate_recorded([].iter(), &|_| None).unwrap();
+//! let bytes = cache.to_bytes();
+//! // on the next boot
+//! let cache = RelocationCache::from_bytes(&bytes).unwrap();
+//! let liba = load_dylib!("target/liba.so", lazy: false).unwrap();
+//! let liba = liba.relocate_cached(&cache, [].iter(), &|_| None).unwrap();
+//! ```
+use crate::{
+    CoreComponent, ElfDylib, Error, RelocFailure, RelocatedDylib, Result,
+    arch::*,
+    format::ElfCommonPart,
+    relocation::{begin_relocation, finish_relocation, is_own_ifunc, unresolved_error, write_val},
+    symbol::SymbolTable,
+    verify::find_build_id,
+};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::ops::Range;
+use elf::abi::{PF_W, PT_LOAD, PT_NOTE};
+
+const MAGIC: &[u8; 4] = b"ELRC";
+const VERSION: u32 = 2;
+
+/// A symbol resolved when the cache was recorded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedSymbol {
+    name: String,
+    lib: Option<String>,
+    offset: usize,
+}
+
+impl CachedSymbol {
+    /// Gets the name of the symbol.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the file name of the library defining the symbol, or `None` if the symbol was found by
+    /// `pre_find`.
+    #[inline]
+    pub fn lib(&self) -> Option<&str> {
+        self.lib.as_deref()
+    }
+
+    /// Gets the offset of the symbol from the base of the library defining it. For a symbol found by
+    /// `pre_find`, it is the offset from the address returned by `pre_find`.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// A word written by the relocation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Patch {
+    /// The base of the library plus `addend` is written at `offset`.
+    Relative { offset: usize, addend: usize },
+    /// The address of the symbol `symbol` of the cache is written at `offset`.
+    Symbol { offset: usize, symbol: usize },
+    /// `value` is written at `offset`, which is used for undefined weak symbols.
+    Absolute { offset: usize, value: usize },
+}
+
+/// The relocation results of a dynamic library, see the [module-level documentation](self)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelocationCache {
+    key: Vec<u8>,
+    // 符号所在的库的文件名 -> 库的key
+    deps: BTreeMap<String, Vec<u8>>,
+    patches: Vec<Patch>,
+    symbols: Vec<CachedSymbol>,
+}
+
+impl RelocationCache {
+    /// Gets the key identifying the library, which is its build-id or a hash of its read-only segments.
+    #[inline]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Gets the key of the library `name` of the scope when the cache was recorded.
+    #[inline]
+    pub fn dependency_key(&self, name: &str) -> Option<&[u8]> {
+        self.deps.get(name).map(|key| key.as_slice())
+    }
+
+    /// Gets the words written by the relocation.
+    #[inline]
+    pub fn patches(&self) -> &[Patch] {
+        &self.patches
+    }
+
+    /// Gets the symbols the library was bound to.
+    #[inline]
+    pub fn symbols(&self) -> &[CachedSymbol] {
+        &self.symbols
+    }
+
+    /// Whether the cache was recorded for `lib`.
+    pub fn matches(&self, lib: &ElfCommonPart) -> bool {
+        self.key == cache_key(lib)
+    }
+
+    /// Serializes the cache.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        put_u32(&mut bytes, VERSION);
+        put_bytes(&mut bytes, &self.key);
+        put_u32(&mut bytes, self.deps.len() as u32);
+        for (name, key) in &self.deps {
+            put_bytes(&mut bytes, name.as_bytes());
+            put_bytes(&mut bytes, key);
+        }
+        put_u32(&mut bytes, self.symbols.len() as u32);
+        for symbol in &self.symbols {
+            put_bytes(&mut bytes, symbol.name.as_bytes());
+            match &symbol.lib {
+                Some(lib) => {
+                    bytes.push(1);
+                    put_bytes(&mut bytes, lib.as_bytes());
+                }
+                None => bytes.push(0),
+            }
+            put_u64(&mut bytes, symbol.offset as u64);
+        }
+        put_u32(&mut bytes, self.patches.len() as u32);
+        for patch in &self.patches {
+            let (kind, offset, value) = match *patch {
+                Patch::Relative { offset, addend } => (0, offset, addend),
+                Patch::Symbol { offset, symbol } => (1, offset, symbol),
+                Patch::Absolute { offset, value } => (2, offset, value),
+            };
+            bytes.push(kind);
+            put_u64(&mut bytes, offset as u64);
+            put_u64(&mut bytes, value as u64);
+        }
+        bytes
+    }
+
+    /// Deserializes a cache serialized by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return Err(cache_error("unsupported relocation cache format"));
+        }
+        let key = reader.bytes()?.to_vec();
+        let mut deps = BTreeMap::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.str()?;
+            deps.insert(name, reader.bytes()?.to_vec());
+        }
+        let nsymbols = reader.u32()?;
+        let mut symbols = Vec::new();
+        for _ in 0..nsymbols {
+            let name = reader.str()?;
+            let lib = match reader.take(1)?[0] {
+                0 => None,
+                _ => Some(reader.str()?),
+            };
+            let offset = reader.u64()? as usize;
+            symbols.push(CachedSymbol { name, lib, offset });
+        }
+        let npatches = reader.u32()?;
+        let mut patches = Vec::new();
+        for _ in 0..npatches {
+            let kind = reader.take(1)?[0];
+            let offset = reader.u64()? as usize;
+            let value = reader.u64()? as usize;
+            patches.push(match kind {
+                0 => Patch::Relative {
+                    offset,
+                    addend: value,
+                },
+                1 if value < symbols.len() => Patch::Symbol {
+                    offset,
+                    symbol: value,
+                },
+                2 => Patch::Absolute { offset, value },
+                _ => return Err(cache_error("invalid patch in relocation cache")),
+            });
+        }
+        if !reader.0.is_empty() {
+            return Err(cache_error("trailing bytes in relocation cache"));
+        }
+        Ok(Self {
+            key,
+            deps,
+            patches,
+            symbols,
+        })
+    }
+}
+
+impl ElfDylib {
+    /// Relocates the dynamic library like `easy_relocate`, and records the results in a
+    /// `RelocationCache` before the init functions are called.
+    ///
+    /// # Note
+    /// The library must be loaded with lazy binding disabled.
+    pub fn relocate_recorded<'iter, 'scope, 'find, 'lib, S, F>(
+        self,
+        scope: S,
+        pre_find: &'find F,
+    ) -> Result<(RelocatedDylib<'lib>, RelocationCache)>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone,
+        F: Fn(&str) -> Option<*const ()>,
+        'scope: 'iter,
+        'iter: 'lib,
+        'find: 'lib,
+    {
+        check_eager(&self.common)?;
+        let libs: Vec<&CoreComponent> = scope.clone().map(|lib| &**lib).collect();
+        let mut cache = None;
+        let mut recorder = |common: &ElfCommonPart| {
+            cache = Some(record(common, &libs, pre_find)?);
+            Ok(())
+        };
+        let lib = self.relocate_inner(
+            scope,
+            pre_find,
+            |_, _, _| Err(Box::new(())),
+            None,
+            None,
+            Some(&mut recorder),
+        )?;
+        Ok((lib, cache.unwrap()))
+    }
+
+    /// Relocates the dynamic library by replaying `cache`. The symbols of the cache are found in the
+    /// libraries of `scope` by file name, and the ones found by `pre_find` when the cache was
+    /// recorded are looked up in `pre_find` again.
+    ///
+    /// Returns an error if the cache was recorded for another library or against another version
+    /// of a library of the scope, in which case the library should be loaded again and relocated
+    /// normally.
+    pub fn relocate_cached<'iter, 'scope, 'find, 'lib, S, F>(
+        self,
+        cache: &RelocationCache,
+        scope: S,
+        pre_find: &'find F,
+    ) -> Result<RelocatedDylib<'lib>>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>> + Clone,
+        F: Fn(&str) -> Option<*const ()>,
+        'scope: 'iter,
+        'iter: 'lib,
+        'find: 'lib,
+    {
+        let common = self.common;
+        check_eager(&common)?;
+        if !cache.matches(&common) {
+            return Err(cache_error(format!(
+                "the relocation cache does not match {}",
+                common.shortname()
+            )));
+        }
+        // 符号的偏移只对记录时的库有效
+        for lib in scope.clone() {
+            let Some(key) = cache.deps.get(lib.shortname()) else {
+                continue;
+            };
+            if *key != cache_key(lib) {
+                return Err(cache_error(format!(
+                    "the relocation cache of {} was recorded against another {}",
+                    common.shortname(),
+                    lib.shortname()
+                )));
+            }
+        }
+        begin_relocation(&common)?;
+        // 缓存来自外部,需要与重定位表一样检查写入的位置
+        let writable = writable_ranges(&common);
+        for patch in &cache.patches {
+            let (Patch::Relative { offset, .. }
+            | Patch::Symbol { offset, .. }
+            | Patch::Absolute { offset, .. }) = *patch;
+            let valid = offset.checked_add(size_of::<usize>()).is_some_and(|end| {
+                writable
+                    .iter()
+                    .any(|range| range.start <= offset && end <= range.end)
+            });
+            if !valid {
+                return Err(cache_error(format!(
+                    "the relocation cache of {} writes outside the writable segments",
+                    common.shortname()
+                )));
+            }
+        }
+        // 每个符号只需要根据库名查找一次
+        let mut failures = Vec::new();
+        let symbols: Vec<usize> = cache
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let addr = match &symbol.lib {
+                    Some(name) => scope
+                        .clone()
+                        .find(|lib| lib.shortname() == name)
+                        .map(|lib| lib.base()),
+                    None => pre_find(&symbol.name).map(|addr| addr as usize),
+                };
+                addr.map(|addr| addr.wrapping_add(symbol.offset))
+                    .unwrap_or_else(|| {
+                        failures.push(RelocFailure {
+                            r_type: 0,
+                            r_offset: 0,
+                            symbol: Some(symbol.name.clone()),
+                            custom_err: Box::new(()),
+                        });
+                        0
+                    })
+            })
+            .collect();
+        if !failures.is_empty() {
+            return Err(unresolved_error(&common, failures));
+        }
+        let base = common.base();
+        let mode = common.write_mode();
+        for patch in &cache.patches {
+            match *patch {
+                Patch::Relative { offset, addend } => {
+                    write_val(mode, base, offset, base.wrapping_add(addend))
+                }
+                Patch::Symbol { offset, symbol } => write_val(mode, base, offset, symbols[symbol]),
+                Patch::Absolute { offset, value } => write_val(mode, base, offset, value),
+            }
+        }
+        // IFUNC的结果不会被缓存,每次都重新调用解析函数
+        let symtab = common.symtab().unwrap();
+        let ifuncs: Vec<&ElfRela> = common
+            .relocation
+            .entries()
+            .filter(|rela| is_ifunc(symtab, rela))
+            .collect();
+        Ok(RelocatedDylib {
+            core: finish_relocation(common, None, Vec::new(), &ifuncs, None)?,
+        })
+    }
+}
+
+fn record<F>(
+    common: &ElfCommonPart,
+    libs: &[&CoreComponent],
+    pre_find: &F,
+) -> Result<RelocationCache>
+where
+    F: Fn(&str) -> Option<*const ()>,
+{
+    let base = common.base();
+    let mode = common.write_mode();
+    let own = common.map_range();
+    let symtab = common.symtab().unwrap();
+    let mut patches = Vec::new();
+    let mut symbols = Vec::new();
+    let mut deps = BTreeMap::new();
+    let mut indices = BTreeMap::new();
+    for rela in common.relocation.entries() {
+        let r_type = rela.r_type() as u32;
+        if r_type == REL_NONE || is_ifunc(symtab, rela) {
+            continue;
+        }
+        let offset = rela.r_offset();
+        if r_type == REL_RELATIVE {
+            patches.push(Patch::Relative {
+                offset,
+                addend: rela.r_addend(),
+            });
+            continue;
+        }
+        if !matches!(r_type, REL_GOT | REL_SYMBOLIC | REL_JUMP_SLOT) {
+            return Err(cache_error(format!(
+                "relocation type {r_type} of {} can not be cached",
+                common.shortname()
+            )));
+        }
+        let value = unsafe { mode.read((base + offset) as *const usize) };
+        if own.contains(&value) {
+            patches.push(Patch::Relative {
+                offset,
+                addend: value - base,
+            });
+            continue;
+        }
+        let (dynsym, syminfo) = symtab.symbol_idx(rela.r_symbol());
+        let name = syminfo.name();
+        // 按照地址找到定义符号的库,找不到时符号来自pre_find
+        let symbol = if let Some(lib) = libs.iter().find(|lib| lib.map_range().contains(&value)) {
+            deps.entry(lib.shortname().to_string())
+                .or_insert_with(|| cache_key(lib));
+            CachedSymbol {
+                name: name.to_string(),
+                lib: Some(lib.shortname().to_string()),
+                offset: value - lib.base(),
+            }
+        } else if let Some(addr) = pre_find(name) {
+            CachedSymbol {
+                name: name.to_string(),
+                lib: None,
+                offset: value.wrapping_sub(addr as usize),
+            }
+        } else if value == 0 && dynsym.is_weak() {
+            // 未定义的弱符号解析为0
+            patches.push(Patch::Absolute { offset, value });
+            continue;
+        } else {
+            return Err(cache_error(format!(
+                "symbol {name} of {} is not defined in the scope",
+                common.shortname()
+            )));
+        };
+        let idx = *indices
+            .entry((symbol.name.clone(), symbol.lib.clone(), symbol.offset))
+            .or_insert_with(|| {
+                symbols.push(symbol);
+                symbols.len() - 1
+            });
+        patches.push(Patch::Symbol {
+            offset,
+            symbol: idx,
+        });
+    }
+    Ok(RelocationCache {
+        key: cache_key(common),
+        deps,
+        patches,
+        symbols,
+    })
+}
+
+#[inline]
+fn is_ifunc(symtab: &SymbolTable, rela: &ElfRela) -> bool {
+    let r_type = rela.r_type() as u32;
+    r_type == REL_IRELATIVE
+        || (matches!(r_type, REL_GOT | REL_SYMBOLIC | REL_JUMP_SLOT)
+            && is_own_ifunc(symtab, rela.r_symbol()))
+}
+
+fn check_eager(common: &ElfCommonPart) -> Result<()> {
+    if common.is_lazy() {
+        return Err(cache_error(format!(
+            "{} is loaded with lazy binding, which can not be cached",
+            common.shortname()
+        )));
+    }
+    Ok(())
+}
+
+fn writable_ranges(common: &ElfCommonPart) -> Vec<Range<usize>> {
+    common
+        .phdrs()
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_W != 0)
+        .map(|phdr| phdr.p_vaddr as usize..(phdr.p_vaddr + phdr.p_memsz) as usize)
+        .collect()
+}
+
+/// 使用build-id作为缓存的key,没有build-id时使用只读段的FNV-1a哈希
+fn cache_key(lib: &CoreComponent) -> Vec<u8> {
+    let segments = lib.elf_segments();
+    let build_id = lib
+        .phdrs()
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_NOTE)
+        .find_map(|phdr| {
+            let notes = segments.get_slice::<u8>(phdr.p_vaddr as usize, phdr.p_filesz as usize);
+            find_build_id(notes, phdr.p_align as usize)
+        });
+    if let Some(build_id) = build_id {
+        return build_id.to_vec();
+    }
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for phdr in lib
+        .phdrs()
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_W == 0)
+    {
+        let bytes = segments.get_slice::<u8>(phdr.p_vaddr as usize, phdr.p_filesz as usize);
+        for byte in bytes {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash.to_le_bytes().to_vec()
+}
+
+#[inline]
+fn put_u32(bytes: &mut Vec<u8>, val: u32) {
+    bytes.extend_from_slice(&val.to_le_bytes());
+}
+
+#[inline]
+fn put_u64(bytes: &mut Vec<u8>, val: u64) {
+    bytes.extend_from_slice(&val.to_le_bytes());
+}
+
+#[inline]
+fn put_bytes(bytes: &mut Vec<u8>, val: &[u8]) {
+    put_u32(bytes, val.len() as u32);
+    bytes.extend_from_slice(val);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(cache_error("truncated relocation cache"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        core::str::from_utf8(self.bytes()?)
+            .map(|s| s.to_string())
+            .map_err(|_| cache_error("invalid string in relocation cache"))
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn cache_error(msg: impl ToString) -> Error {
+    Error::CacheError {
+        msg: msg.to_string(),
+    }
+}
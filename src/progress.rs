@@ -0,0 +1,204 @@
+//! Tracking the progress of relocation passes
+//!
+//! A relocation pass may not be able to handle every entry at once, for example when a symbol is
+//! provided by a library that has not been loaded yet. `RelocateState` records which entries are
+//! still pending, so the pass can be run again later only for the entries that failed.
+//!
+//! # Examples
+//! ```
+//! use elf_loader::progress::RelocateState;
+//!
+//! let mut state = RelocateState::new(4);
+//! // the first pass can only handle even entries
+//! state.retry(|idx| idx % 2 == 0);
+//! assert_eq!(state.pending().collect::<Vec<_>>(), [1, 3]);
+//! // the second pass handles the rest
+//! state.retry(|_| true);
+//! assert!(state.is_done());
+//! ```
+use crate::arena::{Arena, ArenaVec};
+use alloc::vec::Vec;
+
+const BITS: usize = usize::BITS as usize;
+
+/// A fixed-size set of bits.
+#[derive(Clone, Debug)]
+pub struct BitMap {
+    bits: ArenaVec<usize>,
+    len: usize,
+}
+
+impl BitMap {
+    /// Creates a bitmap of `len` bits which are all cleared.
+    pub fn new(len: usize) -> Self {
+        Self::new_in(len, false, None)
+    }
+
+    /// Creates a bitmap of `len` bits which are all set.
+    pub fn new_set(len: usize) -> Self {
+        Self::new_in(len, true, None)
+    }
+
+    // 位图的内存从arena中分配
+    pub(crate) fn new_in(len: usize, set: bool, arena: Option<&'static dyn Arena>) -> Self {
+        let word = if set { usize::MAX } else { 0 };
+        let mut bits = ArenaVec::from_elem(word, len.div_ceil(BITS), arena);
+        if set && len % BITS != 0 {
+            if let Some(last) = bits.last_mut() {
+                *last = (1 << (len % BITS)) - 1;
+            }
+        }
+        BitMap { bits, len }
+    }
+
+    /// Gets the number of bits in the bitmap.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the bitmap has no bits.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the bit at `idx`.
+    #[inline]
+    pub fn get(&self, idx: usize) -> bool {
+        assert!(idx < self.len);
+        self.bits[idx / BITS] & (1 << (idx % BITS)) != 0
+    }
+
+    /// Sets the bit at `idx`.
+    #[inline]
+    pub fn set(&mut self, idx: usize) {
+        assert!(idx < self.len);
+        self.bits[idx / BITS] |= 1 << (idx % BITS);
+    }
+
+    /// Clears the bit at `idx`.
+    #[inline]
+    pub fn clear(&mut self, idx: usize) {
+        assert!(idx < self.len);
+        self.bits[idx / BITS] &= !(1 << (idx % BITS));
+    }
+
+    /// Gets the number of set bits.
+    #[inline]
+    pub fn count_ones(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Iterates over the indices of the set bits in ascending order.
+    #[inline]
+    pub fn iter_ones(&self) -> Ones<'_> {
+        Ones {
+            bits: &self.bits,
+            word_idx: 0,
+            cur: self.bits.first().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// An iterator over the indices of the set bits of a `BitMap`.
+pub struct Ones<'bitmap> {
+    bits: &'bitmap [usize],
+    word_idx: usize,
+    cur: usize,
+}
+
+impl Iterator for Ones<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.cur == 0 {
+            self.word_idx += 1;
+            self.cur = *self.bits.get(self.word_idx)?;
+        }
+        let bit = self.cur.trailing_zeros() as usize;
+        // 清除最低位的1
+        self.cur &= self.cur - 1;
+        Some(self.word_idx * BITS + bit)
+    }
+}
+
+/// The progress of a relocation pass over a table of `len` entries.
+#[derive(Clone, Debug)]
+pub struct RelocateState {
+    pending: BitMap,
+}
+
+impl RelocateState {
+    /// Creates a state in which all `len` entries are pending.
+    pub fn new(len: usize) -> Self {
+        RelocateState {
+            pending: BitMap::new_set(len),
+        }
+    }
+
+    /// Creates a state in which none of the `len` entries is pending.
+    pub fn new_done(len: usize) -> Self {
+        RelocateState {
+            pending: BitMap::new(len),
+        }
+    }
+
+    pub(crate) fn new_in(len: usize, pending: bool, arena: Option<&'static dyn Arena>) -> Self {
+        RelocateState {
+            pending: BitMap::new_in(len, pending, arena),
+        }
+    }
+
+    /// Marks the entry at `idx` as pending again.
+    #[inline]
+    pub fn reset(&mut self, idx: usize) {
+        self.pending.set(idx);
+    }
+
+    /// Marks the entry at `idx` as done.
+    #[inline]
+    pub fn finish(&mut self, idx: usize) {
+        self.pending.clear(idx);
+    }
+
+    /// Whether the entry at `idx` is still pending.
+    #[inline]
+    pub fn is_pending(&self, idx: usize) -> bool {
+        self.pending.get(idx)
+    }
+
+    /// Whether all entries are done.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Gets the number of pending entries.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.pending.count_ones()
+    }
+
+    /// Iterates over the indices of the pending entries in ascending order.
+    #[inline]
+    pub fn pending(&self) -> Ones<'_> {
+        self.pending.iter_ones()
+    }
+
+    /// Runs `f` on every pending entry and marks the entry as done if `f` returns true.
+    /// Returns the number of entries that are still pending.
+    pub fn retry<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let done: Vec<usize> = self.pending().filter(|idx| f(*idx)).collect();
+        for idx in done {
+            self.finish(idx);
+        }
+        self.remaining()
+    }
+}
@@ -0,0 +1,105 @@
+//! GNU program properties
+//!
+//! Elf objects built with `-fcf-protection` on x86_64 or with `-mbranch-protection` on aarch64
+//! carry a `.note.gnu.property` section described by `PT_GNU_PROPERTY`. The loader reads the
+//! feature bits of the current architecture from it, so the embedder can enable shadow stacks or
+//! BTI page protections through `Mmap::exec_prot`.
+use crate::{mmap::ProtFlags, note::Notes};
+
+/// `NT_GNU_PROPERTY_TYPE_0`
+const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+/// `GNU_PROPERTY_AARCH64_FEATURE_1_AND`
+const GNU_PROPERTY_AARCH64_FEATURE_1_AND: u32 = 0xc0000000;
+/// `GNU_PROPERTY_X86_FEATURE_1_AND`
+const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+
+/// `GNU_PROPERTY_X86_FEATURE_1_IBT`
+pub const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 1 << 0;
+/// `GNU_PROPERTY_X86_FEATURE_1_SHSTK`
+pub const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 1 << 1;
+/// `GNU_PROPERTY_AARCH64_FEATURE_1_BTI`
+pub const GNU_PROPERTY_AARCH64_FEATURE_1_BTI: u32 = 1 << 0;
+/// `GNU_PROPERTY_AARCH64_FEATURE_1_PAC`
+pub const GNU_PROPERTY_AARCH64_FEATURE_1_PAC: u32 = 1 << 1;
+
+/// The GNU properties of an elf object
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GnuProperty {
+    /// The bits of `GNU_PROPERTY_X86_FEATURE_1_AND`.
+    pub x86_feature_1: u32,
+    /// The bits of `GNU_PROPERTY_AARCH64_FEATURE_1_AND`.
+    pub aarch64_feature_1: u32,
+}
+
+impl GnuProperty {
+    /// Whether the elf object supports indirect branch tracking(x86_64).
+    #[inline]
+    pub fn ibt(&self) -> bool {
+        self.x86_feature_1 & GNU_PROPERTY_X86_FEATURE_1_IBT != 0
+    }
+
+    /// Whether the elf object supports shadow stacks(x86_64).
+    #[inline]
+    pub fn shstk(&self) -> bool {
+        self.x86_feature_1 & GNU_PROPERTY_X86_FEATURE_1_SHSTK != 0
+    }
+
+    /// Whether the elf object supports branch target identification(aarch64).
+    #[inline]
+    pub fn bti(&self) -> bool {
+        self.aarch64_feature_1 & GNU_PROPERTY_AARCH64_FEATURE_1_BTI != 0
+    }
+
+    /// Whether the elf object uses pointer authentication(aarch64).
+    #[inline]
+    pub fn pac(&self) -> bool {
+        self.aarch64_feature_1 & GNU_PROPERTY_AARCH64_FEATURE_1_PAC != 0
+    }
+
+    /// Gets the protection of the executable segments suggested for the current architecture,
+    /// which is `PROT_BTI` for elf objects supporting BTI on aarch64.
+    pub fn prot_hint(&self) -> ProtFlags {
+        if cfg!(target_arch = "aarch64") && self.bti() {
+            ProtFlags::PROT_BTI
+        } else {
+            ProtFlags::PROT_NONE
+        }
+    }
+
+    /// Parses the contents of a `PT_GNU_PROPERTY` segment.
+    pub(crate) fn parse(notes: &[u8], align: usize) -> Self {
+        let mut property = Self::default();
+        for note in Notes::new(notes, align) {
+            if note.n_type == NT_GNU_PROPERTY_TYPE_0 && note.name == b"GNU\0" {
+                property.parse_desc(note.desc, align.max(4));
+            }
+        }
+        property
+    }
+
+    // 每一项由pr_type, pr_datasz和按段的对齐方式对齐的数据组成
+    fn parse_desc(&mut self, mut desc: &[u8], align: usize) {
+        while desc.len() >= 8 {
+            let pr_type = u32::from_ne_bytes(desc[0..4].try_into().unwrap());
+            let pr_datasz = u32::from_ne_bytes(desc[4..8].try_into().unwrap()) as usize;
+            let Some(data) = desc.get(8..8 + pr_datasz) else {
+                break;
+            };
+            if data.len() >= 4 {
+                let bits = u32::from_ne_bytes(data[0..4].try_into().unwrap());
+                // 处理器相关的pr_type在不同架构上含义不同
+                match pr_type {
+                    GNU_PROPERTY_X86_FEATURE_1_AND if cfg!(target_arch = "x86_64") => {
+                        self.x86_feature_1 = bits
+                    }
+                    GNU_PROPERTY_AARCH64_FEATURE_1_AND if cfg!(target_arch = "aarch64") => {
+                        self.aarch64_feature_1 = bits
+                    }
+                    _ => {}
+                }
+            }
+            let next = (8 + pr_datasz + align - 1) & !(align - 1);
+            desc = desc.get(next.min(desc.len())..).unwrap_or(&[]);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Rebinding the GOT entries of relocated libraries
+//!
+//! [`RelocatedDylib::rebind`] redirects the calls and references of a library to one of its
+//! imported symbols by replacing the `R_*_JUMP_SLOT` and `R_*_GLOB_DAT` entries of the symbol in
+//! its GOT, which is useful for live-patching. Each entry is swapped atomically, and entries in a
+//! protected `PT_GNU_RELRO` segment are made writable through the `Mmap` implementation of the
+//! loader for the duration of the write. The returned [`PreviousBinding`] undoes the change.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::load_dylib;
+//!
+//! extern "C" fn patched() -> i32 {
+//!     42
+//! }
+//!
+//! let liba = load_dylib!("target/liba.so").unwrap();
+//! let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+//! let libb = load_dylib!("target/libb.so").unwrap();
+//! let libb = libb.easy_relocate([&liba].into_iter(), &|_| None).unwrap();
+//! let previous = unsafe { libb.rebind("a", patched as *const ()) }.unwrap();
+//! // calls of `a` from libb.so go to `patched`
+//! libb.restore(previous).unwrap();
+//! ```
+use crate::{
+    Error, RelocatedDylib, Result,
+    arch::{REL_GOT, REL_JUMP_SLOT},
+    mmap::ProtFlags,
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// 修改relro的权限时需要互斥,避免一个线程恢复只读后另一个线程仍在写入
+static LOCK: AtomicBool = AtomicBool::new(false);
+
+struct LockGuard;
+
+impl LockGuard {
+    fn lock() -> Self {
+        while LOCK
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        LockGuard
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        LOCK.store(false, Ordering::Release);
+    }
+}
+
+/// The GOT entries replaced by `RelocatedDylib::rebind`, which can only be restored on the library
+/// they were replaced in.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PreviousBinding {
+    symbol: String,
+    // 修改的库的generation
+    generation: usize,
+    // (got表项的地址, 原来的值)
+    slots: Vec<(usize, usize)>,
+}
+
+impl PreviousBinding {
+    /// Gets the name of the rebound symbol.
+    #[inline]
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Gets the address the symbol was bound to before, which is the address of the PLT stub if
+    /// the symbol was not bound yet with lazy binding.
+    #[inline]
+    pub fn previous(&self) -> *const () {
+        self.slots[0].1 as *const ()
+    }
+
+    /// Gets the addresses of the GOT entries which were replaced.
+    pub fn slots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.slots.iter().map(|&(slot, _)| slot)
+    }
+}
+
+impl RelocatedDylib<'_> {
+    /// Binds the imported symbol `name` to `addr` by replacing its GOT entries. It fails with
+    /// `Error::SymbolError` if the library does not import the symbol through the GOT.
+    ///
+    /// # Safety
+    /// `addr` must have the same type as the symbol, for example a function with the same signature.
+    pub unsafe fn rebind(&self, name: &str, addr: *const ()) -> Result<PreviousBinding> {
+        let slots = self.got_slots(name);
+        if slots.is_empty() {
+            return Err(rebind_error(
+                self,
+                name,
+                "the symbol is not imported through the got",
+            ));
+        }
+        let mut previous = PreviousBinding {
+            symbol: name.to_string(),
+            generation: self.generation(),
+            slots: Vec::with_capacity(slots.len()),
+        };
+        for slot in slots {
+            match self.swap_slot(name, slot, addr as usize) {
+                Ok(old) => previous.slots.push((slot, old)),
+                Err(err) => {
+                    // 撤销已经修改的表项
+                    let _ = self.restore(previous);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(previous)
+    }
+
+    /// Restores the GOT entries replaced by `rebind`. A later rebinding of the same symbol is
+    /// overwritten, so the bindings should be restored in reverse order. It fails if `previous` was
+    /// returned by another library, even one loaded at the same address.
+    pub fn restore(&self, previous: PreviousBinding) -> Result<()> {
+        if previous.generation != self.generation() {
+            return Err(rebind_error(
+                self,
+                &previous.symbol,
+                "the binding does not belong to the library",
+            ));
+        }
+        for &(slot, old) in previous.slots.iter().rev() {
+            self.swap_slot(&previous.symbol, slot, old)?;
+        }
+        Ok(())
+    }
+
+    /// 找到符号对应的所有got表项
+    fn got_slots(&self, name: &str) -> Vec<usize> {
+        let symtab = self.symtab();
+        let base = self.base();
+        self.relas()
+            .into_iter()
+            .filter(|rela| {
+                let r_type = rela.r_type() as u32;
+                (r_type == REL_JUMP_SLOT || r_type == REL_GOT)
+                    && rela.r_symbol() != 0
+                    && symtab.symbol_idx(rela.r_symbol()).1.name() == name
+            })
+            .map(|rela| base + rela.r_offset())
+            .collect()
+    }
+
+    /// 原子地替换got表项,位于已保护的relro中时临时解除保护
+    fn swap_slot(&self, name: &str, slot: usize, val: usize) -> Result<usize> {
+        if slot % size_of::<usize>() != 0 {
+            return Err(rebind_error(self, name, "the got entry is misaligned"));
+        }
+        let _guard = LockGuard::lock();
+        let relro = self.inner.relro.as_ref();
+        let unprotected = match relro {
+            Some(relro) => {
+                relro.protect_page(slot, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)?
+            }
+            None => false,
+        };
+        let old = unsafe { AtomicUsize::from_ptr(slot as *mut usize) }.swap(val, Ordering::AcqRel);
+        if unprotected {
+            relro.unwrap().protect_page(slot, ProtFlags::PROT_READ)?;
+        }
+        Ok(old)
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn rebind_error(lib: &RelocatedDylib, name: &str, msg: &'static str) -> Error {
+    Error::SymbolError {
+        lib_name: lib.name().to_string(),
+        symbol: name.to_string(),
+        msg,
+    }
+}
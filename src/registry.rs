@@ -0,0 +1,189 @@
+//! A registry of relocated elf objects for `dl_iterate_phdr`
+//!
+//! Unwinders such as libgcc and libunwind find the `.eh_frame` of a function by iterating over
+//! the loaded objects with `dl_iterate_phdr`. Elf objects loaded by this crate are registered here
+//! once they are relocated and removed when they are dropped, so [`iterate_phdr`] can report them.
+//!
+//! With the `use-libc` feature, [`dl_iterate_phdr`] first iterates over the objects known to the
+//! system dynamic linker and then over the objects in this registry. It can be given to the loaded
+//! code through `pre_find`. The `export-dl-iterate-phdr` feature also exports it as the
+//! `dl_iterate_phdr` symbol, which interposes the one of the C library in the whole process, so
+//! that the unwinder of the host can unwind through code loaded by `elf_loader`.
+use crate::{arch::ElfPhdr, format::CoreComponent};
+use alloc::vec::Vec;
+use core::{
+    cell::UnsafeCell,
+    ffi::{c_char, c_int, c_void},
+    ptr::null_mut,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+/// The information of an elf object passed to the callback, the same as `struct dl_phdr_info` in glibc
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DlPhdrInfo {
+    /// The base address of the object.
+    pub dlpi_addr: usize,
+    /// The name of the object.
+    pub dlpi_name: *const c_char,
+    /// The program headers of the object.
+    pub dlpi_phdr: *const ElfPhdr,
+    /// The number of program headers.
+    pub dlpi_phnum: u16,
+    /// The number of objects that have been loaded.
+    pub dlpi_adds: u64,
+    /// The number of objects that have been unloaded.
+    pub dlpi_subs: u64,
+    /// The tls module id of the object, or 0 if it has no tls.
+    pub dlpi_tls_modid: usize,
+    /// The tls block of the object in the calling thread. It is always null.
+    pub dlpi_tls_data: *mut c_void,
+}
+
+/// The callback of `dl_iterate_phdr`. A non-zero return value stops the iteration.
+pub type DlIterateCallback =
+    unsafe extern "C" fn(info: *mut DlPhdrInfo, size: usize, data: *mut c_void) -> c_int;
+
+struct Entry {
+    generation: usize,
+    info: DlPhdrInfo,
+}
+
+// 读者计数,WRITER表示有写者持有锁
+const WRITER: usize = usize::MAX;
+
+struct Registry {
+    state: AtomicUsize,
+    entries: UnsafeCell<Vec<Entry>>,
+}
+
+unsafe impl Sync for Registry {}
+
+impl Registry {
+    // 回调函数中可能会再次调用dl_iterate_phdr,因此读锁是可重入的
+    fn read<R>(&self, f: impl FnOnce(&[Entry]) -> R) -> R {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state == WRITER {
+                core::hint::spin_loop();
+                state = self.state.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(cur) => state = cur,
+            }
+        }
+        let res = f(unsafe { &*self.entries.get() });
+        self.state.fetch_sub(1, Ordering::Release);
+        res
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut Vec<Entry>) -> R) -> R {
+        while self
+            .state
+            .compare_exchange_weak(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let res = f(unsafe { &mut *self.entries.get() });
+        self.state.store(0, Ordering::Release);
+        res
+    }
+}
+
+static REGISTRY: Registry = Registry {
+    state: AtomicUsize::new(0),
+    entries: UnsafeCell::new(Vec::new()),
+};
+static ADDS: AtomicU64 = AtomicU64::new(0);
+static SUBS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn register(core: &CoreComponent) {
+    let phdrs = core.phdrs();
+    let entry = Entry {
+        generation: core.generation(),
+        info: DlPhdrInfo {
+            dlpi_addr: core.base(),
+            dlpi_name: core.cname().as_ptr(),
+            dlpi_phdr: phdrs.as_ptr(),
+            dlpi_phnum: phdrs.len() as u16,
+            dlpi_adds: 0,
+            dlpi_subs: 0,
+            dlpi_tls_modid: core.tls_modid().unwrap_or(0),
+            dlpi_tls_data: null_mut(),
+        },
+    };
+    REGISTRY.write(|entries| entries.push(entry));
+    ADDS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn unregister(generation: usize) {
+    let removed = REGISTRY.write(|entries| {
+        entries
+            .iter()
+            .position(|entry| entry.generation == generation)
+            .map(|idx| entries.remove(idx))
+            .is_some()
+    });
+    if removed {
+        SUBS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Calls `callback` for each registered elf object in the order they were relocated, until it
+/// returns a non-zero value, which is then returned.
+///
+/// # Safety
+/// `callback` must be safe to call with `data`. It must not load or drop elf objects.
+pub unsafe extern "C" fn iterate_phdr(callback: DlIterateCallback, data: *mut c_void) -> c_int {
+    REGISTRY.read(|entries| {
+        let adds = ADDS.load(Ordering::Relaxed);
+        let subs = SUBS.load(Ordering::Relaxed);
+        for entry in entries {
+            let mut info = DlPhdrInfo {
+                dlpi_adds: adds,
+                dlpi_subs: subs,
+                ..entry.info
+            };
+            let ret = unsafe { callback(&mut info, size_of::<DlPhdrInfo>(), data) };
+            if ret != 0 {
+                return ret;
+            }
+        }
+        0
+    })
+}
+
+/// Iterates over the objects loaded by the system dynamic linker and then over the objects in
+/// this registry.
+///
+/// # Safety
+/// See [`iterate_phdr`].
+#[cfg(feature = "use-libc")]
+#[cfg_attr(feature = "export-dl-iterate-phdr", unsafe(no_mangle))]
+pub unsafe extern "C" fn dl_iterate_phdr(callback: DlIterateCallback, data: *mut c_void) -> c_int {
+    use core::sync::atomic::AtomicPtr;
+    // 缓存系统动态链接器中的dl_iterate_phdr
+    static NEXT: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+    let mut next = NEXT.load(Ordering::Relaxed);
+    if next.is_null() {
+        next = unsafe { libc::dlsym(libc::RTLD_NEXT, c"dl_iterate_phdr".as_ptr()) };
+        NEXT.store(next, Ordering::Relaxed);
+    }
+    if !next.is_null() {
+        let next: unsafe extern "C" fn(DlIterateCallback, *mut c_void) -> c_int =
+            unsafe { core::mem::transmute(next) };
+        let ret = unsafe { next(callback, data) };
+        if ret != 0 {
+            return ret;
+        }
+    }
+    unsafe { iterate_phdr(callback, data) }
+}
@@ -1,27 +1,207 @@
 //! Relocation of elf objects
+#[cfg(feature = "stats")]
+use crate::stats::Timer;
 use crate::{
-    CoreComponent, Error, Result,
+    CoreComponent, Error, RelocFailure, RelocateErrorKind, Result,
     arch::*,
-    format::{CoreComponentInner, ElfCommonPart, Relocated},
+    format::{CoreComponentInner, ElfCommonPart, Relocated, dylib::RelocatedDylib},
+    progress::RelocateState,
     relocate_error,
     symbol::{SymbolInfo, SymbolTable},
+    tls::{ElfTls, TlsDescDynamic, TlsDescs, tlsdesc_dynamic, tlsdesc_return, tlsdesc_undefweak},
+    unique,
+};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
 };
-use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
 use core::{
     any::Any,
+    cell::RefCell,
     marker::PhantomData,
     num::NonZeroUsize,
+    ops::Range,
     ptr::null,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
 };
 use elf::abi::*;
 
 // lazy binding 时会先从这里寻找符号
 pub(crate) static GLOBAL_SCOPE: AtomicUsize = AtomicUsize::new(0);
 
+/// How the relocation results are written to the memory of the elf object
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Plain memory accesses.
+    #[default]
+    Plain,
+    /// Volatile memory accesses, for memory backed by devices.
+    Volatile,
+    /// Relaxed atomic memory accesses, for memory shared with other processes or threads.
+    /// Misaligned targets can not be accessed atomically and fall back to plain accesses.
+    AtomicRelaxed,
+}
+
+impl WriteMode {
+    #[inline(always)]
+    pub(crate) unsafe fn read(self, ptr: *const usize) -> usize {
+        unsafe {
+            match self {
+                WriteMode::Plain => ptr.read(),
+                WriteMode::Volatile => ptr.read_volatile(),
+                WriteMode::AtomicRelaxed if ptr.is_aligned() => {
+                    AtomicUsize::from_ptr(ptr as *mut usize).load(Ordering::Relaxed)
+                }
+                // 未对齐的地址无法进行原子访问
+                WriteMode::AtomicRelaxed => ptr.read_unaligned(),
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn write(self, ptr: *mut usize, val: usize) {
+        unsafe {
+            match self {
+                WriteMode::Plain => ptr.write(val),
+                WriteMode::Volatile => ptr.write_volatile(val),
+                WriteMode::AtomicRelaxed if ptr.is_aligned() => {
+                    AtomicUsize::from_ptr(ptr).store(val, Ordering::Relaxed)
+                }
+                WriteMode::AtomicRelaxed => ptr.write_unaligned(val),
+            }
+        }
+    }
+
+    #[inline]
+    fn copy(self, dest: &mut [u8], src: &[u8]) {
+        match self {
+            WriteMode::Plain => dest.copy_from_slice(src),
+            // 逐字节写入,保证每次访问都不会被合并或省略
+            WriteMode::Volatile => dest
+                .iter_mut()
+                .zip(src)
+                .for_each(|(dest, src)| unsafe { (dest as *mut u8).write_volatile(*src) }),
+            WriteMode::AtomicRelaxed => dest.iter_mut().zip(src).for_each(|(dest, src)| unsafe {
+                AtomicU8::from_ptr(dest).store(*src, Ordering::Relaxed)
+            }),
+        }
+    }
+}
+
+/// A PLT symbol whose lazy binding differs from the eager resolution, see `CoreComponent::binding_report`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BindingMismatch {
+    /// The name of the symbol.
+    pub name: String,
+    /// The address resolved eagerly when the elf object was relocated, or `None` if the symbol could not be resolved then.
+    pub eager: Option<*const ()>,
+    /// The address resolved by lazy binding.
+    pub lazy: *const (),
+}
+
+/// A relocation passed to the hook of `ElfDylib::relocate_with_hook`
+pub struct RelocateContext<'a> {
+    /// The elf object being relocated.
+    pub lib: &'a CoreComponent,
+    /// The relocation entry.
+    pub rela: &'a ElfRela,
+    /// The name of the symbol referenced by the relocation.
+    pub name: &'a str,
+    /// The address the symbol is resolved to by the loader, or `None` if it can not be resolved.
+    pub resolved: Option<*const ()>,
+}
+
+/// What to do with a relocation, returned by the hook of `ElfDylib::relocate_with_hook`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocateAction {
+    /// Use the address resolved by the loader.
+    Default,
+    /// Write the given address instead.
+    Replace(*const ()),
+}
+
+pub(crate) type RelocateHook<'hook> = &'hook dyn Fn(&RelocateContext) -> RelocateAction;
+
+// 超过这个数量的REL_RELATIVE会被并行处理
+#[cfg(feature = "rayon")]
+const PARALLEL_THRESHOLD: usize = 0x10000;
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK: usize = 0x1000;
+
+// 在eager时无法解析的符号
+const UNRESOLVED: usize = usize::MAX;
+
+/// 延迟绑定时记录每个plt重定位eager和lazy两种方式解析到的地址
+pub(crate) struct BindingReport {
+    eager: Box<[AtomicUsize]>,
+    // 0表示还未进行延迟绑定
+    lazy: Box<[AtomicUsize]>,
+}
+
+impl BindingReport {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            eager: (0..len).map(|_| AtomicUsize::new(UNRESOLVED)).collect(),
+            lazy: (0..len).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.eager.len()
+    }
+
+    pub(crate) fn mismatches(
+        &self,
+        symtab: &SymbolTable,
+        pltrel: &[ElfRela],
+    ) -> Vec<BindingMismatch> {
+        self.eager
+            .iter()
+            .zip(self.lazy.iter())
+            .zip(pltrel)
+            .filter_map(|((eager, lazy), rela)| {
+                let eager = eager.load(Ordering::Relaxed);
+                let lazy = lazy.load(Ordering::Relaxed);
+                if lazy == 0 || eager == lazy {
+                    return None;
+                }
+                Some(BindingMismatch {
+                    name: symtab.symbol_idx(rela.r_symbol()).1.name().to_string(),
+                    eager: (eager != UNRESOLVED).then_some(eager as *const ()),
+                    lazy: lazy as *const (),
+                })
+            })
+            .collect()
+    }
+}
+
+/// 一次重定位中符号的解析结果,同一个符号只会被查找一次
+#[derive(Default)]
+pub(crate) struct SymbolCache {
+    // 符号在动态符号表中的下标 -> 符号的地址
+    symbols: BTreeMap<usize, *const ()>,
+}
+
+impl SymbolCache {
+    #[inline]
+    fn find(&mut self, r_sym: usize, f: impl FnOnce() -> Option<*const ()>) -> Option<*const ()> {
+        if let Some(symbol) = self.symbols.get(&r_sym) {
+            return Some(*symbol);
+        }
+        let symbol = f()?;
+        self.symbols.insert(r_sym, symbol);
+        Some(symbol)
+    }
+}
+
 pub(crate) struct SymDef<'temp> {
     pub(crate) sym: Option<&'temp ElfSymbol>,
     pub(crate) base: usize,
+    pub(crate) tls: Option<&'temp ElfTls>,
 }
 
 impl<'temp> SymDef<'temp> {
@@ -30,7 +210,10 @@ impl<'temp> SymDef<'temp> {
     pub(crate) fn convert(self) -> *const () {
         if likely(self.sym.is_some()) {
             let sym = unsafe { self.sym.unwrap_unchecked() };
-            if likely(sym.st_type() != STT_GNU_IFUNC) {
+            if unlikely(sym.is_abs()) {
+                // SHN_ABS符号的值是绝对地址,不需要加上基址
+                sym.st_value() as _
+            } else if likely(sym.st_type() != STT_GNU_IFUNC) {
                 (self.base + sym.st_value()) as _
             } else {
                 // IFUNC会在运行时确定地址，这里使用的是ifunc的返回值
@@ -48,6 +231,7 @@ impl<'temp> SymDef<'temp> {
 pub(crate) struct RelocateHelper<'core> {
     pub base: usize,
     pub symtab: &'core SymbolTable,
+    pub tls: Option<&'core ElfTls>,
     #[cfg(feature = "log")]
     pub lib_name: &'core str,
 }
@@ -57,53 +241,500 @@ pub(crate) type LazyScope<'lib> = Box<dyn for<'a> Fn(&'a str) -> Option<*const (
 type DealUnknown<'deal> =
     &'deal dyn Fn(&ElfRela, &CoreComponent) -> core::result::Result<(), Box<dyn Any>>;
 
+// 在执行初始化函数之前读取重定位的结果
+pub(crate) type Record<'record> = &'record mut dyn FnMut(&ElfCommonPart) -> Result<()>;
+
+type BoxedDealUnknown<'lib> =
+    Box<dyn Fn(&ElfRela, &CoreComponent) -> core::result::Result<(), Box<dyn Any>> + 'lib>;
+
+// 重定位表的处理顺序
+#[derive(Clone, Copy)]
+enum RelocTable {
+    Relative,
+    Dynamic,
+    Plt,
+}
+
+const TABLES: [RelocTable; 3] = [RelocTable::Relative, RelocTable::Dynamic, RelocTable::Plt];
+
+// 在处理重定位表之前进行的检查
+pub(crate) fn begin_relocation(common: &ElfCommonPart) -> Result<()> {
+    common
+        .relocation
+        .check_targets(common, common.symtab().unwrap(), common.textrel())?;
+    if let Some(textrel) = &common.textrel {
+        textrel.make_writable()?;
+    }
+    // 需要在relro之前写入DT_DEBUG
+    #[cfg(feature = "debug")]
+    crate::debug::add(&common.core);
+    Ok(())
+}
+
+// 处理完所有重定位表之后的工作
+pub(crate) fn finish_relocation<'lib>(
+    mut common: ElfCommonPart,
+    local_lazy_scope: Option<LazyScope<'lib>>,
+    mut tls_desc: TlsDescs,
+    ifuncs: &[&ElfRela],
+    hook: Option<RelocateHook>,
+) -> Result<Relocated<'lib>> {
+    if common.is_lazy() {
+        if !common.relocation.pltrel.is_empty() {
+            prepare_lazy_bind(
+                common.got.unwrap().as_ptr(),
+                Arc::as_ptr(&common.core.inner) as usize,
+            );
+        }
+        assert!(
+            common.relocation.pltrel.is_empty()
+                || local_lazy_scope.is_some()
+                || GLOBAL_SCOPE.load(Ordering::Relaxed) != 0,
+            "neither local lazy scope nor global scope is set"
+        );
+        common.set_lazy_scope(local_lazy_scope);
+    }
+    // IFUNC的解析函数位于代码段中,需要先恢复代码段的权限
+    if let Some(textrel) = &common.textrel {
+        textrel.restore()?;
+        common.elf_segments().flush_icache(common.phdrs(), false);
+    }
+    // IFUNC的解析函数可能会使用got和plt,因此在其他重定位以及延迟绑定的准备工作完成后才调用
+    relocate_ifuncs(&common, ifuncs, hook);
+    // 可写又可执行的段中的代码可能被重定位修改
+    common.elf_segments().flush_icache(common.phdrs(), true);
+    // 延迟绑定时默认不保护relro
+    if !common.is_lazy() || common.enforce_relro {
+        if let Some(relro) = common.relro.take() {
+            relro.relro()?;
+            // 保留已保护的relro,使之后修改got时可以临时解除保护
+            common.set_relro(relro);
+        }
+    }
+    // 重定位时的临时数据已经释放,只保留需要的内存
+    tls_desc.shrink_to_fit();
+    common.set_tls_desc(tls_desc);
+    // 在执行初始化函数之前注册,使初始化函数中抛出的异常也能被展开
+    #[cfg(feature = "dl-iterate-phdr")]
+    crate::registry::register(&common.core);
+    // 在初始化函数执行前通知,使分析工具能够看到初始化函数中的代码
+    common.core.notify_load();
+    // DF_1_NODELETE: 持有一个永远不会释放的引用,使elf对象不会被卸载.
+    // 定义了唯一符号的elf对象也不会被卸载,因为其他elf对象可能绑定到了它的定义
+    if unique::register(&common) || common.flags().nodelete() {
+        core::mem::forget(common.core.clone());
+    }
+    if !common.defer_init {
+        common.core.call_init();
+    }
+    Ok(Relocated {
+        core: common.core,
+        _marker: PhantomData,
+    })
+}
+
 /// 在此之前检查是否需要relocate
 pub(crate) fn relocate_impl<'iter, 'find, 'lib, F>(
     common: ElfCommonPart,
     scope: Vec<RelocateHelper<'iter>>,
     pre_find: &'find F,
     deal_unknown: DealUnknown,
+    hook: Option<RelocateHook>,
     local_lazy_scope: Option<LazyScope<'lib>>,
+    record: Option<Record>,
 ) -> Result<Relocated<'lib>>
 where
     F: Fn(&str) -> Option<*const ()>,
     'iter: 'lib,
     'find: 'lib,
 {
-    let symtab = common.symtab().unwrap();
-    let relocation = &common.relocation;
-    let base = common.base();
-    relocation.relocate_relative(base);
-    relocation.relocate_dynrel(&common, symtab, &scope, pre_find, &deal_unknown)?;
-    if common.is_lazy() {
-        relocation.relocate_pltrel_lazy(&common, common.got.unwrap().as_ptr())?;
+    begin_relocation(&common)?;
+    let mut tls_desc = Vec::new();
+    let mut cache = SymbolCache::default();
+    let mut failures = Vec::new();
+    let mut ifuncs = Vec::new();
+    for table in TABLES {
+        let mut done = 0;
+        common.relocation.relocate_range(
+            table,
+            0..common.relocation.table_len(table),
+            &common,
+            &scope,
+            pre_find,
+            deal_unknown,
+            hook,
+            &mut tls_desc,
+            &mut cache,
+            &mut failures,
+            &mut ifuncs,
+            &mut done,
+        )?;
+    }
+    if !failures.is_empty() {
+        return Err(unresolved_error(&common, failures));
+    }
+    // 在执行初始化函数之前记录重定位的结果
+    if let Some(record) = record {
+        record(&common)?;
+    }
+    finish_relocation(common, local_lazy_scope, tls_desc, &ifuncs, hook)
+}
+
+/// The status of a chunked relocation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocateStatus {
+    /// Some relocation entries have not been processed yet.
+    Pending,
+    /// All relocation entries have been processed, `ChunkedRelocation::finish` can be called.
+    Done,
+}
+
+/// A relocation of a dynamic library which is performed in chunks, see `ElfDylib::relocate_chunked`.
+///
+/// Each call to [`ChunkedRelocation::step`] processes at most the given number of relocation
+/// entries, so the host can interleave the relocation with other work. The relocation tables are
+/// processed in order, and the progress of the current table is tracked by a `RelocateState`. If a
+/// step fails, the entries before the failed one stay done and the next step starts from it.
+/// Entries rejected by `deal_unknown` do not fail a step, they are reported together by
+/// [`ChunkedRelocation::finish`].
+pub struct ChunkedRelocation<'iter, 'find, 'lib, F> {
+    common: ElfCommonPart,
+    scope: Vec<RelocateHelper<'iter>>,
+    pre_find: &'find F,
+    deal_unknown: BoxedDealUnknown<'lib>,
+    local_lazy_scope: Option<LazyScope<'lib>>,
+    // 当前正在处理的重定位表
+    table: usize,
+    state: RelocateState,
+    tls_desc: TlsDescs,
+    cache: SymbolCache,
+    // 无法处理的重定位项,在finish时一并报告
+    failures: Vec<RelocFailure>,
+    // 推迟到finish时处理的IFUNC重定位项
+    ifuncs: Vec<&'static ElfRela>,
+}
+
+impl<'iter, 'find, 'lib, F> ChunkedRelocation<'iter, 'find, 'lib, F>
+where
+    F: Fn(&str) -> Option<*const ()>,
+    'iter: 'lib,
+    'find: 'lib,
+{
+    pub(crate) fn new(
+        common: ElfCommonPart,
+        scope: Vec<RelocateHelper<'iter>>,
+        pre_find: &'find F,
+        deal_unknown: BoxedDealUnknown<'lib>,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+    ) -> Result<Self> {
+        begin_relocation(&common)?;
+        let len = common.relocation.table_len(TABLES[0]);
+        let state = RelocateState::new_in(len, true, common.arena);
+        Ok(Self {
+            common,
+            scope,
+            pre_find,
+            deal_unknown,
+            local_lazy_scope,
+            table: 0,
+            state,
+            tls_desc: Vec::new(),
+            cache: SymbolCache::default(),
+            failures: Vec::new(),
+            ifuncs: Vec::new(),
+        })
+    }
+
+    /// Processes at most `max` relocation entries.
+    pub fn step(&mut self, max: usize) -> Result<RelocateStatus> {
+        let relocation = &self.common.relocation;
+        let mut budget = max;
+        while self.table < TABLES.len() {
+            let table = TABLES[self.table];
+            // 重定位表是按顺序处理的,因此未完成的项总是在末尾
+            let Some(start) = self.state.pending().next() else {
+                self.table += 1;
+                if let Some(&next) = TABLES.get(self.table) {
+                    let len = relocation.table_len(next);
+                    self.state = RelocateState::new_in(len, true, self.common.arena);
+                }
+                continue;
+            };
+            if budget == 0 {
+                return Ok(RelocateStatus::Pending);
+            }
+            let end = (start + budget).min(relocation.table_len(table));
+            let mut done = start;
+            let res = relocation.relocate_range(
+                table,
+                start..end,
+                &self.common,
+                &self.scope,
+                self.pre_find,
+                &self.deal_unknown,
+                None,
+                &mut self.tls_desc,
+                &mut self.cache,
+                &mut self.failures,
+                &mut self.ifuncs,
+                &mut done,
+            );
+            (start..done).for_each(|idx| self.state.finish(idx));
+            budget -= done - start;
+            res?;
+        }
+        Ok(RelocateStatus::Done)
+    }
+
+    /// Gets the progress of the relocation table that is being processed.
+    #[inline]
+    pub fn state(&self) -> &RelocateState {
+        &self.state
+    }
+
+    /// Gets the number of relocation entries that have not been processed.
+    pub fn remaining(&self) -> usize {
+        if self.table >= TABLES.len() {
+            return 0;
+        }
+        self.state.remaining()
+            + TABLES[self.table + 1..]
+                .iter()
+                .map(|&table| self.common.relocation.table_len(table))
+                .sum::<usize>()
+    }
+
+    /// Finishes the relocation after all entries have been processed, which also runs the init functions.
+    ///
+    /// Returns all relocation entries that could not be processed if there are any.
+    ///
+    /// # Panics
+    /// Panics if `step` has not returned `RelocateStatus::Done`.
+    pub fn finish(self) -> Result<RelocatedDylib<'lib>> {
         assert!(
-            relocation.pltrel.is_empty()
-                || local_lazy_scope.is_some()
-                || GLOBAL_SCOPE.load(Ordering::Relaxed) != 0,
-            "neither local lazy scope nor global scope is set"
+            self.table >= TABLES.len(),
+            "the relocation has not been finished"
         );
-        common.set_lazy_scope(local_lazy_scope);
-    } else {
-        relocation.relocate_pltrel(&common, symtab, &scope, pre_find, &deal_unknown)?;
-        if let Some(relro) = common.relro {
-            relro.relro()?;
+        if !self.failures.is_empty() {
+            return Err(unresolved_error(&self.common, self.failures));
         }
+        Ok(RelocatedDylib {
+            core: finish_relocation(
+                self.common,
+                self.local_lazy_scope,
+                self.tls_desc,
+                &self.ifuncs,
+                None,
+            )?,
+        })
+    }
+}
+
+/// A symbol which could not be resolved by a partial relocation, see `ElfDylib::relocate_partial`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedSymbol {
+    /// The name of the symbol.
+    pub name: String,
+    /// The number of relocation entries referencing the symbol.
+    pub count: usize,
+}
+
+/// A relocation of a dynamic library whose unresolved entries can be completed later, see
+/// `ElfDylib::relocate_partial`.
+///
+/// The entries that can be resolved are processed at once, and the ones referencing unknown symbols
+/// are kept pending in a `RelocateState` of each relocation table. [`PartialRelocation::resume_with`]
+/// only revisits the pending entries, so symbol providers can be added one by one, such as the
+/// libraries loaded later. No init function is called until [`PartialRelocation::finish`].
+pub struct PartialRelocation<'iter, 'lib> {
+    common: ElfCommonPart,
+    scope: Vec<RelocateHelper<'iter>>,
+    local_lazy_scope: Option<LazyScope<'lib>>,
+    // 每个重定位表中尚未解析的项
+    states: [RelocateState; 3],
+    tls_desc: TlsDescs,
+    cache: SymbolCache,
+    // 推迟到finish时处理的IFUNC重定位项
+    ifuncs: Vec<&'static ElfRela>,
+}
+
+impl<'iter, 'lib> PartialRelocation<'iter, 'lib> {
+    pub(crate) fn new<F>(
+        common: ElfCommonPart,
+        scope: Vec<RelocateHelper<'iter>>,
+        pre_find: &F,
+        local_lazy_scope: Option<LazyScope<'lib>>,
+    ) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<*const ()>,
+    {
+        begin_relocation(&common)?;
+        let states = TABLES.map(|table| {
+            RelocateState::new_in(common.relocation.table_len(table), false, common.arena)
+        });
+        let mut partial = Self {
+            common,
+            scope,
+            local_lazy_scope,
+            states,
+            tls_desc: Vec::new(),
+            cache: SymbolCache::default(),
+            ifuncs: Vec::new(),
+        };
+        for table in TABLES {
+            let len = partial.common.relocation.table_len(table);
+            partial.relocate(table, 0..len, pre_find)?;
+        }
+        Ok(partial)
+    }
+
+    // 处理range内的项,无法解析的项被标记为未完成,返回它们的数量
+    fn relocate<F>(&mut self, table: RelocTable, range: Range<usize>, pre_find: &F) -> Result<usize>
+    where
+        F: Fn(&str) -> Option<*const ()>,
+    {
+        let pending = RefCell::new(Vec::new());
+        let deal_unknown = |rela: &ElfRela, _: &CoreComponent| {
+            pending.borrow_mut().push(rela as *const ElfRela as usize);
+            Ok(())
+        };
+        let relas = self.common.relocation.table(table);
+        let mut failures = Vec::new();
+        let mut done = range.start;
+        self.common.relocation.relocate_range(
+            table,
+            range,
+            &self.common,
+            &self.scope,
+            pre_find,
+            &deal_unknown,
+            None,
+            &mut self.tls_desc,
+            &mut self.cache,
+            &mut failures,
+            &mut self.ifuncs,
+            &mut done,
+        )?;
+        let pending = pending.into_inner();
+        for &rela in &pending {
+            let idx = (rela - relas.as_ptr() as usize) / size_of::<ElfRela>();
+            self.states[table as usize].reset(idx);
+        }
+        Ok(pending.len())
+    }
+
+    /// Gets the symbols that are still unresolved, sorted by name.
+    pub fn unresolved(&self) -> Vec<UnresolvedSymbol> {
+        let symtab = self.common.symtab().unwrap();
+        let mut symbols: BTreeMap<&str, usize> = BTreeMap::new();
+        for table in TABLES {
+            let relas = self.common.relocation.table(table);
+            for idx in self.states[table as usize].pending() {
+                let r_sym = relas[idx].r_symbol();
+                if r_sym == 0 {
+                    continue;
+                }
+                let name = symtab
+                    .strtab()
+                    .get_str(symtab.symbol_idx(r_sym).0.st_name());
+                *symbols.entry(name).or_default() += 1;
+            }
+        }
+        symbols
+            .into_iter()
+            .map(|(name, count)| UnresolvedSymbol {
+                name: name.to_string(),
+                count,
+            })
+            .collect()
+    }
+
+    /// Gets the number of relocation entries that are still pending, including the ones without a
+    /// symbol which no provider can resolve.
+    pub fn remaining(&self) -> usize {
+        self.states.iter().map(|state| state.remaining()).sum()
+    }
+
+    /// Retries the pending relocation entries, looking up their symbols in `provider` before the
+    /// scope given to `relocate_partial`. The entries that have been relocated are not revisited.
+    ///
+    /// Returns the number of relocation entries that are still pending.
+    pub fn resume_with<F>(&mut self, provider: &F) -> Result<usize>
+    where
+        F: Fn(&str) -> Option<*const ()>,
+    {
+        for table in TABLES {
+            let pending: Vec<usize> = self.states[table as usize].pending().collect();
+            for idx in pending {
+                // 只有成功写入的项才标记为完成,出错或者panic时仍然是未完成的
+                if self.relocate(table, idx..idx + 1, provider)? == 0 {
+                    self.states[table as usize].finish(idx);
+                }
+            }
+        }
+        Ok(self.remaining())
+    }
+
+    /// Finishes the relocation after all entries have been resolved, which also runs the init functions.
+    ///
+    /// Returns all relocation entries that are still pending if there are any.
+    pub fn finish(self) -> Result<RelocatedDylib<'lib>> {
+        let symtab = self.common.symtab().unwrap();
+        let failures: Vec<RelocFailure> = TABLES
+            .iter()
+            .flat_map(|&table| {
+                let relas = self.common.relocation.table(table);
+                self.states[table as usize]
+                    .pending()
+                    .map(move |idx| reloc_failure(&relas[idx], Box::new(()), symtab))
+            })
+            .collect();
+        if !failures.is_empty() {
+            return Err(unresolved_error(&self.common, failures));
+        }
+        Ok(RelocatedDylib {
+            core: finish_relocation(
+                self.common,
+                self.local_lazy_scope,
+                self.tls_desc,
+                &self.ifuncs,
+                None,
+            )?,
+        })
     }
-    common.init.call_init();
-    common.core.set_init();
-    Ok(Relocated {
-        core: common.core,
-        _marker: PhantomData,
-    })
 }
 
 #[inline(always)]
-fn write_val(base: usize, offset: usize, val: usize) {
+pub(crate) fn write_val(mode: WriteMode, base: usize, offset: usize, val: usize) {
     unsafe {
         let rel_addr = (base + offset) as *mut usize;
-        rel_addr.write(val)
+        mode.write(rel_addr, val)
+    };
+}
+
+// 将符号的解析结果交给hook,返回最终写入的地址
+#[inline(always)]
+fn apply_hook(
+    hook: Option<RelocateHook>,
+    core: &CoreComponent,
+    symtab: &SymbolTable,
+    rela: &ElfRela,
+    resolved: Option<*const ()>,
+) -> Option<*const ()> {
+    let Some(hook) = hook else {
+        return resolved;
+    };
+    let (_, syminfo) = symtab.symbol_idx(rela.r_symbol());
+    let context = RelocateContext {
+        lib: core,
+        rela,
+        name: syminfo.name(),
+        resolved,
     };
+    match hook(&context) {
+        RelocateAction::Default => resolved,
+        RelocateAction::Replace(symbol) => Some(symbol),
+    }
 }
 
 #[unsafe(no_mangle)]
@@ -112,7 +743,7 @@ unsafe extern "C" fn dl_fixup(dylib: &CoreComponentInner, rela_idx: usize) -> us
     let r_type = rela.r_type();
     let r_sym = rela.r_symbol();
     assert!(r_type == REL_JUMP_SLOT as usize && r_sym != 0);
-    let (_, syminfo) = dylib.symbols.as_ref().unwrap().symbol_idx(r_sym);
+    let (dynsym, syminfo) = dylib.symbols.as_ref().unwrap().symbol_idx(r_sym);
     let scope = GLOBAL_SCOPE.load(core::sync::atomic::Ordering::Acquire);
     let symbol = if scope == 0 {
         dylib.lazy_scope.as_ref().unwrap()(syminfo.name())
@@ -120,9 +751,14 @@ unsafe extern "C" fn dl_fixup(dylib: &CoreComponentInner, rela_idx: usize) -> us
         unsafe { core::mem::transmute::<_, fn(&str) -> Option<*const ()>>(scope)(syminfo.name()) }
             .or_else(|| dylib.lazy_scope.as_ref().unwrap()(syminfo.name()))
     }
+    // 未定义的弱符号解析为0
+    .or_else(|| (dynsym.is_weak() && dynsym.is_undef()).then_some(null()))
     .expect("lazy bind fail") as usize;
+    if let Some(report) = &dylib.binding_report {
+        report.lazy[rela_idx].store(symbol, Ordering::Relaxed);
+    }
     let ptr = (dylib.segments.base() + rela.r_offset()) as *mut usize;
-    unsafe { ptr.write(symbol) };
+    unsafe { dylib.write_mode.write(ptr, symbol) };
     symbol
 }
 
@@ -145,70 +781,201 @@ fn find_symdef<'iter, 'temp>(
 where
     'iter: 'temp,
 {
-    if unlikely(dynsym.is_local()) {
+    // 局部符号以及已定义的hidden和protected符号总是绑定到自身的定义
+    if unlikely(
+        dynsym.is_local() || (!dynsym.is_undef() && (dynsym.is_hidden() || dynsym.is_protected())),
+    ) {
         Some(SymDef {
             sym: Some(dynsym),
             base: core.base(),
+            tls: core.tls(),
         })
+    } else if unlikely(dynsym.is_unique() && !dynsym.is_undef()) {
+        // 还没有其他定义时绑定到自身的定义
+        unique_def(dynsym, syminfo.name()).or(Some(SymDef {
+            sym: Some(dynsym),
+            base: core.base(),
+            tls: core.tls(),
+        }))
     } else {
-        libs.iter()
-            .find_map(|lib| {
-                lib.symtab.lookup_filter(&syminfo).map(|sym| {
-                    #[cfg(feature = "log")]
-                    log::trace!(
-                        "binding file [{}] to [{}]: symbol [{}]",
-                        core.name(),
-                        lib.lib_name,
-                        syminfo.name()
-                    );
-                    SymDef {
-                        sym: Some(sym),
-                        base: lib.base,
-                    }
-                })
-            })
-            .or_else(|| {
-                // 弱符号 + WEAK 用 0 填充rela offset
-                if dynsym.is_weak() && dynsym.is_undef() {
-                    assert!(dynsym.st_value() == 0);
-                    Some(SymDef {
-                        sym: None,
-                        base: core.base(),
-                    })
-                } else {
-                    None
+        #[cfg(feature = "stats")]
+        let _timer = core.inner.stats.as_deref().map(Timer::lookup);
+        // 强符号优先于搜索顺序中更靠前的弱符号,找不到强符号时才使用第一个弱符号
+        let mut weak_def = None;
+        for lib in libs {
+            let Some(sym) = lib.symtab.lookup_filter(&syminfo) else {
+                continue;
+            };
+            #[cfg(feature = "log")]
+            log::trace!(
+                "binding file [{}] to [{}]: symbol [{}]",
+                core.name(),
+                lib.lib_name,
+                syminfo.name()
+            );
+            if unlikely(sym.is_unique()) {
+                if let Some(symdef) = unique_def(sym, syminfo.name()) {
+                    return Some(symdef);
                 }
-            })
+            }
+            let symdef = SymDef {
+                sym: Some(sym),
+                base: lib.base,
+                tls: lib.tls,
+            };
+            if !sym.is_weak() {
+                return Some(symdef);
+            }
+            weak_def.get_or_insert(symdef);
+        }
+        weak_def.or_else(|| {
+            // 未定义的弱符号解析为0
+            if dynsym.is_weak() && dynsym.is_undef() {
+                Some(SymDef {
+                    sym: None,
+                    base: core.base(),
+                    tls: None,
+                })
+            } else {
+                None
+            }
+        })
+    }
+}
+
+// 唯一符号绑定到进程中的第一个定义,调整基址使convert得到它的地址
+#[inline]
+fn unique_def<'temp>(sym: &'temp ElfSymbol, name: &str) -> Option<SymDef<'temp>> {
+    unique::find(name).map(|addr| SymDef {
+        sym: Some(sym),
+        base: addr.wrapping_sub(sym.st_value()),
+        tls: None,
+    })
+}
+
+// 局部符号以及已定义的hidden和protected符号总是绑定到自身的定义,
+// 如果它们是IFUNC,解析函数需要推迟到自身的其他重定位完成之后调用
+#[inline(always)]
+pub(crate) fn is_own_ifunc(symtab: &SymbolTable, r_sym: usize) -> bool {
+    let (dynsym, _) = symtab.symbol_idx(r_sym);
+    dynsym.st_type() == STT_GNU_IFUNC
+        && !dynsym.is_undef()
+        && !dynsym.is_abs()
+        && (dynsym.is_local() || dynsym.is_hidden() || dynsym.is_protected())
+}
+
+fn relocate_ifuncs(common: &ElfCommonPart, ifuncs: &[&ElfRela], hook: Option<RelocateHook>) {
+    let base = common.base();
+    let mode = common.write_mode();
+    let symtab = common.symtab().unwrap();
+    for rela in ifuncs {
+        let symbol = if rela.r_type() == REL_IRELATIVE as usize {
+            // B + A
+            let ifunc: fn() -> usize = unsafe { core::mem::transmute(base + rela.r_addend()) };
+            ifunc() as *const ()
+        } else {
+            let (dynsym, _) = symtab.symbol_idx(rela.r_symbol());
+            let symbol = SymDef {
+                sym: Some(dynsym),
+                base,
+                tls: None,
+            }
+            .convert();
+            apply_hook(hook, common, symtab, rela, Some(symbol)).unwrap_or(symbol)
+        };
+        write_val(mode, base, rela.r_offset(), symbol as usize);
     }
 }
 
 #[cold]
-fn reloc_error(
-    r_type: usize,
-    r_sym: usize,
-    custom_err: Box<dyn Any>,
-    lib: &CoreComponent,
-) -> Error {
-    if r_sym == 0 {
-        relocate_error(
-            format!(
-                "file: {}, relocation type: {}, no symbol",
-                lib.shortname(),
-                r_type,
-            ),
-            custom_err,
-        )
+fn static_tls_error(lib: &CoreComponent) -> Error {
+    relocate_error(lib.shortname(), RelocateErrorKind::StaticTls)
+}
+
+#[cold]
+fn reloc_failure(rela: &ElfRela, custom_err: Box<dyn Any>, symtab: &SymbolTable) -> RelocFailure {
+    let r_sym = rela.r_symbol();
+    RelocFailure {
+        r_type: rela.r_type(),
+        r_offset: rela.r_offset(),
+        symbol: (r_sym != 0).then(|| symtab.symbol_idx(r_sym).1.name().to_string()),
+        custom_err,
+    }
+}
+
+#[cold]
+pub(crate) fn unresolved_error(lib: &CoreComponent, failures: Vec<RelocFailure>) -> Error {
+    relocate_error(lib.shortname(), RelocateErrorKind::Unresolved(failures))
+}
+
+#[cold]
+fn target_error(lib: &CoreComponent, table: &'static str, idx: usize, rela: &ElfRela) -> Error {
+    relocate_error(
+        lib.shortname(),
+        RelocateErrorKind::InvalidTarget {
+            table,
+            idx,
+            r_type: rela.r_type(),
+            r_offset: rela.r_offset(),
+        },
+    )
+}
+
+// tls描述符由两项组成: 解析函数和解析函数的参数
+fn relocate_tlsdesc(
+    core: &CoreComponent,
+    symtab: &SymbolTable,
+    scope: &[RelocateHelper],
+    rela: &ElfRela,
+    tls_desc: &mut TlsDescs,
+) -> bool {
+    // 不支持tls描述符的架构上REL_TLSDESC只是占位值
+    if REL_TLSDESC == u32::MAX {
+        return false;
+    }
+    let r_sym = rela.r_symbol();
+    let (tls, offset) = if r_sym == 0 {
+        (core.tls(), rela.r_addend())
     } else {
-        relocate_error(
-            format!(
-                "file: {}, relocation type: {}, symbol name: {}",
-                lib.shortname(),
-                r_type,
-                lib.symtab().unwrap().symbol_idx(r_sym).1.name(),
-            ),
-            custom_err,
-        )
+        let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+        match find_symdef(core, scope, dynsym, &syminfo) {
+            Some(SymDef {
+                sym: Some(sym),
+                tls,
+                ..
+            }) => (tls, sym.st_value() + rela.r_addend()),
+            Some(SymDef { sym: None, .. }) => {
+                write_tlsdesc(core, rela, tlsdesc_undefweak(), rela.r_addend());
+                return true;
+            }
+            None => return false,
+        }
+    };
+    if let Some(tls) = tls {
+        // 已经位于静态tls中的模块可以直接使用偏移
+        if let Some(static_offset) = tls.reserved_static() {
+            write_tlsdesc(
+                core,
+                rela,
+                tlsdesc_return(),
+                (static_offset as usize).wrapping_add(offset),
+            );
+            return true;
+        }
+        let desc = Box::new(TlsDescDynamic::new(tls, offset));
+        write_tlsdesc(core, rela, tlsdesc_dynamic(), &*desc as *const _ as usize);
+        tls_desc.push(desc);
+        return true;
     }
+    false
+}
+
+#[inline(always)]
+fn write_tlsdesc(core: &CoreComponent, rela: &ElfRela, resolver: usize, arg: usize) {
+    let base = core.base();
+    let mode = core.write_mode();
+    write_val(mode, base, rela.r_offset() + size_of::<usize>(), arg);
+    write_val(mode, base, rela.r_offset(), resolver);
 }
 
 impl ElfRelocation {
@@ -238,6 +1005,52 @@ impl ElfRelocation {
         }
     }
 
+    /// 检查所有重定位的目标是否位于可写的段中,防止损坏的r_offset写到映射的内存之外。
+    /// 有文本重定位时只读段在重定位期间也是可写的,但IFUNC在恢复段的权限之后才会被写入
+    fn check_targets(
+        &self,
+        core: &CoreComponent,
+        symtab: &SymbolTable,
+        textrel: bool,
+    ) -> Result<()> {
+        let targets: Vec<(Range<usize>, bool)> = core
+            .phdrs()
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(|phdr| {
+                let range = phdr.p_vaddr as usize..(phdr.p_vaddr + phdr.p_memsz) as usize;
+                (range, phdr.p_flags & PF_W != 0)
+            })
+            .collect();
+        for (table, relas) in [
+            ("relative", self.relative),
+            ("dynamic", self.dynrel),
+            ("plt", self.pltrel),
+        ] {
+            for (idx, rela) in relas.iter().enumerate() {
+                let len = match rela.r_type() as u32 {
+                    REL_NONE => continue,
+                    // tls描述符占用两项
+                    REL_TLSDESC => 2 * size_of::<usize>(),
+                    REL_COPY => symtab.symbol_idx(rela.r_symbol()).0.st_size(),
+                    _ => size_of::<usize>(),
+                };
+                let start = rela.r_offset();
+                let textrel = textrel && rela.r_type() as u32 != REL_IRELATIVE;
+                let valid = start.checked_add(len).is_some_and(|end| {
+                    targets.iter().any(|(range, writable)| {
+                        (*writable || textrel) && range.start <= start && end <= range.end
+                    })
+                });
+                if unlikely(!valid) {
+                    return Err(target_error(core, table, idx, rela));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn relocate_pltrel<F>(
         &self,
         core: &CoreComponent,
@@ -245,71 +1058,145 @@ impl ElfRelocation {
         scope: &[RelocateHelper],
         pre_find: &F,
         deal_unknown: DealUnknown,
+        hook: Option<RelocateHook>,
+        tls_desc: &mut TlsDescs,
+        cache: &mut SymbolCache,
+        failures: &mut Vec<RelocFailure>,
+        ifuncs: &mut Vec<&'static ElfRela>,
+        range: Range<usize>,
+        done: &mut usize,
     ) -> Result<()>
     where
         F: Fn(&str) -> Option<*const ()>,
     {
         let base = core.base();
-        for rela in self.pltrel {
+        let mode = core.write_mode();
+        for (idx, rela) in self.pltrel[range.clone()].iter().enumerate() {
+            *done = range.start + idx;
             let r_type = rela.r_type() as u32;
             let r_sym = rela.r_symbol();
             // S
             // 对于.rela.plt来说通常只有这两种重定位类型
             if likely(r_type == REL_JUMP_SLOT) {
-                let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
-                if let Some(symbol) = pre_find(syminfo.name()).or_else(|| {
-                    find_symdef(core, &scope, dynsym, &syminfo).map(|symdef| symdef.convert())
-                }) {
-                    write_val(base, rela.r_offset(), symbol as usize);
+                if unlikely(is_own_ifunc(symtab, r_sym)) {
+                    ifuncs.push(rela);
+                    continue;
+                }
+                let symbol = cache.find(r_sym, || {
+                    let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+                    pre_find(syminfo.name()).or_else(|| {
+                        find_symdef(core, scope, dynsym, &syminfo).map(|symdef| symdef.convert())
+                    })
+                });
+                if let Some(symbol) = apply_hook(hook, core, symtab, rela, symbol) {
+                    write_val(mode, base, rela.r_offset(), symbol as usize);
                     continue;
                 }
             } else if unlikely(r_type == REL_IRELATIVE) {
-                let ifunc: fn() -> usize = unsafe { core::mem::transmute(base + rela.r_addend()) };
-                write_val(base, rela.r_offset(), ifunc());
+                ifuncs.push(rela);
                 continue;
+            } else if unlikely(r_type == REL_TLSDESC)
+                && relocate_tlsdesc(core, symtab, scope, rela, tls_desc)
+            {
+                continue;
+            }
+            if let Err(err) = deal_unknown(rela, core) {
+                failures.push(reloc_failure(rela, err, symtab));
             }
-            deal_unknown(&rela, &core)
-                .map_err(|err| reloc_error(r_type as _, r_sym, err, &core))?;
         }
+        *done = range.end;
         Ok(())
     }
 
-    fn relocate_pltrel_lazy(&self, core: &CoreComponent, got: *mut usize) -> Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    fn relocate_pltrel_lazy<F>(
+        &self,
+        core: &CoreComponent,
+        symtab: &SymbolTable,
+        scope: &[RelocateHelper],
+        pre_find: &F,
+        deal_unknown: DealUnknown,
+        tls_desc: &mut TlsDescs,
+        cache: &mut SymbolCache,
+        failures: &mut Vec<RelocFailure>,
+        ifuncs: &mut Vec<&'static ElfRela>,
+        range: Range<usize>,
+        done: &mut usize,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> Option<*const ()>,
+    {
         // 开启lazy bind后会跳过plt相关的重定位
         let base = core.base();
-        for rela in self.pltrel {
+        let mode = core.write_mode();
+        let report = core.inner.binding_report.as_ref();
+        for (idx, rela) in self.pltrel[range.clone()].iter().enumerate() {
+            let idx = range.start + idx;
+            *done = idx;
             let r_type = rela.r_type() as u32;
             // S
             if likely(r_type == REL_JUMP_SLOT) {
+                // 只在暂存表中记录eager的解析结果,不写入got
+                if let Some(report) = report {
+                    let r_sym = rela.r_symbol();
+                    if let Some(symbol) = cache.find(r_sym, || {
+                        let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+                        pre_find(syminfo.name()).or_else(|| {
+                            find_symdef(core, scope, dynsym, &syminfo)
+                                .map(|symdef| symdef.convert())
+                        })
+                    }) {
+                        report.eager[idx].store(symbol as usize, Ordering::Relaxed);
+                    }
+                }
                 let ptr = (base + rela.r_offset()) as *mut usize;
                 // 即使是延迟加载也需要进行简单重定位，好让plt代码能够正常工作
                 unsafe {
-                    let origin_val = ptr.read();
+                    let origin_val = mode.read(ptr);
                     let new_val = origin_val + base;
-                    ptr.write(new_val);
+                    mode.write(ptr, new_val);
                 }
             } else if unlikely(r_type == REL_IRELATIVE) {
-                let ifunc: fn() -> usize = unsafe { core::mem::transmute(base + rela.r_addend()) };
-                write_val(base, rela.r_offset(), ifunc());
-            } else {
-                unreachable!()
+                ifuncs.push(rela);
+            } else if r_type == REL_TLSDESC {
+                // tls描述符不进行延迟绑定
+                if !relocate_tlsdesc(core, symtab, scope, rela, tls_desc) {
+                    if let Err(err) = deal_unknown(rela, core) {
+                        failures.push(reloc_failure(rela, err, symtab));
+                    }
+                }
+            } else if unlikely(r_type != REL_NONE) {
+                // 未知的重定位类型交给deal_unknown处理,失败时连同类型和符号名一起报告
+                if let Err(err) = deal_unknown(rela, core) {
+                    failures.push(reloc_failure(rela, err, symtab));
+                }
             }
         }
-        if !self.pltrel.is_empty() {
-            prepare_lazy_bind(got, Arc::as_ptr(&core.inner) as usize);
-        }
+        *done = range.end;
         Ok(())
     }
 
-    fn relocate_relative(&self, base: usize) {
+    fn relocate_relative(&self, mode: WriteMode, base: usize, range: Range<usize>) {
         assert!(!(self.relative.len() > 0 && self.relative[0].r_type() != REL_RELATIVE as usize));
-        self.relative.into_iter().for_each(|rela| {
-            // B + A
-            debug_assert!(rela.r_type() == REL_RELATIVE as usize);
-            write_val(base, rela.r_offset(), base + rela.r_addend());
-        });
+        let relocate = |relas: &[ElfRela]| {
+            relas.iter().for_each(|rela| {
+                // B + A
+                debug_assert!(rela.r_type() == REL_RELATIVE as usize);
+                write_val(mode, base, rela.r_offset(), base + rela.r_addend());
+            })
+        };
+        let relas = &self.relative[range];
+        // REL_RELATIVE之间互不依赖,数量很多时可以分块并行处理
+        #[cfg(feature = "rayon")]
+        if relas.len() >= PARALLEL_THRESHOLD {
+            use rayon::prelude::*;
+            relas.par_chunks(PARALLEL_CHUNK).for_each(relocate);
+            return;
+        }
+        relocate(relas);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn relocate_dynrel<F>(
         &self,
         core: &CoreComponent,
@@ -317,6 +1204,13 @@ impl ElfRelocation {
         scope: &[RelocateHelper],
         pre_find: &F,
         deal_unknown: DealUnknown,
+        hook: Option<RelocateHook>,
+        tls_desc: &mut TlsDescs,
+        cache: &mut SymbolCache,
+        failures: &mut Vec<RelocFailure>,
+        ifuncs: &mut Vec<&'static ElfRela>,
+        range: Range<usize>,
+        done: &mut usize,
     ) -> Result<()>
     where
         F: Fn(&str) -> Option<*const ()>,
@@ -328,60 +1222,228 @@ impl ElfRelocation {
         */
 
         let base = core.base();
-        for rela in self.dynrel {
+        let mode = core.write_mode();
+        for (idx, rela) in self.dynrel[range.clone()].iter().enumerate() {
+            *done = range.start + idx;
             let r_type = rela.r_type() as _;
             let r_sym = rela.r_symbol();
             match r_type {
+                // 架构不支持的重定位类型使用u32::MAX占位,不能被当作有效的类型处理
+                _ if r_type == u32::MAX => {}
                 // REL_GOT: S  REL_SYMBOLIC: S + A
                 REL_GOT | REL_SYMBOLIC => {
-                    let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
-                    if let Some(symbol) = pre_find(syminfo.name()).or_else(|| {
-                        find_symdef(core, &scope, dynsym, &syminfo).map(|symdef| symdef.convert())
-                    }) {
-                        write_val(base, rela.r_offset(), symbol as usize);
+                    if unlikely(is_own_ifunc(symtab, r_sym)) {
+                        ifuncs.push(rela);
+                        continue;
+                    }
+                    let symbol = cache.find(r_sym, || {
+                        let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+                        pre_find(syminfo.name()).or_else(|| {
+                            find_symdef(core, scope, dynsym, &syminfo)
+                                .map(|symdef| symdef.convert())
+                        })
+                    });
+                    if let Some(symbol) = apply_hook(hook, core, symtab, rela, symbol) {
+                        write_val(mode, base, rela.r_offset(), symbol as usize);
+                        continue;
+                    }
+                }
+                REL_DTPMOD => {
+                    // r_sym为0时使用当前模块
+                    let modid = if r_sym == 0 {
+                        core.tls().map(|tls| tls.modid)
+                    } else {
+                        let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+                        match find_symdef(core, scope, dynsym, &syminfo) {
+                            // 未定义的弱tls符号使用模块0,与ld.so的行为保持一致
+                            Some(SymDef { sym: None, .. }) => Some(0),
+                            Some(SymDef { tls, .. }) => tls.map(|tls| tls.modid),
+                            None => None,
+                        }
+                    };
+                    if let Some(modid) = modid {
+                        write_val(mode, base, rela.r_offset(), modid);
                         continue;
                     }
                 }
                 REL_DTPOFF => {
                     let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
                     if let Some(symdef) = find_symdef(core, &scope, dynsym, &syminfo) {
-                        // offset in tls
-                        let tls_val = (symdef.sym.unwrap().st_value() + rela.r_addend())
-                            .wrapping_sub(TLS_DTV_OFFSET);
-                        write_val(base, rela.r_offset(), tls_val);
+                        // offset in tls, 未定义的弱tls符号的偏移为0
+                        let tls_val = symdef.sym.map_or(0, |sym| {
+                            (sym.st_value() + rela.r_addend()).wrapping_sub(TLS_DTV_OFFSET)
+                        });
+                        write_val(mode, base, rela.r_offset(), tls_val);
+                        continue;
+                    }
+                }
+                REL_TPOFF => {
+                    // r_sym为0时使用当前模块
+                    let symdef = if r_sym == 0 {
+                        Some((core.tls(), rela.r_addend()))
+                    } else {
+                        let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
+                        match find_symdef(core, scope, dynsym, &syminfo) {
+                            Some(SymDef {
+                                sym: Some(sym),
+                                tls,
+                                ..
+                            }) => Some((tls, sym.st_value() + rela.r_addend())),
+                            // 与ld.so相同,未定义的弱tls符号不做处理
+                            Some(SymDef { sym: None, .. }) => continue,
+                            None => None,
+                        }
+                    };
+                    if let Some((Some(tls), offset)) = symdef {
+                        let static_offset =
+                            tls.static_offset().ok_or_else(|| static_tls_error(core))?;
+                        write_val(
+                            mode,
+                            base,
+                            rela.r_offset(),
+                            (static_offset as usize).wrapping_add(offset),
+                        );
                         continue;
                     }
                 }
                 REL_COPY => {
                     let (dynsym, syminfo) = symtab.symbol_idx(r_sym);
-                    if let Some(symbol) = find_symdef(core, &scope, dynsym, &syminfo) {
-                        let len = symbol.sym.unwrap().st_size();
-                        let dest = core.segments().get_slice_mut::<u8>(rela.r_offset(), len);
-                        let src = core
-                            .segments()
-                            .get_slice(symbol.sym.unwrap().st_value(), len);
-                        dest.copy_from_slice(src);
+                    // 未定义的弱符号无法复制,作为失败项报告
+                    if let Some(SymDef { sym: Some(sym), .. }) =
+                        find_symdef(core, &scope, dynsym, &syminfo)
+                    {
+                        let len = sym.st_size();
+                        let dest = core
+                            .elf_segments()
+                            .get_slice_mut::<u8>(rela.r_offset(), len);
+                        let src = core.elf_segments().get_slice(sym.st_value(), len);
+                        mode.copy(dest, src);
                         continue;
                     }
                 }
                 _ => {}
             }
             if unlikely(r_type == REL_RELATIVE) {
-                write_val(base, rela.r_offset(), base + rela.r_addend());
+                write_val(mode, base, rela.r_offset(), base + rela.r_addend());
+                continue;
+            } else if unlikely(r_type == REL_IRELATIVE) {
+                ifuncs.push(rela);
                 continue;
             } else if unlikely(r_type == REL_NONE) {
                 continue;
             }
-            deal_unknown(&rela, &core)
-                .map_err(|err| reloc_error(r_type as _, r_sym, err, &core))?;
+            if unlikely(r_type == REL_TLSDESC)
+                && relocate_tlsdesc(core, symtab, scope, rela, tls_desc)
+            {
+                continue;
+            }
+            if let Err(err) = deal_unknown(rela, core) {
+                failures.push(reloc_failure(rela, err, symtab));
+            }
         }
+        *done = range.end;
         Ok(())
     }
 
+    #[inline]
+    fn table(&self, table: RelocTable) -> &'static [ElfRela] {
+        match table {
+            RelocTable::Relative => self.relative,
+            RelocTable::Dynamic => self.dynrel,
+            RelocTable::Plt => self.pltrel,
+        }
+    }
+
+    #[inline]
+    fn table_len(&self, table: RelocTable) -> usize {
+        self.table(table).len()
+    }
+
+    /// 处理重定位表中range内的项,done记录了第一个未完成的项
+    #[allow(clippy::too_many_arguments)]
+    fn relocate_range<F>(
+        &self,
+        table: RelocTable,
+        range: Range<usize>,
+        common: &ElfCommonPart,
+        scope: &[RelocateHelper],
+        pre_find: &F,
+        deal_unknown: DealUnknown,
+        hook: Option<RelocateHook>,
+        tls_desc: &mut TlsDescs,
+        cache: &mut SymbolCache,
+        failures: &mut Vec<RelocFailure>,
+        ifuncs: &mut Vec<&'static ElfRela>,
+        done: &mut usize,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> Option<*const ()>,
+    {
+        #[cfg(feature = "stats")]
+        let _timer = common.inner.stats.as_deref().map(Timer::relocate);
+        let symtab = common.symtab().unwrap();
+        match table {
+            RelocTable::Relative => {
+                self.relocate_relative(common.write_mode(), common.base(), range.clone());
+                *done = range.end;
+                Ok(())
+            }
+            RelocTable::Dynamic => self.relocate_dynrel(
+                common,
+                symtab,
+                scope,
+                pre_find,
+                deal_unknown,
+                hook,
+                tls_desc,
+                cache,
+                failures,
+                ifuncs,
+                range,
+                done,
+            ),
+            RelocTable::Plt if common.is_lazy() => self.relocate_pltrel_lazy(
+                common,
+                symtab,
+                scope,
+                pre_find,
+                deal_unknown,
+                tls_desc,
+                cache,
+                failures,
+                ifuncs,
+                range,
+                done,
+            ),
+            RelocTable::Plt => self.relocate_pltrel(
+                common,
+                symtab,
+                scope,
+                pre_find,
+                deal_unknown,
+                hook,
+                tls_desc,
+                cache,
+                failures,
+                ifuncs,
+                range,
+                done,
+            ),
+        }
+    }
+
     #[inline]
     pub(crate) fn is_empty(&self) -> bool {
         self.relative.is_empty() && self.dynrel.is_empty() && self.pltrel.is_empty()
     }
+
+    /// 按处理顺序遍历所有重定位项
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &'static ElfRela> {
+        self.relative
+            .iter()
+            .chain(self.dynrel.iter())
+            .chain(self.pltrel.iter())
+    }
 }
 
 #[inline]
@@ -0,0 +1,170 @@
+//! Running a loaded program like a subprocess
+//!
+//! The program is started from its entry point on a separate stack. The exit functions and
+//! `__libc_start_main` it imports are interposed by the functions returned from [`interpose`],
+//! so when the program returns from `main` or calls `exit`, control goes back to the host
+//! together with the exit code instead of terminating the host process.
+//!
+//! The program shares the C library of the host, so its imports other than the interposed ones
+//! should be resolved to the host. Functions registered with `atexit` by the program are not run.
+//! Each thread can run its own program, and a program can run another one.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{load_dylib, mmap::MmapImpl, run};
+//!
+//! let exec = load_dylib!("target/hello").unwrap();
+//! let entry = exec.entry();
+//! let exec = exec
+//!     .easy_relocate([].into_iter(), &|name| run::interpose(name))
+//!     .unwrap();
+//! let code = unsafe { run::run::<MmapImpl>(entry, &[c"hello"], &[], 1 << 20) }.unwrap();
+//! ```
+use crate::{
+    Result,
+    bootstrap::{AT_NULL, jump_to_entry},
+    mmap::{MapFlags, Mmap, ProtFlags},
+    segment::{MASK, PAGE_SIZE},
+};
+use core::{
+    cell::Cell,
+    ffi::{CStr, c_char, c_int, c_void},
+    ptr::{NonNull, null_mut},
+};
+
+/// The callee-saved registers of the host when the program is started
+#[repr(C)]
+struct Context {
+    regs: [usize; 32],
+}
+
+unsafe extern "C" {
+    fn dl_context_enter(
+        ctx: *mut Context,
+        f: unsafe extern "C" fn(*mut c_void) -> c_int,
+        arg: *mut c_void,
+    ) -> c_int;
+    fn dl_context_leave(ctx: *mut Context, code: c_int) -> !;
+}
+
+std::thread_local! {
+    // 当前线程正在运行的程序的上下文
+    static CONTEXT: Cell<*mut Context> = const { Cell::new(null_mut()) };
+}
+
+struct StartArg {
+    entry: usize,
+    sp: *const usize,
+}
+
+unsafe extern "C" fn start(arg: *mut c_void) -> c_int {
+    let arg = unsafe { &*(arg as *const StartArg) };
+    unsafe { jump_to_entry(arg.entry, arg.sp) }
+}
+
+type Main = unsafe extern "C" fn(c_int, *const *const c_char, *const *const c_char) -> c_int;
+
+unsafe extern "C" fn libc_start_main(main: Main, argc: c_int, argv: *const *const c_char) -> c_int {
+    // envp紧跟在argv之后
+    let envp = unsafe { argv.add(argc as usize + 1) };
+    let code = unsafe { main(argc, argv, envp) };
+    unsafe { exit(code) }
+}
+
+unsafe extern "C" fn exit(code: c_int) -> ! {
+    let ctx = CONTEXT.with(|ctx| ctx.get());
+    assert!(!ctx.is_null(), "exit is called outside of run");
+    unsafe { dl_context_leave(ctx, code) }
+}
+
+/// Gets the function that replaces `name` in the program, which is one of `__libc_start_main`,
+/// `exit`, `_exit` and `_Exit`. It is meant to be used before other symbol lookups in `pre_find`.
+pub fn interpose(name: &str) -> Option<*const ()> {
+    match name {
+        "__libc_start_main" => Some(libc_start_main as *const ()),
+        "exit" | "_exit" | "_Exit" => Some(exit as *const ()),
+        _ => None,
+    }
+}
+
+// 栈的最低一页是不可访问的保护页,溢出时会触发段错误而不是破坏其他内存
+struct Stack<M: Mmap> {
+    ptr: NonNull<c_void>,
+    len: usize,
+    _marker: core::marker::PhantomData<M>,
+}
+
+impl<M: Mmap> Stack<M> {
+    fn new(size: usize) -> Result<Self> {
+        let len = ((size + PAGE_SIZE - 1) & MASK) + PAGE_SIZE;
+        let ptr = unsafe {
+            M::mmap_anonymous(
+                0,
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE,
+            )
+        }?;
+        let stack = Self {
+            ptr,
+            len,
+            _marker: core::marker::PhantomData,
+        };
+        unsafe { M::mprotect(ptr, PAGE_SIZE, ProtFlags::PROT_NONE) }?;
+        Ok(stack)
+    }
+
+    #[inline]
+    fn top(&self) -> *mut usize {
+        (self.ptr.as_ptr() as usize + self.len) as *mut usize
+    }
+
+    #[inline]
+    fn words(&self) -> usize {
+        (self.len - PAGE_SIZE) / size_of::<usize>()
+    }
+}
+
+impl<M: Mmap> Drop for Stack<M> {
+    fn drop(&mut self) {
+        let _ = unsafe { M::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Runs the program from `entry` on a new stack of `stack_size` bytes and returns its exit code.
+/// The stack is mapped by `M` with a guard page below it.
+///
+/// # Safety
+/// The program must be relocated with the functions returned by [`interpose`], and it must not
+/// terminate the process in other ways, such as calling `exit_group` directly.
+pub unsafe fn run<M: Mmap>(
+    entry: usize,
+    args: &[&CStr],
+    envs: &[&CStr],
+    stack_size: usize,
+) -> Result<i32> {
+    let stack = Stack::<M>::new(stack_size)?;
+    // argc, argv, 0, envp, 0, AT_NULL, 0
+    let init_len = 1 + args.len() + 1 + envs.len() + 1 + 2;
+    assert!(stack.words() > init_len + 1, "the stack is too small");
+    // 初始栈需要16字节对齐
+    let mut init = unsafe { stack.top().sub(init_len) };
+    if init as usize % 16 != 0 {
+        init = unsafe { init.sub(1) };
+    }
+    let values = core::iter::once(args.len())
+        .chain(args.iter().map(|arg| arg.as_ptr() as usize))
+        .chain(core::iter::once(0))
+        .chain(envs.iter().map(|env| env.as_ptr() as usize))
+        .chain([0, AT_NULL, 0]);
+    for (idx, val) in values.enumerate() {
+        unsafe { init.add(idx).write(val) };
+    }
+    let mut arg = StartArg { entry, sp: init };
+    let mut ctx = Context { regs: [0; 32] };
+    let prev = CONTEXT.with(|cur| cur.replace(&mut ctx));
+    let code =
+        unsafe { dl_context_enter(&mut ctx, start, &mut arg as *mut StartArg as *mut c_void) };
+    CONTEXT.with(|cur| cur.set(prev));
+    Ok(code)
+}
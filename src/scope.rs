@@ -0,0 +1,155 @@
+//! Symbol lookup scopes
+//!
+//! Like `dlopen`, the symbols used by a dynamic library are first looked up in the global scope
+//! and then in its local scope. The local scope consists of the dependencies(`DT_NEEDED`) of the
+//! library in breadth-first order. A library loaded with [`Visibility::Global`] is added to the
+//! global scope together with its dependencies, so its symbols are visible to all libraries loaded
+//! afterwards, while the symbols of a library loaded with [`Visibility::Local`] are only visible
+//! to the libraries depending on it.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{load_dylib, scope::{Scope, Visibility}};
+//!
+//! let mut global = Scope::new();
+//! // RTLD_GLOBAL
+//! let liba = load_dylib!("target/liba.so").unwrap();
+//! let local = Scope::local(&liba, global.iter());
+//! let scope = global.chain(&local);
+//! let liba = liba.easy_relocate(scope.iter(), &|_| None).unwrap();
+//! global.register(&liba, &local, Visibility::Global);
+//! // libb depends on liba, which is found in the global scope
+//! let libb = load_dylib!("target/libb.so").unwrap();
+//! let local = Scope::local(&libb, global.iter());
+//! let scope = global.chain(&local);
+//! let libb = libb.easy_relocate(scope.iter(), &|_| None).unwrap();
+//! ```
+use crate::{CoreComponent, RelocatedDylib};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::slice::Iter;
+
+/// Whether the symbols of a dynamic library are visible to the libraries loaded afterwards, like
+/// `RTLD_LOCAL` and `RTLD_GLOBAL` of `dlopen`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Visibility {
+    /// The symbols are only visible to the libraries depending on the library.
+    #[default]
+    Local,
+    /// The library and its dependencies are added to the global scope.
+    Global,
+}
+
+/// An ordered list of relocated dynamic libraries in which symbols are looked up
+///
+/// Each library appears at most once, and the scope holds a strong reference to it, so the
+/// libraries stay loaded as long as the scope exists. Like the libraries, the scope can't outlive
+/// the libraries they were relocated against.
+#[derive(Clone, Debug, Default)]
+pub struct Scope<'scope> {
+    libs: Vec<RelocatedDylib<'scope>>,
+}
+
+impl<'scope> Scope<'scope> {
+    /// Creates an empty scope.
+    pub const fn new() -> Self {
+        Self { libs: Vec::new() }
+    }
+
+    /// Builds the local scope of `lib`, which consists of the dependencies of `lib` found in
+    /// `libs` by file name or soname. The dependencies are ordered breadth-first like the local scope of `dlopen`.
+    /// Dependencies that can not be found in `libs` are skipped.
+    pub fn local<'a>(
+        lib: &CoreComponent,
+        libs: impl Iterator<Item = &'a RelocatedDylib<'scope>> + Clone,
+    ) -> Self
+    where
+        'scope: 'a,
+    {
+        let mut scope = Self::new();
+        let mut queue: VecDeque<&str> = lib.needed_libs().iter().copied().collect();
+        while let Some(needed) = queue.pop_front() {
+            let Some(dep) = libs.clone().find(|dep| dep.provides(needed)) else {
+                continue;
+            };
+            if scope.add(dep) {
+                queue.extend(dep.needed_libs().iter().copied());
+            }
+        }
+        scope
+    }
+
+    /// Appends `lib` to the end of the scope. Returns `false` if the library is already in the scope.
+    pub fn add(&mut self, lib: &RelocatedDylib<'scope>) -> bool {
+        if self.contains(lib) {
+            return false;
+        }
+        self.libs.push(lib.clone());
+        true
+    }
+
+    /// Removes `lib` from the scope. Returns `false` if the library is not in the scope.
+    pub fn remove(&mut self, lib: &CoreComponent) -> bool {
+        let len = self.libs.len();
+        self.libs
+            .retain(|other| other.generation() != lib.generation());
+        self.libs.len() != len
+    }
+
+    /// Whether `lib` is in the scope.
+    pub fn contains(&self, lib: &CoreComponent) -> bool {
+        self.libs
+            .iter()
+            .any(|other| other.generation() == lib.generation())
+    }
+
+    /// Makes `lib` visible according to `visibility`. With `Visibility::Global`, `lib` and the
+    /// libraries in its local scope are added to this scope, which should be the global scope.
+    pub fn register(
+        &mut self,
+        lib: &RelocatedDylib<'scope>,
+        local: &Scope<'scope>,
+        visibility: Visibility,
+    ) {
+        if visibility == Visibility::Global {
+            self.add(lib);
+            local.iter().for_each(|dep| {
+                self.add(dep);
+            });
+        }
+    }
+
+    /// Creates the scope used to relocate a library: the libraries of this scope followed by
+    /// the ones of `other` which are not in this scope.
+    pub fn chain(&self, other: &Scope<'scope>) -> Scope<'scope> {
+        let mut scope = self.clone();
+        other.iter().for_each(|lib| {
+            scope.add(lib);
+        });
+        scope
+    }
+
+    /// Finds the first definition of the symbol `name` in the scope.
+    pub fn find(&self, name: &str) -> Option<*const ()> {
+        self.libs
+            .iter()
+            .find_map(|lib| unsafe { lib.get::<()>(name).map(|sym| sym.into_raw()) })
+    }
+
+    /// Gets the libraries in the scope in lookup order.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, RelocatedDylib<'scope>> {
+        self.libs.iter()
+    }
+
+    /// Gets the number of libraries in the scope.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.libs.len()
+    }
+
+    /// Whether the scope is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.libs.is_empty()
+    }
+}
@@ -0,0 +1,249 @@
+//! Searching the dependencies of elf objects
+//!
+//! The search order follows the dynamic linker of glibc: `DT_RPATH`(only when there is no
+//! `DT_RUNPATH`), the library paths of the config(like `LD_LIBRARY_PATH`), `DT_RUNPATH`, and
+//! finally the default paths. `$ORIGIN`, `$LIB` and `$PLATFORM` in the paths are expanded.
+use crate::ElfObject;
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const PLATFORM: &str = "x86_64";
+    } else if #[cfg(target_arch = "aarch64")] {
+        const PLATFORM: &str = "aarch64";
+    } else if #[cfg(target_arch = "riscv64")] {
+        const PLATFORM: &str = "riscv64";
+    } else if #[cfg(target_arch = "loongarch64")] {
+        const PLATFORM: &str = "loongarch64";
+    }
+}
+
+/// The elf object whose dependency is being searched
+#[derive(Clone, Copy, Debug)]
+pub struct NeededBy<'a> {
+    /// The path of the elf object.
+    pub name: &'a str,
+    /// DT_RPATH
+    pub rpath: Option<&'a str>,
+    /// DT_RUNPATH
+    pub runpath: Option<&'a str>,
+}
+
+impl NeededBy<'_> {
+    /// Gets the directory containing the elf object, which is the value of `$ORIGIN`.
+    #[inline]
+    pub fn origin(&self) -> &str {
+        match self.name.rsplit_once('/') {
+            Some(("", _)) => "/",
+            Some((dir, _)) => dir,
+            None => ".",
+        }
+    }
+}
+
+/// Expands the dynamic string tokens `$ORIGIN`, `$LIB` and `$PLATFORM` in `path`.
+/// The tokens can also be written as `${ORIGIN}`, `${LIB}` and `${PLATFORM}`.
+///
+/// # Examples
+/// ```
+/// use elf_loader::search::expand_dst;
+///
+/// let path = expand_dst("$ORIGIN/../${LIB}", "/opt/app/bin", "lib64", "x86_64");
+/// assert_eq!(path, "/opt/app/bin/../lib64");
+/// // `$ORIGINAL` is not a token
+/// assert_eq!(expand_dst("$ORIGINAL", "/opt", "lib", "x86_64"), "$ORIGINAL");
+/// ```
+pub fn expand_dst(path: &str, origin: &str, lib: &str, platform: &str) -> String {
+    let tokens = [("ORIGIN", origin), ("LIB", lib), ("PLATFORM", platform)];
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let token = tokens.iter().find_map(|(token, value)| {
+            if let Some(after) = rest
+                .strip_prefix('{')
+                .and_then(|s| s.strip_prefix(token))
+                .and_then(|s| s.strip_prefix('}'))
+            {
+                return Some((after, value));
+            }
+            let after = rest.strip_prefix(token)?;
+            // `$ORIGINAL`中的`$ORIGIN`不是token
+            match after.bytes().next() {
+                Some(b) if b.is_ascii_alphanumeric() || b == b'_' => None,
+                _ => Some((after, value)),
+            }
+        });
+        match token {
+            Some((after, value)) => {
+                expanded.push_str(value);
+                rest = after;
+            }
+            None => expanded.push('$'),
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The configuration used to search the dependencies of elf objects
+#[derive(Clone, Debug)]
+pub struct SearchConfig {
+    library_paths: Vec<String>,
+    default_paths: Vec<String>,
+    lib: String,
+    platform: String,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            library_paths: Vec::new(),
+            default_paths: vec!["/lib".to_string(), "/usr/lib".to_string()],
+            lib: "lib".to_string(),
+            platform: PLATFORM.to_string(),
+        }
+    }
+}
+
+impl SearchConfig {
+    /// Creates a config which searches `/lib` and `/usr/lib` by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a path searched before `DT_RUNPATH`, like a path in `LD_LIBRARY_PATH`.
+    pub fn library_path(mut self, path: &str) -> Self {
+        self.library_paths.push(path.to_string());
+        self
+    }
+
+    /// Replaces the paths searched after all other paths.
+    pub fn default_paths(mut self, paths: &[&str]) -> Self {
+        self.default_paths = paths.iter().map(|path| path.to_string()).collect();
+        self
+    }
+
+    /// Sets the value of `$LIB`, the default is `lib`.
+    pub fn lib(mut self, lib: &str) -> Self {
+        self.lib = lib.to_string();
+        self
+    }
+
+    /// Sets the value of `$PLATFORM`, the default is the name of the current architecture.
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.platform = platform.to_string();
+        self
+    }
+
+    /// Gets the paths where the dependency `needed` of `needed_by` is searched, in order.
+    pub fn candidates(&self, needed: &str, needed_by: &NeededBy) -> Vec<String> {
+        // 含有'/'的名字直接作为路径使用
+        if needed.contains('/') {
+            return vec![self.expand(needed, needed_by)];
+        }
+        let rpath = needed_by.rpath.filter(|_| needed_by.runpath.is_none());
+        let dirs = rpath
+            .into_iter()
+            .flat_map(|rpath| rpath.split(':'))
+            .chain(self.library_paths.iter().flat_map(|path| path.split(':')))
+            .chain(
+                needed_by
+                    .runpath
+                    .into_iter()
+                    .flat_map(|path| path.split(':')),
+            )
+            .chain(self.default_paths.iter().map(|path| path.as_str()));
+        let mut candidates: Vec<String> = Vec::new();
+        for dir in dirs {
+            let dir = if dir.is_empty() { "." } else { dir };
+            let mut path = self.expand(dir, needed_by);
+            if !path.ends_with('/') {
+                path.push('/');
+            }
+            path.push_str(needed);
+            if !candidates.contains(&path) {
+                candidates.push(path);
+            }
+        }
+        candidates
+    }
+
+    #[inline]
+    fn expand(&self, path: &str, needed_by: &NeededBy) -> String {
+        expand_dst(path, needed_by.origin(), &self.lib, &self.platform)
+    }
+}
+
+/// A trait turning the dependencies(`DT_NEEDED`) of elf objects into elf objects
+pub trait LibraryResolver {
+    type Object: ElfObject;
+    /// Finds the dependency `needed` of the elf object `needed_by`.
+    fn resolve(&mut self, needed: &str, needed_by: &NeededBy) -> Option<Self::Object>;
+}
+
+/// Adapts a closure which only looks at the name of the dependency
+pub(crate) struct FnResolver<F>(pub(crate) F);
+
+impl<O: ElfObject, F: FnMut(&str) -> Option<O>> LibraryResolver for FnResolver<F> {
+    type Object = O;
+
+    #[inline]
+    fn resolve(&mut self, needed: &str, _needed_by: &NeededBy) -> Option<O> {
+        (self.0)(needed)
+    }
+}
+
+/// A resolver opening the first existing elf file built for the host among the candidates of
+/// `SearchConfig`
+#[cfg(feature = "fs")]
+#[derive(Clone, Debug, Default)]
+pub struct FsResolver {
+    config: SearchConfig,
+}
+
+#[cfg(feature = "fs")]
+impl FsResolver {
+    pub fn new(config: SearchConfig) -> Self {
+        Self { config }
+    }
+
+    /// Gets the search config
+    #[inline]
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
+}
+
+#[cfg(feature = "fs")]
+impl LibraryResolver for FsResolver {
+    type Object = crate::object::ElfFile;
+
+    fn resolve(&mut self, needed: &str, needed_by: &NeededBy) -> Option<Self::Object> {
+        self.config
+            .candidates(needed, needed_by)
+            .iter()
+            .filter_map(|path| crate::object::ElfFile::from_path(path).ok())
+            .find_map(|mut object| is_native(&mut object).then_some(object))
+    }
+}
+
+/// Whether the elf object is built for the host. Like glibc, candidates of another class or
+/// architecture, such as 32-bit libraries with the same name, are skipped.
+#[cfg(feature = "fs")]
+fn is_native(object: &mut impl ElfObject) -> bool {
+    use crate::arch::{E_CLASS, E_DATA, EM_ARCH};
+    use elf::abi::{EI_CLASS, EI_DATA, ELFMAGIC};
+    // e_ident和e_type之后是e_machine
+    let mut ident = [0u8; 20];
+    object.read(&mut ident, 0).is_ok()
+        && ident[..4] == ELFMAGIC
+        && ident[EI_CLASS] == E_CLASS
+        && ident[EI_DATA] == E_DATA
+        && u16::from_ne_bytes([ident[18], ident[19]]) == EM_ARCH
+}
@@ -1,10 +1,15 @@
 //! The Memory mapping of elf object
 use super::mmap::{self, Mmap, ProtFlags};
-use crate::{Result, arch::Phdr};
+use crate::{
+    Result,
+    arch::{ElfPhdr, Phdr, flush_icache},
+};
+use alloc::vec::Vec;
 use core::ffi::c_void;
 use core::fmt::Debug;
+use core::ops::Range;
 use core::ptr::NonNull;
-use elf::abi::{PF_R, PF_W, PF_X};
+use elf::abi::{PF_R, PF_W, PF_X, PT_LOAD};
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const MASK: usize = !(PAGE_SIZE - 1);
@@ -26,12 +31,96 @@ impl ELFRelro {
     }
 }
 
+/// The read-only segments of an elf object with text relocations(`DT_TEXTREL`)
+pub(crate) struct TextRel {
+    // 段的地址,长度以及原本的权限
+    segments: Vec<(usize, usize, ProtFlags)>,
+    mprotect: unsafe fn(NonNull<c_void>, usize, ProtFlags) -> Result<()>,
+}
+
+impl TextRel {
+    pub(crate) fn new<M: Mmap>(phdrs: &[ElfPhdr], base: usize, exec_prot: ProtFlags) -> TextRel {
+        let segments = phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_W == 0)
+            .map(|phdr| {
+                let start = (base + phdr.p_vaddr as usize) & MASK;
+                let end = (base + (phdr.p_vaddr + phdr.p_memsz) as usize + PAGE_SIZE - 1) & MASK;
+                let mut prot = ElfSegments::map_prot(phdr.p_flags);
+                if phdr.p_flags & PF_X != 0 {
+                    prot |= exec_prot;
+                }
+                (start, end - start, prot)
+            })
+            .collect();
+        TextRel {
+            segments,
+            mprotect: M::mprotect,
+        }
+    }
+
+    /// 重定位期间使只读段可写,可执行段在此期间不可执行
+    pub(crate) fn make_writable(&self) -> Result<()> {
+        for &(addr, len, _) in &self.segments {
+            let addr = unsafe { NonNull::new_unchecked(addr as _) };
+            unsafe { (self.mprotect)(addr, len, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE) }?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn restore(&self) -> Result<()> {
+        for &(addr, len, prot) in &self.segments {
+            let addr = unsafe { NonNull::new_unchecked(addr as _) };
+            unsafe { (self.mprotect)(addr, len, prot) }?;
+        }
+        Ok(())
+    }
+}
+
+/// The final layout of a `PT_LOAD` segment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// The address of the segment in memory.
+    pub addr: usize,
+    /// The size of the segment in memory.
+    pub memsz: usize,
+    /// The size of the segment in the elf object.
+    pub filesz: usize,
+    /// The offset of the segment in the elf object.
+    pub offset: usize,
+    /// The protection of the segment.
+    pub prot: ProtFlags,
+}
+
+impl SegmentInfo {
+    #[inline]
+    pub(crate) fn new(phdr: &Phdr, base: usize) -> Self {
+        Self {
+            addr: base + phdr.p_vaddr as usize,
+            memsz: phdr.p_memsz as usize,
+            filesz: phdr.p_filesz as usize,
+            offset: phdr.p_offset as usize,
+            prot: ElfSegments::map_prot(phdr.p_flags),
+        }
+    }
+
+    /// Gets the page-aligned address range which is mapped for the segment.
+    #[inline]
+    pub fn page_range(&self) -> Range<usize> {
+        (self.addr & MASK)..((self.addr + self.memsz + PAGE_SIZE - 1) & MASK)
+    }
+}
+
 /// The Memory mapping of elf object
 pub struct ElfSegments {
     pub(crate) memory: NonNull<c_void>,
     /// addr_min
     pub(crate) offset: usize,
     pub(crate) len: usize,
+    /// The length of the memory at the start which is used in place and not owned
+    pub(crate) borrowed: usize,
+    /// The size of the guard pages on each side of the memory
+    pub(crate) guard: usize,
     pub(crate) munmap: unsafe fn(NonNull<c_void>, usize) -> Result<()>,
 }
 
@@ -41,6 +130,8 @@ impl Debug for ElfSegments {
             .field("memory", &self.memory)
             .field("offset", &self.offset)
             .field("len", &self.len)
+            .field("borrowed", &self.borrowed)
+            .field("guard", &self.guard)
             .finish()
     }
 }
@@ -56,12 +147,35 @@ impl ELFRelro {
         }
         Ok(())
     }
+
+    /// 修改relro中addr所在页的权限,addr不在relro中时返回false
+    pub(crate) fn protect_page(&self, addr: usize, prot: ProtFlags) -> Result<bool> {
+        if !(self.addr..self.addr + self.len).contains(&addr) {
+            return Ok(false);
+        }
+        let page = unsafe { NonNull::new_unchecked((addr & MASK) as _) };
+        unsafe { (self.mprotect)(page, PAGE_SIZE, prot) }?;
+        Ok(true)
+    }
 }
 
 impl Drop for ElfSegments {
     fn drop(&mut self) {
-        unsafe {
-            (self.munmap)(self.memory, self.len).unwrap();
+        // 原地使用的内存不属于ElfSegments
+        if self.len > self.borrowed {
+            unsafe {
+                (self.munmap)(
+                    self.memory.byte_add(self.borrowed),
+                    self.len - self.borrowed,
+                )
+                .unwrap();
+            }
+        }
+        if self.guard != 0 {
+            unsafe {
+                (self.munmap)(self.memory.byte_sub(self.guard), self.guard).unwrap();
+                (self.munmap)(self.memory.byte_add(self.len), self.guard).unwrap();
+            }
         }
     }
 }
@@ -76,6 +190,8 @@ impl ElfSegments {
             memory,
             offset: 0,
             len,
+            borrowed: 0,
+            guard: 0,
             munmap,
         }
     }
@@ -128,6 +244,22 @@ impl ElfSegments {
         self.get_ptr::<T>(offset) as *mut T
     }
 
+    /// 使写入可执行段的指令对取指可见, writable_only为true时只处理同时可写的段
+    pub(crate) fn flush_icache(&self, phdrs: &[ElfPhdr], writable_only: bool) {
+        let base = self.base();
+        phdrs
+            .iter()
+            .filter(|phdr| {
+                phdr.p_type == PT_LOAD
+                    && phdr.p_flags & PF_X != 0
+                    && (!writable_only || phdr.p_flags & PF_W != 0)
+            })
+            .for_each(|phdr| {
+                let start = base + phdr.p_vaddr as usize;
+                flush_icache(start..start + phdr.p_memsz as usize);
+            });
+    }
+
     /// base = memory_addr - offset
     #[inline]
     pub fn base(&self) -> usize {
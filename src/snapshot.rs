@@ -0,0 +1,340 @@
+//! Snapshots of relocated dynamic libraries
+//!
+//! [`RelocatedDylib::snapshot`] captures the loaded state of a library for bug reports: its base,
+//! the contents of its segments, the values written by the relocations and the libraries its
+//! dependencies were resolved to. The snapshot is written into a [`ByteSink`], which is any
+//! `std::io::Write` with `std` or a `Vec<u8>` without it, and can be read back by
+//! [`Snapshot::parse`] and compared against a library loaded again in a test.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{load_dylib, snapshot::Snapshot};
+//!
+//! let libb = load_dylib!("target/libb.so").unwrap();
+//! let libb = libb.easy_relocate([].iter(), &|_| None).unwrap();
+//! let mut bytes = Vec::new();
+//! libb.snapshot([].iter(), &mut bytes).unwrap();
+//! // in a test
+//! let snapshot = Snapshot::parse(&bytes).unwrap();
+//! let libb = load_dylib!("target/libb.so").unwrap();
+//! let libb = libb.easy_relocate([].iter(), &|_| None).unwrap();
+//! assert!(snapshot.mismatches(&libb).is_empty());
+//! ```
+use crate::{CoreComponent, RelocatedDylib, Result, io_error};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use elf::abi::{PF_R, PT_LOAD};
+
+const MAGIC: &[u8; 4] = b"ELSN";
+const VERSION: u32 = 1;
+
+/// A destination of the bytes of a snapshot
+pub trait ByteSink {
+    /// Writes all of `bytes`.
+    fn put(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> ByteSink for W {
+    #[inline]
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_all(bytes)
+            .map_err(|err| io_error(alloc::format!("failed to write the snapshot: {err}")))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteSink for Vec<u8> {
+    #[inline]
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// The contents of a `PT_LOAD` segment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentSnapshot {
+    /// The offset of the segment from the base.
+    pub vaddr: usize,
+    pub memsz: usize,
+    /// The flags(`PF_*`) of the segment.
+    pub flags: u32,
+    /// The memory of the segment, which is empty if the segment is not readable.
+    pub data: Vec<u8>,
+}
+
+/// The word at the target of a relocation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelocValue {
+    /// The offset of the target from the base.
+    pub offset: usize,
+    pub r_type: u32,
+    /// The name of the symbol of the relocation, which is empty if it has no symbol.
+    pub symbol: String,
+    pub value: usize,
+}
+
+/// A dependency(`DT_NEEDED`) and the library it was resolved to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DependencySnapshot {
+    pub needed: String,
+    /// The name and the base of the library, or `None` if it was not in the scope.
+    pub resolved: Option<(String, usize)>,
+}
+
+/// A snapshot read by `Snapshot::parse`
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    name: String,
+    base: usize,
+    segments: Vec<SegmentSnapshot>,
+    relocations: Vec<RelocValue>,
+    dependencies: Vec<DependencySnapshot>,
+}
+
+impl RelocatedDylib<'_> {
+    /// Writes a snapshot of the library into `sink`. `scope` is the scope used to relocate the
+    /// library, in which its dependencies are looked up.
+    /// # Note
+    /// The library does not remember which libraries its relocations were bound to, so each
+    /// `DT_NEEDED` is recorded as the first library in `scope` providing it. Only the relocations
+    /// of `DT_RELA` and `DT_JMPREL` are recorded, as the loader does not apply `DT_REL` and
+    /// `DT_RELR`.
+    pub fn snapshot<'iter, 'scope, S>(&self, scope: S, sink: &mut impl ByteSink) -> Result<()>
+    where
+        S: Iterator<Item = &'iter RelocatedDylib<'scope>>,
+        'scope: 'iter,
+    {
+        let base = self.base();
+        let mut writer = Writer(sink);
+        writer.put(MAGIC)?;
+        writer.u32(VERSION)?;
+        writer.bytes(self.name().as_bytes())?;
+        writer.u64(base as u64)?;
+
+        let loads = || self.phdrs().iter().filter(|phdr| phdr.p_type == PT_LOAD);
+        writer.u32(loads().count() as u32)?;
+        for phdr in loads() {
+            writer.u64(phdr.p_vaddr)?;
+            writer.u64(phdr.p_memsz)?;
+            writer.u32(phdr.p_flags)?;
+            // 不可读的段无法读取其内容
+            let data = if phdr.p_flags & PF_R != 0 {
+                let start = (base + phdr.p_vaddr as usize) as *const u8;
+                unsafe { core::slice::from_raw_parts(start, phdr.p_memsz as usize) }
+            } else {
+                &[]
+            };
+            writer.bytes(data)?;
+        }
+
+        let relas = self.relas();
+        writer.u32(relas.len() as u32)?;
+        for rela in relas {
+            writer.u64(rela.r_offset() as u64)?;
+            writer.u32(rela.r_type() as u32)?;
+            match rela.r_symbol() {
+                0 => writer.bytes(&[])?,
+                idx => writer.bytes(self.symtab().symbol_idx(idx).1.name().as_bytes())?,
+            }
+            let target = (base + rela.r_offset()) as *const usize;
+            writer.u64(unsafe { target.read_unaligned() } as u64)?;
+        }
+
+        let scope: Vec<&RelocatedDylib> = scope.collect();
+        writer.u32(self.needed_libs().len() as u32)?;
+        for needed in self.needed_libs() {
+            writer.bytes(needed.as_bytes())?;
+            match scope.iter().find(|lib| lib.provides(needed)) {
+                Some(lib) => {
+                    writer.put(&[1])?;
+                    writer.bytes(lib.name().as_bytes())?;
+                    writer.u64(lib.base() as u64)?;
+                }
+                None => writer.put(&[0])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Reads a snapshot written by `RelocatedDylib::snapshot`.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return Err(io_error("unsupported snapshot format"));
+        }
+        let name = reader.str()?;
+        let base = reader.u64()? as usize;
+        let mut segments = Vec::new();
+        for _ in 0..reader.u32()? {
+            segments.push(SegmentSnapshot {
+                vaddr: reader.u64()? as usize,
+                memsz: reader.u64()? as usize,
+                flags: reader.u32()?,
+                data: reader.bytes()?.to_vec(),
+            });
+        }
+        let mut relocations = Vec::new();
+        for _ in 0..reader.u32()? {
+            relocations.push(RelocValue {
+                offset: reader.u64()? as usize,
+                r_type: reader.u32()?,
+                symbol: reader.str()?,
+                value: reader.u64()? as usize,
+            });
+        }
+        let mut dependencies = Vec::new();
+        for _ in 0..reader.u32()? {
+            let needed = reader.str()?;
+            let resolved = match reader.take(1)?[0] {
+                0 => None,
+                _ => Some((reader.str()?, reader.u64()? as usize)),
+            };
+            dependencies.push(DependencySnapshot { needed, resolved });
+        }
+        Ok(Self {
+            name,
+            base,
+            segments,
+            relocations,
+            dependencies,
+        })
+    }
+
+    /// Gets the name of the library.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the base of the library when the snapshot was taken.
+    #[inline]
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Gets the `PT_LOAD` segments of the library.
+    #[inline]
+    pub fn segments(&self) -> &[SegmentSnapshot] {
+        &self.segments
+    }
+
+    /// Gets the values written by the relocations of `.rela.dyn` and `.rela.plt`.
+    #[inline]
+    pub fn relocations(&self) -> &[RelocValue] {
+        &self.relocations
+    }
+
+    /// Gets the dependencies of the library.
+    #[inline]
+    pub fn dependencies(&self) -> &[DependencySnapshot] {
+        &self.dependencies
+    }
+
+    /// Compares the relocation values with the same library loaded again, and returns the ones
+    /// that differ. Values pointing into the library itself are compared relative to its base,
+    /// while the other values are compared as they are. Relocations whose target is not in a
+    /// readable `PT_LOAD` segment of `lib` are returned as well.
+    pub fn mismatches(&self, lib: &CoreComponent) -> Vec<&RelocValue> {
+        let old = self.base..self.base + self.map_len();
+        let new = lib.map_range();
+        let new_base = lib.base();
+        // 快照可能来自其他构建或者已经损坏,读取前检查目标是否在可读的段中
+        let readable = |offset: usize| {
+            let Some(end) = offset.checked_add(size_of::<usize>()) else {
+                return false;
+            };
+            lib.phdrs().iter().any(|phdr| {
+                phdr.p_type == PT_LOAD
+                    && phdr.p_flags & PF_R != 0
+                    && offset >= phdr.p_vaddr as usize
+                    && end <= (phdr.p_vaddr + phdr.p_memsz) as usize
+            }) && new_base + end <= new.end
+        };
+        self.relocations
+            .iter()
+            .filter(|reloc| {
+                if !readable(reloc.offset) {
+                    return true;
+                }
+                let target = (new_base + reloc.offset) as *const usize;
+                let value = unsafe { target.read_unaligned() };
+                if old.contains(&reloc.value) && new.contains(&value) {
+                    reloc.value - self.base != value - new_base
+                } else {
+                    reloc.value != value
+                }
+            })
+            .collect()
+    }
+
+    fn map_len(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| segment.vaddr + segment.memsz)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+struct Writer<'a, S: ByteSink>(&'a mut S);
+
+impl<S: ByteSink> Writer<'_, S> {
+    #[inline]
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.0.put(bytes)
+    }
+
+    #[inline]
+    fn u32(&mut self, val: u32) -> Result<()> {
+        self.put(&val.to_le_bytes())
+    }
+
+    #[inline]
+    fn u64(&mut self, val: u64) -> Result<()> {
+        self.put(&val.to_le_bytes())
+    }
+
+    #[inline]
+    fn bytes(&mut self, val: &[u8]) -> Result<()> {
+        self.u64(val.len() as u64)?;
+        self.put(val)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io_error("truncated snapshot"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        core::str::from_utf8(self.bytes()?)
+            .map(|s| s.to_string())
+            .map_err(|_| io_error("invalid string in snapshot"))
+    }
+}
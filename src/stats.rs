@@ -0,0 +1,258 @@
+//! Statistics of loading and relocation
+//!
+//! With the `stats` feature, every elf object loaded by a [`Loader`] records how many times the
+//! file was read and mapped, how many symbols were looked up in the scope, and how long each of
+//! them took. The numbers are kept until the elf object is dropped, and [`CoreComponent::stats`]
+//! returns them as a [`LoadStats`] together with the number of relocation entries of each type.
+//!
+//! Durations are measured by the [`Clock`] of the loader, which uses `std::time::Instant` with the
+//! `std` feature. Without it the durations stay zero unless a clock is set by `Loader::set_clock`.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::load_dylib;
+//!
+//! let liba = load_dylib!("target/liba.so").unwrap();
+//! let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+//! let stats = liba.stats().unwrap();
+//! println!("{} reads, {} mmaps, relocated in {:?}", stats.reads, stats.mmaps, stats.relocate_time);
+//! ```
+//!
+//! [`Loader`]: crate::Loader
+//! [`CoreComponent::stats`]: crate::CoreComponent::stats
+use crate::{
+    CoreComponent, Result,
+    object::{ElfObject, ElfObjectAsync, OffsetType},
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    ffi::CStr,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// A monotonic clock, which returns the time elapsed since an arbitrary fixed point.
+pub type Clock = fn() -> Duration;
+
+#[cfg(feature = "std")]
+pub(crate) fn default_clock() -> Duration {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_clock() -> Duration {
+    Duration::ZERO
+}
+
+/// The statistics of an elf object, see `CoreComponent::stats`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    /// The number of reads from the elf object.
+    pub reads: usize,
+    pub read_bytes: usize,
+    pub read_time: Duration,
+    /// The number of segments mapped, including the reservation of the whole memory.
+    pub mmaps: usize,
+    /// The time spent mapping segments, which includes reading them when they can not be mapped
+    /// from a file.
+    pub mmap_time: Duration,
+    /// The number of symbols looked up in the scope during relocation.
+    pub lookups: usize,
+    pub lookup_time: Duration,
+    /// The time spent processing the relocation tables.
+    pub relocate_time: Duration,
+    /// The number of relocation entries of each type, sorted by type.
+    pub relocations: Vec<(u32, usize)>,
+}
+
+/// 各项统计数据,时间以纳秒为单位
+pub(crate) struct StatsCollector {
+    clock: Clock,
+    reads: AtomicUsize,
+    read_bytes: AtomicUsize,
+    read_time: AtomicU64,
+    mmaps: AtomicUsize,
+    mmap_time: AtomicU64,
+    lookups: AtomicUsize,
+    lookup_time: AtomicU64,
+    relocate_time: AtomicU64,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            reads: AtomicUsize::new(0),
+            read_bytes: AtomicUsize::new(0),
+            read_time: AtomicU64::new(0),
+            mmaps: AtomicUsize::new(0),
+            mmap_time: AtomicU64::new(0),
+            lookups: AtomicUsize::new(0),
+            lookup_time: AtomicU64::new(0),
+            relocate_time: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn now(&self) -> Duration {
+        (self.clock)()
+    }
+
+    #[inline]
+    fn elapsed(&self, time: &AtomicU64, start: Duration) {
+        let nanos = self.now().saturating_sub(start).as_nanos() as u64;
+        time.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_read(&self, start: Duration, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.elapsed(&self.read_time, start);
+    }
+
+    pub(crate) fn record_mmap(&self, start: Duration) {
+        self.mmaps.fetch_add(1, Ordering::Relaxed);
+        self.elapsed(&self.mmap_time, start);
+    }
+
+    pub(crate) fn record_lookup(&self, start: Duration) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.elapsed(&self.lookup_time, start);
+    }
+
+    pub(crate) fn record_relocate(&self, start: Duration) {
+        self.elapsed(&self.relocate_time, start);
+    }
+}
+
+/// 在离开作用域时记录经过的时间
+pub(crate) struct Timer<'a> {
+    stats: &'a StatsCollector,
+    start: Duration,
+    record: fn(&StatsCollector, Duration),
+}
+
+impl<'a> Timer<'a> {
+    #[inline]
+    pub(crate) fn lookup(stats: &'a StatsCollector) -> Self {
+        Self {
+            stats,
+            start: stats.now(),
+            record: StatsCollector::record_lookup,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn relocate(stats: &'a StatsCollector) -> Self {
+        Self {
+            stats,
+            start: stats.now(),
+            record: StatsCollector::record_relocate,
+        }
+    }
+}
+
+impl Drop for Timer<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        (self.record)(self.stats, self.start);
+    }
+}
+
+impl CoreComponent {
+    /// Gets the statistics of loading and relocating the elf object. Returns `None` if the elf
+    /// object was not loaded by a `Loader`.
+    pub fn stats(&self) -> Option<LoadStats> {
+        let stats = self.inner.stats.as_ref()?;
+        let nanos = |time: &AtomicU64| Duration::from_nanos(time.load(Ordering::Relaxed));
+        let mut relocations: BTreeMap<u32, usize> = BTreeMap::new();
+        for rela in self.relas() {
+            *relocations.entry(rela.r_type() as u32).or_default() += 1;
+        }
+        Some(LoadStats {
+            reads: stats.reads.load(Ordering::Relaxed),
+            read_bytes: stats.read_bytes.load(Ordering::Relaxed),
+            read_time: nanos(&stats.read_time),
+            mmaps: stats.mmaps.load(Ordering::Relaxed),
+            mmap_time: nanos(&stats.mmap_time),
+            lookups: stats.lookups.load(Ordering::Relaxed),
+            lookup_time: nanos(&stats.lookup_time),
+            relocate_time: nanos(&stats.relocate_time),
+            relocations: relocations.into_iter().collect(),
+        })
+    }
+}
+
+/// An elf object whose reads are recorded
+pub(crate) struct StatsObject<O> {
+    object: O,
+    stats: Arc<StatsCollector>,
+}
+
+impl<O> StatsObject<O> {
+    pub(crate) fn new(object: O, stats: Arc<StatsCollector>) -> Self {
+        Self { object, stats }
+    }
+}
+
+impl<O: ElfObject> ElfObject for StatsObject<O> {
+    #[inline]
+    fn file_name(&self) -> &CStr {
+        self.object.file_name()
+    }
+
+    fn read(&mut self, buf: &mut [u8], offset: usize) -> Result<()> {
+        let start = self.stats.now();
+        let res = self.object.read(buf, offset);
+        self.stats.record_read(start, buf.len());
+        res
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [&mut [u8]], offset: usize) -> Result<()> {
+        let start = self.stats.now();
+        let res = self.object.read_vectored(bufs, offset);
+        let bytes = bufs.iter().map(|buf| buf.len()).sum();
+        self.stats.record_read(start, bytes);
+        res
+    }
+
+    #[inline]
+    fn size(&self) -> Option<usize> {
+        self.object.size()
+    }
+
+    #[inline]
+    fn as_fd(&self) -> Option<i32> {
+        self.object.as_fd()
+    }
+
+    #[inline]
+    fn offset_type(&self) -> OffsetType {
+        self.object.offset_type()
+    }
+
+    #[inline]
+    fn as_static_bytes(&self) -> Option<&'static [u8]> {
+        self.object.as_static_bytes()
+    }
+}
+
+impl<O: ElfObjectAsync> ElfObjectAsync for StatsObject<O> {
+    fn read_async(
+        &mut self,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> impl Future<Output = Result<()>> + Send {
+        // 只借用统计数据,使返回的future不要求O是Send
+        let stats: &StatsCollector = &self.stats;
+        let len = buf.len();
+        let start = stats.now();
+        let read = self.object.read_async(buf, offset);
+        async move {
+            let res = read.await;
+            stats.record_read(start, len);
+            res
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{arch::ElfSymbol, dynamic::ElfDynamic};
-use core::ffi::CStr;
+use core::{
+    ffi::CStr,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 #[repr(C)]
 struct ElfGnuHeader {
@@ -92,6 +95,30 @@ impl ElfStringTable {
     }
 }
 
+/// The binding of a symbol that can be used for relocation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolBinding {
+    /// STB_GLOBAL
+    Global,
+    /// STB_WEAK, a global definition is preferred over it during relocation.
+    Weak,
+    /// STB_GNU_UNIQUE, all references are bound to the first definition in the process.
+    Unique,
+}
+
+impl SymbolBinding {
+    #[inline]
+    pub(crate) fn of(sym: &ElfSymbol) -> Self {
+        if sym.is_weak() {
+            SymbolBinding::Weak
+        } else if sym.is_unique() {
+            SymbolBinding::Unique
+        } else {
+            SymbolBinding::Global
+        }
+    }
+}
+
 /// Symbol table of elf file.
 pub struct SymbolTable {
     /// .gnu.hash
@@ -109,6 +136,9 @@ pub struct SymbolTable {
 pub struct SymbolInfo<'symtab> {
     name: &'symtab str,
     cname: Option<&'symtab CStr>,
+    // 查找时计算一次gnu hash,之后在每个库中查找时复用。0表示还没有计算,
+    // 哈希值恰好为0时只是每次重新计算
+    hash: AtomicU32,
     #[cfg(feature = "version")]
     version: Option<super::version::SymbolVersion<'symtab>>,
 }
@@ -118,6 +148,7 @@ impl<'symtab> SymbolInfo<'symtab> {
         SymbolInfo {
             name,
             cname: None,
+            hash: AtomicU32::new(0),
             #[cfg(feature = "version")]
             version: None,
         }
@@ -128,6 +159,7 @@ impl<'symtab> SymbolInfo<'symtab> {
         SymbolInfo {
             name,
             cname: None,
+            hash: AtomicU32::new(0),
             version: Some(crate::version::SymbolVersion::new(version)),
         }
     }
@@ -143,6 +175,17 @@ impl<'symtab> SymbolInfo<'symtab> {
     pub fn cname(&self) -> Option<&CStr> {
         self.cname
     }
+
+    #[inline]
+    fn hash(&self) -> u32 {
+        let hash = self.hash.load(Ordering::Relaxed);
+        if hash != 0 {
+            return hash;
+        }
+        let hash = ElfGnuHash::gnu_hash(self.name.as_bytes());
+        self.hash.store(hash, Ordering::Relaxed);
+        hash
+    }
 }
 
 impl SymbolTable {
@@ -172,7 +215,7 @@ impl SymbolTable {
 
     /// Use the symbol specific information to get the symbol in the symbol table
     pub fn lookup(&self, symbol: &SymbolInfo) -> Option<&ElfSymbol> {
-        let hash = ElfGnuHash::gnu_hash(symbol.name.as_bytes());
+        let hash = symbol.hash();
         let fofs = hash as usize / (8 * size_of::<usize>());
         let fmask = 1 << hash % (8 * size_of::<usize>() as u32);
         let bloom_idx = fofs & (self.hashtab.header.nbloom - 1) as usize;
@@ -222,11 +265,12 @@ impl SymbolTable {
         None
     }
 
-    /// Use the symbol specific information to get the symbol which can be used for relocation in the symbol table
+    /// Use the symbol specific information to get the symbol which can be used for relocation in the symbol table.
+    /// Hidden symbols can not be referenced by other elf objects, so they are not returned.
     #[inline]
     pub fn lookup_filter(&self, symbol: &SymbolInfo) -> Option<&ElfSymbol> {
         if let Some(sym) = self.lookup(symbol) {
-            if !sym.is_undef() && sym.is_ok_bind() && sym.is_ok_type() {
+            if !sym.is_undef() && !sym.is_hidden() && sym.is_ok_bind() && sym.is_ok_type() {
                 return Some(sym);
             }
         }
@@ -247,6 +291,7 @@ impl SymbolTable {
             SymbolInfo {
                 name,
                 cname: Some(&cname),
+                hash: AtomicU32::new(0),
                 #[cfg(feature = "version")]
                 version: self.get_requirement(idx),
             },
@@ -0,0 +1,196 @@
+//! Symbols in the section headers of elf files
+//!
+//! The loader only reads the dynamic symbols, which are mapped into memory. The `.symtab` section
+//! also contains the local symbols, such as static functions, but it is not mapped, so it is
+//! parsed from the bytes of the elf file or of its separate debug file instead. The symbols are
+//! merged with `.dynsym`, so stripped elf files still have their dynamic symbols.
+//!
+//! # Examples
+//! ```no_run
+//! use elf_loader::{load_dylib, symtab::FileSymbols};
+//!
+//! let liba = load_dylib!("target/liba.so").unwrap();
+//! let symbols = FileSymbols::from_path("target/liba.so").unwrap();
+//! let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+//! // a static function of liba.so
+//! let addr = symbols.lookup(&liba, "helper").unwrap();
+//! assert_eq!(symbols.symbol_at(&liba, addr as usize).unwrap().name, "helper");
+//! ```
+use crate::{CoreComponent, Result, io_error, parse_ehdr_error, symbol::SymbolInfo};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use elf::{
+    ElfBytes,
+    abi::{SHN_ABS, SHN_UNDEF, STB_LOCAL, STT_FILE, STT_SECTION, STT_TLS},
+    endian::NativeEndian,
+};
+
+/// A symbol in `.symtab` or `.dynsym`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileSymbol {
+    pub name: String,
+    /// The address of the symbol relative to the base of the elf object. It is the offset in the
+    /// TLS block for TLS symbols and the value itself for absolute symbols.
+    pub value: usize,
+    pub size: usize,
+    /// The type of the symbol(`STT_*`).
+    pub symtype: u8,
+    /// The binding of the symbol(`STB_*`).
+    pub bind: u8,
+    /// The index of the section defining the symbol(`st_shndx`).
+    pub shndx: u16,
+}
+
+impl FileSymbol {
+    /// Whether the symbol is local to the elf object.
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        self.bind == STB_LOCAL
+    }
+
+    /// Whether the symbol is a thread-local variable.
+    #[inline]
+    pub fn is_tls(&self) -> bool {
+        self.symtype == STT_TLS
+    }
+
+    /// Whether the value of the symbol is absolute(`SHN_ABS`) instead of relative to the base.
+    #[inline]
+    pub fn is_abs(&self) -> bool {
+        self.shndx == SHN_ABS
+    }
+}
+
+/// The symbols defined in an elf file, including the local ones
+pub struct FileSymbols {
+    // 按地址排序
+    symbols: Vec<FileSymbol>,
+    // 名字 -> 下标
+    names: BTreeMap<String, usize>,
+}
+
+impl FileSymbols {
+    /// Parses `.symtab` and `.dynsym` of the elf file `bytes`.
+    ///
+    /// `bytes` can also be a separate debug file of the elf object, which has the same addresses.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let file = ElfBytes::<NativeEndian>::minimal_parse(bytes).map_err(parse_ehdr_error)?;
+        let mut symbols = Vec::new();
+        for table in [file.symbol_table(), file.dynamic_symbol_table()] {
+            let Some((symtab, strtab)) = table.map_err(parse_ehdr_error)? else {
+                continue;
+            };
+            for sym in symtab.iter() {
+                // 跳过未定义的符号以及文件和节的符号
+                if sym.st_shndx == SHN_UNDEF
+                    || sym.st_name == 0
+                    || matches!(sym.st_symtype(), STT_FILE | STT_SECTION)
+                {
+                    continue;
+                }
+                let name = strtab.get(sym.st_name as usize).map_err(parse_ehdr_error)?;
+                symbols.push(FileSymbol {
+                    name: name.to_string(),
+                    value: sym.st_value as usize,
+                    size: sym.st_size as usize,
+                    symtype: sym.st_symtype(),
+                    bind: sym.st_bind(),
+                    shndx: sym.st_shndx,
+                });
+            }
+        }
+        // .dynsym中的符号通常也在.symtab中
+        symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+        symbols.dedup_by(|a, b| a.value == b.value && a.name == b.name);
+        let mut names = BTreeMap::new();
+        for (idx, symbol) in symbols.iter().enumerate() {
+            // 全局符号优先于同名的局部符号
+            let old = names.entry(symbol.name.clone()).or_insert(idx);
+            if symbols[*old].is_local() && !symbol.is_local() {
+                *old = idx;
+            }
+        }
+        Ok(Self { symbols, names })
+    }
+
+    /// Reads and parses the elf file at `path`.
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|err| io_error(format!("failed to read {}: {err}", path.display())))?;
+        Self::parse(&bytes)
+    }
+
+    /// Gets the symbol `name`. Global symbols take precedence over local symbols with the same name.
+    pub fn get(&self, name: &str) -> Option<&FileSymbol> {
+        self.names.get(name).map(|&idx| &self.symbols[idx])
+    }
+
+    /// Iterates over the symbols in the order of their addresses.
+    pub fn iter(&self) -> impl Iterator<Item = &FileSymbol> {
+        self.symbols.iter()
+    }
+
+    /// Gets the number of symbols.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Whether there are no symbols.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Gets the symbol containing the address `offset` relative to the base of the elf object.
+    /// A symbol without size only contains its own address. TLS and absolute symbols are skipped,
+    /// since their values are not addresses in the elf object.
+    pub fn symbol_at_offset(&self, offset: usize) -> Option<&FileSymbol> {
+        let end = self.symbols.partition_point(|sym| sym.value <= offset);
+        // 从最接近的符号开始向前找
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .filter(|sym| !sym.is_tls() && !sym.is_abs())
+            .find(|sym| offset - sym.value < sym.size.max(1))
+    }
+
+    /// Gets the address of the symbol `name` in the loaded elf object `lib`, which is the elf
+    /// object parsed by `FileSymbols`. The dynamic symbols of `lib` are looked up first.
+    ///
+    /// The value of an absolute symbol is returned as it is. TLS symbols are not found, since each
+    /// thread has its own copy of them.
+    pub fn lookup(&self, lib: &CoreComponent, name: &str) -> Option<*const ()> {
+        let dynamic = lib
+            .symtab()
+            .and_then(|symtab| symtab.lookup_filter(&SymbolInfo::from_str(name)));
+        let (value, abs) = match dynamic {
+            Some(sym) if sym.st_type() == STT_TLS => return None,
+            Some(sym) => (sym.st_value(), sym.is_abs()),
+            None => {
+                let sym = self.get(name).filter(|sym| !sym.is_tls())?;
+                (sym.value, sym.is_abs())
+            }
+        };
+        let addr = if abs { value } else { lib.base() + value };
+        Some(addr as *const ())
+    }
+
+    /// Gets the symbol containing the address `addr` in the loaded elf object `lib`.
+    pub fn symbol_at(&self, lib: &CoreComponent, addr: usize) -> Option<&FileSymbol> {
+        self.symbol_at_offset(addr.checked_sub(lib.base())?)
+    }
+}
+
+/// Gets the conventional path of the separate debug file of an elf object with the GNU build-id
+/// `build_id`, such as `/usr/lib/debug/.build-id/ab/cdef.debug`.
+pub fn build_id_path(build_id: &[u8]) -> Option<String> {
+    let (first, rest) = build_id.split_first()?;
+    let rest: String = rest.iter().map(|byte| format!("{byte:02x}")).collect();
+    Some(format!("/usr/lib/debug/.build-id/{first:02x}/{rest}.debug"))
+}
@@ -0,0 +1,341 @@
+//! Thread local storage
+use crate::arch::ElfPhdr;
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicIsize, AtomicU8, Ordering},
+};
+
+/// The argument of `__tls_get_addr`.
+#[repr(C)]
+pub struct TlsIndex {
+    /// The module id of the elf object.
+    pub ti_module: usize,
+    /// The offset of the variable in the tls block of the module.
+    pub ti_offset: usize,
+}
+
+/// A trait representing the thread local storage management of the embedder.
+///
+/// The loader itself does not know how the tls blocks of each thread are laid out, so it asks the
+/// implementation for module ids when loading an elf object with a `PT_TLS` segment, and uses
+/// `tls_get_addr` to resolve dynamic tls descriptors. Modules accessed with the initial-exec model
+/// additionally need a block in the static tls area, which is reserved through `reserve_static`.
+pub trait ThreadLocal {
+    /// This function registers the `PT_TLS` segment of an elf object and returns the module id assigned to it.
+    /// It may be called by several loaders on different threads at the same time, so the module ids must be
+    /// allocated atomically.
+    ///
+    /// # Arguments
+    /// * `phdr` - The `PT_TLS` program header.
+    /// * `base` - The base address of the elf object. The tls initialization image is at `base + phdr.p_vaddr`.
+    ///
+    /// # Safety
+    /// The initialization image is only valid while the elf object is alive.
+    unsafe fn register(phdr: &ElfPhdr, base: usize) -> Option<usize>;
+
+    /// This function releases a module id previously returned by `register`. It is called when the elf object is dropped.
+    ///
+    /// # Safety
+    /// The module id must not be used after it is released.
+    unsafe fn unregister(modid: usize);
+
+    /// This function returns the address of the tls variable described by `ti` in the current thread,
+    /// allocating the tls block of the module if necessary. It has the same semantics as `__tls_get_addr`.
+    ///
+    /// # Safety
+    /// `ti` must point to a valid `TlsIndex` whose module id was returned by `register`.
+    unsafe extern "C" fn tls_get_addr(ti: *const TlsIndex) -> *mut u8;
+
+    /// This function reserves space for the tls block of a module in the static tls area and returns the offset
+    /// of the block relative to the thread pointer. It returns `None` if the static tls area is exhausted.
+    ///
+    /// It is called at most once per module, the first time a `TPOFF` relocation refers to the module.
+    /// The default implementation does not support static tls.
+    ///
+    /// # Safety
+    /// `modid` must be a module id returned by `register`.
+    unsafe fn reserve_static(modid: usize) -> Option<isize> {
+        let _ = modid;
+        None
+    }
+}
+
+/// Thread local storage is not supported.
+impl ThreadLocal for () {
+    unsafe fn register(_phdr: &ElfPhdr, _base: usize) -> Option<usize> {
+        None
+    }
+
+    unsafe fn unregister(_modid: usize) {}
+
+    unsafe extern "C" fn tls_get_addr(_ti: *const TlsIndex) -> *mut u8 {
+        null_mut()
+    }
+}
+
+#[cfg(feature = "std")]
+pub use imp::ThreadLocalImpl;
+
+#[cfg(feature = "std")]
+mod imp {
+    use super::{ThreadLocal, TlsIndex};
+    use crate::arch::{ElfPhdr, TLS_DTV_OFFSET};
+    use alloc::collections::BTreeMap;
+    use core::{
+        alloc::Layout,
+        ptr::null_mut,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use std::{cell::RefCell, sync::RwLock};
+
+    /// The initialization image of a tls module
+    #[derive(Clone, Copy)]
+    struct Module {
+        image: usize,
+        filesz: usize,
+        layout: Layout,
+    }
+
+    // 模块id不会被重复使用,因此线程中残留的旧块不会被新的模块误用
+    static NEXT_MODID: AtomicUsize = AtomicUsize::new(1);
+    static MODULES: RwLock<BTreeMap<usize, Module>> = RwLock::new(BTreeMap::new());
+
+    struct Block {
+        ptr: *mut u8,
+        layout: Layout,
+    }
+
+    impl Drop for Block {
+        fn drop(&mut self) {
+            unsafe { std::alloc::dealloc(self.ptr, self.layout) };
+        }
+    }
+
+    std::thread_local! {
+        static BLOCKS: RefCell<BTreeMap<usize, Block>> = const { RefCell::new(BTreeMap::new()) };
+    }
+
+    /// An implementation of `ThreadLocal` keeping the tls blocks of each thread in a thread local map
+    ///
+    /// The module ids are taken from a process wide atomic counter and never reused, so loaders on
+    /// different threads always get distinct ids. The block of a module is allocated in a thread the
+    /// first time it is accessed there, and freed when the thread exits. Static tls is not supported.
+    ///
+    /// The elf objects using the general dynamic model call `__tls_get_addr`, which should be
+    /// resolved to `ThreadLocalImpl::tls_get_addr`, for example by `pre_find`.
+    pub struct ThreadLocalImpl;
+
+    impl ThreadLocal for ThreadLocalImpl {
+        unsafe fn register(phdr: &ElfPhdr, base: usize) -> Option<usize> {
+            let layout = Layout::from_size_align(
+                (phdr.p_memsz as usize).max(1),
+                (phdr.p_align as usize).max(1),
+            )
+            .ok()?;
+            let module = Module {
+                image: base + phdr.p_vaddr as usize,
+                filesz: phdr.p_filesz as usize,
+                layout,
+            };
+            let modid = NEXT_MODID.fetch_add(1, Ordering::Relaxed);
+            MODULES
+                .write()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(modid, module);
+            Some(modid)
+        }
+
+        unsafe fn unregister(modid: usize) {
+            MODULES
+                .write()
+                .unwrap_or_else(|err| err.into_inner())
+                .remove(&modid);
+            let _ = BLOCKS.try_with(|blocks| blocks.borrow_mut().remove(&modid));
+        }
+
+        unsafe extern "C" fn tls_get_addr(ti: *const TlsIndex) -> *mut u8 {
+            let ti = unsafe { &*ti };
+            let offset = ti.ti_offset.wrapping_add(TLS_DTV_OFFSET);
+            BLOCKS
+                .try_with(|blocks| {
+                    let mut blocks = blocks.borrow_mut();
+                    if let Some(block) = blocks.get(&ti.ti_module) {
+                        return block.ptr.wrapping_add(offset);
+                    }
+                    // 第一次在这个线程中访问时分配块并复制初始化镜像
+                    let Some(module) = MODULES
+                        .read()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .get(&ti.ti_module)
+                        .copied()
+                    else {
+                        return null_mut();
+                    };
+                    let ptr = unsafe { std::alloc::alloc_zeroed(module.layout) };
+                    if ptr.is_null() {
+                        std::alloc::handle_alloc_error(module.layout);
+                    }
+                    unsafe {
+                        ptr.copy_from_nonoverlapping(module.image as *const u8, module.filesz)
+                    };
+                    blocks.insert(
+                        ti.ti_module,
+                        Block {
+                            ptr,
+                            layout: module.layout,
+                        },
+                    );
+                    ptr.wrapping_add(offset)
+                })
+                .unwrap_or(null_mut())
+        }
+    }
+}
+
+pub(crate) type TlsGetAddr = unsafe extern "C" fn(*const TlsIndex) -> *mut u8;
+
+/// The tls module of an elf object
+pub(crate) struct ElfTls {
+    pub(crate) modid: usize,
+    pub(crate) tls_get_addr: TlsGetAddr,
+    unregister: unsafe fn(usize),
+    reserve_static: unsafe fn(usize) -> Option<isize>,
+    /// whether the static tls block has been reserved
+    static_state: AtomicU8,
+    /// offset of the static tls block relative to the thread pointer
+    static_offset: AtomicIsize,
+}
+
+impl ElfTls {
+    pub(crate) fn new<T: ThreadLocal>(phdr: &ElfPhdr, base: usize) -> Option<ElfTls> {
+        unsafe { T::register(phdr, base) }.map(|modid| ElfTls {
+            modid,
+            tls_get_addr: T::tls_get_addr,
+            unregister: T::unregister,
+            reserve_static: T::reserve_static,
+            static_state: AtomicU8::new(Self::NOT_RESERVED),
+            static_offset: AtomicIsize::new(0),
+        })
+    }
+
+    const NOT_RESERVED: u8 = 0;
+    const RESERVING: u8 = 1;
+    const RESERVED: u8 = 2;
+
+    /// Gets the offset of the static tls block if it has been reserved.
+    #[inline]
+    pub(crate) fn reserved_static(&self) -> Option<isize> {
+        (self.static_state.load(Ordering::Acquire) == Self::RESERVED)
+            .then(|| self.static_offset.load(Ordering::Relaxed))
+    }
+
+    /// Gets the offset of the static tls block, reserving it first if necessary. The block is
+    /// reserved only once even if several threads relocate against the module at the same time.
+    pub(crate) fn static_offset(&self) -> Option<isize> {
+        loop {
+            match self.static_state.compare_exchange(
+                Self::NOT_RESERVED,
+                Self::RESERVING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // reserve_static发生panic时恢复状态,否则等待的线程会一直自旋
+                    let reset = ResetOnUnwind(&self.static_state);
+                    let offset = unsafe { (self.reserve_static)(self.modid) };
+                    core::mem::forget(reset);
+                    let state = match offset {
+                        Some(offset) => {
+                            self.static_offset.store(offset, Ordering::Relaxed);
+                            Self::RESERVED
+                        }
+                        None => Self::NOT_RESERVED,
+                    };
+                    self.static_state.store(state, Ordering::Release);
+                    return offset;
+                }
+                Err(Self::RESERVING) => core::hint::spin_loop(),
+                Err(_) => return Some(self.static_offset.load(Ordering::Relaxed)),
+            }
+        }
+    }
+}
+
+struct ResetOnUnwind<'a>(&'a AtomicU8);
+
+impl Drop for ResetOnUnwind<'_> {
+    fn drop(&mut self) {
+        self.0.store(ElfTls::NOT_RESERVED, Ordering::Release);
+    }
+}
+
+impl Drop for ElfTls {
+    fn drop(&mut self) {
+        unsafe { (self.unregister)(self.modid) };
+    }
+}
+
+/// Dynamic tls descriptors must not move after relocation, so each of them is boxed.
+#[allow(clippy::vec_box)]
+pub(crate) type TlsDescs = Vec<Box<TlsDescDynamic>>;
+
+/// The argument of the dynamic tls descriptor resolver
+#[repr(C)]
+pub(crate) struct TlsDescDynamic {
+    tls_get_addr: TlsGetAddr,
+    index: TlsIndex,
+}
+
+impl TlsDescDynamic {
+    pub(crate) fn new(tls: &ElfTls, offset: usize) -> Self {
+        TlsDescDynamic {
+            tls_get_addr: tls.tls_get_addr,
+            index: TlsIndex {
+                ti_module: tls.modid,
+                ti_offset: offset,
+            },
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] {
+        unsafe extern "C" {
+            fn dl_tlsdesc_return();
+            fn dl_tlsdesc_undefweak();
+            fn dl_tlsdesc_dynamic();
+        }
+
+        #[inline]
+        pub(crate) fn tlsdesc_return() -> usize {
+            dl_tlsdesc_return as *const () as usize
+        }
+
+        #[inline]
+        pub(crate) fn tlsdesc_undefweak() -> usize {
+            dl_tlsdesc_undefweak as *const () as usize
+        }
+
+        #[inline]
+        pub(crate) fn tlsdesc_dynamic() -> usize {
+            crate::arch::prepare_tlsdesc_dynamic();
+            dl_tlsdesc_dynamic as *const () as usize
+        }
+    } else {
+        #[inline]
+        pub(crate) fn tlsdesc_return() -> usize {
+            unreachable!()
+        }
+
+        #[inline]
+        pub(crate) fn tlsdesc_undefweak() -> usize {
+            unreachable!()
+        }
+
+        #[inline]
+        pub(crate) fn tlsdesc_dynamic() -> usize {
+            unreachable!()
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Typed symbols
+//!
+//! `get::<T>()` trusts whatever type it is given. The types used by [`define_symbols!`] instead
+//! implement [`SymbolType`], which checks the type(`STT_*`) and size of the symbol before the
+//! address is converted, so a function can not be read as a variable and a variable can not be
+//! read as a larger type. The signatures of the functions still can not be checked.
+//!
+//! [`define_symbols!`]: crate::define_symbols
+use crate::{
+    Error, RelocatedDylib, Result, arch::ElfSymbol, relocation::SymDef, symbol::SymbolInfo,
+};
+use alloc::string::ToString;
+use core::ptr::NonNull;
+use elf::abi::{STT_COMMON, STT_FUNC, STT_GNU_IFUNC, STT_NOTYPE, STT_OBJECT};
+
+/// A type that a symbol can be read as
+///
+/// # Safety
+/// `from_addr` must be sound for the address of any symbol accepted by `check`.
+pub unsafe trait SymbolType: Sized {
+    /// Checks the type and the size of the symbol, and returns the reason if it is rejected.
+    fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str>;
+
+    /// Creates the value from the address of the symbol.
+    ///
+    /// # Safety
+    /// `addr` must be the address of a symbol accepted by `check`.
+    unsafe fn from_addr(addr: *const ()) -> Self;
+
+    /// Gets the value used when the symbol is not found. The default rejects missing symbols.
+    #[inline]
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+// 汇编中定义的函数可能没有类型
+#[inline]
+fn check_func(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+    match sym.st_type() {
+        STT_FUNC | STT_GNU_IFUNC | STT_NOTYPE => Ok(()),
+        _ => Err("the symbol is not a function"),
+    }
+}
+
+#[inline]
+fn check_object<T>(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+    match sym.st_type() {
+        STT_OBJECT | STT_COMMON | STT_NOTYPE => {}
+        _ => return Err("the symbol is not a variable"),
+    }
+    // 没有大小的符号无法检查
+    if sym.st_size() != 0 && sym.st_size() < size_of::<T>() {
+        return Err("the symbol is smaller than the type");
+    }
+    Ok(())
+}
+
+macro_rules! impl_fn {
+    ($($arg:ident),*) => {
+        impl_fn!(@abi [$($arg),*] fn($($arg),*) -> R);
+        impl_fn!(@abi [$($arg),*] unsafe fn($($arg),*) -> R);
+        impl_fn!(@abi [$($arg),*] extern "C" fn($($arg),*) -> R);
+        impl_fn!(@abi [$($arg),*] unsafe extern "C" fn($($arg),*) -> R);
+    };
+    (@abi [$($arg:ident),*] $ty:ty) => {
+        unsafe impl<R, $($arg),*> SymbolType for $ty {
+            #[inline]
+            fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+                check_func(sym)
+            }
+
+            #[inline]
+            unsafe fn from_addr(addr: *const ()) -> Self {
+                unsafe { core::mem::transmute::<*const (), Self>(addr) }
+            }
+        }
+    };
+}
+
+impl_fn!();
+impl_fn!(A);
+impl_fn!(A, B);
+impl_fn!(A, B, C);
+impl_fn!(A, B, C, D);
+impl_fn!(A, B, C, D, E);
+impl_fn!(A, B, C, D, E, F);
+impl_fn!(A, B, C, D, E, F, G);
+impl_fn!(A, B, C, D, E, F, G, H);
+
+unsafe impl<T> SymbolType for *const T {
+    #[inline]
+    fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+        check_object::<T>(sym)
+    }
+
+    #[inline]
+    unsafe fn from_addr(addr: *const ()) -> Self {
+        addr.cast()
+    }
+}
+
+unsafe impl<T> SymbolType for *mut T {
+    #[inline]
+    fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+        check_object::<T>(sym)
+    }
+
+    #[inline]
+    unsafe fn from_addr(addr: *const ()) -> Self {
+        addr.cast_mut().cast()
+    }
+}
+
+unsafe impl<T> SymbolType for NonNull<T> {
+    #[inline]
+    fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+        check_object::<T>(sym)
+    }
+
+    #[inline]
+    unsafe fn from_addr(addr: *const ()) -> Self {
+        unsafe { NonNull::new_unchecked(addr.cast_mut().cast()) }
+    }
+}
+
+/// An optional symbol, which is `None` if it is not found.
+unsafe impl<S: SymbolType> SymbolType for Option<S> {
+    #[inline]
+    fn check(sym: &ElfSymbol) -> core::result::Result<(), &'static str> {
+        S::check(sym)
+    }
+
+    #[inline]
+    unsafe fn from_addr(addr: *const ()) -> Self {
+        Some(unsafe { S::from_addr(addr) })
+    }
+
+    #[inline]
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// Looks up the symbol `name` in `lib` and reads it as `S`, which is used by `define_symbols!`.
+///
+/// # Safety
+/// The symbol must really have the type `S`, for example a function must have the signature of `S`.
+pub unsafe fn lookup<S: SymbolType>(lib: &RelocatedDylib, name: &str) -> Result<S> {
+    let Some(sym) = lib.symtab().lookup_filter(&SymbolInfo::from_str(name)) else {
+        return S::missing().ok_or_else(|| symbol_error(lib, name, "the symbol is not found"));
+    };
+    S::check(sym).map_err(|msg| symbol_error(lib, name, msg))?;
+    let addr = SymDef {
+        sym: Some(sym),
+        base: lib.base(),
+        tls: None,
+    }
+    .convert();
+    Ok(unsafe { S::from_addr(addr) })
+}
+
+#[cold]
+#[inline(never)]
+fn symbol_error(lib: &RelocatedDylib, name: &str, msg: &'static str) -> Error {
+    Error::SymbolError {
+        lib_name: lib.name().to_string(),
+        symbol: name.to_string(),
+        msg,
+    }
+}
@@ -0,0 +1,71 @@
+//! The process wide definitions of `STB_GNU_UNIQUE` symbols
+//!
+//! Like the dynamic linker of glibc, all references to a `STB_GNU_UNIQUE` symbol are bound to the
+//! first definition relocated in the process, even when another elf object in the scope defines it.
+//! Elf objects defining such symbols are never unloaded, so the definitions stay valid.
+use crate::format::ElfCommonPart;
+use alloc::{collections::BTreeMap, string::String};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use elf::abi::{STT_FUNC, STT_OBJECT};
+
+struct UniqueTable {
+    lock: AtomicBool,
+    // 符号名 -> 第一个定义的地址
+    defs: UnsafeCell<BTreeMap<String, usize>>,
+}
+
+unsafe impl Sync for UniqueTable {}
+
+impl UniqueTable {
+    fn with<R>(&self, f: impl FnOnce(&mut BTreeMap<String, usize>) -> R) -> R {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let res = f(unsafe { &mut *self.defs.get() });
+        self.lock.store(false, Ordering::Release);
+        res
+    }
+}
+
+static TABLE: UniqueTable = UniqueTable {
+    lock: AtomicBool::new(false),
+    defs: UnsafeCell::new(BTreeMap::new()),
+};
+
+/// Gets the address of the first definition of the unique symbol `name`.
+pub(crate) fn find(name: &str) -> Option<usize> {
+    TABLE.with(|defs| defs.get(name).copied())
+}
+
+/// Records the unique symbols defined by the relocated elf object which are not defined yet.
+/// Returns whether the elf object defines any unique symbol.
+pub(crate) fn register(common: &ElfCommonPart) -> bool {
+    let Some(symtab) = common.core.symtab() else {
+        return false;
+    };
+    let mut defines = false;
+    for idx in 0..symtab.count_syms() {
+        let (sym, syminfo) = symtab.symbol_idx(idx);
+        // 只有数据和函数可以是唯一符号
+        if !sym.is_unique()
+            || sym.is_undef()
+            || sym.is_abs()
+            || !matches!(sym.st_type(), STT_OBJECT | STT_FUNC)
+        {
+            continue;
+        }
+        defines = true;
+        let addr = common.core.base() + sym.st_value();
+        TABLE.with(|defs| {
+            defs.entry(String::from(syminfo.name())).or_insert(addr);
+        });
+    }
+    defines
+}
@@ -0,0 +1,79 @@
+//! Integrity verification of elf objects
+//!
+//! A verifier set by `Loader::set_verifier` is called after all segments of an elf object are
+//! mapped and before it is relocated. Until the verifier accepts the elf object, its segments are
+//! mapped without `PROT_EXEC`, so no code of a rejected elf object can be executed.
+use crate::{Error, arch::ElfPhdr, note::Notes, segment::ElfSegments};
+use alloc::{borrow::Cow, boxed::Box};
+use core::{any::Any, ffi::CStr};
+use elf::abi::PT_LOAD;
+
+/// `NT_GNU_BUILD_ID`
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// The function verifying an elf object, an error aborts the load.
+pub type Verifier = Box<dyn Fn(&Image) -> Result<(), Box<dyn Any>> + Send>;
+
+/// The mapped image of an elf object passed to the verifier
+pub struct Image<'a> {
+    pub(crate) name: &'a CStr,
+    pub(crate) phdrs: &'a [ElfPhdr],
+    pub(crate) segments: &'a ElfSegments,
+    pub(crate) build_id: Option<&'a [u8]>,
+}
+
+impl Image<'_> {
+    /// Gets the name of the elf object. Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    #[inline]
+    pub fn name(&self) -> Cow<'_, str> {
+        self.name.to_string_lossy()
+    }
+
+    /// Gets the C style name of the elf object.
+    #[inline]
+    pub fn cname(&self) -> &CStr {
+        self.name
+    }
+
+    /// Gets the GNU build-id of the elf object.
+    #[inline]
+    pub fn build_id(&self) -> Option<&[u8]> {
+        self.build_id
+    }
+
+    /// Gets the program headers of the elf object.
+    #[inline]
+    pub fn phdrs(&self) -> &[ElfPhdr] {
+        self.phdrs
+    }
+
+    /// Gets the contents of the `PT_LOAD` segments as read from the file, in the order of the
+    /// program headers. The bytes of each segment are the `p_filesz` bytes at `p_offset` of the file.
+    pub fn segments(&self) -> impl Iterator<Item = (&ElfPhdr, &[u8])> {
+        self.phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(|phdr| {
+                let bytes = self
+                    .segments
+                    .get_slice::<u8>(phdr.p_vaddr as usize, phdr.p_filesz as usize);
+                (phdr, bytes)
+            })
+    }
+}
+
+/// Finds the `NT_GNU_BUILD_ID` note in the contents of a `PT_NOTE` segment.
+pub(crate) fn find_build_id(notes: &[u8], align: usize) -> Option<&[u8]> {
+    Notes::new(notes, align)
+        .find(|note| note.n_type == NT_GNU_BUILD_ID && note.name == b"GNU\0")
+        .map(|note| note.desc)
+}
+
+#[cold]
+#[inline(never)]
+pub(crate) fn verify_error(name: &CStr, custom_err: Box<dyn Any>) -> Error {
+    Error::VerifyError {
+        lib_name: name.to_string_lossy().into_owned(),
+        custom_err,
+    }
+}
@@ -1,5 +1,8 @@
-use crate::symbol::{ElfStringTable, SymbolTable};
-use alloc::vec::Vec;
+use crate::{
+    CoreComponent, Error, RelocatedDylib, Result,
+    symbol::{ElfStringTable, SymbolTable},
+};
+use alloc::{string::ToString, vec::Vec};
 use core::num::NonZeroUsize;
 use elf::abi;
 
@@ -255,6 +258,8 @@ pub(crate) struct ELFVersion {
     // 因为verdef和verneed的idx不重叠，因此我们可以使用数组将其存起来
     // 这样可以加快之后符号版本号的匹配
     versions: Vec<Version>,
+    verdefs: Option<VerDefTable>,
+    verneeds: Option<VerNeedTable>,
 }
 
 impl ELFVersion {
@@ -332,6 +337,14 @@ impl ELFVersion {
                 ptr: version_ids_off.get() as _,
             },
             versions,
+            verdefs: verdefs.map(|(ptr, num)| VerDefTable {
+                ptr: ptr.get() as _,
+                num: num.get(),
+            }),
+            verneeds: verneeds.map(|(ptr, num)| VerNeedTable {
+                ptr: ptr.get() as _,
+                num: num.get(),
+            }),
         })
     }
 }
@@ -417,3 +430,161 @@ impl SymbolTable {
         true
     }
 }
+
+/// A version defined by an elf object(`DT_VERDEF`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionDefinition<'a> {
+    /// The index of the version in `.gnu.version`.
+    pub index: u16,
+    /// `VER_FLG_BASE` for the definition naming the elf object itself, or `VER_FLG_WEAK`.
+    pub flags: u16,
+    pub name: &'a str,
+    /// The versions this version inherits from.
+    pub parents: Vec<&'a str>,
+}
+
+/// A version required by an elf object(`DT_VERNEED`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionRequirement<'a> {
+    /// The dependency which should define the version.
+    pub file: &'a str,
+    /// The index of the version in `.gnu.version`.
+    pub index: u16,
+    /// `VER_FLG_WEAK` if the version is not required to be defined by the dependency.
+    pub flags: u16,
+    pub name: &'a str,
+}
+
+impl SymbolTable {
+    fn verdefs(&self) -> impl Iterator<Item = (VerDef, VerDefAuxIterator)> {
+        self.version
+            .as_ref()
+            .and_then(|version| version.verdefs.as_ref())
+            .into_iter()
+            .flat_map(|verdefs| verdefs.into_iter())
+    }
+
+    fn verneeds(&self) -> impl Iterator<Item = (VerNeed, VerNeedAuxIterator)> {
+        self.version
+            .as_ref()
+            .and_then(|version| version.verneeds.as_ref())
+            .into_iter()
+            .flat_map(|verneeds| verneeds.into_iter())
+    }
+
+    /// 是否定义了名称和哈希值都相同的版本
+    fn defines(&self, name: &str, hash: u32) -> bool {
+        let strtab = self.strtab();
+        self.verdefs().any(|(verdef, mut vd_iter)| {
+            verdef.vd_hash == hash
+                && vd_iter
+                    .next()
+                    .is_some_and(|aux| strtab.get_str(aux.vda_name as usize) == name)
+        })
+    }
+}
+
+impl CoreComponent {
+    /// Gets the versions defined by the elf object. The first one is usually the base definition
+    /// with `VER_FLG_BASE`, which is named after the elf object itself.
+    pub fn versions(&self) -> Vec<VersionDefinition<'_>> {
+        let Some(symtab) = self.symtab() else {
+            return Vec::new();
+        };
+        let strtab = symtab.strtab();
+        symtab
+            .verdefs()
+            .map(|(verdef, mut vd_iter)| {
+                let name = vd_iter
+                    .next()
+                    .map_or("", |aux| strtab.get_str(aux.vda_name as usize));
+                VersionDefinition {
+                    index: verdef.index() as u16,
+                    flags: verdef.vd_flags,
+                    name,
+                    parents: vd_iter
+                        .map(|aux| strtab.get_str(aux.vda_name as usize))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Gets the versions the elf object requires from its dependencies.
+    pub fn needed_versions(&self) -> Vec<VersionRequirement<'_>> {
+        let Some(symtab) = self.symtab() else {
+            return Vec::new();
+        };
+        let strtab = symtab.strtab();
+        symtab
+            .verneeds()
+            .flat_map(|(verneed, vna_iter)| {
+                let file = strtab.get_str(verneed.vn_file as usize);
+                vna_iter.map(move |aux| VersionRequirement {
+                    file,
+                    index: aux.index() as u16,
+                    flags: aux.vna_flags,
+                    name: strtab.get_str(aux.vna_name as usize),
+                })
+            })
+            .collect()
+    }
+
+    /// Checks that the versions required by the elf object are defined by `deps` before it is
+    /// relocated against them.
+    ///
+    /// It fails with `Error::DependencyError` if a dependency requiring versions is not in `deps`,
+    /// and with `Error::VersionError` if a version is not defined by the dependency. Like the
+    /// dynamic linker of glibc, weak requirements and dependencies without version definitions are
+    /// not checked.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use elf_loader::load_dylib;
+    ///
+    /// let liba = load_dylib!("target/liba.so").unwrap();
+    /// let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+    /// let libb = load_dylib!("target/libb.so").unwrap();
+    /// // reject libb.so before running any of its code
+    /// libb.verify_needed_versions(&[liba.clone()]).unwrap();
+    /// let libb = libb.easy_relocate([&liba].into_iter(), &|_| None).unwrap();
+    /// ```
+    pub fn verify_needed_versions(&self, deps: &[RelocatedDylib]) -> Result<()> {
+        let Some(symtab) = self.symtab() else {
+            return Ok(());
+        };
+        let strtab = symtab.strtab();
+        for (verneed, vna_iter) in symtab.verneeds() {
+            let file = strtab.get_str(verneed.vn_file as usize);
+            let Some(dep) = deps.iter().find(|dep| dep.provides(file)) else {
+                return Err(missing_dependency(self, file));
+            };
+            let dep_symtab = dep.symtab();
+            if dep_symtab.verdefs().next().is_none() {
+                continue;
+            }
+            for aux in vna_iter {
+                let name = strtab.get_str(aux.vna_name as usize);
+                if aux.vna_flags & abi::VER_FLG_WEAK == 0 && !dep_symtab.defines(name, aux.vna_hash)
+                {
+                    return Err(Error::VersionError {
+                        lib_name: self.name().to_string(),
+                        needed: file.to_string(),
+                        version: name.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn missing_dependency(lib: &CoreComponent, needed: &str) -> Error {
+    Error::DependencyError {
+        lib_name: lib.name().to_string(),
+        needed: needed.to_string(),
+        msg: alloc::format!("{needed} is not in the dependencies of {}", lib.name()),
+    }
+}
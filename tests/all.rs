@@ -1,6 +1,14 @@
 #[cfg(all(feature = "fs", feature = "mmap"))]
 mod fs {
-    use elf_loader::{Elf, load, load_dylib, load_exec};
+    use elf_loader::{
+        Elf, Loader, RelocateStatus, SequentialBase, WriteMode, close_all, load, load_dylib,
+        load_exec,
+        mmap::{
+            AuditedMmap, MapFlags, MapKind, MapRequest, Mmap, MmapFromAlloc, MmapImpl, MmapPolicy,
+            ProtFlags, WxorX,
+        },
+        object::{ElfBinary, ElfFile},
+    };
     use std::env::consts;
     use std::path::PathBuf;
     use std::sync::OnceLock;
@@ -19,7 +27,7 @@ mod fs {
             .to_string()
     }
 
-    const PACKAGE_NAME: [&str; 3] = ["a", "b", "c"];
+    const PACKAGE_NAME: [&str; 4] = ["a", "b", "c", "ifunc"];
 
     fn compile() {
         static ONCE: ::std::sync::Once = ::std::sync::Once::new();
@@ -56,6 +64,16 @@ mod fs {
                     .arg("--")
                     .arg("-C")
                     .arg("panic=abort");
+                // ifunc依赖a, 使它带有DT_NEEDED
+                if name == "ifunc" {
+                    let dep = "liba.so";
+                    let dir = lib_path(dep);
+                    let dir = dir.rsplit_once('/').unwrap().0;
+                    cmd.arg("-C")
+                        .arg(format!("link-arg=-L{dir}"))
+                        .arg("-C")
+                        .arg(format!("link-arg=-l:{dep}"));
+                }
                 assert!(
                     cmd.status()
                         .expect("could not compile the test helpers!")
@@ -65,6 +83,94 @@ mod fs {
         });
     }
 
+    /// Writes `src` to `{name}.c` in the target directory and compiles it into `name` with `cc`,
+    /// passing `args` after the source. Shared libraries named `*.so` are built with
+    /// `-shared -fPIC -nostdlib`. Returns the path of the output.
+    fn compile_c(name: &str, src: &str, args: &[&str]) -> String {
+        let path = lib_path(name);
+        let file = lib_path(&format!("{name}.c"));
+        std::fs::write(&file, src).unwrap();
+        let mut cmd = std::process::Command::new("cc");
+        if name.ends_with(".so") {
+            cmd.args(["-shared", "-fPIC", "-nostdlib"]);
+        }
+        let status = cmd.args(["-o", &path, &file]).args(args).status().unwrap();
+        assert!(status.success(), "failed to compile {name}");
+        path
+    }
+
+    /// Compiles `liblazy.so`, which calls `a` of liba through the plt. The rust libraries are linked
+    /// with `-z now` and call their imports through the got, so they are never bound lazily.
+    fn lazy_lib() -> String {
+        static ONCE: ::std::sync::Once = ::std::sync::Once::new();
+        compile();
+        let path = lib_path("liblazy.so");
+        ONCE.call_once(|| {
+            compile_c(
+                "liblazy.so",
+                "int a(void);\nint call_a(void) { return a() + 1; }\n",
+                &["-Wl,-z,lazy"],
+            );
+        });
+        path
+    }
+
+    /// Compiles `libtls.so`, which has a tls variable initialized to 5 and accessed through
+    /// `__tls_get_addr`.
+    #[cfg(feature = "std")]
+    fn tls_lib() -> String {
+        static ONCE: ::std::sync::Once = ::std::sync::Once::new();
+        compile();
+        let path = lib_path("libtls.so");
+        ONCE.call_once(|| {
+            compile_c(
+                "libtls.so",
+                "static __thread int counter = 5;\nint bump(void) { return ++counter; }\n",
+                &[],
+            );
+        });
+        path
+    }
+
+    /// Compiles `libneeda.so`, `libneedb.so` and `libneedc.so`. Each library calls the previous one
+    /// and has it in `DT_NEEDED`, and `c` returns 3. Returns their paths.
+    fn needed_libs() -> [String; 3] {
+        static ONCE: ::std::sync::Once = ::std::sync::Once::new();
+        compile();
+        let paths = ["a", "b", "c"].map(|name| lib_path(&format!("libneed{name}.so")));
+        ONCE.call_once(|| {
+            let dir = lib_path("");
+            compile_c(
+                "libneeda.so",
+                "int a(void) { return 1; }\n",
+                &["-Wl,-z,now"],
+            );
+            compile_c(
+                "libneedb.so",
+                "int a(void);\nint b(void) { return a() + 1; }\n",
+                &["-Wl,-z,now", "-L", &dir, "-l:libneeda.so"],
+            );
+            compile_c(
+                "libneedc.so",
+                "int b(void);\nint c(void) { return b() + 1; }\n",
+                &["-Wl,-z,now", "-L", &dir, "-l:libneedb.so"],
+            );
+        });
+        paths
+    }
+
+    /// Gets the offset of the dynamic symbol `name` in the elf file.
+    fn dynsym_offset(bytes: &[u8], name: &str) -> usize {
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(bytes).unwrap();
+        let dynsym = elf.section_header_by_name(".dynsym").unwrap().unwrap();
+        let (symtab, strtab) = elf.dynamic_symbol_table().unwrap().unwrap();
+        let idx = symtab
+            .iter()
+            .position(|sym| strtab.get(sym.st_name as usize).unwrap() == name)
+            .unwrap();
+        dynsym.sh_offset as usize + idx * dynsym.sh_entsize as usize
+    }
+
     #[test]
     fn relocate_dylib() {
         compile();
@@ -89,6 +195,672 @@ mod fs {
         assert!(f() == 3);
     }
 
+    #[test]
+    fn snapshot() {
+        use elf_loader::snapshot::Snapshot;
+        let [liba, libb, _] = needed_libs();
+        let load = |path: &str| load_dylib!(path).unwrap();
+        let a = load(&liba).easy_relocate([].iter(), &|_| None).unwrap();
+        let b = load(&libb)
+            .easy_relocate([&a].into_iter(), &|_| None)
+            .unwrap();
+        let mut bytes = Vec::new();
+        b.snapshot([&a].into_iter(), &mut bytes).unwrap();
+        let snapshot = Snapshot::parse(&bytes).unwrap();
+        assert_eq!(snapshot.name(), b.name());
+        assert_eq!(snapshot.base(), b.base());
+        let first = &snapshot.segments()[0];
+        assert_eq!(first.vaddr, 0);
+        assert_eq!(&first.data[..4], b"\x7fELF");
+        assert!(!snapshot.relocations().is_empty());
+        let dep = &snapshot.dependencies()[0];
+        assert_eq!(dep.needed, "libneeda.so");
+        assert_eq!(dep.resolved, Some((a.name().to_owned(), a.base())));
+        assert!(snapshot.mismatches(&b).is_empty());
+        assert!(Snapshot::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        // 重新加载后只有指向liba的值会改变
+        let a2 = load(&liba).easy_relocate([].iter(), &|_| None).unwrap();
+        let b2 = load(&libb)
+            .easy_relocate([&a2].into_iter(), &|_| None)
+            .unwrap();
+        assert_ne!(a2.base(), a.base());
+        let a_range = a.map_range();
+        for reloc in snapshot.mismatches(&b2) {
+            assert!(a_range.contains(&reloc.value), "{reloc:?}");
+        }
+
+        // 目标不在可读段中的重定位被当作不匹配,而不是读取越界的内存
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"ELSN");
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for offset in [1u64 << 40, u64::MAX - 4] {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        let corrupted = Snapshot::parse(&bytes).unwrap();
+        assert_eq!(corrupted.mismatches(&b2).len(), 2);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn relocatable_object() {
+        compile();
+        extern "C" fn host_add(a: i32, b: i32) -> i32 {
+            a + b
+        }
+        let path = compile_c(
+            "module.o",
+            "int host_add(int, int);\nint a(void);\nint missing(void) __attribute__((weak));\n\
+             static int counter;\nint base_value = 40;\n\
+             static int helper(int x) { return x + 1; }\n\
+             int *value_ptr(void) { return &base_value; }\n\
+             int module_init(void) {\n\
+                 counter = helper(counter);\n\
+                 return host_add(base_value, counter) + a() + (missing ? 100 : 0);\n\
+             }\n",
+            &["-c", "-fPIC", "-O0"],
+        );
+        let pre_find = |name: &str| (name == "host_add").then_some(host_add as *const ());
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let object = || ElfFile::from_path(&path).unwrap();
+        let mut loader = Loader::<MmapImpl>::new();
+        let module = loader
+            .load_relocatable(object(), [&liba].into_iter(), &pre_find)
+            .unwrap();
+        let init = unsafe { module.get::<extern "C" fn() -> i32>("module_init").unwrap() };
+        assert_eq!(init(), 42);
+        assert_eq!(init(), 43);
+        let value_ptr = unsafe { module.get::<extern "C" fn() -> *mut i32>("value_ptr") };
+        let value_ptr = value_ptr.unwrap();
+        let base_value = module.symbol("base_value").unwrap();
+        assert_eq!(value_ptr() as *const (), base_value);
+        assert_eq!(unsafe { *value_ptr() }, 40);
+        assert!(module.symbol("helper").is_none());
+        let text = module.section(".text").unwrap();
+        assert!(text.contains(&(*init as usize)));
+        assert!(module.map_range().contains(&text.start));
+        // 目标文件持有其符号绑定到的动态库
+        assert_eq!(module.deps().len(), 1);
+        assert_eq!(module.deps()[0].base(), liba.base());
+        drop(liba);
+        assert_eq!(init(), 44);
+
+        // 找不到的强符号会导致重定位失败
+        let err = loader
+            .load_relocatable(object(), [].iter(), &pre_find)
+            .unwrap_err();
+        assert_eq!(err.unresolved_symbols(), ["a"]);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn relocatable_out_of_section() {
+        compile();
+        let path = compile_c("oob.o", "__asm__(\".data\\nv:\\n.quad v\\n\");\n", &["-c"]);
+        // 将.data的大小改为4,8字节的重定位会越过节的末尾
+        let mut bytes = std::fs::read(&path).unwrap();
+        let read = |bytes: &[u8], off: usize, len: usize| {
+            let mut buf = [0u8; 8];
+            buf[..len].copy_from_slice(&bytes[off..off + len]);
+            u64::from_le_bytes(buf) as usize
+        };
+        let shoff = read(&bytes, 0x28, 8);
+        let shnum = read(&bytes, 0x3c, 2);
+        let rela = (0..shnum)
+            .map(|idx| shoff + idx * 64)
+            .find(|&shdr| read(&bytes, shdr + 4, 4) == 4)
+            .unwrap();
+        let target = shoff + read(&bytes, rela + 0x2c, 4) * 64;
+        assert_eq!(read(&bytes, target + 0x20, 8), 8);
+        bytes[target + 0x20..target + 0x28].copy_from_slice(&4u64.to_le_bytes());
+        let object = ElfBinary::new("oob.o", &bytes);
+        let err = Loader::<MmapImpl>::new()
+            .load_relocatable(object, [].iter(), &|_| None)
+            .unwrap_err();
+        assert!(err.to_string().contains("out of the section"), "{err}");
+    }
+
+    #[test]
+    fn owned_symbol() {
+        use elf_loader::OwnedSymbol;
+        compile();
+        let mut loader = Loader::<MmapImpl>::new();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let weak = liba.downgrade();
+        let a: OwnedSymbol<fn() -> i32> = unsafe { liba.get_owned::<fn() -> i32>("a").unwrap() };
+        drop(liba);
+        // 符号持有库的强引用
+        assert!(weak.upgrade().is_some());
+        let a = std::thread::spawn(move || {
+            assert!(a() == 1);
+            a
+        })
+        .join()
+        .unwrap();
+        assert_eq!(a.lib().name(), lib_path("liba.so"));
+        drop(a);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[cfg(feature = "debug-handle")]
+    #[test]
+    fn weak_symbol() {
+        compile();
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let generation = liba.generation();
+        let a = unsafe { liba.get::<fn() -> i32>("a").unwrap().downgrade() };
+        assert_eq!(a.generation(), generation);
+        assert!(a() == 1);
+        assert!(unsafe { a.upgrade() }.is_some());
+        drop(liba);
+        // 库被卸载后使用符号会panic
+        assert!(!a.is_alive());
+        assert!(unsafe { a.upgrade() }.is_none());
+        let err =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a.as_raw())).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("liba.so") && msg.contains(&generation.to_string()));
+    }
+
+    #[test]
+    fn concurrent_loading() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Loader<MmapImpl>>();
+        assert_send::<elf_loader::ElfDylib>();
+        assert_send::<elf_loader::RelocatedDylib<'static>>();
+        compile();
+        fn print(_: &str) {}
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+                    let mut loader = Loader::<MmapImpl>::new();
+                    let mut load = |name: &str| {
+                        loader
+                            .easy_load_dylib(ElfFile::from_path(&lib_path(name)).unwrap())
+                            .unwrap()
+                    };
+                    for _ in 0..8 {
+                        let (liba, libb, libc) =
+                            (load("liba.so"), load("libb.so"), load("libc.so"));
+                        let a = liba.easy_relocate([].iter(), &pre_find).unwrap();
+                        let b = libb.easy_relocate([&a].into_iter(), &pre_find).unwrap();
+                        let c = libc.easy_relocate([&b].into_iter(), &pre_find).unwrap();
+                        let f = unsafe { c.get::<fn() -> i32>("c").unwrap() };
+                        assert!(f() == 3);
+                    }
+                    // 未重定位的库可以交给其他线程
+                    load("liba.so")
+                })
+            })
+            .collect();
+        for thread in threads {
+            let liba = thread.join().unwrap();
+            let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+            let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+            assert!(f() == 1);
+        }
+
+        // 并行加载带有tls的库,每个库都有不同的模块id,每个线程都有自己的tls块
+        #[cfg(feature = "std")]
+        {
+            use elf_loader::tls::{ThreadLocal, ThreadLocalImpl};
+            fn pre_find(name: &str) -> Option<*const ()> {
+                (name == "__tls_get_addr").then_some(ThreadLocalImpl::tls_get_addr as *const ())
+            }
+            let path = tls_lib();
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    let path = path.clone();
+                    std::thread::spawn(move || {
+                        let mut loader = Loader::<MmapImpl, ThreadLocalImpl>::new();
+                        (0..8)
+                            .map(|_| {
+                                let lib = loader
+                                    .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+                                    .unwrap()
+                                    .easy_relocate([].iter(), &pre_find)
+                                    .unwrap();
+                                let bump =
+                                    unsafe { lib.get::<extern "C" fn() -> i32>("bump").unwrap() };
+                                assert_eq!((bump(), bump()), (6, 7));
+                                lib
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let libs: Vec<_> = threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect();
+            let mut modids: Vec<_> = libs.iter().map(|lib| lib.tls_modid().unwrap()).collect();
+            modids.sort();
+            modids.dedup();
+            assert_eq!(modids.len(), 64);
+            for lib in &libs {
+                let bump = unsafe { lib.get::<extern "C" fn() -> i32>("bump").unwrap() };
+                assert_eq!(bump(), 6);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn static_tls_panic() {
+        use elf_loader::{
+            arch::ElfPhdr,
+            tls::{ThreadLocal, ThreadLocalImpl, TlsIndex},
+        };
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // 第一次预留静态tls块时panic,之后不支持静态tls
+        struct PanicOnce;
+        static PANICKED: AtomicBool = AtomicBool::new(false);
+        impl ThreadLocal for PanicOnce {
+            unsafe fn register(phdr: &ElfPhdr, base: usize) -> Option<usize> {
+                unsafe { ThreadLocalImpl::register(phdr, base) }
+            }
+
+            unsafe fn unregister(modid: usize) {
+                unsafe { ThreadLocalImpl::unregister(modid) }
+            }
+
+            unsafe extern "C" fn tls_get_addr(ti: *const TlsIndex) -> *mut u8 {
+                unsafe { ThreadLocalImpl::tls_get_addr(ti) }
+            }
+
+            unsafe fn reserve_static(_modid: usize) -> Option<isize> {
+                if !PANICKED.swap(true, Ordering::Relaxed) {
+                    panic!("reserve_static failed");
+                }
+                None
+            }
+        }
+
+        compile();
+        let dep = compile_c("libtlsdef.so", "__thread int shared = 3;\n", &[]);
+        // initial-exec模型通过TPOFF重定位访问依赖库的tls变量
+        let user = compile_c(
+            "libtlsie.so",
+            "extern __thread int shared __attribute__((tls_model(\"initial-exec\")));\n\
+             int get_shared(void) { return shared; }\n",
+            &[],
+        );
+        let mut loader = Loader::<MmapImpl, PanicOnce>::new();
+        let dep = loader
+            .easy_load_dylib(ElfFile::from_path(&dep).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let mut load_user = || {
+            loader
+                .easy_load_dylib(ElfFile::from_path(&user).unwrap())
+                .unwrap()
+        };
+        let first = load_user();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            first.easy_relocate([&dep].into_iter(), &|_| None)
+        }));
+        assert!(panicked.is_err());
+        // panic之后再次重定位不会一直等待预留完成
+        let second = load_user();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let res = second.easy_relocate([&dep].into_iter(), &|_| None);
+            tx.send(res.is_err()).unwrap();
+        });
+        assert!(
+            rx.recv_timeout(std::time::Duration::from_secs(10))
+                .expect("relocation hangs after reserve_static panicked")
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn static_tls_offset() {
+        use elf_loader::{
+            arch::ElfPhdr,
+            tls::{ThreadLocal, ThreadLocalImpl, TlsIndex},
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // 静态tls块的偏移可以是任意值,包括-1和-2
+        struct NegativeOffset;
+        static RESERVED: AtomicUsize = AtomicUsize::new(0);
+        impl ThreadLocal for NegativeOffset {
+            unsafe fn register(phdr: &ElfPhdr, base: usize) -> Option<usize> {
+                unsafe { ThreadLocalImpl::register(phdr, base) }
+            }
+
+            unsafe fn unregister(modid: usize) {
+                unsafe { ThreadLocalImpl::unregister(modid) }
+            }
+
+            unsafe extern "C" fn tls_get_addr(ti: *const TlsIndex) -> *mut u8 {
+                unsafe { ThreadLocalImpl::tls_get_addr(ti) }
+            }
+
+            unsafe fn reserve_static(_modid: usize) -> Option<isize> {
+                RESERVED.fetch_add(1, Ordering::Relaxed);
+                Some(-2)
+            }
+        }
+
+        compile();
+        let dep = compile_c("libtlsoff_def.so", "__thread int shared = 3;\n", &[]);
+        let user = compile_c(
+            "libtlsoff_ie.so",
+            "extern __thread int shared __attribute__((tls_model(\"initial-exec\")));\n\
+             int get_shared(void) { return shared; }\n",
+            &[],
+        );
+        let mut loader = Loader::<MmapImpl, NegativeOffset>::new();
+        let dep = loader
+            .easy_load_dylib(ElfFile::from_path(&dep).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let users = [(); 2].map(|_| {
+            loader
+                .easy_load_dylib(ElfFile::from_path(&user).unwrap())
+                .unwrap()
+        });
+        // 第二次重定位直接使用已经预留的偏移
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let ok = users
+                .into_iter()
+                .all(|user| user.easy_relocate([&dep].into_iter(), &|_| None).is_ok());
+            tx.send(ok).unwrap();
+        });
+        assert!(
+            rx.recv_timeout(std::time::Duration::from_secs(10))
+                .expect("relocation hangs with the static tls offset -2")
+        );
+        assert_eq!(RESERVED.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(all(feature = "std", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[test]
+    fn tls_models() {
+        use elf_loader::{
+            RelocatedDylib,
+            arch::ElfPhdr,
+            tls::{ThreadLocal, ThreadLocalImpl, TlsIndex},
+        };
+        use std::{
+            cell::UnsafeCell,
+            sync::{
+                Mutex,
+                atomic::{AtomicUsize, Ordering},
+            },
+        };
+
+        // 静态tls块放在一个thread_local中,它相对于线程指针的偏移在所有线程中都相同
+        thread_local! {
+            static AREA: UnsafeCell<[u64; 8]> = const { UnsafeCell::new([0; 8]) };
+        }
+        static RESERVED: AtomicUsize = AtomicUsize::new(0);
+        static IMAGES: Mutex<Vec<(usize, usize, usize)>> = Mutex::new(Vec::new());
+
+        fn thread_pointer() -> usize {
+            let tp: usize;
+            #[cfg(target_arch = "x86_64")]
+            unsafe {
+                core::arch::asm!("mov {}, fs:0", out(reg) tp)
+            };
+            #[cfg(target_arch = "aarch64")]
+            unsafe {
+                core::arch::asm!("mrs {}, tpidr_el0", out(reg) tp)
+            };
+            tp
+        }
+
+        // 只在当前线程中为一个模块提供静态tls,动态tls的解析函数会破坏ymm8
+        struct StaticTls;
+        impl ThreadLocal for StaticTls {
+            unsafe fn register(phdr: &ElfPhdr, base: usize) -> Option<usize> {
+                let modid = unsafe { ThreadLocalImpl::register(phdr, base) }?;
+                IMAGES.lock().unwrap().push((
+                    modid,
+                    base + phdr.p_vaddr as usize,
+                    phdr.p_filesz as usize,
+                ));
+                Some(modid)
+            }
+
+            unsafe fn unregister(modid: usize) {
+                unsafe { ThreadLocalImpl::unregister(modid) }
+            }
+
+            unsafe extern "C" fn tls_get_addr(ti: *const TlsIndex) -> *mut u8 {
+                let ti = unsafe { &*ti };
+                if ti.ti_module == RESERVED.load(Ordering::Relaxed) {
+                    return AREA.with(|area| area.get().cast::<u8>().wrapping_add(ti.ti_offset));
+                }
+                #[cfg(target_arch = "x86_64")]
+                if std::arch::is_x86_feature_detected!("avx") {
+                    unsafe { core::arch::asm!("vxorps ymm8, ymm8, ymm8", out("xmm8") _) };
+                }
+                unsafe { ThreadLocalImpl::tls_get_addr(ti) }
+            }
+
+            unsafe fn reserve_static(modid: usize) -> Option<isize> {
+                let (_, image, filesz) = *IMAGES
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(id, ..)| *id == modid)?;
+                if RESERVED.swap(modid, Ordering::Relaxed) != 0 || filesz > 64 {
+                    return None;
+                }
+                AREA.with(|area| unsafe {
+                    let area = area.get().cast::<u8>();
+                    area.copy_from_nonoverlapping(image as *const u8, filesz);
+                    Some(area as isize - thread_pointer() as isize)
+                })
+            }
+        }
+
+        compile();
+        let def = compile_c("libtlsmodel_def.so", "__thread int shared = 3;\n", &[]);
+        // 访问未定义的弱tls符号和依赖库中的tls变量
+        let gd = compile_c(
+            "libtlsmodel_gd.so",
+            "extern __thread int shared;\n\
+             extern __thread int missing __attribute__((weak));\n\
+             int get_gd(void) { return shared; }\n\
+             int *addr_missing(void) { return &missing; }\n",
+            &[],
+        );
+        // initial-exec模型通过TPOFF重定位访问依赖库的tls变量
+        let ie = compile_c(
+            "libtlsmodel_ie.so",
+            "extern __thread int shared __attribute__((tls_model(\"initial-exec\")));\n\
+             void set_ie(int v) { shared = v; }\n\
+             int get_ie(void) { return shared; }\n",
+            &[],
+        );
+        fn pre_find(name: &str) -> Option<*const ()> {
+            (name == "__tls_get_addr").then_some(StaticTls::tls_get_addr as *const ())
+        }
+        fn load<'a>(
+            loader: &mut Loader<MmapImpl, StaticTls>,
+            path: &str,
+            deps: &[&'a RelocatedDylib<'a>],
+        ) -> RelocatedDylib<'a> {
+            loader
+                .easy_load_dylib(ElfFile::from_path(path).unwrap())
+                .unwrap()
+                .easy_relocate(deps.iter().copied(), &pre_find)
+                .unwrap()
+        }
+
+        // 没有静态tls时initial-exec模型的库无法重定位
+        let mut loader = Loader::<MmapImpl, ThreadLocalImpl>::new();
+        let dep = loader
+            .easy_load_dylib(ElfFile::from_path(&def).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        assert!(
+            loader
+                .easy_load_dylib(ElfFile::from_path(&ie).unwrap())
+                .unwrap()
+                .easy_relocate([&dep].into_iter(), &|_| None)
+                .is_err()
+        );
+
+        let mut loader = Loader::<MmapImpl, StaticTls>::new();
+        let dep = load(&mut loader, &def, &[]);
+        let gd = load(&mut loader, &gd, &[&dep]);
+        let ie = load(&mut loader, &ie, &[&dep]);
+        let get_gd = unsafe { gd.get::<extern "C" fn() -> i32>("get_gd").unwrap() };
+        let addr_missing = unsafe {
+            gd.get::<extern "C" fn() -> *mut i32>("addr_missing")
+                .unwrap()
+        };
+        let set_ie = unsafe { ie.get::<extern "C" fn(i32)>("set_ie").unwrap() };
+        let get_ie = unsafe { ie.get::<extern "C" fn() -> i32>("get_ie").unwrap() };
+        // 未定义的弱tls符号位于模块0的偏移0
+        assert!(addr_missing().is_null());
+        assert_eq!((get_gd(), get_ie()), (3, 3));
+        // 两种模型访问的是同一个变量
+        set_ie(9);
+        assert_eq!(get_gd(), 9);
+
+        // tls描述符分别经过静态tls,未定义的弱符号和动态tls三种解析函数
+        #[cfg(target_arch = "x86_64")]
+        {
+            let dynamic = compile_c("libtlsmodel_dyn.so", "__thread int dynamic = 7;\n", &[]);
+            let desc = compile_c(
+                "libtlsmodel_desc.so",
+                "extern __thread int shared, dynamic;\n\
+                 extern __thread int missing __attribute__((weak));\n\
+                 int get_shared(void) { return shared; }\n\
+                 int get_dynamic(void) { return dynamic; }\n\
+                 int *addr_missing(void) { return &missing; }\n\
+                 long dynamic_keeps_ymm(void) {\n\
+                     long off, hi;\n\
+                     __asm__ volatile(\"vcmptrueps %%ymm8, %%ymm8, %%ymm8\\n\\t\"\n\
+                                      \"leaq dynamic@TLSDESC(%%rip), %%rax\\n\\t\"\n\
+                                      \"call *dynamic@TLSCALL(%%rax)\\n\\t\"\n\
+                                      \"vextractf128 $1, %%ymm8, %%xmm8\\n\\t\"\n\
+                                      \"vmovq %%xmm8, %1\\n\\t\"\n\
+                                      : \"=a\"(off), \"=r\"(hi) :: \"xmm8\", \"memory\");\n\
+                     return hi;\n\
+                 }\n",
+                &["-mtls-dialect=gnu2", "-mno-red-zone"],
+            );
+            let dynamic = load(&mut loader, &dynamic, &[]);
+            let desc = load(&mut loader, &desc, &[&dep, &dynamic]);
+            let get_shared = unsafe { desc.get::<extern "C" fn() -> i32>("get_shared").unwrap() };
+            let get_dynamic = unsafe { desc.get::<extern "C" fn() -> i32>("get_dynamic").unwrap() };
+            let addr_missing = unsafe {
+                desc.get::<extern "C" fn() -> *mut i32>("addr_missing")
+                    .unwrap()
+            };
+            assert_eq!((get_shared(), get_dynamic()), (9, 7));
+            assert!(addr_missing().is_null());
+            // 动态tls的解析函数要保存AVX的状态
+            if std::arch::is_x86_feature_detected!("avx") {
+                let keeps_ymm = unsafe {
+                    desc.get::<extern "C" fn() -> i64>("dynamic_keeps_ymm")
+                        .unwrap()
+                };
+                assert_eq!(keeps_ymm(), -1);
+            }
+        }
+    }
+
+    #[test]
+    fn flush_icache() {
+        use elf_loader::arch::flush_icache;
+        // 返回value的函数
+        fn code(value: u32) -> Vec<u8> {
+            #[cfg(target_arch = "x86_64")]
+            let insts: Vec<u8> = [&[0xb8][..], &value.to_le_bytes(), &[0xc3]].concat();
+            // mov w0, #value; ret
+            #[cfg(target_arch = "aarch64")]
+            let insts = [0x5280_0000 | (value << 5), 0xd65f_03c0];
+            // addi a0, zero, value; ret
+            #[cfg(target_arch = "riscv64")]
+            let insts = [(value << 20) | 0x513, 0x8067];
+            // addi.w $a0, $zero, value; jirl $zero, $ra, 0
+            #[cfg(target_arch = "loongarch64")]
+            let insts = [0x0280_0004 | (value << 10), 0x4c00_0020];
+            #[cfg(not(target_arch = "x86_64"))]
+            let insts: Vec<u8> = insts
+                .iter()
+                .flat_map(|inst: &u32| inst.to_le_bytes())
+                .collect();
+            insts
+        }
+        let len = 0x1000;
+        let rw = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+        let rx = ProtFlags::PROT_READ | ProtFlags::PROT_EXEC;
+        let ptr = unsafe { MmapImpl::mmap_anonymous(0, len, rw, MapFlags::MAP_PRIVATE) }.unwrap();
+        let start = ptr.as_ptr() as usize;
+        let f: extern "C" fn() -> u32 = unsafe { core::mem::transmute(ptr.as_ptr()) };
+        // 改写已经执行过的指令之后,新的指令必须可见
+        for value in [42, 43, 44] {
+            let insts = code(value);
+            unsafe {
+                MmapImpl::mprotect(ptr, len, rw).unwrap();
+                std::ptr::copy_nonoverlapping(insts.as_ptr(), ptr.as_ptr().cast(), insts.len());
+                MmapImpl::mprotect(ptr, len, rx).unwrap();
+            }
+            flush_icache(start..start + insts.len());
+            assert_eq!(f(), value);
+        }
+        unsafe { MmapImpl::munmap(ptr, len) }.unwrap();
+    }
+
+    #[test]
+    fn symbol_info_sync() {
+        fn assert_sync<T: Sync>(_: &T) {}
+        compile();
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let symtab = liba.symtab();
+        let (idx, info) = (0..symtab.count_syms())
+            .map(|idx| (idx, symtab.symbol_idx(idx).1))
+            .find(|(_, info)| info.name() == "a")
+            .unwrap();
+        assert_sync(&info);
+        // 多个线程同时计算并缓存同一个符号的哈希值
+        let expected = symtab.symbol_idx(idx).0 as *const _ as usize;
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let sym = liba.symtab().lookup(&info).unwrap();
+                    assert_eq!(sym as *const _ as usize, expected);
+                });
+            }
+        });
+    }
+
     #[test]
     fn lazy_binding() {
         compile();
@@ -118,75 +890,983 @@ mod fs {
     }
 
     #[test]
-    fn load_from_memory() {
+    fn relocate_with_hook() {
+        use elf_loader::RelocateAction;
+        use std::cell::RefCell;
         compile();
-        let mut file = File::open(&lib_path("liba.so")).unwrap();
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes).unwrap();
-        let liba = load_dylib!("liba.so", &bytes).unwrap();
-        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
-        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
-        assert!(f() == 1);
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        fn fake_a() -> i32 {
+            41
+        }
+        let mut map = HashMap::new();
+        map.insert("print", print as _);
+        let pre_find = |name: &str| -> Option<*const ()> { map.get(name).copied() };
+        let a = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let seen = RefCell::new(Vec::new());
+        let b = libb
+            .relocate_with_hook(
+                [&a].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                |ctx| {
+                    seen.borrow_mut()
+                        .push((ctx.name.to_string(), ctx.resolved.is_some()));
+                    if ctx.name == "a" {
+                        RelocateAction::Replace(fake_a as *const ())
+                    } else {
+                        RelocateAction::Default
+                    }
+                },
+                None,
+            )
+            .unwrap();
+        let f = unsafe { b.get::<fn() -> i32>("b").unwrap() };
+        assert!(f() == 42);
+        let seen = seen.into_inner();
+        assert!(seen.contains(&("a".to_string(), true)));
+        assert!(seen.contains(&("print".to_string(), true)));
     }
 
     #[test]
-    fn wrong_name_fails() {
+    fn got_rebind() {
+        use std::sync::Mutex;
         compile();
-        let _ = load_dylib!("target/this_location_is_definitely_non existent:^~")
-            .err()
+        static PRINTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static PATCHED: &str = "Patched!";
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        fn record(s: &str) {
+            PRINTED.lock().unwrap().push(s.to_string());
+        }
+        fn fake_a() -> i32 {
+            41
+        }
+        let mut map = HashMap::new();
+        map.insert("print", print as _);
+        let pre_find = |name: &str| -> Option<*const ()> { map.get(name).copied() };
+        let a = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
             .unwrap();
-    }
+        let b = load_dylib!(&lib_path("libb.so"), lazy: false)
+            .unwrap()
+            .easy_relocate([&a].into_iter(), &pre_find)
+            .unwrap();
+        let f = unsafe { b.get::<fn() -> i32>("b").unwrap() };
+        assert!(f() == 2);
 
-    #[test]
-    fn type_mismatch() {
-        compile();
-        let _ = load_exec!(&lib_path("liba.so")).err().unwrap();
-    }
+        let prev_a = unsafe { b.rebind("a", fake_a as *const ()) }.unwrap();
+        assert_eq!(prev_a.symbol(), "a");
+        assert_eq!(prev_a.previous(), unsafe {
+            a.get::<fn() -> i32>("a").unwrap().into_raw()
+        });
+        let prev_print = unsafe { b.rebind("print", record as *const ()) }.unwrap();
+        // HELLO的got表项位于relro中
+        let prev_hello = unsafe { b.rebind("HELLO", &raw const PATCHED as *const ()) }.unwrap();
+        assert!(f() == 42);
+        assert_eq!(*PRINTED.lock().unwrap(), ["call b()", "Patched!"]);
 
-    #[test]
-    fn load_elf() {
-        compile();
-        let liba = load!(&lib_path("liba.so")).unwrap();
-        assert!(matches!(liba, Elf::Dylib(_)));
-        let a = liba
-            .easy_relocate([].into_iter(), &|_| None)
+        b.restore(prev_hello).unwrap();
+        b.restore(prev_print).unwrap();
+        b.restore(prev_a).unwrap();
+        assert!(f() == 2);
+        assert_eq!(PRINTED.lock().unwrap().len(), 2);
+        assert!(unsafe { b.rebind("missing", fake_a as *const ()) }.is_err());
+
+        // 只能在修改它的库上恢复
+        let b2 = load_dylib!(&lib_path("libb.so"), lazy: false)
             .unwrap()
-            .into_dylib()
+            .easy_relocate([&a].into_iter(), &pre_find)
             .unwrap();
-        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
-        assert!(f() == 1);
+        let prev_a = unsafe { b2.rebind("a", fake_a as *const ()) }.unwrap();
+        assert!(b.restore(prev_a).is_err());
+        assert!(f() == 2);
     }
 
     #[test]
-    fn missing_symbol_fails() {
+    fn weak_symbol_binding() {
+        use elf::abi::STB_WEAK;
+        use elf_loader::{RelocateAction, SymbolBinding};
+        use std::cell::Cell;
         compile();
-        let lib = load_dylib!(&lib_path("liba.so"))
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        // 将liba中的HELLO修改为弱符号
+        let st_info = dynsym_offset(&bytes, "HELLO") + 4;
+        bytes[st_info] = STB_WEAK << 4 | (bytes[st_info] & 0xf);
+
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let weak_a = load_dylib!("liba.so", &bytes)
             .unwrap()
-            .easy_relocate([].into_iter(), &|_| None)
+            .easy_relocate([].iter(), &pre_find)
             .unwrap();
-        unsafe {
-            assert!(lib.get::<*mut ()>("test_does_not_exist").is_none());
-        }
+        let strong_a = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let weak_hello = unsafe { weak_a.get::<()>("HELLO").unwrap() };
+        let strong_hello = unsafe { strong_a.get::<()>("HELLO").unwrap() };
+        assert_eq!(weak_hello.binding(), SymbolBinding::Weak);
+        assert_eq!(strong_hello.binding(), SymbolBinding::Global);
+
+        // 强符号优先于搜索顺序中更靠前的弱符号
+        let resolved = Cell::new(None);
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let _b = libb
+            .relocate_with_hook(
+                [&weak_a, &strong_a].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                |ctx| {
+                    if ctx.name == "HELLO" {
+                        resolved.set(ctx.resolved);
+                    }
+                    RelocateAction::Default
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(resolved.get(), Some(strong_hello.into_raw()));
     }
 
-    #[repr(C)]
-    #[derive(Clone, Copy, PartialEq, Debug)]
-    struct S {
-        a: u64,
-        b: u32,
-        c: u16,
-        d: u8,
+    #[test]
+    fn unique_symbols() {
+        use elf_loader::SymbolBinding;
+        compile();
+        if consts::ARCH != "x86_64" {
+            return;
+        }
+        // 两个库都定义了唯一符号unique_counter
+        let build = |idx: i32| {
+            let path = compile_c(
+                &format!("libunique{idx}.so"),
+                &format!(
+                    "int unique_counter = {idx};\n\
+                     __asm__(\".type unique_counter, @gnu_unique_object\");\n\
+                     int *get(void) {{ return &unique_counter; }}\n"
+                ),
+                &[],
+            );
+            load_dylib!(&path)
+                .unwrap()
+                .easy_relocate([].iter(), &|_| None)
+                .unwrap()
+        };
+        let lib1 = build(1);
+        let lib2 = build(2);
+        let get1 = *unsafe { lib1.get::<extern "C" fn() -> *const i32>("get").unwrap() };
+        let get2 = *unsafe { lib2.get::<extern "C" fn() -> *const i32>("get").unwrap() };
+        let counter = unsafe { lib2.get::<()>("unique_counter").unwrap() };
+        assert_eq!(counter.binding(), SymbolBinding::Unique);
+        // 两个库都绑定到第一个定义
+        assert_eq!(get2(), get1());
+        assert_eq!(unsafe { *get2() }, 1);
+        // 定义了唯一符号的库不会被卸载
+        let weak = lib1.downgrade();
+        drop(lib1);
+        assert!(weak.upgrade().is_some());
+        assert_eq!(unsafe { *get2() }, 1);
     }
 
     #[test]
-    fn test_id_struct() {
+    fn symbol_visibility() {
+        use elf::abi::{SHN_ABS, STV_HIDDEN, STV_PROTECTED};
         compile();
-        let lib = load_dylib!(&lib_path("liba.so"))
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut origin = Vec::new();
+        file.read_to_end(&mut origin).unwrap();
+        let pre_find = |_: &str| None;
+        let sym = dynsym_offset(&origin, "HELLO");
+
+        // hidden符号不能被其他elf对象引用
+        let mut bytes = origin.clone();
+        bytes[sym + 5] = STV_HIDDEN;
+        let a = load_dylib!("liba.so", &bytes)
             .unwrap()
-            .easy_relocate([].into_iter(), &|_| None)
+            .easy_relocate([].iter(), &pre_find)
             .unwrap();
-        unsafe {
-            let f = lib
+        assert!(unsafe { a.get::<()>("HELLO") }.is_none());
+
+        let mut bytes = origin.clone();
+        bytes[sym + 5] = STV_PROTECTED;
+        let a = load_dylib!("liba.so", &bytes)
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        assert!(unsafe { a.get::<()>("HELLO") }.is_some());
+
+        // SHN_ABS符号的值不加上基址
+        let mut bytes = origin.clone();
+        bytes[sym + 6..sym + 8].copy_from_slice(&SHN_ABS.to_ne_bytes());
+        bytes[sym + 8..sym + 16].copy_from_slice(&0x1234u64.to_ne_bytes());
+        let a = load_dylib!("liba.so", &bytes)
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let hello = unsafe { a.get::<()>("HELLO").unwrap() };
+        assert_eq!(hello.into_raw() as usize, 0x1234);
+    }
+
+    #[test]
+    fn load_from_memory() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let liba = load_dylib!("liba.so", &bytes).unwrap();
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+    }
+
+    #[test]
+    fn inspect_foreign_endian() {
+        use elf_loader::{
+            Error,
+            abi::{
+                DT_HASH, DT_NEEDED, DT_SONAME, DT_STRSZ, DT_STRTAB, DT_SYMTAB, ELFCLASS64,
+                ELFDATA2MSB, EM_PPC64, PT_DYNAMIC, STB_GLOBAL, STT_FUNC,
+            },
+            inspect::ElfInfo,
+        };
+        let [_, libb, _] = needed_libs();
+        let mut bytes = std::fs::read(libb).unwrap();
+        let info = ElfInfo::parse(&bytes).unwrap();
+        assert!(info.is_native());
+        assert_eq!(info.needed, ["libneeda.so"]);
+        assert!(
+            info.symbols
+                .iter()
+                .any(|symbol| symbol.name == "b" && symbol.defined)
+        );
+        assert!(
+            info.symbols
+                .iter()
+                .any(|symbol| symbol.name == "a" && !symbol.defined)
+        );
+        // 去掉节头后通过DT_SYMTAB和DT_GNU_HASH读取符号
+        bytes[0x28..0x30].fill(0);
+        bytes[0x3c..0x40].fill(0);
+        let stripped = ElfInfo::parse(&bytes).unwrap();
+        assert_eq!(stripped.symbols, info.symbols);
+
+        // 手动构造一个没有节头的大端ppc64动态库
+        let strtab = b"\0libfoo.so\0libc.so.6\0foo\0";
+        let (phoff, dynoff) = (64u64, 64 + 2 * 56u64);
+        let stroff = dynoff + 7 * 16;
+        let symoff = stroff + 32;
+        let hashoff = symoff + 2 * 24;
+        let len = hashoff + 4 * 4;
+        let mut elf = Vec::new();
+        elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', ELFCLASS64, ELFDATA2MSB, 1]);
+        elf.resize(16, 0);
+        elf.extend_from_slice(&3u16.to_be_bytes());
+        elf.extend_from_slice(&EM_PPC64.to_be_bytes());
+        elf.extend_from_slice(&1u32.to_be_bytes());
+        for val in [0x1000, phoff, 0] {
+            elf.extend_from_slice(&u64::to_be_bytes(val));
+        }
+        elf.extend_from_slice(&0u32.to_be_bytes());
+        for val in [64u16, 56, 2, 64, 0, 0] {
+            elf.extend_from_slice(&val.to_be_bytes());
+        }
+        for (p_type, p_flags, offset, size) in [(1, 5, 0, len), (PT_DYNAMIC, 6, dynoff, 112)] {
+            elf.extend_from_slice(&u32::to_be_bytes(p_type));
+            elf.extend_from_slice(&u32::to_be_bytes(p_flags));
+            for val in [offset, offset, offset, size, size, 8] {
+                elf.extend_from_slice(&val.to_be_bytes());
+            }
+        }
+        for (tag, val) in [
+            (DT_STRTAB, stroff),
+            (DT_STRSZ, strtab.len() as u64),
+            (DT_SYMTAB, symoff),
+            (DT_HASH, hashoff),
+            (DT_SONAME, 1),
+            (DT_NEEDED, 11),
+            (0, 0),
+        ] {
+            elf.extend_from_slice(&tag.to_be_bytes());
+            elf.extend_from_slice(&val.to_be_bytes());
+        }
+        elf.extend_from_slice(strtab);
+        elf.resize(symoff as usize + 24, 0);
+        // foo: 定义在0x1100的全局函数
+        elf.extend_from_slice(&21u32.to_be_bytes());
+        elf.extend_from_slice(&[STB_GLOBAL << 4 | STT_FUNC, 0]);
+        elf.extend_from_slice(&1u16.to_be_bytes());
+        elf.extend_from_slice(&0x1100u64.to_be_bytes());
+        elf.extend_from_slice(&16u64.to_be_bytes());
+        // nbucket, nchain, bucket[0], chain[0..2]
+        for val in [1u32, 2, 1, 0] {
+            elf.extend_from_slice(&val.to_be_bytes());
+        }
+        // 加载器一次读取elf头和多个程序头,文件不能太短
+        elf.resize(1024, 0);
+
+        let info = ElfInfo::parse(&elf).unwrap();
+        assert!(!info.is_native());
+        assert_eq!((info.data, info.machine), (ELFDATA2MSB, EM_PPC64));
+        assert_eq!(info.entry, 0x1000);
+        assert_eq!(info.phdrs.len(), 2);
+        assert_eq!(info.dynamic_value(DT_STRTAB), Some(stroff));
+        assert_eq!(info.soname.as_deref(), Some("libfoo.so"));
+        assert_eq!(info.needed, ["libc.so.6"]);
+        assert_eq!(info.symbols.len(), 1);
+        let foo = &info.symbols[0];
+        assert_eq!(
+            (foo.name.as_str(), foo.value, foo.size),
+            ("foo", 0x1100, 16)
+        );
+        assert_eq!(
+            (foo.symtype, foo.bind, foo.defined),
+            (STT_FUNC, STB_GLOBAL, true)
+        );
+        // 加载仍然只支持本机的格式
+        assert!(matches!(
+            load_dylib!("libfoo.so", &elf),
+            Err(Error::EndianMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn load_in_place() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let loads: Vec<_> = elf
+            .segments()
+            .unwrap()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+            .collect();
+        // 去掉不需要加载的部分,使其余segment的内存可以放在镜像之后
+        let file_len = loads
+            .iter()
+            .map(|phdr| (phdr.p_offset + phdr.p_filesz) as usize)
+            .max()
+            .unwrap();
+        let image_len = loads
+            .iter()
+            .map(|phdr| ((phdr.p_vaddr + phdr.p_memsz) as usize + 0xfff) & !0xfff)
+            .max()
+            .unwrap();
+        let buf_len = (file_len + 0xfff) & !0xfff;
+        let make_image = |len: usize| unsafe {
+            let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+            let ptr = MmapImpl::mmap_anonymous(0, image_len, prot, MapFlags::MAP_PRIVATE).unwrap();
+            // 空出镜像之后的内存
+            MmapImpl::munmap(ptr.byte_add(buf_len), image_len - buf_len).unwrap();
+            let image = std::slice::from_raw_parts_mut(ptr.as_ptr().cast::<u8>(), len);
+            image[..file_len].copy_from_slice(&bytes[..file_len]);
+            &*image
+        };
+        // 镜像占据整数个页,原地使用的页不会超出镜像
+        let image = make_image(buf_len);
+        let mut loader = Loader::<MmapImpl>::new();
+        let liba = loader
+            .easy_load_dylib(unsafe { ElfBinary::from_static("liba.so", image) })
+            .unwrap();
+        assert_eq!(liba.base(), image.as_ptr() as usize);
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+        // 最后一页的一部分不属于镜像,不能修改它的权限
+        let image = make_image(file_len);
+        let liba = loader
+            .easy_load_dylib(unsafe { ElfBinary::from_static("liba.so", image) })
+            .unwrap();
+        assert_ne!(liba.base(), image.as_ptr() as usize);
+        // 原地使用失败时镜像的权限不变,预留的内存被释放
+        struct DenyInPlace;
+        impl MmapPolicy for DenyInPlace {
+            fn check(request: &mut MapRequest) -> elf_loader::Result<()> {
+                if request.kind == MapKind::InPlace {
+                    return Err(elf_loader::Error::MmapError {
+                        msg: "in place denied".to_string(),
+                    });
+                }
+                Ok(())
+            }
+        }
+        let image = make_image(buf_len);
+        assert!(
+            Loader::<AuditedMmap<MmapImpl, DenyInPlace>>::new()
+                .easy_load_dylib(unsafe { ElfBinary::from_static("liba.so", image) })
+                .is_err()
+        );
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let mapping = |addr: usize| {
+            let prefix = format!("{addr:x}-");
+            maps.lines().find(|line| line.starts_with(&prefix))
+        };
+        let first = mapping(image.as_ptr() as usize).unwrap();
+        assert!(first.contains(" rw-p "), "{first}");
+        assert!(mapping(image.as_ptr() as usize + buf_len).is_none());
+        // 完整的文件会与其余segment的内存重叠,此时退回到复制
+        let full = unsafe {
+            let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+            let ptr =
+                MmapImpl::mmap_anonymous(0, bytes.len(), prot, MapFlags::MAP_PRIVATE).unwrap();
+            let full = std::slice::from_raw_parts_mut(ptr.as_ptr().cast::<u8>(), bytes.len());
+            full.copy_from_slice(&bytes);
+            &*full
+        };
+        let liba = loader
+            .easy_load_dylib(unsafe { ElfBinary::from_static("liba.so", full) })
+            .unwrap();
+        assert_ne!(liba.base(), full.as_ptr() as usize);
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+    }
+
+    #[test]
+    fn truncated_file_rejected() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let last = elf
+            .segments()
+            .unwrap()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+            .last()
+            .unwrap();
+        let len = (last.p_offset + last.p_filesz) as usize - 1;
+        let err = load_dylib!("liba.so", &bytes[..len]).err().unwrap();
+        assert!(matches!(err, elf_loader::Error::InvalidPhdr { .. }));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn load_from_reader() {
+        use elf_loader::object::{ElfObject, ElfReader};
+        compile();
+        let bytes = std::fs::read(&lib_path("liba.so")).unwrap();
+        let mut reader = ElfReader::new("liba.so", std::io::Cursor::new(&bytes));
+        assert_eq!(reader.size(), Some(bytes.len()));
+        let (mut magic, mut class) = ([0u8; 4], [0u8; 1]);
+        reader
+            .read_vectored(&mut [&mut magic, &mut class], 0)
+            .unwrap();
+        assert_eq!(&magic, b"\x7fELF");
+        assert_eq!(class[0], elf::abi::ELFCLASS64);
+        let mut loader = Loader::<MmapImpl>::new();
+        let liba = loader.easy_load_dylib(reader).unwrap();
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+        let file = File::open(&lib_path("liba.so")).unwrap();
+        assert!(
+            loader
+                .easy_load_dylib(ElfReader::new("liba.so", file))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn offset_type_copy() {
+        use core::ffi::CStr;
+        use elf_loader::object::{ElfObject, OffsetType};
+        compile();
+        // 带有文件描述符的elf对象也可以通过读取加载,记录每次read_vectored的缓冲区数量
+        struct Copied<'a> {
+            file: ElfFile,
+            calls: &'a mut Vec<usize>,
+        }
+        impl ElfObject for Copied<'_> {
+            fn file_name(&self) -> &CStr {
+                self.file.file_name()
+            }
+            fn read(&mut self, buf: &mut [u8], offset: usize) -> elf_loader::Result<()> {
+                self.file.read(buf, offset)
+            }
+            fn read_vectored(
+                &mut self,
+                bufs: &mut [&mut [u8]],
+                offset: usize,
+            ) -> elf_loader::Result<()> {
+                self.calls.push(bufs.len());
+                self.file.read_vectored(bufs, offset)
+            }
+            fn as_fd(&self) -> Option<i32> {
+                self.file.as_fd()
+            }
+            fn offset_type(&self) -> OffsetType {
+                OffsetType::Copy
+            }
+        }
+        let file = ElfFile::from_path(&lib_path("liba.so")).unwrap();
+        assert_eq!(file.offset_type(), OffsetType::Fd(file.as_fd().unwrap()));
+        // ld默认使用-z separate-code,每个segment从新的一页开始
+        let path = compile_c(
+            "libcopied.so",
+            "static volatile int value = 5;\nstatic const int rodata = 2;\nint copied(void) { return value + rodata; }\n",
+            &["-Wl,-z,separate-code"],
+        );
+        let mut calls = Vec::new();
+        let object = Copied {
+            file: ElfFile::from_path(&path).unwrap(),
+            calls: &mut calls,
+        };
+        let lib = Loader::<MmapImpl>::new()
+            .easy_load_dylib(object)
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let f = unsafe { lib.get::<extern "C" fn() -> i32>("copied").unwrap() };
+        assert_eq!(f(), 7);
+        let loads = lib
+            .phdrs()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+            .count();
+        // 连续的segment通过一次read_vectored读取
+        assert!(calls.len() < loads && calls.iter().any(|&bufs| bufs > 1));
+    }
+
+    #[test]
+    fn load_from_block_device() {
+        use elf_loader::object::{BlockDevice, ElfBlockReader, ElfObject, ReadAt};
+        compile();
+        let bytes = std::fs::read(&lib_path("liba.so")).unwrap();
+        // 文件从磁盘中一个不对齐的位置开始
+        let start = 3 * 512 + 100;
+        let mut disk = vec![0xffu8; start];
+        disk.extend_from_slice(&bytes);
+        disk.resize(disk.len() + 300, 0xff);
+
+        struct Disk(Vec<u8>);
+        impl BlockDevice for Disk {
+            fn block_size(&self) -> usize {
+                512
+            }
+
+            fn buf_align(&self) -> usize {
+                64
+            }
+
+            fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> elf_loader::Result<usize> {
+                assert!(buf.len() % 512 == 0 && buf.as_ptr() as usize % 64 == 0);
+                let start = (lba as usize * 512).min(self.0.len());
+                // 每次最多读取两个块
+                let len = buf.len().min(1024).min(self.0.len() - start);
+                buf[..len].copy_from_slice(&self.0[start..start + len]);
+                Ok(len)
+            }
+        }
+
+        let object = ElfBlockReader::new("liba.so", Disk(disk.clone()), Some(bytes.len()))
+            .with_start(start as u64);
+        let liba = Loader::<MmapImpl>::new().easy_load_dylib(object).unwrap();
+        let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { liba.get::<fn() -> i32>("a").unwrap() };
+        assert_eq!(f(), 1);
+
+        let device = ReadAt::new(4096, |lba, buf: &mut [u8]| {
+            let start = (lba as usize * 4096).min(bytes.len());
+            let len = buf.len().min(bytes.len() - start);
+            buf[..len].copy_from_slice(&bytes[start..start + len]);
+            Ok(len)
+        });
+        let mut object = ElfBlockReader::new("liba.so", device, None);
+        let mut buf = [0u8; 4];
+        object.read(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"\x7fELF");
+        // 超出设备末尾的读取会失败
+        assert!(object.read(&mut buf, bytes.len() - 2).is_err());
+        let liba = Loader::<MmapImpl>::new().easy_load_dylib(object).unwrap();
+        let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { liba.get::<fn() -> i32>("a").unwrap() };
+        assert_eq!(f(), 1);
+    }
+
+    #[cfg(all(feature = "std", feature = "use-libc", target_os = "linux"))]
+    #[test]
+    fn host_symbols() {
+        use elf_loader::host::{HostDylib, host_symbol};
+        compile();
+        let path = compile_c(
+            "libhost.so",
+            "unsigned long strlen(const char *);\nint abs(int);\n\
+             unsigned long len(const char *s) { return strlen(s); }\n\
+             int absolute(int x) { return abs(x); }\n",
+            &[],
+        );
+        let check = |lib: &elf_loader::RelocatedDylib| {
+            let len = unsafe { lib.get::<extern "C" fn(*const u8) -> usize>("len").unwrap() };
+            let absolute = unsafe { lib.get::<extern "C" fn(i32) -> i32>("absolute").unwrap() };
+            assert_eq!(len(c"hello".as_ptr().cast()), 5);
+            assert_eq!(absolute(-3), 3);
+        };
+        let mut loader = Loader::<MmapImpl>::new();
+        let load = |loader: &mut Loader<MmapImpl>| {
+            loader
+                .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+                .unwrap()
+        };
+
+        // 在宿主进程的全局符号中查找
+        let lib = load(&mut loader)
+            .easy_relocate([].iter(), &host_symbol)
+            .unwrap();
+        check(&lib);
+        assert!(host_symbol("no_such_symbol").is_none());
+
+        // 将宿主的libc作为依赖
+        let libc = HostDylib::open("libc.so.6").unwrap();
+        assert!(unsafe { libc.dylib().get::<()>("abs") }.is_some());
+        let lib = load(&mut loader)
+            .easy_relocate([libc.dylib()].into_iter(), &|_| None)
+            .unwrap();
+        check(&lib);
+        drop(lib);
+        assert!(HostDylib::open("libno_such_library.so").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_symbols() {
+        use elf_loader::symtab::{FileSymbols, build_id_path};
+        compile();
+        let path = compile_c(
+            "libsymtab.so",
+            "static int helper(int x) { return x * 3; }\n\
+             int call(int x) { return helper(x) + 1; }\n\
+             __thread int counter = 7;\n\
+             __asm__(\".globl abs_value\\n.set abs_value, 0x1234\\n\");\n",
+            &["-O0"],
+        );
+        let symbols = FileSymbols::from_path(&path).unwrap();
+        let helper = symbols.get("helper").unwrap();
+        assert!(helper.is_local() && helper.size != 0);
+        assert!(!symbols.get("call").unwrap().is_local());
+
+        let mut loader = Loader::<MmapImpl>::new();
+        let lib = loader
+            .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        // 动态符号表中没有静态函数
+        assert!(unsafe { lib.get::<()>("helper") }.is_none());
+        let ptr = symbols.lookup(&lib, "helper").unwrap();
+        let helper_fn: extern "C" fn(i32) -> i32 = unsafe { core::mem::transmute(ptr) };
+        assert_eq!(helper_fn(5), 15);
+        let call = symbols.lookup(&lib, "call").unwrap();
+        assert_eq!(call, unsafe { lib.get::<()>("call") }.unwrap().into_raw());
+        let inside = symbols.symbol_at(&lib, ptr as usize + 1).unwrap();
+        assert_eq!(inside.name, "helper");
+        assert!(symbols.symbol_at(&lib, lib.base() - 1).is_none());
+        assert!(symbols.lookup(&lib, "no_such_symbol").is_none());
+        // TLS符号的值是TLS块中的偏移,绝对符号的值不随基址变化
+        let counter = symbols.get("counter").unwrap();
+        assert!(counter.is_tls());
+        assert!(symbols.lookup(&lib, "counter").is_none());
+        assert!(
+            symbols
+                .symbol_at(&lib, lib.base() + counter.value)
+                .is_none_or(|sym| sym.name != "counter")
+        );
+        assert!(symbols.get("abs_value").unwrap().is_abs());
+        assert_eq!(symbols.lookup(&lib, "abs_value").unwrap() as usize, 0x1234);
+        assert_eq!(
+            build_id_path(&[0xab, 0xcd, 0x01]).unwrap(),
+            "/usr/lib/debug/.build-id/ab/cd01.debug"
+        );
+    }
+
+    #[cfg(feature = "cabi")]
+    #[test]
+    fn cabi_shims() {
+        use elf_loader::{
+            cabi::{self, RTLD_GLOBAL, RTLD_NOW},
+            namespace::Namespace,
+            search::SearchConfig,
+        };
+        use std::{ffi::CStr, ffi::CString, ptr::null};
+        compile();
+        let path = compile_c(
+            "libcabi.so",
+            "void *dlopen(const char *, int);\nvoid *dlsym(void *, const char *);\n\
+             char *dlerror(void);\nint dlclose(void *);\n\
+             int call_a(const char *path) {\n\
+                 void *handle = dlopen(path, 2);\n\
+                 if (!handle) return -1;\n\
+                 int (*a)(void) = (int (*)(void))dlsym(handle, \"a\");\n\
+                 if (!a) return -2;\n\
+                 if (dlsym(handle, \"missing\") || !dlerror() || dlerror()) return -3;\n\
+                 int ret = a();\n\
+                 if (dlclose(handle)) return -4;\n\
+                 return ret;\n\
+             }\n",
+            &[],
+        );
+
+        // C代码通过pre_find得到这些函数
+        let lib = load_dylib!(&path)
+            .unwrap()
+            .easy_relocate([].iter(), &cabi::symbol)
+            .unwrap();
+        let call_a = unsafe {
+            lib.get::<extern "C" fn(*const std::ffi::c_char) -> i32>("call_a")
+                .unwrap()
+        };
+        let liba = CString::new(lib_path("liba.so")).unwrap();
+        assert_eq!(call_a(liba.as_ptr()), 1);
+
+        unsafe {
+            let handle = cabi::dlopen(liba.as_ptr(), RTLD_NOW | RTLD_GLOBAL);
+            assert!(!handle.is_null());
+            // 再次打开得到相同的句柄
+            assert_eq!(cabi::dlopen(liba.as_ptr(), RTLD_NOW), handle);
+            let global = cabi::dlopen(null(), RTLD_NOW);
+            assert_eq!(
+                cabi::dlsym(global, c"a".as_ptr()),
+                cabi::dlsym(handle, c"a".as_ptr())
+            );
+            assert!(cabi::dlsym(handle, c"missing".as_ptr()).is_null());
+            let msg = CStr::from_ptr(cabi::dlerror());
+            assert_eq!(msg.to_str().unwrap(), "undefined symbol: missing");
+            assert!(cabi::dlerror().is_null());
+            assert_eq!(cabi::dlclose(handle), 0);
+            assert_eq!(cabi::dlclose(handle), 0);
+            // 句柄已经失效
+            assert_eq!(cabi::dlclose(handle), -1);
+            assert!(cabi::dlsym(handle, c"a".as_ptr()).is_null());
+            assert!(!cabi::dlerror().is_null());
+            assert!(cabi::dlopen(c"libno_such_library.so".as_ptr(), RTLD_NOW).is_null());
+            let msg = CStr::from_ptr(cabi::dlerror());
+            assert!(msg.to_str().unwrap().contains("libno_such_library.so"));
+        }
+
+        // 初始化函数中调用dlopen
+        let [liba, libb, _] = needed_libs();
+        let path = compile_c(
+            "libcabi_init.so",
+            &format!(
+                "void *dlopen(const char *, int);\n\
+                 static void *handle;\n\
+                 __attribute__((constructor)) static void init(void) {{ handle = dlopen(\"{liba}\", 2); }}\n\
+                 int opened(void) {{ return handle != 0; }}\n"
+            ),
+            &[],
+        );
+        let dir = lib_path("");
+        let mut namespace = Namespace::new(Loader::new());
+        namespace.set_pre_find(cabi::symbol);
+        cabi::init(namespace, SearchConfig::new().default_paths(&[&dir]));
+        let [liba, libb, path] = [liba, libb, path].map(|path| CString::new(path).unwrap());
+        unsafe {
+            let init = cabi::dlopen(path.as_ptr(), RTLD_NOW);
+            let opened = cabi::dlsym(init, c"opened".as_ptr());
+            let opened: extern "C" fn() -> i32 = std::mem::transmute(opened);
+            assert_eq!(opened(), 1);
+
+            // 依赖仍被其他库使用时,关闭它的句柄不会把它从命名空间中移除
+            let b_handle = cabi::dlopen(libb.as_ptr(), RTLD_NOW);
+            let a_handle = cabi::dlopen(liba.as_ptr(), RTLD_NOW);
+            assert!(!b_handle.is_null() && !a_handle.is_null());
+            let a = cabi::dlsym(a_handle, c"a".as_ptr());
+            // 初始化函数已经打开过liba,因此需要关闭两次
+            assert_eq!(cabi::dlclose(a_handle), 0);
+            assert_eq!(cabi::dlclose(a_handle), 0);
+            let b = cabi::dlsym(b_handle, c"b".as_ptr());
+            let b: extern "C" fn() -> i32 = std::mem::transmute(b);
+            assert_eq!(b(), 2);
+            let a_handle = cabi::dlopen(liba.as_ptr(), RTLD_NOW);
+            assert_eq!(cabi::dlsym(a_handle, c"a".as_ptr()), a);
+            assert_eq!(cabi::dlclose(a_handle), 0);
+            assert_eq!(cabi::dlclose(b_handle), 0);
+            assert_eq!(cabi::dlclose(init), 0);
+        }
+    }
+
+    #[cfg(feature = "version")]
+    #[test]
+    fn needed_versions() {
+        use elf_loader::{Error, abi::VER_FLG_BASE};
+        compile();
+        let dir = lib_path("");
+        // 两个soname相同的库分别定义VERS_1和VERS_2
+        for (name, version) in [("libver.so", "VERS_1"), ("libver2.so", "VERS_2")] {
+            let map = lib_path(&format!("{version}.map"));
+            std::fs::write(&map, format!("{version} {{ global: ver; local: *; }};\n")).unwrap();
+            let script = format!("-Wl,--version-script={map}");
+            compile_c(
+                name,
+                "int ver(void) { return 1; }\n",
+                &["-Wl,-soname,libver.so", &script],
+            );
+        }
+        let user_path = compile_c(
+            "libuser.so",
+            "int ver(void);\nint use_ver(void) { return ver() + 1; }\n",
+            &["-L", &dir, "-l:libver.so"],
+        );
+
+        let ver = load_dylib!(&lib_path("libver.so")).unwrap();
+        let versions = ver.versions();
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].flags & VER_FLG_BASE != 0);
+        assert_eq!(versions[0].name, "libver.so");
+        assert_eq!(versions[1].name, "VERS_1");
+        let ver = ver.easy_relocate([].iter(), &|_| None).unwrap();
+        let ver2 = load_dylib!(&lib_path("libver2.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+
+        let user = load_dylib!(&user_path).unwrap();
+        let needed = user.needed_versions();
+        assert_eq!(needed.len(), 1);
+        assert_eq!((needed[0].file, needed[0].name), ("libver.so", "VERS_1"));
+        assert!(matches!(
+            user.verify_needed_versions(&[ver2.clone()]),
+            Err(Error::VersionError { version, .. }) if version == "VERS_1"
+        ));
+        assert!(matches!(
+            user.verify_needed_versions(&[]),
+            Err(Error::DependencyError { .. })
+        ));
+        user.verify_needed_versions(&[ver.clone()]).unwrap();
+        let user = user.easy_relocate([&ver].into_iter(), &|_| None).unwrap();
+        let f = unsafe { user.get::<extern "C" fn() -> i32>("use_ver").unwrap() };
+        assert_eq!(f(), 2);
+    }
+
+    #[test]
+    fn wrap_vdso() {
+        use elf_loader::RelocatedDylib;
+        const AT_SYSINFO_EHDR: usize = 33;
+        let auxv = std::fs::read("/proc/self/auxv").unwrap();
+        let Some(base) = auxv
+            .chunks_exact(2 * size_of::<usize>())
+            .map(|entry| {
+                let (key, val) = entry.split_at(size_of::<usize>());
+                (
+                    usize::from_ne_bytes(key.try_into().unwrap()),
+                    usize::from_ne_bytes(val.try_into().unwrap()),
+                )
+            })
+            .find_map(|(key, val)| (key == AT_SYSINFO_EHDR).then_some(val))
+        else {
+            return;
+        };
+        let vdso = unsafe { RelocatedDylib::from_mapped("linux-vdso.so.1", base) }.unwrap();
+        assert!(vdso.needed_libs().is_empty());
+        let name = if cfg!(target_arch = "aarch64") {
+            "__kernel_clock_gettime"
+        } else {
+            "__vdso_clock_gettime"
+        };
+        let clock_gettime: extern "C" fn(i32, *mut [i64; 2]) -> i32 =
+            unsafe { core::mem::transmute(vdso.get::<()>(name).unwrap().into_raw()) };
+        let mut ts = [0i64; 2];
+        // CLOCK_MONOTONIC
+        assert_eq!(clock_gettime(1, &mut ts), 0);
+        assert!(ts != [0, 0]);
+        // vDSO不会被解除映射
+        drop(vdso);
+        assert_eq!(clock_gettime(1, &mut ts), 0);
+    }
+
+    #[test]
+    fn wrong_name_fails() {
+        compile();
+        let _ = load_dylib!("target/this_location_is_definitely_non existent:^~")
+            .err()
+            .unwrap();
+    }
+
+    #[test]
+    fn type_mismatch() {
+        compile();
+        let _ = load_exec!(&lib_path("liba.so")).err().unwrap();
+    }
+
+    #[test]
+    fn exec_fixed_address() {
+        compile();
+        let path = compile_c(
+            "fixed_exec",
+            "int value = 7;\nvoid _start(void) {}\n",
+            &["-no-pie", "-nostdlib", "-Wl,-Ttext-segment=0x30000000"],
+        );
+        let exec = load_exec!(&path).unwrap();
+        // 第一个segment包含elf头
+        let header = 0x30000000 as *const [u8; 4];
+        assert_eq!(unsafe { *header }, *b"\x7fELF");
+        // 地址已被占用时加载失败,而不是覆盖原有的映射
+        assert!(matches!(
+            load_exec!(&path),
+            Err(elf_loader::Error::MmapError { .. })
+        ));
+        assert_eq!(unsafe { *header }, *b"\x7fELF");
+        drop(exec);
+        assert!(load_exec!(&path).is_ok());
+    }
+
+    #[test]
+    fn load_elf() {
+        compile();
+        let liba = load!(&lib_path("liba.so")).unwrap();
+        assert!(matches!(liba, Elf::Dylib(_)));
+        let a = liba
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap()
+            .into_dylib()
+            .unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+    }
+
+    #[test]
+    fn missing_symbol_fails() {
+        compile();
+        let lib = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap();
+        unsafe {
+            assert!(lib.get::<*mut ()>("test_does_not_exist").is_none());
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct S {
+        a: u64,
+        b: u32,
+        c: u16,
+        d: u8,
+    }
+
+    #[test]
+    fn test_id_struct() {
+        compile();
+        let lib = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap();
+        unsafe {
+            let f = lib
                 .get::<unsafe extern "C" fn(S) -> S>("test_identity_struct")
                 .unwrap();
             assert_eq!(
@@ -205,4 +1885,1952 @@ mod fs {
             );
         }
     }
+
+    #[test]
+    fn lookup_scope() {
+        use elf_loader::scope::{Scope, Visibility};
+        let [liba, libb, libc] = needed_libs();
+        let pre_find = |_: &str| None;
+        let mut global = Scope::new();
+        let liba = load_dylib!(&liba).unwrap();
+        let local = Scope::local(&liba, global.iter());
+        assert!(local.is_empty());
+        let liba = liba.easy_relocate(local.iter(), &pre_find).unwrap();
+        // RTLD_LOCAL的库不会加入全局scope
+        global.register(&liba, &local, Visibility::Local);
+        assert!(global.is_empty() && global.find("a").is_none());
+
+        let libb = load_dylib!(&libb).unwrap();
+        let local = Scope::local(&libb, [&liba].into_iter());
+        assert!(local.contains(&liba));
+        let scope = global.chain(&local);
+        let libb = libb.easy_relocate(scope.iter(), &pre_find).unwrap();
+        global.register(&libb, &local, Visibility::Global);
+        let names: Vec<_> = global.iter().map(|lib| lib.shortname()).collect();
+        assert_eq!(names, ["libneedb.so", "libneeda.so"]);
+        assert_eq!(global.find("a"), unsafe {
+            Some(liba.get::<()>("a").unwrap().into_raw())
+        });
+
+        // 依赖按广度优先的顺序加入局部scope
+        let libc = load_dylib!(&libc).unwrap();
+        let local = Scope::local(&libc, [&liba, &libb].into_iter());
+        let names: Vec<_> = local.iter().map(|lib| lib.shortname()).collect();
+        assert_eq!(names, ["libneedb.so", "libneeda.so"]);
+        let scope = global.chain(&local);
+        assert_eq!(scope.len(), 2);
+        let libc = libc.easy_relocate(scope.iter(), &pre_find).unwrap();
+        let f = unsafe { libc.get::<extern "C" fn() -> i32>("c").unwrap() };
+        assert!(f() == 3);
+        assert!(global.remove(&libb) && !global.contains(&libb));
+    }
+
+    #[test]
+    fn namespace() {
+        use elf_loader::{
+            namespace::Namespace,
+            scope::Visibility,
+            search::{FsResolver, SearchConfig},
+        };
+        let [_, _, libc] = needed_libs();
+        let dir = lib_path("");
+        let mut resolver = FsResolver::new(SearchConfig::new().default_paths(&[&dir]));
+        let mut open = |visibility| {
+            let mut ns = Namespace::new(Loader::<MmapImpl>::new());
+            let libc = ns
+                .open(
+                    ElfFile::from_path(&libc).unwrap(),
+                    visibility,
+                    &mut resolver,
+                )
+                .unwrap();
+            let f = unsafe { libc.get::<extern "C" fn() -> i32>("c").unwrap() };
+            assert!(f() == 3);
+            ns
+        };
+        let first = open(Visibility::Global);
+        let second = open(Visibility::Local);
+        assert_ne!(first.id(), second.id());
+        // 依赖在使用它们的库之前
+        let names: Vec<_> = first.libs().iter().map(|lib| lib.shortname()).collect();
+        assert_eq!(names, ["libneeda.so", "libneedb.so", "libneedc.so"]);
+        // 两个命名空间中的库互相独立
+        for name in ["libneeda.so", "libneedb.so", "libneedc.so"] {
+            assert_ne!(
+                first.get(name).unwrap().base(),
+                second.get(name).unwrap().base()
+            );
+        }
+        assert!(first.find("a").is_some());
+        assert!(second.find("a").is_none());
+        let liba = first.get("libneeda.so").unwrap();
+        assert_eq!(first.find("a"), unsafe {
+            Some(liba.get::<()>("a").unwrap().into_raw())
+        });
+
+        // 关闭依赖或者释放命名空间后,已经交出的库仍然可以使用它的依赖
+        let mut first = first;
+        let libc = first.get("libneedc.so").unwrap().clone();
+        let weak = first.get("libneeda.so").unwrap().downgrade();
+        for name in ["libneeda.so", "libneedb.so"] {
+            let lib = first.get(name).unwrap().clone();
+            assert!(first.close(&lib));
+        }
+        drop(first);
+        assert!(weak.upgrade().is_some());
+        let f = unsafe { libc.get::<extern "C" fn() -> i32>("c").unwrap() };
+        assert!(f() == 3);
+        drop(f);
+        drop(libc);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn namespace_cycle() {
+        use elf_loader::{
+            event::LoadEvent,
+            namespace::Namespace,
+            scope::Visibility,
+            search::{FsResolver, SearchConfig},
+        };
+        use std::sync::Mutex;
+        compile();
+        let dir = lib_path("");
+        // cyca和cycb互相依赖
+        let cyca = "int cycb(void);\nint cyca(void) { return 1; }\nint call_b(void) { return cycb() + 10; }\n";
+        let cycb = "int cyca(void);\nint cycb(void) { return 2; }\nint call_a(void) { return cyca() + 20; }\n";
+        compile_c("libcycb.so", cycb, &["-Wl,-z,now"]);
+        compile_c(
+            "libcyca.so",
+            cyca,
+            &["-Wl,-z,now", "-L", &dir, "-l:libcycb.so"],
+        );
+        compile_c(
+            "libcycb.so",
+            cycb,
+            &["-Wl,-z,now", "-L", &dir, "-l:libcyca.so"],
+        );
+        static UNLOADED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        fn on_unload(event: &LoadEvent) {
+            let name = event.name().rsplit('/').next().unwrap().to_string();
+            UNLOADED.lock().unwrap().push(name);
+        }
+        let mut resolver = FsResolver::new(SearchConfig::new().default_paths(&[&dir]));
+        let loader = Loader::<MmapImpl>::builder().on_unload(on_unload).build();
+        let mut ns = Namespace::new(loader);
+        let liba = ns
+            .open(
+                ElfFile::from_path(&lib_path("libcyca.so")).unwrap(),
+                Visibility::Local,
+                &mut resolver,
+            )
+            .unwrap();
+        let libb = ns.get("libcycb.so").unwrap().clone();
+        let call_b = unsafe { liba.get::<extern "C" fn() -> i32>("call_b").unwrap() };
+        let call_a = unsafe { libb.get::<extern "C" fn() -> i32>("call_a").unwrap() };
+        assert_eq!((call_b(), call_a()), (12, 21));
+
+        // 关闭循环依赖中的所有库之后它们都会被卸载
+        assert!(ns.close(&liba) && ns.close(&libb));
+        drop((liba, libb));
+        let mut unloaded = UNLOADED.lock().unwrap().clone();
+        unloaded.sort();
+        assert_eq!(unloaded, ["libcyca.so", "libcycb.so"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn namespace_tls() {
+        use elf_loader::{
+            namespace::Namespace,
+            scope::Visibility,
+            search::{FsResolver, SearchConfig},
+            tls::{ThreadLocal, ThreadLocalImpl},
+        };
+        let path = tls_lib();
+        let mut resolver = FsResolver::new(SearchConfig::new());
+        let mut open = || {
+            let mut ns = Namespace::new(Loader::<MmapImpl, ThreadLocalImpl>::new());
+            ns.set_pre_find(|name| {
+                (name == "__tls_get_addr").then_some(ThreadLocalImpl::tls_get_addr as *const ())
+            });
+            let lib = ns
+                .open(
+                    ElfFile::from_path(&path).unwrap(),
+                    Visibility::Local,
+                    &mut resolver,
+                )
+                .unwrap();
+            (ns, lib)
+        };
+        let (_first, lib1) = open();
+        let (_second, lib2) = open();
+        // 每个命名空间中的库都有自己的模块id和tls变量
+        assert_ne!(lib1.tls_modid().unwrap(), lib2.tls_modid().unwrap());
+        let bump1 = unsafe { lib1.get::<extern "C" fn() -> i32>("bump").unwrap() };
+        let bump2 = unsafe { lib2.get::<extern "C" fn() -> i32>("bump").unwrap() };
+        assert_eq!((bump1(), bump1(), bump2()), (6, 7, 6));
+    }
+
+    #[test]
+    fn estimate_closure() {
+        let [_, _, libc] = needed_libs();
+        let mut loader = Loader::<MmapImpl>::new();
+        let plan = loader
+            .plan_mapping(&mut ElfFile::from_path(&libc).unwrap())
+            .unwrap();
+        assert!(plan.len > 0 && !plan.fixed);
+        let estimate = loader
+            .estimate_closure(ElfFile::from_path(&libc).unwrap(), |name| {
+                ElfFile::from_path(&lib_path(name)).ok()
+            })
+            .unwrap();
+        assert!(estimate.total_len() >= plan.len);
+    }
+
+    #[test]
+    fn search_dependency() {
+        use elf_loader::{
+            object::ElfObject,
+            search::{FsResolver, LibraryResolver, NeededBy, SearchConfig},
+        };
+        compile();
+        let liba = lib_path("liba.so");
+        let needed_by = NeededBy {
+            name: &liba,
+            rpath: Some("/rpath/only"),
+            runpath: Some("$ORIGIN"),
+        };
+        let config = SearchConfig::new().library_path("/nonexistent:/also/nonexistent");
+        let candidates = config.candidates("libc.so", &needed_by);
+        // DT_RPATH is ignored when DT_RUNPATH is present
+        assert_eq!(candidates[0], "/nonexistent/libc.so");
+        assert_eq!(candidates[2], lib_path("libc.so"));
+        assert!(
+            !candidates
+                .iter()
+                .any(|path| path.starts_with("/rpath/only"))
+        );
+        let rpath_only = NeededBy {
+            runpath: None,
+            ..needed_by
+        };
+        assert_eq!(
+            config.candidates("libc.so", &rpath_only)[0],
+            "/rpath/only/libc.so"
+        );
+        let mut resolver = FsResolver::new(config);
+        assert!(resolver.resolve("libc.so", &needed_by).is_some());
+        assert!(resolver.resolve("libmissing.so", &needed_by).is_none());
+
+        // 跳过其他class的同名库
+        let dir = lib_path("elf32");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut bytes = std::fs::read(lib_path("libc.so")).unwrap();
+        bytes[elf::abi::EI_CLASS] = elf::abi::ELFCLASS32;
+        std::fs::write(format!("{dir}/libc.so"), bytes).unwrap();
+        let mut resolver = FsResolver::new(SearchConfig::new().library_path(&dir));
+        let found = resolver.resolve("libc.so", &needed_by).unwrap();
+        assert_eq!(found.file_name().to_str().unwrap(), lib_path("libc.so"));
+
+        let mut loader = Loader::<MmapImpl>::new();
+        let estimate = loader
+            .estimate_closure_with(ElfFile::from_path(&liba).unwrap(), &mut resolver)
+            .unwrap();
+        assert!(estimate.missing.is_empty());
+    }
+
+    #[test]
+    fn load_async_from_memory() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let mut loader = Loader::<MmapImpl>::new();
+        let mut future =
+            std::pin::pin!(loader.load_dylib_async(ElfBinary::new("liba.so", &bytes), None));
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        let liba = loop {
+            if let std::task::Poll::Ready(liba) = future.as_mut().poll(&mut cx) {
+                break liba.unwrap();
+            }
+        };
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+    }
+
+    #[test]
+    fn volatile_write_mode() {
+        compile();
+        for mode in [WriteMode::Volatile, WriteMode::AtomicRelaxed] {
+            let mut loader = Loader::<MmapImpl>::new();
+            loader.set_write_mode(mode);
+            let liba = loader
+                .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+                .unwrap();
+            let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+            let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+            assert!(f() == 1);
+        }
+    }
+
+    #[test]
+    fn load_with_alloc_fallback() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let mut loader = Loader::<MmapFromAlloc>::new();
+        let liba = loader
+            .easy_load_dylib(ElfBinary::new("liba.so", &bytes))
+            .unwrap();
+        // 堆内存不可执行,因此这里不进行重定位
+        assert!(liba.symtab().is_some());
+    }
+
+    #[test]
+    fn mmap_policy() {
+        compile();
+        struct DenyExec;
+        impl MmapPolicy for DenyExec {
+            fn check(request: &mut MapRequest) -> elf_loader::Result<()> {
+                if request.prot.contains(ProtFlags::PROT_EXEC) {
+                    return Err(elf_loader::Error::MmapError {
+                        msg: "exec denied".to_string(),
+                    });
+                }
+                Ok(())
+            }
+        }
+        let mut loader = Loader::<AuditedMmap<MmapImpl, WxorX>>::new();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap();
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+        let mut loader = Loader::<AuditedMmap<MmapImpl, DenyExec>>::new();
+        let err = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .err()
+            .unwrap();
+        assert!(matches!(err, elf_loader::Error::MmapError { .. }));
+    }
+
+    #[test]
+    fn sequential_base() {
+        compile();
+        const SEED: usize = 0x2000_0000_0000;
+        let load = |loader: &mut Loader<MmapImpl>| {
+            loader
+                .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+                .unwrap()
+        };
+        let mut loader = Loader::<MmapImpl>::new();
+        loader.set_sequential_base(SequentialBase::new(SEED).with_gap(0x1000));
+        let first = load(&mut loader);
+        let second = load(&mut loader);
+        assert_eq!(first.base(), SEED);
+        assert!(second.base() > first.base());
+        let second_base = second.base();
+        drop((first, second));
+        let mut loader = Loader::<MmapImpl>::new();
+        loader.set_sequential_base(SequentialBase::new(SEED).with_gap(0x1000));
+        let first = load(&mut loader);
+        let second = load(&mut loader);
+        assert_eq!(first.base(), SEED);
+        assert_eq!(second.base(), second_base);
+    }
+
+    #[test]
+    fn guard_pages() {
+        use elf_loader::GuardPages;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        compile();
+        const GUARD: usize = 0x4000;
+        const ALIGN: usize = 0x10000;
+        const RANGE: core::ops::Range<usize> = 0x5000_0000_0000..0x5000_1000_0000;
+        // 检查地址范围是否被某个映射完整覆盖,并返回其权限
+        let covered = |start: usize, end: usize| {
+            let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+            maps.lines().find_map(|line| {
+                let (range, rest) = line.split_once(' ')?;
+                let (lo, hi) = range.split_once('-')?;
+                let lo = usize::from_str_radix(lo, 16).unwrap();
+                let hi = usize::from_str_radix(hi, 16).unwrap();
+                (lo <= start && end <= hi).then(|| rest[..4].to_owned())
+            })
+        };
+        fn random() -> usize {
+            static NEXT: AtomicUsize = AtomicUsize::new(0x1234);
+            NEXT.fetch_add(0x777, Ordering::Relaxed)
+        }
+        let mut loader = Loader::<MmapImpl>::builder()
+            .guard_pages(
+                GuardPages::new(GUARD)
+                    .with_align(ALIGN)
+                    .with_random_base(RANGE, random),
+            )
+            .build();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let f = unsafe { liba.get::<fn() -> i32>("a").unwrap() };
+        assert_eq!(f(), 1);
+        let range = liba.map_range();
+        assert_eq!(range.start % ALIGN, 0);
+        assert!(RANGE.start <= range.start - GUARD && range.end + GUARD <= RANGE.end);
+        let before = covered(range.start - GUARD, range.start);
+        let after = covered(range.end, range.end + GUARD);
+        assert_eq!(before.as_deref(), Some("---p"));
+        assert_eq!(after.as_deref(), Some("---p"));
+        drop(liba);
+        assert!(covered(range.start - GUARD, range.start - GUARD + 1).is_none());
+        assert!(covered(range.end + GUARD - 1, range.end + GUARD).is_none());
+
+        // 范围放不下映射时直接报错,不能越过范围的末尾
+        let mut loader = Loader::<MmapImpl>::builder()
+            .guard_pages(
+                GuardPages::new(GUARD)
+                    .with_random_base(RANGE.start + 1..RANGE.start + 0x2000, random),
+            )
+            .build();
+        assert!(
+            loader
+                .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+                .is_err()
+        );
+
+        // 没有随机基址时由mmap决定地址
+        let mut loader = Loader::<MmapImpl>::new();
+        loader.set_guard_pages(GuardPages::new(GUARD));
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let range = liba.map_range();
+        let before = covered(range.start - GUARD, range.start);
+        assert_eq!(before.as_deref(), Some("---p"));
+    }
+
+    #[test]
+    fn loader_builder() {
+        compile();
+        const HINT: usize = 0x3000_0000_0000;
+        let mut loader = Loader::<MmapImpl>::builder()
+            .lazy(true)
+            .enforce_relro(true)
+            .map_hint(HINT)
+            .max_phdrs(32)
+            .build();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap();
+        assert_eq!(liba.base(), HINT);
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { a.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+        let lib = loader
+            .easy_load_dylib(ElfFile::from_path(&lazy_lib()).unwrap())
+            .unwrap();
+        assert!(lib.is_lazy());
+        let mut loader = Loader::<MmapImpl>::builder().max_phdrs(2).build();
+        let err = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .err()
+            .unwrap();
+        assert!(matches!(err, elf_loader::Error::InvalidPhdrTable { .. }));
+    }
+
+    #[test]
+    fn segment_layout() {
+        compile();
+        let liba = load_dylib!(&lib_path("liba.so"), lazy: false).unwrap();
+        let map = liba.map_range();
+        let segments: Vec<_> = liba.segments().collect();
+        let loads: Vec<_> = liba
+            .phdrs()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf_loader::abi::PT_LOAD)
+            .collect();
+        let count = loads.len();
+        assert_eq!(segments.len(), count);
+        for (segment, phdr) in segments.iter().zip(loads) {
+            assert_eq!(segment.addr, liba.base() + phdr.p_vaddr as usize);
+            assert_eq!(segment.offset, phdr.p_offset as usize);
+            let pages = segment.page_range();
+            assert!(map.start <= pages.start && pages.end <= map.end);
+        }
+        assert!(
+            segments
+                .iter()
+                .any(|segment| segment.prot.contains(ProtFlags::PROT_EXEC))
+        );
+        let relro = liba.relro_range().unwrap();
+        assert!(segments.iter().any(|segment| {
+            segment.prot.contains(ProtFlags::PROT_WRITE)
+                && segment.addr <= relro.start
+                && relro.end <= segment.addr + segment.memsz
+        }));
+        let a = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        assert_eq!(a.segments().count(), count);
+    }
+
+    #[test]
+    fn unload() {
+        compile();
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap();
+        let another = liba.clone();
+        let liba = liba.try_unload().err().unwrap();
+        assert!(!another.unload());
+        assert!(liba.unload());
+        let libs = [0, 1].map(|_| {
+            load_dylib!(&lib_path("liba.so"))
+                .unwrap()
+                .easy_relocate([].into_iter(), &|_| None)
+                .unwrap()
+        });
+        let kept = libs[1].clone();
+        let remaining = close_all(libs.into());
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].generation(), kept.generation());
+    }
+
+    #[test]
+    fn load_events() {
+        use elf_loader::event::LoadEvent;
+        use std::sync::Mutex;
+        // (generation, 是否为加载事件, 基址, PT_LOAD数量)
+        static EVENTS: Mutex<Vec<(usize, bool, usize, usize)>> = Mutex::new(Vec::new());
+        fn on_load(event: &LoadEvent) {
+            assert_eq!(event.name(), lib_path("liba.so"));
+            let count = event.segments().count();
+            EVENTS
+                .lock()
+                .unwrap()
+                .push((event.generation(), true, event.base(), count));
+        }
+        fn on_unload(event: &LoadEvent) {
+            let count = event.segments().count();
+            EVENTS
+                .lock()
+                .unwrap()
+                .push((event.generation(), false, event.base(), count));
+        }
+        compile();
+        let mut loader = Loader::<MmapImpl>::builder()
+            .on_load(on_load)
+            .on_unload(on_unload)
+            .build();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap();
+        let generation = liba.generation();
+        let events = |generation| {
+            EVENTS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event.0 == generation)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        assert!(events(generation).is_empty());
+        let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let (base, count) = (liba.base(), liba.segments().count());
+        assert_eq!(events(generation), [(generation, true, base, count)]);
+        drop(liba);
+        assert_eq!(
+            events(generation),
+            [
+                (generation, true, base, count),
+                (generation, false, base, count)
+            ]
+        );
+
+        // 未重定位的elf对象不会产生事件
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap();
+        let generation = liba.generation();
+        drop(liba);
+        assert!(events(generation).is_empty());
+
+        // 延迟初始化时,从未初始化的elf对象也会产生卸载事件
+        loader.set_deferred_init(true);
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let generation = liba.generation();
+        assert!(!liba.is_init());
+        drop(liba);
+        let kinds: Vec<_> = events(generation).iter().map(|event| event.1).collect();
+        assert_eq!(kinds, [true, false]);
+    }
+
+    #[test]
+    fn deferred_init() {
+        use elf_loader::init_all;
+        use std::sync::Mutex;
+        compile();
+        let dir = lib_path("");
+        compile_c(
+            "libinit1.so",
+            "void record(int);\n\
+             void forward(int idx) { record(idx); }\n\
+             __attribute__((constructor)) static void init(void) { record(1); }\n",
+            &[],
+        );
+        compile_c(
+            "libinit2.so",
+            "void record(int);\n\
+             __attribute__((constructor)) static void init(void) { record(2); }\n",
+            &["-L", &dir, "-Wl,--no-as-needed", "-l:libinit1.so"],
+        );
+        // 可执行文件的.preinit_array在所有依赖的初始化函数之前执行
+        compile_c(
+            "init_exec",
+            "void forward(int);\n\
+             static void preinit(void) { forward(0); }\n\
+             __attribute__((section(\".preinit_array\"), used))\n\
+             static void (*preinit_fn)(void) = preinit;\n\
+             __attribute__((constructor)) static void init(void) { forward(3); }\n\
+             void _start(void) {}\n",
+            &[
+                "-fPIC",
+                "-nostdlib",
+                "-no-pie",
+                "-L",
+                &dir,
+                "-Wl,--no-as-needed",
+                "-l:libinit2.so",
+                "-l:libinit1.so",
+                "-Wl,--allow-shlib-undefined",
+            ],
+        );
+
+        static ORDER: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+        extern "C" fn record(idx: i32) {
+            ORDER.lock().unwrap().push(idx);
+        }
+        let pre_find = |name: &str| (name == "record").then_some(record as *const ());
+        let mut loader = Loader::<MmapImpl>::builder().deferred_init(true).build();
+        let mut load = |name: &str| {
+            loader
+                .easy_load_dylib(ElfFile::from_path(&lib_path(name)).unwrap())
+                .unwrap()
+        };
+        let init1 = load("libinit1.so")
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let init2 = load("libinit2.so")
+            .easy_relocate([&init1].into_iter(), &pre_find)
+            .unwrap();
+        let exec = loader
+            .easy_load_exec(ElfFile::from_path(&lib_path("init_exec")).unwrap())
+            .unwrap()
+            .easy_relocate([&init2, &init1].into_iter(), &pre_find)
+            .unwrap();
+        // 重定位时不会调用初始化函数
+        assert!(ORDER.lock().unwrap().is_empty());
+        assert!(!init1.is_init() && !exec.is_init());
+        init_all(Some(&exec), &[init2.clone(), init1.clone()]);
+        assert_eq!(*ORDER.lock().unwrap(), [0, 1, 2, 3]);
+        assert!(init1.is_init() && init2.is_init() && exec.is_init());
+        // 初始化函数只会被调用一次
+        init_all(Some(&exec), &[init1.clone(), init2.clone()]);
+        assert_eq!(ORDER.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn corrupted_relocation_fails() {
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let rela_dyn = elf.section_header_by_name(".rela.dyn").unwrap().unwrap();
+        // 将第一项重定位的r_offset改到所有段之外
+        let offset = rela_dyn.sh_offset as usize;
+        bytes[offset..offset + 8].copy_from_slice(&0x7fff_0000_0000u64.to_ne_bytes());
+        let liba = load_dylib!("liba.so", &bytes).unwrap();
+        let err = liba.easy_relocate([].iter(), &|_| None).err().unwrap();
+        assert!(matches!(err, elf_loader::Error::RelocateError { .. }));
+    }
+
+    #[test]
+    fn unresolved_symbols() {
+        use elf_loader::{Error, RelocateErrorKind};
+        compile();
+        fn print(_: &str) {}
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let check = |err: Error| {
+            let mut missing = err.unresolved_symbols();
+            missing.sort_unstable();
+            missing.dedup();
+            assert_eq!(missing, ["HELLO", "a", "print"]);
+            let Error::RelocateError { lib_name, kind } = &err else {
+                unreachable!()
+            };
+            assert_eq!(lib_name, "libb.so");
+            assert!(matches!(kind, RelocateErrorKind::Unresolved(_)));
+            assert!(err.to_string().contains("symbol name: a"));
+        };
+        // 所有无法解析的符号会被一并报告
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        check(libb.easy_relocate([].iter(), &|_| None).err().unwrap());
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let mut relocation = libb
+            .relocate_chunked([].into_iter(), &|_| None, |_, _, _| Err(Box::new(())), None)
+            .unwrap();
+        while relocation.step(1).unwrap() == RelocateStatus::Pending {}
+        check(relocation.finish().err().unwrap());
+        // 补充提供符号的库后重试
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let b = libb.easy_relocate([&liba].into_iter(), &pre_find).unwrap();
+        let f = unsafe { b.get::<fn() -> i32>("b").unwrap() };
+        assert!(f() == 2);
+    }
+
+    #[test]
+    fn unknown_relocation_type() {
+        use elf_loader::{Error, RelocateErrorKind};
+        compile();
+        let mut file = File::open(&lib_path("libb.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let rela_plt = elf.section_header_by_name(".rela.plt").unwrap().unwrap();
+        // 将第一项的重定位类型改为未定义的值,保留符号索引
+        let offset = rela_plt.sh_offset as usize + 8;
+        bytes[offset..offset + 4].copy_from_slice(&0xfeu32.to_ne_bytes());
+        for lazy in [false, true] {
+            let libb = load_dylib!("libb.so", &bytes, lazy: lazy).unwrap();
+            let err = libb.easy_relocate([].iter(), &|_| None).err().unwrap();
+            let Error::RelocateError {
+                kind: RelocateErrorKind::Unresolved(failures),
+                ..
+            } = &err
+            else {
+                unreachable!()
+            };
+            let failure = failures
+                .iter()
+                .find(|failure| failure.r_type == 0xfe)
+                .unwrap();
+            assert!(failure.symbol.is_some());
+            assert!(err.to_string().contains("relocation type: 254"));
+        }
+    }
+
+    #[test]
+    fn partial_relocation() {
+        use elf_loader::UnresolvedSymbol;
+        compile();
+        fn print(_: &str) {}
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let mut relocation = libb.relocate_partial([].iter(), &|_| None, None).unwrap();
+        let names = |unresolved: Vec<UnresolvedSymbol>| {
+            unresolved
+                .into_iter()
+                .map(|symbol| {
+                    assert!(symbol.count > 0);
+                    symbol.name
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(relocation.unresolved()), ["HELLO", "a", "print"]);
+        let remaining = relocation.remaining();
+
+        // 每次只提供一部分符号
+        let left = relocation.resume_with(&pre_find).unwrap();
+        assert!(left < remaining && left == relocation.remaining());
+        assert_eq!(names(relocation.unresolved()), ["HELLO", "a"]);
+        let provider = |name: &str| unsafe { liba.get::<()>(name).map(|sym| sym.into_raw()) };
+        assert_eq!(relocation.resume_with(&provider).unwrap(), 0);
+        assert!(relocation.unresolved().is_empty());
+        let libb = relocation.finish().unwrap();
+        let f = unsafe { libb.get::<fn() -> i32>("b").unwrap() };
+        assert_eq!(f(), 2);
+
+        // 仍有未解析的项时无法完成重定位
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let mut relocation = libb.relocate_partial([].iter(), &pre_find, None).unwrap();
+        let left = relocation.resume_with(&|_| None).unwrap();
+        assert_eq!(left, relocation.remaining());
+        let err = relocation.finish().err().unwrap();
+        let mut unresolved = err.unresolved_symbols();
+        unresolved.sort();
+        unresolved.dedup();
+        assert_eq!(unresolved, ["HELLO", "a"]);
+
+        // 处理失败的项仍然是未完成的
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let mut relocation = libb.relocate_partial([].iter(), &pre_find, None).unwrap();
+        let remaining = relocation.remaining();
+        let failed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            relocation.resume_with(&|name| {
+                assert_ne!(name, "a", "provider failed");
+                None
+            })
+        }));
+        assert!(failed.is_err());
+        assert_eq!(relocation.remaining(), remaining);
+        assert_eq!(names(relocation.unresolved()), ["HELLO", "a"]);
+        let err = relocation.finish().err().unwrap();
+        assert!(err.unresolved_symbols().contains(&"a"));
+    }
+
+    #[test]
+    fn ifunc_resolution() {
+        compile();
+        fn print(_: &str) {}
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        // IFUNC的解析函数依赖其他重定位的结果
+        for lazy in [false, true] {
+            let lib = load_dylib!(&lib_path("libifunc.so"), lazy: lazy)
+                .unwrap()
+                .easy_relocate([&liba].into_iter(), &pre_find)
+                .unwrap();
+            let f = unsafe { lib.get::<fn() -> i32>("ifunc").unwrap() };
+            assert_eq!(f(), 42);
+        }
+        let lib = load_dylib!(&lib_path("libifunc.so"), lazy: false).unwrap();
+        let mut relocation = lib
+            .relocate_chunked(
+                [&liba].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                None,
+            )
+            .unwrap();
+        while relocation.step(2).unwrap() == RelocateStatus::Pending {}
+        let lib = relocation.finish().unwrap();
+        let f = unsafe { lib.get::<fn() -> i32>("ifunc").unwrap() };
+        assert_eq!(f(), 42);
+    }
+
+    #[test]
+    fn relocation_cache() {
+        use elf_loader::prelink::RelocationCache;
+        compile();
+        fn print(_: &str) {}
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let liba = load_dylib!(&lib_path("liba.so"), lazy: false).unwrap();
+        let (liba, cache_a) = liba.relocate_recorded([].iter(), &pre_find).unwrap();
+        let libb = load_dylib!(&lib_path("libb.so"), lazy: false).unwrap();
+        let (libb, cache_b) = libb
+            .relocate_recorded([&liba].into_iter(), &pre_find)
+            .unwrap();
+        assert!(
+            cache_b
+                .symbols()
+                .iter()
+                .any(|sym| sym.name() == "a" && sym.lib() == Some("liba.so"))
+        );
+        let bytes = cache_b.to_bytes();
+        let cache_b = RelocationCache::from_bytes(&bytes).unwrap();
+        assert!(RelocationCache::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        // 在新的基址上重放缓存
+        let new_a = load_dylib!(&lib_path("liba.so"), lazy: false)
+            .unwrap()
+            .relocate_cached(&cache_a, [].iter(), &pre_find)
+            .unwrap();
+        let new_b = load_dylib!(&lib_path("libb.so"), lazy: false)
+            .unwrap()
+            .relocate_cached(&cache_b, [&new_a].into_iter(), &pre_find)
+            .unwrap();
+        assert_ne!(new_b.base(), libb.base());
+        let f = unsafe { new_b.get::<fn() -> i32>("b").unwrap() };
+        assert_eq!(f(), 2);
+        // IFUNC的解析函数会被重新调用
+        let (_, cache) = load_dylib!(&lib_path("libifunc.so"), lazy: false)
+            .unwrap()
+            .relocate_recorded([&liba].into_iter(), &pre_find)
+            .unwrap();
+        let lib = load_dylib!(&lib_path("libifunc.so"), lazy: false)
+            .unwrap()
+            .relocate_cached(&cache, [&new_a].into_iter(), &pre_find)
+            .unwrap();
+        let f = unsafe { lib.get::<fn() -> i32>("ifunc").unwrap() };
+        assert_eq!(f(), 42);
+        // 不同的文件不能使用同一个缓存
+        let libc = load_dylib!(&lib_path("libc.so"), lazy: false).unwrap();
+        assert!(
+            libc.relocate_cached(&cache_b, [&new_b].into_iter(), &pre_find)
+                .is_err()
+        );
+        let lazy = load_dylib!(&lazy_lib(), lazy: true).unwrap();
+        assert!(lazy.relocate_recorded([].iter(), &pre_find).is_err());
+        // 依赖的库改变后缓存的符号偏移不再有效
+        assert!(cache_b.dependency_key("liba.so").is_some());
+        std::fs::create_dir_all(lib_path("changed")).unwrap();
+        let path = compile_c(
+            "changed/liba.so",
+            "static int pad[64] = {1};\nint a(void) { return pad[0]; }\n",
+            &[],
+        );
+        let changed_a = load_dylib!(&path, lazy: false)
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let err = load_dylib!(&lib_path("libb.so"), lazy: false)
+            .unwrap()
+            .relocate_cached(&cache_b, [&changed_a].into_iter(), &pre_find)
+            .unwrap_err();
+        assert!(err.to_string().contains("another liba.so"), "{err}");
+    }
+
+    #[test]
+    fn gnu_property() {
+        use core::ffi::c_void;
+        use core::ptr::NonNull;
+        use elf_loader::property::GnuProperty;
+        use std::sync::Mutex;
+        compile();
+        let flag = match consts::ARCH {
+            "x86_64" => "-fcf-protection=full",
+            "aarch64" => "-mbranch-protection=standard",
+            _ => return,
+        };
+        // 不链接crt文件,使所有输入都带有gnu property
+        let path = compile_c("libprop.so", "int prop(void) { return 7; }\n", &[flag]);
+
+        static SEEN: Mutex<Option<(GnuProperty, ProtFlags)>> = Mutex::new(None);
+        struct PropMmap;
+        impl Mmap for PropMmap {
+            unsafe fn mmap(
+                addr: Option<usize>,
+                len: usize,
+                prot: ProtFlags,
+                flags: MapFlags,
+                offset: usize,
+                fd: Option<i32>,
+                need_copy: &mut bool,
+            ) -> elf_loader::Result<NonNull<c_void>> {
+                unsafe { MmapImpl::mmap(addr, len, prot, flags, offset, fd, need_copy) }
+            }
+
+            unsafe fn mmap_anonymous(
+                addr: usize,
+                len: usize,
+                prot: ProtFlags,
+                flags: MapFlags,
+            ) -> elf_loader::Result<NonNull<c_void>> {
+                unsafe { MmapImpl::mmap_anonymous(addr, len, prot, flags) }
+            }
+
+            unsafe fn munmap(addr: NonNull<c_void>, len: usize) -> elf_loader::Result<()> {
+                unsafe { MmapImpl::munmap(addr, len) }
+            }
+
+            unsafe fn mprotect(
+                addr: NonNull<c_void>,
+                len: usize,
+                prot: ProtFlags,
+            ) -> elf_loader::Result<()> {
+                unsafe { MmapImpl::mprotect(addr, len, prot) }
+            }
+
+            fn exec_prot(property: &GnuProperty, hint: ProtFlags) -> ProtFlags {
+                *SEEN.lock().unwrap() = Some((*property, hint));
+                // 可执行segment本来就可读,只用来检查额外的权限会被加上
+                ProtFlags::PROT_READ
+            }
+        }
+
+        let lib = Loader::<PropMmap>::new()
+            .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+            .unwrap();
+        let property = lib.gnu_property().unwrap();
+        let (seen, hint) = SEEN.lock().unwrap().unwrap();
+        assert_eq!(seen, property);
+        if consts::ARCH == "x86_64" {
+            assert!(property.ibt() && property.shstk());
+            assert_eq!(hint, ProtFlags::PROT_NONE);
+        } else {
+            assert!(property.bti());
+            assert_eq!(hint, ProtFlags::PROT_BTI);
+        }
+        let lib = lib.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { lib.get::<extern "C" fn() -> i32>("prop").unwrap() };
+        assert_eq!(f(), 7);
+        // 没有PT_GNU_PROPERTY的库
+        let liba = load_dylib!(&lib_path("liba.so")).unwrap();
+        assert!(liba.gnu_property().is_none());
+    }
+
+    #[test]
+    fn huge_bss() {
+        use core::ffi::c_void;
+        use core::ptr::NonNull;
+        use std::sync::Mutex;
+        compile();
+        let path = compile_c(
+            "libbss.so",
+            "static int data = 5;\nstatic char big[64 << 20];\n\
+             int get_data(void) { return data; }\n\
+             char *get(void) { return big; }\n",
+            &[],
+        );
+
+        static FILE_MAPS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        static ADVISED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        struct HugeMmap;
+        impl Mmap for HugeMmap {
+            unsafe fn mmap(
+                addr: Option<usize>,
+                len: usize,
+                prot: ProtFlags,
+                flags: MapFlags,
+                offset: usize,
+                fd: Option<i32>,
+                need_copy: &mut bool,
+            ) -> elf_loader::Result<NonNull<c_void>> {
+                if flags.contains(MapFlags::MAP_FIXED) {
+                    FILE_MAPS.lock().unwrap().push(len);
+                }
+                unsafe { MmapImpl::mmap(addr, len, prot, flags, offset, fd, need_copy) }
+            }
+
+            unsafe fn mmap_anonymous(
+                addr: usize,
+                len: usize,
+                prot: ProtFlags,
+                flags: MapFlags,
+            ) -> elf_loader::Result<NonNull<c_void>> {
+                unsafe { MmapImpl::mmap_anonymous(addr, len, prot, flags) }
+            }
+
+            unsafe fn munmap(addr: NonNull<c_void>, len: usize) -> elf_loader::Result<()> {
+                unsafe { MmapImpl::munmap(addr, len) }
+            }
+
+            unsafe fn mprotect(
+                addr: NonNull<c_void>,
+                len: usize,
+                prot: ProtFlags,
+            ) -> elf_loader::Result<()> {
+                unsafe { MmapImpl::mprotect(addr, len, prot) }
+            }
+
+            unsafe fn advise_huge(addr: NonNull<c_void>, len: usize) -> elf_loader::Result<()> {
+                ADVISED.lock().unwrap().push(len);
+                unsafe { MmapImpl::advise_huge(addr, len) }
+            }
+        }
+
+        let lib = Loader::<HugeMmap>::builder()
+            .huge_bss_threshold(2 << 20)
+            .build()
+            .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        // bss只映射为匿名页,不会从文件映射
+        assert!(FILE_MAPS.lock().unwrap().iter().all(|&len| len < 1 << 20));
+        let advised = ADVISED.lock().unwrap();
+        assert_eq!(advised.len(), 1);
+        assert!(advised[0] >= 63 << 20);
+        let get_data = unsafe { lib.get::<extern "C" fn() -> i32>("get_data").unwrap() };
+        assert_eq!(get_data(), 5);
+        let get = unsafe { lib.get::<extern "C" fn() -> *mut u8>("get").unwrap() };
+        let big = unsafe { std::slice::from_raw_parts_mut(get(), 64 << 20) };
+        assert!(big[0] == 0 && big[big.len() - 1] == 0);
+        big[big.len() - 1] = 1;
+    }
+
+    #[test]
+    fn dynamic_flags() {
+        use elf_loader::{
+            namespace::Namespace,
+            scope::Visibility,
+            search::{FsResolver, SearchConfig},
+        };
+        compile();
+        compile_c(
+            "libflags.so",
+            "int a(void);\nint flags(void) { return a() + 2; }\n",
+            &["-Wl,-z,now", "-Wl,-z,nodelete"],
+        );
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let lib = load_dylib!(&lib_path("libflags.so"), lazy: true).unwrap();
+        assert!(lib.flags().bind_now() && lib.flags().nodelete() && !lib.flags().noopen());
+        // DF_BIND_NOW overrides the lazy binding requested by the user
+        assert!(!lib.is_lazy());
+        let lib = lib.easy_relocate([&liba].into_iter(), &|_| None).unwrap();
+        let f = unsafe { lib.get::<extern "C" fn() -> i32>("flags").unwrap() };
+        assert_eq!(f(), 3);
+        assert!(lib.try_unload().is_err());
+
+        // DF_1_NOOPEN的库只能作为依赖被加载
+        let dir = lib_path("");
+        compile_c(
+            "libnoopen.so",
+            "int noopen(void) { return 4; }\n",
+            &["-Wl,-z,nodlopen"],
+        );
+        compile_c(
+            "libnoopen_user.so",
+            "int noopen(void);\nint noopen_user(void) { return noopen() + 1; }\n",
+            &["-L", &dir, "-Wl,--no-as-needed", "-l:libnoopen.so"],
+        );
+        let lib = load_dylib!(&lib_path("libnoopen.so")).unwrap();
+        assert!(lib.flags().noopen());
+        let mut resolver = FsResolver::new(SearchConfig::new().library_path(&dir));
+        let mut namespace = Namespace::new(Loader::<MmapImpl>::new());
+        let open = |namespace: &mut Namespace<MmapImpl>, resolver: &mut FsResolver, name: &str| {
+            namespace.open(
+                ElfFile::from_path(&lib_path(name)).unwrap(),
+                Visibility::Local,
+                resolver,
+            )
+        };
+        assert!(open(&mut namespace, &mut resolver, "libnoopen.so").is_err());
+        assert!(namespace.libs().is_empty());
+        let user = open(&mut namespace, &mut resolver, "libnoopen_user.so").unwrap();
+        let f = unsafe { user.get::<extern "C" fn() -> i32>("noopen_user").unwrap() };
+        assert_eq!(f(), 5);
+        assert!(namespace.get("libnoopen.so").is_some());
+        assert!(open(&mut namespace, &mut resolver, "libnoopen.so").is_err());
+    }
+
+    #[test]
+    fn dynamic_table() {
+        use elf::abi::{DT_NEEDED, DT_NULL, DT_SONAME, DT_STRTAB};
+        use elf_loader::scope::Scope;
+        compile();
+        let dir = lib_path("");
+        // 文件名与soname不同
+        compile_c(
+            "libsoname_real.so",
+            "int soname(void) { return 1; }\n",
+            &["-Wl,-soname,libsoname.so.1"],
+        );
+        compile_c(
+            "libsoname_user.so",
+            "int soname(void);\nint user(void) { return soname() + 1; }\n",
+            &["-L", &dir, "-Wl,--no-as-needed", "-l:libsoname_real.so"],
+        );
+        let lib = load_dylib!(&lib_path("libsoname_real.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        assert_eq!(lib.shortname(), "libsoname_real.so");
+        assert_eq!(lib.soname(), Some("libsoname.so.1"));
+        let table = lib.dynamic_table().unwrap();
+        assert!(table.get(DT_SONAME).is_some() && table.contains(DT_STRTAB));
+        assert!(table.iter().all(|(tag, _)| tag != DT_NULL));
+        assert_eq!(table.iter().count(), table.entries().len());
+
+        let user = load_dylib!(&lib_path("libsoname_user.so")).unwrap();
+        assert_eq!(user.soname(), None);
+        assert_eq!(user.needed_libs(), ["libsoname.so.1"]);
+        assert_eq!(user.dynamic_table().unwrap().get_all(DT_NEEDED).count(), 1);
+        // 依赖按soname找到
+        let local = Scope::local(&user, [&lib].into_iter());
+        let user = user.easy_relocate(local.iter(), &|_| None).unwrap();
+        let f = unsafe { user.get::<extern "C" fn() -> i32>("user").unwrap() };
+        assert_eq!(f(), 2);
+    }
+
+    #[test]
+    fn verify_image() {
+        use elf::abi::PT_LOAD;
+        use elf_loader::verify::Image;
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let note = elf
+            .section_header_by_name(".note.gnu.build-id")
+            .unwrap()
+            .unwrap();
+        let build_id =
+            bytes[note.sh_offset as usize + 16..(note.sh_offset + note.sh_size) as usize].to_vec();
+        // 用文件中PT_LOAD的内容计算摘要
+        fn digest<'a>(segments: impl Iterator<Item = &'a [u8]>) -> u64 {
+            segments.flatten().fold(0xcbf29ce484222325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+        }
+        let expected = digest(elf.segments().unwrap().iter().filter_map(|phdr| {
+            (phdr.p_type == PT_LOAD)
+                .then(|| &bytes[phdr.p_offset as usize..(phdr.p_offset + phdr.p_filesz) as usize])
+        }));
+        // 校验时代码段还不可执行
+        fn executable(addr: usize) -> bool {
+            std::fs::read_to_string("/proc/self/maps")
+                .unwrap()
+                .lines()
+                .any(|line| {
+                    let (range, perms) = line.split_once(' ').unwrap();
+                    let (start, end) = range.split_once('-').unwrap();
+                    let start = usize::from_str_radix(start, 16).unwrap();
+                    let end = usize::from_str_radix(end, 16).unwrap();
+                    start <= addr && addr < end && perms.as_bytes()[2] == b'x'
+                })
+        }
+        let load = |expected: u64| {
+            let build_id = build_id.clone();
+            let mut loader = Loader::<MmapImpl>::builder()
+                .verifier(Box::new(move |image: &Image| {
+                    assert_eq!(image.build_id(), Some(&build_id[..]));
+                    let (phdr, code) = image
+                        .segments()
+                        .find(|(phdr, _)| phdr.p_flags & elf::abi::PF_X != 0)
+                        .unwrap();
+                    assert!(phdr.p_filesz > 0 && !executable(code.as_ptr() as usize));
+                    if digest(image.segments().map(|(_, bytes)| bytes)) == expected {
+                        Ok(())
+                    } else {
+                        Err(Box::new("digest mismatch"))
+                    }
+                }))
+                .build();
+            loader.easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+        };
+        let liba = load(expected).unwrap();
+        assert_eq!(liba.build_id(), Some(&build_id[..]));
+        let liba = liba.easy_relocate([].iter(), &|_| None).unwrap();
+        let f = unsafe { liba.get::<fn() -> i32>("a").unwrap() };
+        assert!(f() == 1);
+        let err = load(expected + 1).err().unwrap();
+        assert!(
+            matches!(err, elf_loader::Error::VerifyError { lib_name, custom_err }
+                if lib_name.ends_with("liba.so") && custom_err.downcast_ref::<&str>().is_some())
+        );
+    }
+
+    #[test]
+    fn exec_stack_policy() {
+        use elf::abi::{PF_X, PT_GNU_STACK};
+        use elf_loader::policy::ExecStackPolicy;
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let load = |bytes: &[u8], policy| {
+            let mut loader = Loader::<MmapImpl>::builder()
+                .exec_stack_policy(policy)
+                .build();
+            loader.easy_load_dylib(ElfBinary::new("liba.so", bytes))
+        };
+        let liba = load(&bytes, ExecStackPolicy::Deny).unwrap();
+        assert!(!liba.exec_stack());
+        assert!(!liba.stack_prot().unwrap().contains(ProtFlags::PROT_EXEC));
+
+        // 给PT_GNU_STACK加上PF_X
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let idx = elf
+            .segments()
+            .unwrap()
+            .iter()
+            .position(|phdr| phdr.p_type == PT_GNU_STACK)
+            .unwrap();
+        let offset = elf.ehdr.e_phoff as usize + idx * elf.ehdr.e_phentsize as usize + 4;
+        let flags = u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap()) | PF_X;
+        bytes[offset..offset + 4].copy_from_slice(&flags.to_ne_bytes());
+        let liba = load(&bytes, ExecStackPolicy::Allow).unwrap();
+        assert!(liba.exec_stack());
+        let err = load(&bytes, ExecStackPolicy::Deny).err().unwrap();
+        assert!(
+            matches!(err, elf_loader::Error::ExecStackDenied { lib_name } if lib_name == "liba.so")
+        );
+        let callback = ExecStackPolicy::Callback(Box::new(|name| name == "liba.so"));
+        assert!(load(&bytes, callback).is_ok());
+        let callback = ExecStackPolicy::Callback(Box::new(|_| false));
+        assert!(load(&bytes, callback).is_err());
+    }
+
+    #[test]
+    fn non_utf8_name() {
+        use elf::abi::{PF_X, PT_GNU_STACK};
+        use elf_loader::{
+            event::LoadEvent,
+            object::ElfObject,
+            policy::{ExecStackPolicy, SonamePolicy},
+            verify::Image,
+        };
+        use std::{
+            ffi::{CStr, CString},
+            sync::atomic::{AtomicBool, Ordering},
+        };
+        // 名字不是utf-8的elf对象
+        struct RawName<'a>(CString, ElfBinary<'a>);
+        impl ElfObject for RawName<'_> {
+            fn file_name(&self) -> &CStr {
+                &self.0
+            }
+            fn read(&mut self, buf: &mut [u8], offset: usize) -> elf_loader::Result<()> {
+                self.1.read(buf, offset)
+            }
+            fn size(&self) -> Option<usize> {
+                self.1.size()
+            }
+            fn as_fd(&self) -> Option<i32> {
+                None
+            }
+        }
+        fn object(bytes: &[u8]) -> RawName<'_> {
+            RawName(
+                CString::new(b"lib\xffb.so".to_vec()).unwrap(),
+                ElfBinary::new("libb.so", bytes),
+            )
+        }
+        const LOSSY_NAME: &str = "lib\u{fffd}b.so";
+        let [liba, libb, _] = needed_libs();
+        let mut bytes = std::fs::read(libb).unwrap();
+        let err = Loader::<MmapImpl>::builder()
+            .soname_policy(SonamePolicy::new().strict(true))
+            .build()
+            .easy_load_dylib(object(&bytes))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, elf_loader::Error::DependencyError { lib_name, .. } if lib_name == LOSSY_NAME)
+        );
+
+        // 给PT_GNU_STACK加上PF_X
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&bytes).unwrap();
+        let idx = elf
+            .segments()
+            .unwrap()
+            .iter()
+            .position(|phdr| phdr.p_type == PT_GNU_STACK)
+            .unwrap();
+        let offset = elf.ehdr.e_phoff as usize + idx * elf.ehdr.e_phentsize as usize + 4;
+        let flags = u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap()) | PF_X;
+        bytes[offset..offset + 4].copy_from_slice(&flags.to_ne_bytes());
+        let err = Loader::<MmapImpl>::builder()
+            .exec_stack_policy(ExecStackPolicy::Deny)
+            .build()
+            .easy_load_dylib(object(&bytes))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, elf_loader::Error::ExecStackDenied { lib_name } if lib_name == LOSSY_NAME)
+        );
+        let libb = Loader::<MmapImpl>::new()
+            .easy_load_dylib(object(&bytes))
+            .unwrap();
+        assert_eq!(libb.cname().to_bytes(), b"lib\xffb.so");
+        let err = Loader::<MmapImpl>::builder()
+            .verifier(Box::new(|image: &Image| {
+                assert_eq!(image.name(), LOSSY_NAME);
+                assert_eq!(image.cname().to_bytes(), b"lib\xffb.so");
+                Err(Box::new(()))
+            }))
+            .build()
+            .easy_load_dylib(object(&bytes))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, elf_loader::Error::VerifyError { lib_name, .. } if lib_name == LOSSY_NAME)
+        );
+
+        static LOADED: AtomicBool = AtomicBool::new(false);
+        fn on_load(event: &LoadEvent) {
+            assert_eq!(event.name(), LOSSY_NAME);
+            assert_eq!(event.cname().to_bytes(), b"lib\xffb.so");
+            LOADED.store(true, Ordering::Relaxed);
+        }
+        let liba = load_dylib!(&liba)
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let libb = Loader::<MmapImpl>::builder()
+            .on_load(on_load)
+            .build()
+            .easy_load_dylib(object(&bytes))
+            .unwrap()
+            .easy_relocate([&liba].into_iter(), &|_| None)
+            .unwrap();
+        assert!(LOADED.load(Ordering::Relaxed));
+        let f = unsafe { libb.get::<extern "C" fn() -> i32>("b").unwrap() };
+        assert!(f() == 2);
+    }
+
+    #[test]
+    fn text_relocation() {
+        use elf_loader::policy::TextRelPolicy;
+        compile();
+        if consts::ARCH != "x86_64" {
+            return;
+        }
+        // 代码段中的指针需要重定位
+        let path = compile_c(
+            "libtextrel.so",
+            "__attribute__((visibility(\"hidden\"))) int value = 42;\n\
+             extern int *table __attribute__((visibility(\"hidden\")));\n\
+             __asm__(\".text\\n.hidden table\\ntable: .quad value\\n\");\n\
+             int get(void) { return *table; }\n",
+            &["-Wl,-z,notext"],
+        );
+        let load = |policy| {
+            Loader::<MmapImpl>::builder()
+                .textrel_policy(policy)
+                .build()
+                .easy_load_dylib(ElfFile::from_path(&path).unwrap())
+        };
+        let err = load(TextRelPolicy::Deny).err().unwrap();
+        assert!(matches!(err, elf_loader::Error::TextRelDenied { .. }));
+
+        let lib = load(TextRelPolicy::Allow).unwrap();
+        assert!(lib.textrel());
+        let lib = lib.easy_relocate([].iter(), &|_| None).unwrap();
+        let get = unsafe { lib.get::<extern "C" fn() -> i32>("get").unwrap() };
+        assert_eq!(get(), 42);
+        // 重定位之后代码段恢复为只读
+        let addr = get.into_raw() as usize;
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let perms = maps
+            .lines()
+            .find_map(|line| {
+                let mut fields = line.split(' ');
+                let (start, end) = fields.next()?.split_once('-')?;
+                let start = usize::from_str_radix(start, 16).ok()?;
+                let end = usize::from_str_radix(end, 16).ok()?;
+                (start..end).contains(&addr).then(|| fields.next().unwrap())
+            })
+            .unwrap();
+        assert_eq!(&perms[..3], "r-x");
+    }
+
+    #[test]
+    fn invalid_elf_rejected() {
+        use elf::abi::{EM_AARCH64, EM_X86_64, PT_DYNAMIC, PT_LOAD};
+        use elf_loader::Error;
+        compile();
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut origin = Vec::new();
+        file.read_to_end(&mut origin).unwrap();
+        let elf = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&origin).unwrap();
+        let phoff = elf.ehdr.e_phoff as usize;
+        let phentsize = elf.ehdr.e_phentsize as usize;
+        let phdrs: Vec<_> = elf.segments().unwrap().iter().collect();
+        let loads: Vec<_> = (0..phdrs.len())
+            .filter(|&i| phdrs[i].p_type == PT_LOAD)
+            .collect();
+        let dynamic = (0..phdrs.len())
+            .find(|&i| phdrs[i].p_type == PT_DYNAMIC)
+            .unwrap();
+        let write = |bytes: &mut Vec<u8>, idx: usize, field: usize, val: u64| {
+            let offset = phoff + idx * phentsize + field;
+            bytes[offset..offset + 8].copy_from_slice(&val.to_ne_bytes());
+        };
+
+        // 修改e_machine
+        let mut bytes = origin.clone();
+        let machine = if cfg!(target_arch = "x86_64") {
+            EM_AARCH64
+        } else {
+            EM_X86_64
+        };
+        bytes[18..20].copy_from_slice(&machine.to_ne_bytes());
+        let err = load_dylib!("liba.so", &bytes).err().unwrap();
+        assert!(matches!(err, Error::ArchMismatch { found, .. } if found == machine));
+
+        // 让第二个PT_LOAD与第一个重叠
+        let mut bytes = origin.clone();
+        write(&mut bytes, loads[1], 8, phdrs[loads[0]].p_offset);
+        write(&mut bytes, loads[1], 16, phdrs[loads[0]].p_vaddr);
+        let err = load_dylib!("liba.so", &bytes).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::SegmentOverlap { first, second } if first == loads[0] && second == loads[1]
+        ));
+
+        // 将.dynamic移到所有段之外
+        let mut bytes = origin.clone();
+        write(&mut bytes, dynamic, 16, 0x7fff_0000_0000);
+        let err = load_dylib!("liba.so", &bytes).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::DynamicOutsideLoad {
+                vaddr: 0x7fff_0000_0000,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "dl-iterate-phdr")]
+    #[test]
+    fn iterate_phdr() {
+        use elf_loader::registry::{DlPhdrInfo, iterate_phdr};
+        use std::ffi::{c_int, c_void};
+        compile();
+        unsafe extern "C" fn find(info: *mut DlPhdrInfo, _size: usize, data: *mut c_void) -> c_int {
+            unsafe { ((*info).dlpi_addr == *(data as *const usize)) as c_int }
+        }
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap();
+        let mut base = liba.base();
+        let data = &mut base as *mut usize as *mut c_void;
+        assert_eq!(unsafe { iterate_phdr(find, data) }, 1);
+        #[cfg(feature = "use-libc")]
+        assert_eq!(
+            unsafe { elf_loader::registry::dl_iterate_phdr(find, data) },
+            1
+        );
+        // 导出的符号替换了libc中的dl_iterate_phdr
+        #[cfg(feature = "export-dl-iterate-phdr")]
+        {
+            unsafe extern "C" {
+                fn dl_iterate_phdr(
+                    callback: elf_loader::registry::DlIterateCallback,
+                    data: *mut c_void,
+                ) -> c_int;
+            }
+            assert_eq!(unsafe { dl_iterate_phdr(find, data) }, 1);
+        }
+        drop(liba);
+        assert_eq!(unsafe { iterate_phdr(find, data) }, 0);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn load_stats() {
+        use std::{
+            sync::atomic::{AtomicU64, Ordering},
+            time::Duration,
+        };
+        compile();
+        fn print(_: &str) {}
+        // 每次读取时钟前进1微秒
+        fn clock() -> Duration {
+            static TICKS: AtomicU64 = AtomicU64::new(0);
+            Duration::from_micros(TICKS.fetch_add(1, Ordering::Relaxed))
+        }
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let mut loader = Loader::<MmapImpl>::builder().clock(clock).build();
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap();
+        let stats = liba.stats().unwrap();
+        assert!(stats.reads >= 1 && stats.read_bytes >= 64);
+        assert!(stats.read_time > Duration::ZERO);
+        assert!(stats.mmaps >= 1 && stats.mmap_time > Duration::ZERO);
+        assert_eq!(stats.lookups, 0);
+        let liba = liba.easy_relocate([].iter(), &pre_find).unwrap();
+        let file = ElfFile::from_path(&lib_path("libb.so")).unwrap();
+        let libb = loader.load_dylib(file, Some(false)).unwrap();
+        let libb = libb.easy_relocate([&liba].into_iter(), &pre_find).unwrap();
+        let stats = libb.stats().unwrap();
+        assert!(stats.lookups > 0 && stats.lookup_time > Duration::ZERO);
+        assert!(stats.relocate_time >= stats.lookup_time);
+        let total: usize = stats.relocations.iter().map(|(_, count)| count).sum();
+        assert!(total > 0);
+        assert!(stats.relocations.is_sorted_by_key(|(r_type, _)| *r_type));
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn debug_link_map() {
+        use elf_loader::debug::r_debug;
+        use std::ffi::CStr;
+        compile();
+        let contains = |name: &CStr| unsafe {
+            let mut cur = (*r_debug()).r_map;
+            while !cur.is_null() {
+                if CStr::from_ptr((*cur).l_name) == name {
+                    return true;
+                }
+                cur = (*cur).l_next;
+            }
+            false
+        };
+        let mut file = File::open(&lib_path("liba.so")).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let liba = load_dylib!("debug_link_map.so", &bytes)
+            .unwrap()
+            .easy_relocate([].into_iter(), &|_| None)
+            .unwrap();
+        assert!(contains(c"debug_link_map.so"));
+        drop(liba);
+        assert!(!contains(c"debug_link_map.so"));
+        // 调试器可以通过符号找到r_debug,并且不会与ld.so的符号冲突
+        let exe = std::env::current_exe().unwrap();
+        let output = std::process::Command::new("nm").arg(&exe).output().unwrap();
+        let symbols = String::from_utf8_lossy(&output.stdout);
+        let address = |name: &str| {
+            symbols
+                .lines()
+                .find(|line| line.ends_with(&format!(" {name}")))
+                .map(|line| usize::from_str_radix(line.split(' ').next().unwrap(), 16).unwrap())
+        };
+        let r_debug_addr = address("elf_loader_r_debug").unwrap();
+        let brk_addr = address("elf_loader_debug_state").unwrap();
+        assert!(address("_dl_debug_state").is_none());
+        let bias = r_debug() as usize - r_debug_addr;
+        assert_eq!(unsafe { (*r_debug()).r_brk } as usize - bias, brk_addr);
+    }
+
+    #[test]
+    fn binding_report() {
+        compile();
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let mut loader = Loader::<MmapImpl>::new();
+        loader.set_binding_report(true);
+        let load = |loader: &mut Loader<MmapImpl>, lazy| {
+            loader
+                .load_dylib(ElfFile::from_path(&lazy_lib()).unwrap(), Some(lazy))
+                .unwrap()
+        };
+        let lib = load(&mut loader, true)
+            .relocate(
+                [&liba].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                Some(Box::new(|name| unsafe {
+                    liba.get::<()>(name).map(|sym| sym.into_raw())
+                })),
+            )
+            .unwrap();
+        let f = unsafe { lib.get::<extern "C" fn() -> i32>("call_a").unwrap() };
+        assert!(f() == 2);
+        assert!(lib.binding_report().unwrap().is_empty());
+
+        // 延迟绑定的作用域中的a与重定位时解析到的不同
+        extern "C" fn fake_a() -> i32 {
+            41
+        }
+        let lib = load(&mut loader, true)
+            .relocate(
+                [&liba].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                Some(Box::new(|name| {
+                    (name == "a").then_some(fake_a as *const ())
+                })),
+            )
+            .unwrap();
+        assert!(lib.binding_report().unwrap().is_empty());
+        let f = unsafe { lib.get::<extern "C" fn() -> i32>("call_a").unwrap() };
+        assert!(f() == 42);
+        let mismatches = lib.binding_report().unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].name, "a");
+        assert_eq!(
+            mismatches[0].eager,
+            Some(unsafe { liba.get::<()>("a").unwrap().into_raw() })
+        );
+        assert_eq!(mismatches[0].lazy, fake_a as *const ());
+
+        // 只有延迟绑定时才会记录
+        let lib = load(&mut loader, false)
+            .easy_relocate([&liba].into_iter(), &pre_find)
+            .unwrap();
+        assert!(lib.binding_report().is_none());
+    }
+
+    #[test]
+    fn chunked_relocation() {
+        compile();
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        let mut map = HashMap::new();
+        map.insert("print", print as _);
+        let pre_find = |name: &str| -> Option<*const ()> { map.get(name).copied() };
+        let liba = load_dylib!(&lib_path("liba.so"))
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        let libb = load_dylib!(&lib_path("libb.so")).unwrap();
+        let mut relocation = libb
+            .relocate_chunked(
+                [&liba].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                None,
+            )
+            .unwrap();
+        let total = relocation.remaining();
+        let mut steps = 0;
+        while relocation.step(2).unwrap() == RelocateStatus::Pending {
+            steps += 1;
+            assert_eq!(relocation.remaining(), total - 2 * steps);
+        }
+        assert_eq!(relocation.remaining(), 0);
+        assert_eq!(steps, total.div_ceil(2) - 1);
+        let b = relocation.finish().unwrap();
+        let f = unsafe { b.get::<fn() -> i32>("b").unwrap() };
+        assert!(f() == 2);
+    }
+
+    #[test]
+    fn many_relative_relocations() {
+        compile();
+        // REL_RELATIVE的数量超过并行处理的阈值0x10000
+        const COUNT: usize = 0x10000 + 0x800;
+        let path = compile_c(
+            "librelative.so",
+            &format!(
+                "static int values[4];\nint *ptrs[{COUNT}] = {{ [0 ... {}] = &values[1] }};\n",
+                COUNT - 1
+            ),
+            &[],
+        );
+        let check = |lib: &elf_loader::RelocatedDylib| {
+            let ptrs = unsafe { lib.get::<()>("ptrs").unwrap().into_raw() };
+            let ptrs = unsafe { &*(ptrs as *const [*const i32; COUNT]) };
+            assert!(ptrs.iter().all(|&ptr| ptr == ptrs[0]));
+            assert!(lib.map_range().contains(&(ptrs[0] as usize)));
+        };
+        let lib = load_dylib!(&path)
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        check(&lib);
+        // 分块重定位时每一步处理的数量也可以超过阈值
+        let lib = load_dylib!(&path).unwrap();
+        let mut relocation = lib
+            .relocate_chunked([].iter(), &|_| None, |_, _, _| Err(Box::new(())), None)
+            .unwrap();
+        while relocation.step(0x10000 + 0x400).unwrap() == RelocateStatus::Pending {}
+        check(&relocation.finish().unwrap());
+    }
+
+    #[test]
+    fn arena() {
+        use elf_loader::arena::BumpArena;
+        compile();
+        fn print(s: &str) {
+            println!("{}", s);
+        }
+        let pre_find = |name: &str| (name == "print").then_some(print as *const ());
+        let buf = Box::leak(vec![0u8; 4096].into_boxed_slice());
+        let range = buf.as_ptr_range();
+        let arena: &'static BumpArena = Box::leak(Box::new(BumpArena::new(buf)));
+        let mut loader = Loader::<MmapImpl>::builder().arena(arena).build();
+        let mut object = ElfFile::from_path(&lib_path("libb.so")).unwrap();
+        let ehdr = loader.read_ehdr(&mut object).unwrap();
+        let phdrs = loader.read_phdr(&mut object, &ehdr).unwrap();
+        assert!(range.contains(&phdrs.as_ptr().cast()));
+        assert_ne!(arena.used(), 0);
+        loader.shrink();
+        assert_eq!(arena.used(), 0);
+
+        let liba = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &pre_find)
+            .unwrap();
+        // 程序头表不在文件开头时通过arena中的缓冲区读取,它在加载结束时被释放
+        let mut bytes = std::fs::read(lib_path("liba.so")).unwrap();
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        let phoff = u64::from_ne_bytes(bytes[32..40].try_into().unwrap()) as usize;
+        let phnum = u16::from_ne_bytes(bytes[56..58].try_into().unwrap()) as usize;
+        let table = bytes[phoff..phoff + phnum * 56].to_vec();
+        let new_phoff = bytes.len() as u64;
+        bytes[32..40].copy_from_slice(&new_phoff.to_ne_bytes());
+        bytes.extend_from_slice(&table);
+        let moved = loader
+            .easy_load_dylib(ElfBinary::new("liba.so", &bytes))
+            .unwrap();
+        assert_eq!(arena.used(), 0);
+        let moved = moved.easy_relocate([].iter(), &pre_find).unwrap();
+        assert_eq!(unsafe { moved.get::<fn() -> i32>("a").unwrap() }(), 1);
+        let libb = loader.load_dylib(object, Some(false)).unwrap();
+        let mut relocation = libb
+            .relocate_chunked(
+                [&liba].into_iter(),
+                &pre_find,
+                |_, _, _| Err(Box::new(())),
+                None,
+            )
+            .unwrap();
+        // 重定位的进度保存在arena中,finish之后被释放
+        assert_ne!(arena.used(), 0);
+        while relocation.step(2).unwrap() == RelocateStatus::Pending {}
+        let b = relocation.finish().unwrap();
+        assert_eq!(arena.used(), 0);
+        let f = unsafe { b.get::<fn() -> i32>("b").unwrap() };
+        assert_eq!(f(), 2);
+    }
+
+    #[test]
+    fn typed_symbols() {
+        use elf_loader::{Error, define_symbols};
+        compile();
+        define_symbols! {
+            struct LibA {
+                a: fn() -> i32,
+                PLUGIN_ABI_VERSION: *const u32,
+                no_such_symbol: Option<extern "C" fn()>,
+            }
+        }
+        define_symbols! {
+            struct Missing {
+                no_such_symbol: fn(),
+            }
+        }
+        define_symbols! {
+            struct FuncAsData {
+                a: *const i32,
+            }
+        }
+        define_symbols! {
+            struct TooLarge {
+                PLUGIN_ABI_VERSION: *const u64,
+            }
+        }
+        let mut loader = Loader::<MmapImpl>::new();
+        let lib = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let liba = unsafe { LibA::load(&lib) }.unwrap();
+        drop(lib);
+        // 结构体持有库的引用
+        assert_eq!((liba.a)(), 1);
+        assert_eq!(unsafe { *liba.PLUGIN_ABI_VERSION }, 1);
+        assert!(liba.no_such_symbol.is_none());
+        assert_eq!(liba.lib().shortname(), "liba.so");
+
+        let lib = loader
+            .easy_load_dylib(ElfFile::from_path(&lib_path("liba.so")).unwrap())
+            .unwrap()
+            .easy_relocate([].iter(), &|_| None)
+            .unwrap();
+        let check = |res: elf_loader::Result<()>, name: &str| match res.err().unwrap() {
+            Error::SymbolError { symbol, .. } => assert_eq!(symbol, name),
+            err => panic!("unexpected error: {err}"),
+        };
+        check(unsafe { Missing::load(&lib) }.map(drop), "no_such_symbol");
+        check(unsafe { FuncAsData::load(&lib) }.map(drop), "a");
+        let too_large = unsafe { TooLarge::load(&lib) }.map(drop);
+        check(too_large, "PLUGIN_ABI_VERSION");
+    }
+
+    #[test]
+    fn plugin() {
+        use elf_loader::plugin::{Plugin, PluginInterface};
+        compile();
+        #[repr(C)]
+        struct VTable {
+            add: fn(i32, i32) -> i32,
+        }
+        unsafe impl PluginInterface for VTable {
+            const SYMBOL: &'static str = "PLUGIN_VTABLE";
+            const VERSION_SYMBOL: &'static str = "PLUGIN_ABI_VERSION";
+            const VERSION: u32 = 1;
+        }
+        struct NewVTable;
+        unsafe impl PluginInterface for NewVTable {
+            const SYMBOL: &'static str = "PLUGIN_VTABLE";
+            const VERSION_SYMBOL: &'static str = "PLUGIN_ABI_VERSION";
+            const VERSION: u32 = 2;
+        }
+        let mut loader = Loader::<MmapImpl>::new();
+        let plugin = Plugin::<VTable>::load(
+            &mut loader,
+            ElfFile::from_path(&lib_path("liba.so")).unwrap(),
+            &|_| None,
+        )
+        .unwrap();
+        assert!((plugin.interface().add)(1, 2) == 3);
+        let err = Plugin::<NewVTable>::load(
+            &mut loader,
+            ElfFile::from_path(&lib_path("liba.so")).unwrap(),
+            &|_| None,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, elf_loader::Error::PluginError { .. }));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "std"))]
+    #[test]
+    fn run_program() {
+        use elf_loader::run;
+        compile();
+        // 参数为4个时修改MXCSR和x87控制字后调用exit
+        let path = compile_c(
+            "hello",
+            "#include <stdlib.h>\n\
+             int main(int argc, char **argv) {\n\
+             #ifdef __x86_64__\n\
+                 if (argc == 4) {\n\
+                     unsigned int csr = 0x7f80;\n\
+                     unsigned short cw = 0x0f7f;\n\
+                     __asm__ volatile(\"ldmxcsr %0\\n\\tfldcw %1\" :: \"m\"(csr), \"m\"(cw));\n\
+                 }\n\
+             #endif\n\
+                 if (argc > 2) exit(argc);\n\
+                 return argc + 40;\n\
+             }\n",
+            &["-fPIE", "-pie"],
+        );
+        let exec = load_dylib!(&path).unwrap();
+        let entry = exec.entry();
+        let _exec = exec
+            .easy_relocate([].iter(), &|name| run::interpose(name))
+            .unwrap();
+        let run = |args: &[&std::ffi::CStr]| unsafe {
+            run::run::<MmapImpl>(entry, args, &[c"A=1"], 1 << 16).unwrap()
+        };
+        assert_eq!(run(&[c"hello"]), 41);
+        assert_eq!(run(&[c"hello", c"a", c"b"]), 3);
+        // 每个线程都有自己的上下文
+        std::thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| (0..16).map(|_| run(&[c"a", c"b", c"c"])).sum::<i32>()))
+                .collect();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), 48);
+            }
+        });
+        #[cfg(target_arch = "x86_64")]
+        {
+            let state = || {
+                let (mut csr, mut cw) = (0u32, 0u16);
+                unsafe {
+                    std::arch::asm!("stmxcsr [{}]", "fnstcw [{}]", in(reg) &mut csr, in(reg) &mut cw)
+                };
+                (csr, cw)
+            };
+            let before = state();
+            assert_eq!(run(&[c"a", c"b", c"c", c"d"]), 4);
+            assert_eq!(state(), before);
+        }
+    }
 }